@@ -0,0 +1,180 @@
+#![feature(async_await)]
+
+// load generator for the server, built on the crate's own client:
+//
+//     bench [-c connections] [-p pipeline] [-d seconds] http://127.0.0.1:8989/
+//
+// `connections` workers run side by side, each with a keep-alive connection of its own. a
+// worker writes `pipeline` requests on it at once and then reads their responses, in order,
+// before the next batch (`Client::pipeline`). latency is measured per request, from the start
+// of its batch to the end of its response.
+
+use net_test3::{
+    http::client::{Client, ClientError},
+    runtime::Runtime,
+};
+use std::{
+    cell::RefCell,
+    io, process,
+    rc::Rc,
+    time::{Duration, Instant},
+};
+
+struct Options {
+    connections: usize,
+    pipeline: usize,
+    duration: Duration,
+    url: url::Url,
+}
+
+#[derive(Default)]
+struct Stats {
+    latencies: Vec<Duration>,
+    bytes: u64,
+    errors: usize,
+    non_2xx: usize,
+}
+
+fn usage() -> ! {
+    eprintln!("usage: bench [-c connections] [-p pipeline] [-d seconds] URL");
+    process::exit(2);
+}
+
+fn parse_args() -> Options {
+    let mut options = Options {
+        connections: 10,
+        pipeline: 1,
+        duration: Duration::from_secs(10),
+        url: url::Url::parse("http://127.0.0.1:8989/").unwrap(),
+    };
+    let mut args = std::env::args().skip(1);
+    let mut url = None;
+    while let Some(arg) = args.next() {
+        let mut number = || -> u64 {
+            args.next()
+                .and_then(|n| n.parse().ok())
+                .filter(|&n| n > 0)
+                .unwrap_or_else(|| usage())
+        };
+        match &*arg {
+            "-c" => options.connections = number() as usize,
+            "-p" => options.pipeline = number() as usize,
+            "-d" => options.duration = Duration::from_secs(number()),
+            _ if url.is_none() && !arg.starts_with('-') => url = Some(arg),
+            _ => usage(),
+        }
+    }
+    if let Some(url) = url {
+        options.url = url::Url::parse(&url).unwrap_or_else(|e| {
+            eprintln!("{}: {}", url, e);
+            process::exit(2);
+        });
+    }
+    if options.url.scheme() != "http" {
+        eprintln!("only http:// URLs are supported");
+        process::exit(2);
+    }
+    options
+}
+
+fn main() -> io::Result<()> {
+    let options = Rc::new(parse_args());
+    let stats = Rc::new(RefCell::new(Stats::default()));
+    let mut client = Client::new();
+    client.user_agent("bench").max_redirects(0);
+    let mut runtime = Runtime::new();
+    let start = Instant::now();
+    let deadline = start + options.duration;
+    for _ in 0..options.connections {
+        let options = Rc::clone(&options);
+        let stats = Rc::clone(&stats);
+        let mut client = client.clone();
+        // a pool of its own, so that each worker keeps to one connection
+        client.pool(1, options.duration);
+        runtime.spawn(async move {
+            if let Err(e) = worker(&client, &options, deadline, &stats).await {
+                eprintln!("connection: {}", e);
+                stats.borrow_mut().errors += 1;
+            }
+        });
+    }
+    runtime.run()?;
+    report(&options, start.elapsed(), &mut stats.borrow_mut());
+    Ok(())
+}
+
+// runs batches until `deadline`; gives up when the server can't be reached at all
+async fn worker(
+    client: &Client,
+    options: &Options,
+    deadline: Instant,
+    stats: &RefCell<Stats>,
+) -> Result<(), ClientError> {
+    while Instant::now() < deadline {
+        let sent = Instant::now();
+        let batch = (0..options.pipeline)
+            .map(|_| client.get(options.url.as_str()))
+            .collect();
+        let responses = match client.pipeline(batch).await {
+            Ok(responses) => responses,
+            Err(e @ ClientError::Connect(_)) => return Err(e),
+            Err(e) => {
+                eprintln!("request: {}", e);
+                stats.borrow_mut().errors += 1;
+                continue;
+            }
+        };
+        for res in responses {
+            let status = res.status();
+            let len = match res.bytes().await {
+                Ok(body) => body.len(),
+                Err(e) => {
+                    eprintln!("request: {}", e);
+                    stats.borrow_mut().errors += 1;
+                    continue;
+                }
+            };
+            let mut stats = stats.borrow_mut();
+            stats.latencies.push(sent.elapsed());
+            stats.bytes += len as u64;
+            if status < 200 || status >= 300 {
+                stats.non_2xx += 1;
+            }
+        }
+    }
+    Ok(())
+}
+
+fn report(options: &Options, elapsed: Duration, stats: &mut Stats) {
+    let secs = elapsed.as_secs_f64();
+    let requests = stats.latencies.len();
+    println!(
+        "{} connections, pipeline {}, {:.1}s against {}",
+        options.connections, options.pipeline, secs, options.url
+    );
+    println!(
+        "{} requests, {:.1} req/s, {:.1} KiB/s of bodies",
+        requests,
+        requests as f64 / secs,
+        stats.bytes as f64 / 1024.0 / secs
+    );
+    println!(
+        "{} non-2xx responses, {} errors",
+        stats.non_2xx, stats.errors
+    );
+    if requests == 0 {
+        return;
+    }
+    stats.latencies.sort();
+    let percentile = |p: f64| {
+        let i = ((requests as f64 * p).ceil() as usize).max(1) - 1;
+        stats.latencies[i.min(requests - 1)]
+    };
+    println!(
+        "latency p50 {:?}, p90 {:?}, p99 {:?}, max {:?}",
+        percentile(0.5),
+        percentile(0.9),
+        percentile(0.99),
+        stats.latencies[requests - 1]
+    );
+}
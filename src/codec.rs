@@ -0,0 +1,284 @@
+use futures::prelude::*;
+use futures::ready;
+use std::io;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+
+// once this much is waiting to be written, `poll_ready` flushes before taking more
+const BACKPRESSURE: usize = 8 * 1024;
+const READ_CHUNK: usize = 8 * 1024;
+
+// turns the bytes read so far into frames
+pub trait Decoder {
+    type Item;
+
+    // a frame off the front of `buf`, or None when more bytes are needed
+    fn decode(&mut self, buf: &mut Vec<u8>) -> io::Result<Option<Self::Item>>;
+
+    // called once the peer has closed; bytes left over that don't make a frame are an error
+    fn decode_eof(&mut self, buf: &mut Vec<u8>) -> io::Result<Option<Self::Item>> {
+        match self.decode(buf)? {
+            Some(item) => Ok(Some(item)),
+            None if buf.is_empty() => Ok(None),
+            None => Err(io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                "bytes remaining on stream",
+            )),
+        }
+    }
+}
+
+pub trait Encoder {
+    type Item;
+
+    fn encode(&mut self, item: Self::Item, buf: &mut Vec<u8>) -> io::Result<()>;
+}
+
+// a `Stream` of decoded frames and a `Sink` of frames to encode over one byte stream, e.g. a
+// `net::TcpStream`
+#[derive(Debug)]
+pub struct Framed<T, C> {
+    io: T,
+    codec: C,
+    read_buf: Vec<u8>,
+    write_buf: Vec<u8>,
+    eof: bool,
+}
+
+impl<T, C> Framed<T, C> {
+    pub fn new(io: T, codec: C) -> Framed<T, C> {
+        Framed {
+            io,
+            codec,
+            read_buf: Vec::new(),
+            write_buf: Vec::new(),
+            eof: false,
+        }
+    }
+
+    pub fn get_ref(&self) -> &T {
+        &self.io
+    }
+
+    pub fn get_mut(&mut self) -> &mut T {
+        &mut self.io
+    }
+
+    pub fn codec(&self) -> &C {
+        &self.codec
+    }
+
+    pub fn codec_mut(&mut self) -> &mut C {
+        &mut self.codec
+    }
+
+    // the bytes read but not decoded yet come along, e.g. to switch protocols mid-stream
+    pub fn into_parts(self) -> (T, Vec<u8>) {
+        (self.io, self.read_buf)
+    }
+}
+
+impl<T: AsyncRead + Unpin, C: Decoder + Unpin> Stream for Framed<T, C> {
+    type Item = io::Result<C::Item>;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let this = self.get_mut();
+        loop {
+            if this.eof {
+                return Poll::Ready(this.codec.decode_eof(&mut this.read_buf).transpose());
+            }
+            if let Some(item) = this.codec.decode(&mut this.read_buf)? {
+                return Poll::Ready(Some(Ok(item)));
+            }
+            let mut chunk = [0; READ_CHUNK];
+            match ready!(Pin::new(&mut this.io).poll_read(cx, &mut chunk))? {
+                0 => this.eof = true,
+                n => this.read_buf.extend_from_slice(&chunk[..n]),
+            }
+        }
+    }
+}
+
+impl<T: AsyncWrite + Unpin, C: Encoder + Unpin> Sink<C::Item> for Framed<T, C> {
+    type Error = io::Error;
+
+    fn poll_ready(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        if self.write_buf.len() >= BACKPRESSURE {
+            self.poll_flush(cx)
+        } else {
+            Poll::Ready(Ok(()))
+        }
+    }
+
+    fn start_send(self: Pin<&mut Self>, item: C::Item) -> io::Result<()> {
+        let this = self.get_mut();
+        this.codec.encode(item, &mut this.write_buf)
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        while !this.write_buf.is_empty() {
+            match ready!(Pin::new(&mut this.io).poll_write(cx, &this.write_buf))? {
+                0 => return Poll::Ready(Err(io::ErrorKind::WriteZero.into())),
+                n => {
+                    this.write_buf.drain(..n);
+                }
+            }
+        }
+        Pin::new(&mut this.io).poll_flush(cx)
+    }
+
+    fn poll_close(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        ready!(self.as_mut().poll_flush(cx))?;
+        Pin::new(&mut self.get_mut().io).poll_close(cx)
+    }
+}
+
+// text split at '\n', with a trailing '\r' dropped. lines are sent with '\n'.
+#[derive(Clone, Debug, Default)]
+pub struct LinesCodec {
+    max_len: Option<usize>,
+    // where to resume looking for '\n', so a long line isn't scanned again on every read
+    next_index: usize,
+}
+
+impl LinesCodec {
+    pub fn new() -> LinesCodec {
+        LinesCodec::default()
+    }
+
+    // lines longer than this are an error rather than buffered without bound
+    pub fn with_max_len(max_len: usize) -> LinesCodec {
+        LinesCodec {
+            max_len: Some(max_len),
+            next_index: 0,
+        }
+    }
+
+    fn line(&mut self, mut line: Vec<u8>) -> io::Result<String> {
+        self.next_index = 0;
+        if line.last() == Some(&b'\r') {
+            line.pop();
+        }
+        String::from_utf8(line).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+    }
+}
+
+impl Decoder for LinesCodec {
+    type Item = String;
+
+    fn decode(&mut self, buf: &mut Vec<u8>) -> io::Result<Option<String>> {
+        match buf[self.next_index..].iter().position(|&b| b == b'\n') {
+            Some(pos) => {
+                let end = self.next_index + pos;
+                let mut line: Vec<u8> = buf.drain(..=end).collect();
+                line.pop();
+                if self.max_len.map_or(false, |max| line.len() > max) {
+                    return Err(line_too_long());
+                }
+                self.line(line).map(Some)
+            }
+            None => {
+                if self.max_len.map_or(false, |max| buf.len() > max) {
+                    return Err(line_too_long());
+                }
+                self.next_index = buf.len();
+                Ok(None)
+            }
+        }
+    }
+
+    // a last line without '\n'
+    fn decode_eof(&mut self, buf: &mut Vec<u8>) -> io::Result<Option<String>> {
+        match self.decode(buf)? {
+            Some(line) => Ok(Some(line)),
+            None if buf.is_empty() => Ok(None),
+            None => {
+                let line = buf.drain(..).collect();
+                self.line(line).map(Some)
+            }
+        }
+    }
+}
+
+impl Encoder for LinesCodec {
+    type Item = String;
+
+    fn encode(&mut self, line: String, buf: &mut Vec<u8>) -> io::Result<()> {
+        buf.extend_from_slice(line.as_bytes());
+        buf.push(b'\n');
+        Ok(())
+    }
+}
+
+fn line_too_long() -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, "line too long")
+}
+
+// frames preceded by their length as a big-endian u32
+#[derive(Clone, Debug)]
+pub struct LengthPrefixedCodec {
+    max_frame_len: usize,
+}
+
+impl Default for LengthPrefixedCodec {
+    fn default() -> LengthPrefixedCodec {
+        LengthPrefixedCodec {
+            max_frame_len: 8 * 1024 * 1024,
+        }
+    }
+}
+
+impl LengthPrefixedCodec {
+    pub fn new() -> LengthPrefixedCodec {
+        LengthPrefixedCodec::default()
+    }
+
+    // longer frames are an error, whether announced by the peer or sent by us
+    pub fn with_max_frame_len(max_frame_len: usize) -> LengthPrefixedCodec {
+        LengthPrefixedCodec { max_frame_len }
+    }
+
+    fn frame_too_long(&self, len: usize) -> io::Error {
+        io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!(
+                "{}-byte frame over the {}-byte limit",
+                len, self.max_frame_len
+            ),
+        )
+    }
+}
+
+impl Decoder for LengthPrefixedCodec {
+    type Item = Vec<u8>;
+
+    fn decode(&mut self, buf: &mut Vec<u8>) -> io::Result<Option<Vec<u8>>> {
+        if buf.len() < 4 {
+            return Ok(None);
+        }
+        let len = u32::from_be_bytes([buf[0], buf[1], buf[2], buf[3]]) as usize;
+        if len > self.max_frame_len {
+            return Err(self.frame_too_long(len));
+        }
+        if buf.len() < 4 + len {
+            return Ok(None);
+        }
+        let frame = buf[4..4 + len].to_vec();
+        buf.drain(..4 + len);
+        Ok(Some(frame))
+    }
+}
+
+impl Encoder for LengthPrefixedCodec {
+    type Item = Vec<u8>;
+
+    fn encode(&mut self, frame: Vec<u8>, buf: &mut Vec<u8>) -> io::Result<()> {
+        if frame.len() > self.max_frame_len || frame.len() > u32::max_value() as usize {
+            return Err(self.frame_too_long(frame.len()));
+        }
+        buf.extend_from_slice(&(frame.len() as u32).to_be_bytes());
+        buf.extend_from_slice(&frame);
+        Ok(())
+    }
+}
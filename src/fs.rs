@@ -2,17 +2,17 @@ use crate::reactor;
 use futures::io::AsyncRead;
 use lazy_static::*;
 use log::*;
-use mio::*;
 use std::{
-    collections::HashMap,
+    collections::{HashMap, VecDeque},
     fs,
     future::Future,
     io::{self, prelude::*},
+    panic,
     path::{Path, PathBuf},
     pin::Pin,
     sync::{
         atomic::{AtomicUsize, Ordering},
-        mpsc, Mutex,
+        Arc, Condvar, Mutex, MutexGuard, PoisonError,
     },
     task::{self, Context},
     thread,
@@ -20,23 +20,25 @@ use std::{
 
 pub struct File {
     file: fs::File,
-    registration: Registration,
-    set_readiness: SetReadiness,
+    // turned readable by the fs thread once an operation is done
     reactor: reactor::ReactorHandle,
+    waker: reactor::ReactorWaker,
     read_handle: Option<ReadHandle<'static>>,
 }
 
 impl File {
     pub async fn open<P: AsRef<Path>>(path: P) -> io::Result<File> {
-        let (registration, set_readiness) = Registration::new2();
-        let reactor = reactor::register(&registration, Ready::readable())?;
-        let handle = fs_queue().push_open(path, set_readiness.clone());
-        let file = handle.await;
+        let (reactor, waker) = reactor::register_waker()?;
+        let mut handle = fs_queue().push_open(path, waker.clone());
+        let file = futures::future::poll_fn(|cx| {
+            reactor.add_read_waker(cx.waker().clone());
+            Pin::new(&mut handle).poll(cx)
+        })
+        .await;
         file.map(|file| File {
             file,
-            registration,
-            set_readiness,
             reactor,
+            waker,
             read_handle: None,
         })
     }
@@ -44,6 +46,12 @@ impl File {
     pub fn std(&self) -> &fs::File {
         &self.file
     }
+
+    // moves the position the next read starts at. seeking doesn't touch the disk, so it's
+    // done on the calling thread.
+    pub fn seek(&mut self, pos: io::SeekFrom) -> io::Result<u64> {
+        self.file.seek(pos)
+    }
 }
 
 impl AsyncRead for File {
@@ -55,8 +63,9 @@ impl AsyncRead for File {
         if self.read_handle.is_none() {
             let file_cloned = self.file.try_clone().unwrap(); // TODO: avoid cloning
             self.read_handle =
-                Some(fs_queue().push_read(file_cloned, buf.len(), self.set_readiness.clone()));
+                Some(fs_queue().push_read(file_cloned, buf.len(), self.waker.clone()));
         }
+        self.reactor.add_read_waker(cx.waker().clone());
         let poll = Pin::new(self.read_handle.as_mut().unwrap())
             .poll(cx)
             .map(|res| {
@@ -72,40 +81,53 @@ impl AsyncRead for File {
     }
 }
 
-impl Evented for File {
-    fn register(
-        &self,
-        poll: &Poll,
-        token: Token,
-        interest: mio::Ready,
-        opts: mio::PollOpt,
-    ) -> io::Result<()> {
-        self.registration.register(poll, token, interest, opts)
-    }
+lazy_static! {
+    static ref FS_QUEUE: FsQueue = FsQueue::spawn();
+    static ref THREAD_NAME: Mutex<String> = Mutex::new("fs".to_owned());
+}
 
-    fn reregister(
-        &self,
-        poll: &Poll,
-        token: Token,
-        interest: Ready,
-        opts: PollOpt,
-    ) -> io::Result<()> {
-        self.registration.reregister(poll, token, interest, opts)
+// names the threads file operations run on, as seen in panics, debuggers and `top -H`.
+// the first starts with the first file operation; threads already running keep their name
+// until `restart_workers`.
+pub fn set_thread_name<S: Into<String>>(name: S) {
+    if let Ok(mut thread_name) = THREAD_NAME.lock() {
+        *thread_name = name.into();
     }
+}
 
-    fn deregister(&self, poll: &Poll) -> io::Result<()> {
-        poll.deregister(&self.registration)
-    }
+// how many threads run file operations, one by default. takes effect at once: threads are
+// added right away, and surplus ones exit after the operation they are running. nothing
+// queued is dropped.
+pub fn set_workers(n: usize) {
+    fs_queue().pool.resize(n.max(1));
 }
 
-impl Drop for File {
-    fn drop(&mut self) {
-        let _ = self.reactor.deregister(&self.registration);
-    }
+// the threads running file operations, not counting those on their way out
+pub fn workers() -> usize {
+    fs_queue().pool.lock().current
 }
 
-lazy_static! {
-    static ref FS_QUEUE: FsQueue = FsQueue::spawn();
+// file operations waiting for a thread
+pub fn queued() -> usize {
+    fs_queue().pool.lock().tasks.len()
+}
+
+// replaces every thread with a fresh one, e.g. to apply `set_thread_name`. the old threads
+// finish the operation they are running; queued ones wait for the new threads.
+pub fn restart_workers() {
+    fs_queue().pool.restart();
+}
+
+// a panic in a file operation fails that operation only. the thread stays up, as everything
+// queued behind it would otherwise wait forever.
+fn unwind_to_err<T, F: FnOnce() -> io::Result<T>>(f: F) -> io::Result<T> {
+    panic::catch_unwind(panic::AssertUnwindSafe(f)).unwrap_or_else(|_| {
+        error!("file operation panicked");
+        Err(io::Error::new(
+            io::ErrorKind::Other,
+            "file operation panicked",
+        ))
+    })
 }
 
 fn fs_queue() -> &'static FsQueue {
@@ -115,7 +137,7 @@ fn fs_queue() -> &'static FsQueue {
 struct FsTask {
     token: usize,
     content: FsTaskContent,
-    set_readiness: SetReadiness,
+    waker: reactor::ReactorWaker,
 }
 
 enum FsTaskContent {
@@ -123,58 +145,135 @@ enum FsTaskContent {
     Read(fs::File, usize),
 }
 
-struct FsResult {
-    token: usize,
-    content: FsResultContent,
-}
-
 enum FsResultContent {
     Open(io::Result<fs::File>),
     Read(io::Result<Vec<u8>>),
 }
 
+// the worker threads, the operations waiting for them and the results of those done, by
+// the token of their operation
+struct Pool {
+    state: Mutex<PoolState>,
+    task_ready: Condvar,
+    results: Mutex<HashMap<usize, FsResultContent>>,
+}
+
+struct PoolState {
+    tasks: VecDeque<FsTask>,
+    target: usize,
+    // workers of the current generation; `restart` starts a new one, and workers of older
+    // generations exit
+    current: usize,
+    generation: u64,
+}
+
+impl Pool {
+    fn lock(&self) -> MutexGuard<'_, PoolState> {
+        // operations run outside the lock, their panics can't poison it
+        self.state.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    fn push(&self, task: FsTask) {
+        self.lock().tasks.push_back(task);
+        self.task_ready.notify_one();
+    }
+
+    fn resize(self: &Arc<Self>, target: usize) {
+        let mut state = self.lock();
+        debug!("fs workers: {} -> {}", state.current, target);
+        state.target = target;
+        while state.current < state.target {
+            state.current += 1;
+            self.spawn_worker(state.generation);
+        }
+        // surplus workers waiting for a task see they are surplus
+        self.task_ready.notify_all();
+    }
+
+    fn restart(self: &Arc<Self>) {
+        let mut state = self.lock();
+        debug!("restarting {} fs workers", state.current);
+        state.generation += 1;
+        state.current = state.target;
+        for _ in 0..state.target {
+            self.spawn_worker(state.generation);
+        }
+        self.task_ready.notify_all();
+    }
+
+    fn spawn_worker(self: &Arc<Self>, generation: u64) {
+        let name = THREAD_NAME
+            .lock()
+            .map_or_else(|_| "fs".to_owned(), |n| n.clone());
+        let pool = Arc::clone(self);
+        thread::Builder::new()
+            .name(name)
+            .spawn(move || pool.work(generation))
+            .expect("spawning an fs thread");
+    }
+
+    // the next task, or None once this worker is to exit
+    fn next_task(&self, generation: u64) -> Option<FsTask> {
+        let mut state = self.lock();
+        loop {
+            if state.generation != generation {
+                return None;
+            }
+            if state.current > state.target {
+                state.current -= 1;
+                return None;
+            }
+            if let Some(task) = state.tasks.pop_front() {
+                return Some(task);
+            }
+            state = self
+                .task_ready
+                .wait(state)
+                .unwrap_or_else(PoisonError::into_inner);
+        }
+    }
+
+    fn work(&self, generation: u64) {
+        while let Some(task) = self.next_task(generation) {
+            let res = match task.content {
+                FsTaskContent::Open(path) => {
+                    FsResultContent::Open(unwind_to_err(|| fs::File::open(&path)))
+                }
+                FsTaskContent::Read(mut file, len) => {
+                    FsResultContent::Read(unwind_to_err(|| FsQueue::read(&mut file, len)))
+                }
+            };
+            // the result has to be there before the reactor wakes the handle
+            self.results
+                .lock()
+                .unwrap_or_else(PoisonError::into_inner)
+                .insert(task.token, res);
+            task.waker.wake();
+        }
+        trace!("fs worker of generation {} exiting", generation);
+    }
+}
+
 struct FsQueue {
-    task_tx: mpsc::Sender<FsTask>,
-    result_rx: mpsc::Receiver<FsResult>,
-    result_map: Mutex<HashMap<usize, FsResult>>,
+    pool: Arc<Pool>,
     next_token: AtomicUsize,
 }
 
-unsafe impl Sync for FsQueue {}
-
 impl FsQueue {
     fn spawn() -> FsQueue {
-        let (task_tx, task_rx) = mpsc::channel::<FsTask>();
-        let (result_tx, result_rx) = mpsc::channel();
-        let _handle = thread::spawn(move || {
-            for task in task_rx {
-                let (res, readiness) = match task.content {
-                    FsTaskContent::Open(path) => (
-                        FsResultContent::Open(fs::File::open(&path)),
-                        Ready::readable(),
-                    ),
-                    FsTaskContent::Read(mut file, len) => (
-                        FsResultContent::Read(Self::read(&mut file, len)),
-                        Ready::readable(),
-                    ),
-                };
-                let _ = task.set_readiness.set_readiness(readiness);
-                if result_tx
-                    .send(FsResult {
-                        token: task.token,
-                        content: res,
-                    })
-                    .is_err()
-                {
-                    break;
-                }
-            }
+        let pool = Arc::new(Pool {
+            state: Mutex::new(PoolState {
+                tasks: VecDeque::new(),
+                target: 0,
+                current: 0,
+                generation: 0,
+            }),
+            task_ready: Condvar::new(),
+            results: Mutex::new(HashMap::new()),
         });
-
+        pool.resize(1);
         FsQueue {
-            task_tx,
-            result_rx,
-            result_map: Mutex::new(HashMap::new()),
+            pool,
             next_token: AtomicUsize::new(1),
         }
     }
@@ -189,45 +288,34 @@ impl FsQueue {
         })
     }
 
-    fn push_task(&self, content: FsTaskContent, set_readiness: SetReadiness) -> FsQueueHandle {
+    fn push_task(&self, content: FsTaskContent, waker: reactor::ReactorWaker) -> FsQueueHandle {
         let token = self.next_token.fetch_add(1, Ordering::SeqCst);
-        self.task_tx
-            .send(FsTask {
-                content,
-                token,
-                set_readiness,
-            })
-            .unwrap();
+        self.pool.push(FsTask {
+            content,
+            token,
+            waker,
+        });
         FsQueueHandle { token, que: &self }
     }
 
-    fn push_open<P: AsRef<Path>>(&self, path: P, set_readiness: SetReadiness) -> OpenHandle {
+    fn push_open<P: AsRef<Path>>(&self, path: P, waker: reactor::ReactorWaker) -> OpenHandle {
         OpenHandle {
-            inner: self.push_task(FsTaskContent::Open(path.as_ref().to_owned()), set_readiness),
+            inner: self.push_task(FsTaskContent::Open(path.as_ref().to_owned()), waker),
         }
     }
 
-    fn push_read(&self, file: fs::File, len: usize, set_readiness: SetReadiness) -> ReadHandle {
+    fn push_read(&self, file: fs::File, len: usize, waker: reactor::ReactorWaker) -> ReadHandle {
         ReadHandle {
-            inner: self.push_task(FsTaskContent::Read(file, len), set_readiness),
+            inner: self.push_task(FsTaskContent::Read(file, len), waker),
         }
     }
 
-    fn move_results(&self) {
-        if let Ok(mut map) = self.result_map.lock() {
-            for res in self.result_rx.try_iter() {
-                map.insert(res.token, res);
-            }
-        }
-    }
-
-    fn result(&self, key: usize) -> Option<FsResult> {
-        self.move_results(); // TODO: calls fewer
-        if let Ok(mut map) = self.result_map.lock() {
-            map.remove(&key)
-        } else {
-            None
-        }
+    fn result(&self, token: usize) -> Option<FsResultContent> {
+        self.pool
+            .results
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .remove(&token)
     }
 }
 
@@ -240,7 +328,7 @@ impl<'a> Future for FsQueueHandle<'a> {
     type Output = FsResultContent;
     fn poll(self: Pin<&mut Self>, _cx: &mut Context) -> task::Poll<Self::Output> {
         if let Some(res) = self.que.result(self.token) {
-            task::Poll::Ready(res.content)
+            task::Poll::Ready(res)
         } else {
             task::Poll::Pending
         }
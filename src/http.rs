@@ -1,14 +1,110 @@
 use crate::net::*;
 use crate::reactor;
-use crate::runner::{Runner, Spawner};
+use crate::runner::{JoinHandle, Spawner};
+use crate::runtime::Runtime;
+#[cfg(unix)]
+use crate::signal::{Signal, Signals};
+use crate::time;
+use crate::wire::WireTrace;
+use accept::{AcceptError, AcceptErrorHook, AcceptErrorKind, AcceptHook, Accepted, ListenerConfig};
+use cookie::Cookie;
+use error::{ErrorHook, Guarded, HttpError};
+use futures::channel::oneshot;
+use futures::future::{self, Either, LocalBoxFuture};
 use futures::prelude::*;
+use futures::stream::LocalBoxStream;
+use host::HostPattern;
+use ip_filter::{Cidr, IpFilter};
 use log::*;
-use std::{collections::HashMap, future::Future, io, rc::Rc};
+use memory::{Budget, Charge, Charged, Exceeded, SharedCharge};
+use metrics::Metrics;
+use peer_limit::{PeerSlot, PeerSlots, Slotted};
+use progress::{Direction, Progress, ProgressHook, Tracker};
+use session::Session;
+use shutdown::{ShutdownHandle, ShutdownHook};
+use site::Site;
+use slab::Slab;
+use std::{
+    borrow::Cow,
+    cell::{Cell, RefCell},
+    collections::HashMap,
+    future::Future,
+    io,
+    net::{IpAddr, SocketAddr},
+    panic::{self, AssertUnwindSafe},
+    path::{Path, PathBuf},
+    pin::Pin,
+    rc::Rc,
+    str,
+    task::{Poll, Waker},
+    time::{Duration, Instant},
+};
+use trace::{RequestSpan, Traced};
+use trace_context::TraceContext;
+use upgrade::{OnUpgrade, Upgraded};
+
+pub mod accept;
+pub mod access_log;
+pub mod auth;
+pub mod base64;
+pub mod client;
+pub mod compress;
+pub mod conditional;
+pub mod cookie;
+pub mod date;
+pub mod decompress;
+pub mod error;
+#[cfg(feature = "serde")]
+pub mod extract;
+pub mod form;
+mod forwarded;
+pub mod header;
+pub mod host;
+pub mod http2;
+pub mod ip_filter;
+#[cfg(feature = "json")]
+pub mod json;
+#[cfg(feature = "jwt")]
+pub mod jwt;
+pub mod language;
+pub mod memory;
+pub mod metrics;
+pub mod middleware;
+pub mod multipart;
+mod peer_limit;
+pub mod progress;
+pub mod proxy;
+pub mod range;
+pub mod rate_limit;
+pub mod request_id;
+pub mod session;
+pub mod shutdown;
+pub mod site;
+pub mod sse;
+mod trace;
+pub mod trace_context;
+pub mod upgrade;
+
+const MAX_HEAD_LEN: usize = 8 * 1024;
+// a chunk-size line: the size, extensions and CRLF
+const MAX_CHUNK_LINE: usize = 1024;
+// 16 hex digits already overflow a usize on 64-bit targets
+const MAX_CHUNK_SIZE_DIGITS: usize = 16;
+// senders split bodies into chunks of kilobytes; a size past this is a mistake or an attack
+const MAX_CHUNK_SIZE: usize = 64 * 1024 * 1024;
+// all trailer lines together
+const MAX_TRAILERS_LEN: usize = 8 * 1024;
+// how much of a rejected request is read and dropped before closing, and for how long
+const MAX_DRAIN_LEN: usize = 64 * 1024;
+const DRAIN_TIME: Duration = Duration::from_secs(1);
 
 pub trait HttpApp {
     type Output: Future<Output = Response>;
     // TODO: &self to &mut self
     fn app(&self, req: Request) -> Self::Output;
+
+    // pushes a description of every misconfiguration found; see `HttpServer::check`
+    fn check(&self, _problems: &mut Vec<String>) {}
 }
 
 impl<F: Fn(Request) -> T, T> HttpApp for F
@@ -21,126 +117,1676 @@ where
     }
 }
 
-pub struct HttpServer<'a, T> {
-    runner: Runner<'a>,
-    inner: Rc<HttpServerInner<'a, T>>,
+pub struct HttpServer<T> {
+    listeners: Vec<(Listener, ListenerConfig)>,
+    app: T,
+    config: Config,
+}
+
+// how a peer appears in logs
+fn peer_name(peer: Option<SocketAddr>) -> String {
+    peer.map_or_else(|| "unix socket".to_owned(), |addr| addr.to_string())
+}
+
+// makes way for a Unix socket at `path` if the one there is left over from a server that is
+// gone: nothing accepts connections on it any more
+#[cfg(unix)]
+fn remove_stale_socket(path: &Path) -> io::Result<()> {
+    use std::os::unix::fs::FileTypeExt;
+    let meta = match std::fs::symlink_metadata(path) {
+        Ok(meta) => meta,
+        Err(ref e) if e.kind() == io::ErrorKind::NotFound => return Ok(()),
+        Err(e) => return Err(e),
+    };
+    if !meta.file_type().is_socket() {
+        return Err(io::Error::new(
+            io::ErrorKind::AlreadyExists,
+            format!("{} exists and isn't a socket", path.display()),
+        ));
+    }
+    match std::os::unix::net::UnixStream::connect(path) {
+        Ok(_) => Err(io::Error::new(
+            io::ErrorKind::AddrInUse,
+            format!("{} is in use", path.display()),
+        )),
+        Err(ref e) if e.kind() == io::ErrorKind::ConnectionRefused => {
+            info!("removing stale socket {}", path.display());
+            std::fs::remove_file(path)
+        }
+        Err(e) => Err(e),
+    }
+}
+
+struct Config {
+    wire_trace: Option<usize>,
+    http2: bool,
+    progress: Option<(ProgressHook, u64)>,
+    allowed_hosts: Vec<HostPattern>,
+    sites: Vec<(HostPattern, Site)>,
+    memory: Rc<Budget>,
+    max_accepts_per_turn: Option<usize>,
+    accept_pause: Option<Duration>,
+    strict_framing: bool,
+    strict_utf8: bool,
+    on_error: Option<ErrorHook>,
+    shutdown: ShutdownHandle,
+    shutdown_hooks: Vec<ShutdownHook>,
+    shutdown_timeout: Duration,
+    keep_alive_timeout: Duration,
+    #[cfg(unix)]
+    shutdown_signals: Vec<Signal>,
+    #[cfg(unix)]
+    signal_hooks: Vec<(Signal, Box<dyn Fn()>)>,
+    metrics: Option<Metrics>,
+    peer_slots: Option<Rc<PeerSlots>>,
+    ip_filter: Option<IpFilter>,
+    trusted_proxies: Vec<Cidr>,
+    unix_mode: Option<u32>,
+    max_body_len: Option<usize>,
+    on_accept: Option<AcceptHook>,
+    on_accept_error: Option<AcceptErrorHook>,
+    accept_backoff: (Duration, Duration),
+    reserve_fd: bool,
+    socket_options: SocketOptions,
 }
 
-struct HttpServerInner<'a, T> {
-    tcp: TcpListener,
+impl Default for Config {
+    fn default() -> Config {
+        Config {
+            wire_trace: None,
+            http2: true,
+            progress: None,
+            allowed_hosts: Vec::new(),
+            sites: Vec::new(),
+            memory: Rc::new(Budget::default()),
+            max_accepts_per_turn: None,
+            accept_pause: None,
+            strict_framing: true,
+            strict_utf8: false,
+            on_error: None,
+            shutdown: ShutdownHandle::default(),
+            shutdown_hooks: Vec::new(),
+            shutdown_timeout: Duration::from_secs(30),
+            keep_alive_timeout: Duration::from_secs(5),
+            #[cfg(unix)]
+            shutdown_signals: Vec::new(),
+            #[cfg(unix)]
+            signal_hooks: Vec::new(),
+            metrics: None,
+            peer_slots: None,
+            ip_filter: None,
+            trusted_proxies: Vec::new(),
+            unix_mode: None,
+            max_body_len: None,
+            on_accept: None,
+            on_accept_error: None,
+            accept_backoff: (Duration::from_millis(5), Duration::from_secs(1)),
+            reserve_fd: false,
+            socket_options: {
+                let mut options = SocketOptions::new();
+                options.nodelay(true);
+                options
+            },
+        }
+    }
+}
+
+// the server while it runs. connection tasks borrow it rather than sharing ownership, and
+// keep what they have in common in `conns`.
+struct HttpServerInner<T> {
+    listeners: Vec<(Listener, ListenerConfig)>,
     app: T,
-    spawner: Spawner<'a>,
+    config: Config,
+    next_conn_id: Cell<usize>,
+    // open connections, and the shutdown task waiting for there to be none
+    conns: RefCell<Slab<Conn>>,
+    idle_waker: RefCell<Option<Waker>>,
+    // the descriptor `reserve_fd` holds back
+    spare_fd: RefCell<Option<std::fs::File>>,
+}
+
+// an open connection, at its token in `HttpServerInner::conns`
+struct Conn {
+    // for logs; unlike tokens, ids aren't reused
+    id: usize,
+    // index into `listeners`
+    listener: usize,
+    peer: Option<SocketAddr>,
+    opened: Instant,
+    // for tearing the connection down when the shutdown times out
+    task: Option<JoinHandle<()>>,
+}
+
+// removes a connection from `conns` when its task ends, or is dropped once aborted
+struct Closed<'s, T> {
+    server: &'s HttpServerInner<T>,
+    token: usize,
+}
+
+impl<'s, T> Drop for Closed<'s, T> {
+    fn drop(&mut self) {
+        if let Some(metrics) = &self.server.config.metrics {
+            metrics.connection_closed();
+        }
+        let mut conns = self.server.conns.borrow_mut();
+        let conn = conns.remove(self.token);
+        debug!("#{}: closed after {:?}", conn.id, conn.opened.elapsed());
+        if conns.is_empty() {
+            if let Some(waker) = self.server.idle_waker.borrow_mut().take() {
+                waker.wake();
+            }
+        }
+    }
 }
 
-impl<'a, T: HttpApp + 'a> HttpServer<'a, T> {
+impl<T: HttpApp> HttpServer<T> {
     pub fn bind(addr: &std::net::SocketAddr, app: T) -> io::Result<Self> {
-        let runner = Runner::new();
-        Ok(HttpServer {
-            inner: Rc::new(HttpServerInner {
-                tcp: TcpListener::bind(addr)?,
-                app,
-                spawner: runner.spawner(),
-            }),
-            runner,
-        })
+        Ok(HttpServer::from_listener(TcpListener::bind(addr)?, app))
     }
 
-    pub fn run(mut self) -> io::Result<()> {
-        self.inner.spawner.spawn(Rc::clone(&self.inner).accept());
-        loop {
-            reactor::turn(None)?;
-            self.runner.run();
+    // serves on a socket bound elsewhere, e.g. one of `net::systemd::listeners`
+    pub fn from_listener<L: Into<Listener>>(listener: L, app: T) -> Self {
+        HttpServer {
+            listeners: vec![(listener.into(), ListenerConfig::default())],
+            app,
+            config: Config::default(),
+        }
+    }
+
+    // serves on a Unix domain socket at `path`, e.g. for a web server on the same host to
+    // proxy to. a socket left there by a server that is gone is replaced; one still in use,
+    // or any other file, is an error. the socket file is removed when the server is dropped.
+    #[cfg(unix)]
+    pub fn bind_unix<P: AsRef<Path>>(path: P, app: T) -> io::Result<Self> {
+        let path = path.as_ref();
+        remove_stale_socket(path)?;
+        Ok(HttpServer::from_listener(UnixListener::bind(path)?, app))
+    }
+
+    // serves on `addr` too, e.g. an IPv6 address beside the IPv4 one, or a port for
+    // internal clients with a listener config of its own
+    pub fn listen(&mut self, addr: &SocketAddr) -> io::Result<&mut ListenerConfig> {
+        Ok(self.add_listener(TcpListener::bind(addr)?))
+    }
+
+    // serves on a Unix socket at `path` too; see `bind_unix`
+    #[cfg(unix)]
+    pub fn listen_unix<P: AsRef<Path>>(&mut self, path: P) -> io::Result<&mut ListenerConfig> {
+        let path = path.as_ref();
+        remove_stale_socket(path)?;
+        Ok(self.add_listener(UnixListener::bind(path)?))
+    }
+
+    // serves on a socket bound elsewhere too, e.g. the other `net::systemd::listeners`
+    pub fn add_listener<L: Into<Listener>>(&mut self, listener: L) -> &mut ListenerConfig {
+        self.listeners
+            .push((listener.into(), ListenerConfig::default()));
+        &mut self.listeners.last_mut().unwrap().1
+    }
+
+    // permissions for the socket file of `bind_unix`, e.g. 0o660 for the group of the web
+    // server in front; set when `run` starts. the umask decides otherwise.
+    #[cfg(unix)]
+    pub fn unix_mode(&mut self, mode: u32) -> &mut Self {
+        self.config.unix_mode = Some(mode);
+        self
+    }
+
+    // dumps at most `limit` bytes of every chunk sent and received (debug level, `wire` module)
+    pub fn wire_trace(&mut self, limit: usize) -> &mut Self {
+        self.config.wire_trace = Some(limit);
+        self
+    }
+
+    // set on every accepted TCP connection, after those of its listener (see
+    // `ListenerBuilder::accepted`); by default only TCP_NODELAY, as responses are written in
+    // a few writes that shouldn't wait on the peer's delayed ACKs
+    pub fn socket_options(&mut self, options: &SocketOptions) -> &mut Self {
+        self.config.socket_options = options.clone();
+        self
+    }
+
+    // accepts at most `n` connections per reactor turn, then waits for the next turn so the
+    // connections already accepted get served; smooths reconnect storms after a restart
+    pub fn max_accepts_per_turn(&mut self, n: usize) -> &mut Self {
+        self.config.max_accepts_per_turn = Some(n.max(1));
+        self
+    }
+
+    // after each burst of `max_accepts_per_turn` accepts, waits `pause` rather than only
+    // until the next turn
+    pub fn accept_pause(&mut self, pause: Duration) -> &mut Self {
+        self.config.accept_pause = Some(pause);
+        self
+    }
+
+    // refuse request heads with bare LF line ends, obs-fold continuations or malformed header
+    // lines with 400 rather than guessing what the client meant; on by default. ambiguous
+    // message framing is refused either way.
+    pub fn strict_framing(&mut self, enabled: bool) -> &mut Self {
+        self.config.strict_framing = enabled;
+        self
+    }
+
+    // refuse with 400 request targets that aren't visible ASCII and header values that aren't
+    // UTF-8, rather than replacing what doesn't decode with U+FFFD; off by default
+    pub fn strict_utf8(&mut self, enabled: bool) -> &mut Self {
+        self.config.strict_utf8 = enabled;
+        self
+    }
+
+    // accept cleartext h2 (`Upgrade: h2c` or prior knowledge); on by default
+    pub fn http2(&mut self, enabled: bool) -> &mut Self {
+        self.config.http2 = enabled;
+        self
+    }
+
+    // called with the bytes received/sent so far for each HTTP/1 request and response body,
+    // every `sample_bytes` bytes and once more when the transfer completes
+    pub fn on_progress<F: Fn(&Progress) + 'static>(
+        &mut self,
+        sample_bytes: u64,
+        hook: F,
+    ) -> &mut Self {
+        self.config.progress = Some((Box::new(hook), sample_bytes.max(1)));
+        self
+    }
+
+    // called with every connection accepted, before anything is read from it, to admit it or
+    // turn it away: returning false closes it. runs after `ip_filter`.
+    pub fn on_accept<F>(&mut self, hook: F) -> &mut Self
+    where
+        F: Fn(&Accepted) -> bool + 'static,
+    {
+        self.config.on_accept = Some(Box::new(hook));
+        self
+    }
+
+    // called with every failed accept, classified, e.g. to alert when the process runs out of
+    // file descriptors. the errors go to `on_error` as well.
+    pub fn on_accept_error<F>(&mut self, hook: F) -> &mut Self
+    where
+        F: Fn(&AcceptError) + 'static,
+    {
+        self.config.on_accept_error = Some(Box::new(hook));
+        self
+    }
+
+    // after an accept fails for want of descriptors or memory, or for no known reason, the
+    // listener waits `min` before accepting again, twice as long after each further failure
+    // up to `max`, rather than failing again at once; 5ms and 1s by default
+    pub fn accept_backoff(&mut self, min: Duration, max: Duration) -> &mut Self {
+        self.config.accept_backoff = (min, max.max(min));
+        self
+    }
+
+    // holds a file descriptor back while running. once the process is out of them, it is
+    // given up to accept the connection waiting and close it at once, so that clients are
+    // turned away rather than left to time out in the listen backlog; Unix only.
+    pub fn reserve_fd(&mut self, reserve: bool) -> &mut Self {
+        self.config.reserve_fd = reserve;
+        self
+    }
+
+    // called with requests that couldn't be parsed, error responses without a body (404, 413
+    // and the like, from the server or the app), handler panics and connection I/O errors.
+    // a response returned replaces the built-in one, e.g. a branded page or a JSON body;
+    // for I/O errors there is no one to answer. a panicking handler gets 500 either way.
+    pub fn on_error<F>(&mut self, hook: F) -> &mut Self
+    where
+        F: Fn(&HttpError) -> Option<Response> + 'static,
+    {
+        self.config.on_error = Some(Rc::new(hook));
+        self
+    }
+
+    // `hook` runs during graceful shutdown, once the open connections are done: flush logs,
+    // close pools, deregister from service discovery. all hooks run concurrently.
+    pub fn on_shutdown<F, Fut>(&mut self, hook: F) -> &mut Self
+    where
+        F: FnOnce() -> Fut + 'static,
+        Fut: Future<Output = ()> + 'static,
+    {
+        self.config
+            .shutdown_hooks
+            .push(Box::new(move || hook().boxed_local()));
+        self
+    }
+
+    // how long a graceful shutdown may take, open connections and hooks together; `run`
+    // returns when it has passed even if they aren't done. 30 seconds by default.
+    pub fn shutdown_timeout(&mut self, timeout: Duration) -> &mut Self {
+        self.config.shutdown_timeout = timeout;
+        self
+    }
+
+    // how long an HTTP/1.1 connection may sit idle between requests before it is closed, 5
+    // seconds by default; zero closes every connection after its first response. idle
+    // connections are closed at once on shutdown.
+    pub fn keep_alive_timeout(&mut self, timeout: Duration) -> &mut Self {
+        self.config.keep_alive_timeout = timeout;
+        self
+    }
+
+    // for starting a graceful shutdown, after which `run` returns
+    pub fn shutdown_handle(&self) -> ShutdownHandle {
+        self.config.shutdown.clone()
+    }
+
+    // starts a graceful shutdown on any of `signals`, usually `Interrupt` and `Terminate`; a
+    // second one during the shutdown ends the process at once
+    #[cfg(unix)]
+    pub fn shutdown_on(&mut self, signals: &[Signal]) -> &mut Self {
+        self.config.shutdown_signals.extend_from_slice(signals);
+        self
+    }
+
+    // `hook` runs on the server's thread each time `signal` arrives while the server runs,
+    // e.g. reloading configuration or reopening log files on `Hangup`
+    #[cfg(unix)]
+    pub fn on_signal<F>(&mut self, signal: Signal, hook: F) -> &mut Self
+    where
+        F: Fn() + 'static,
+    {
+        self.config.signal_hooks.push((signal, Box::new(hook)));
+        self
+    }
+
+    // a client IP with `max` responses in flight gets 429 Too Many Requests for more, so it
+    // can't take up the whole server with slow downloads; a response counts until its body
+    // is written
+    pub fn max_in_flight_per_ip(&mut self, max: usize) -> &mut Self {
+        self.config.peer_slots = Some(Rc::new(PeerSlots::new(max)));
+        self
+    }
+
+    // connections from peers `filter` refuses are closed as soon as they are accepted
+    pub fn ip_filter(&mut self, filter: &IpFilter) -> &mut Self {
+        self.config.ip_filter = Some(filter.clone());
+        self
+    }
+
+    // peers whose X-Forwarded-For and Forwarded headers are believed, for
+    // `Request::client_addr`; none by default, as anyone can send them
+    pub fn trusted_proxies(&mut self, proxies: &[Cidr]) -> &mut Self {
+        self.config.trusted_proxies = proxies.to_vec();
+        self
+    }
+
+    // counts open and accepted connections into `metrics`
+    pub fn metrics(&mut self, metrics: &Metrics) -> &mut Self {
+        self.config.metrics = Some(metrics.clone());
+        self
+    }
+
+    // once any pattern is added, requests addressed to other hosts get 421 Misdirected Request
+    pub fn allow_host<P: Into<HostPattern>>(&mut self, pattern: P) -> &mut Self {
+        self.config.allowed_hosts.push(pattern.into());
+        self
+    }
+
+    // requests for hosts matching `pattern` go to `site` instead of the server's app;
+    // an exact name beats a wildcard, and a longer wildcard beats a shorter one
+    pub fn site<P: Into<HostPattern>>(&mut self, pattern: P, site: Site) -> &mut Self {
+        self.config.sites.push((pattern.into(), site));
+        self
+    }
+
+    // larger request bodies get 413 Payload Too Large, as soon as Content-Length or the chunk
+    // going over it arrives; nothing past the limit is buffered, and the connection is closed.
+    // a site's own `Site::max_body_len` takes precedence. h2 streams are bounded at
+    // `http2::DEFAULT_MAX_BODY_LEN` when it isn't set.
+    pub fn max_body_len(&mut self, len: usize) -> &mut Self {
+        self.config.max_body_len = Some(len);
+        self
+    }
+
+    // approximate memory one request may hold (head, body and response body); requests over it
+    // get 413 Payload Too Large, or 503 when an over-sized response was already produced
+    pub fn request_memory_limit(&mut self, bytes: usize) -> &mut Self {
+        self.config.memory.set_per_request(bytes);
+        self
+    }
+
+    // memory all in-flight requests together may hold; requests arriving while it is used up
+    // are shed with 503 Service Unavailable
+    pub fn memory_limit(&mut self, bytes: usize) -> &mut Self {
+        self.config.memory.set_global(bytes);
+        self
+    }
+
+    // current and peak accounted memory, readable while the server runs
+    pub fn memory_usage(&self) -> Rc<Budget> {
+        Rc::clone(&self.config.memory)
+    }
+
+    // validates the configuration without serving: every problem found is logged and the
+    // first error names them all. `bind` has already proved the address can be bound, and
+    // holds it until the server is dropped; `ListenerBuilder::check` binds and releases at
+    // once, for a dry run that mustn't take the port.
+    pub fn check(&self) -> io::Result<()> {
+        let mut problems = Vec::new();
+        for (i, (listener, config)) in self.listeners.iter().enumerate() {
+            if let Err(e) = listener.check() {
+                let name = config.name.clone().unwrap_or_else(|| i.to_string());
+                problems.push(format!("listener {}: {}", name, e));
+            }
+        }
+        #[cfg(unix)]
+        let unix = |listener: &Listener| match listener {
+            Listener::Unix(_) => true,
+            _ => false,
+        };
+        #[cfg(not(unix))]
+        let unix = |_: &Listener| false;
+        if self.config.unix_mode.is_some() && !self.listeners.iter().any(|(l, _)| unix(l)) {
+            problems.push("unix_mode set without a Unix socket listener".to_owned());
+        }
+        for (i, (pattern, site)) in self.config.sites.iter().enumerate() {
+            if self.config.sites[..i].iter().any(|(p, _)| p == pattern) {
+                problems.push(format!("site {:?} registered twice", pattern));
+            }
+            site.check(&mut problems);
+        }
+        self.app.check(&mut problems);
+        for problem in &problems {
+            error!("{}", problem);
+        }
+        if problems.is_empty() {
+            Ok(())
+        } else {
+            Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                problems.join("; "),
+            ))
         }
     }
+
+    // serves until a graceful shutdown has finished; see `shutdown_handle`
+    pub fn run(self) -> io::Result<()> {
+        let HttpServer {
+            listeners,
+            app,
+            mut config,
+        } = self;
+        #[cfg(unix)]
+        for (listener, _) in &listeners {
+            if let (Listener::Unix(unix), Some(mode)) = (listener, config.unix_mode) {
+                unix.set_mode(mode)?;
+            }
+        }
+        let hooks = std::mem::replace(&mut config.shutdown_hooks, Vec::new());
+        let inner = HttpServerInner {
+            listeners,
+            app,
+            config,
+            next_conn_id: Cell::new(1),
+            conns: RefCell::new(Slab::new()),
+            idle_waker: RefCell::new(None),
+            spare_fd: RefCell::new(None),
+        };
+        // declared after what its tasks borrow, so they are dropped first
+        let mut runtime = Runtime::new();
+        let spawner = runtime.spawner();
+        inner.reserve_fd();
+        for i in 0..inner.listeners.len() {
+            spawner.spawn_named(format!("accept {}", i), inner.accept(i, runtime.spawner()));
+        }
+        #[cfg(unix)]
+        {
+            let mut wanted = inner.config.shutdown_signals.clone();
+            wanted.extend(inner.config.signal_hooks.iter().map(|(signal, _)| *signal));
+            if !wanted.is_empty() {
+                spawner.spawn_named("signals", inner.signals(Signals::new(&wanted)?));
+            }
+        }
+        runtime.block_on(inner.shutdown(hooks));
+        Ok(())
+    }
+}
+
+#[cfg(feature = "test-util")]
+impl<T: HttpApp> HttpServer<T> {
+    // serves `sock`, e.g. a `test_util::MemoryStream`, as a connection accepted from `peer`,
+    // with the server's app and settings; its listener is left alone. resolves once the
+    // connection would be closed.
+    pub async fn serve_connection<S>(self, peer: SocketAddr, sock: &mut S) -> io::Result<()>
+    where
+        S: AsyncRead + AsyncWrite + Unpin,
+    {
+        let HttpServer {
+            listeners,
+            app,
+            config,
+        } = self;
+        let inner = HttpServerInner {
+            listeners,
+            app,
+            config,
+            next_conn_id: Cell::new(1),
+            conns: RefCell::new(Slab::new()),
+            idle_waker: RefCell::new(None),
+            spare_fd: RefCell::new(None),
+        };
+        inner.connection_inner(0, 0, Some(peer), sock).await?;
+        sock.close().await
+    }
 }
 
-impl<'a, T: HttpApp + 'a> HttpServerInner<'a, T> {
-    async fn accept(self: Rc<Self>) {
+impl<T: HttpApp> HttpServerInner<T> {
+    // accepts on `listeners[on]` and runs the connections on `spawner`
+    async fn accept<'s>(&'s self, on: usize, spawner: Spawner<'s>) {
+        let (listener, listener_config) = &self.listeners[on];
+        let ip_filter = listener_config
+            .ip_filter
+            .as_ref()
+            .or_else(|| self.config.ip_filter.as_ref());
+        let on_name = listener_config
+            .name
+            .as_ref()
+            .map_or_else(String::new, |name| format!(" on {}", name));
+        // connections accepted during reactor turn `turn`
+        let (mut turn, mut accepted) = (reactor::turns(), 0);
+        let mut shutdown = self.config.shutdown.triggered();
+        // the last wait after a failed accept; None once one succeeds
+        let mut backoff: Option<Duration> = None;
+        // set when the spare descriptor was given up for the next connection
+        let mut shed = false;
         loop {
-            match self.tcp.accept().await {
+            let max = listener_config
+                .max_accepts_per_turn
+                .or(self.config.max_accepts_per_turn);
+            if max.map_or(false, |max| accepted >= max && reactor::turns() == turn) {
+                // a timer that is already due fires on the next turn
+                let pause = self.config.accept_pause.unwrap_or_default();
+                trace!("accepted {} this turn, pausing for {:?}", accepted, pause);
+                time::sleep(pause).await;
+            }
+            // new connections wait in the listen backlog until `run` returns and closes it
+            let accept = future::poll_fn(|cx| listener.poll_accept(cx));
+            let accepted_sock = match future::select(accept, &mut shutdown).await {
+                Either::Left((accepted, _)) => accepted,
+                Either::Right(_) => return,
+            };
+            match accepted_sock {
                 Ok((sock, addr)) => {
-                    info!("accepted: {}", addr);
-                    let cloned = Rc::clone(&self);
-                    self.spawner.spawn(cloned.connection(sock));
+                    backoff = None;
+                    if shed {
+                        shed = false;
+                        warn!("out of file descriptors: closed {}", peer_name(addr));
+                        drop(sock);
+                        self.reserve_fd();
+                        continue;
+                    }
+                    self.reserve_fd();
+                    if reactor::turns() != turn {
+                        turn = reactor::turns();
+                        accepted = 0;
+                    }
+                    accepted += 1;
+                    // local peers on a Unix socket aren't filtered
+                    if let (Some(filter), Some(addr)) = (ip_filter, addr) {
+                        if !filter.permits(addr.ip()) {
+                            debug!("refused connection from {}{}", addr, on_name);
+                            continue;
+                        }
+                    }
+                    if let Socket::Tcp(tcp) = &sock {
+                        if let Err(e) = self.config.socket_options.apply(tcp) {
+                            debug!("setting options for {}: {}", peer_name(addr), e);
+                        }
+                    }
+                    let id = self.next_conn_id.get();
+                    self.next_conn_id.set(id + 1);
+                    if let Some(hook) = &self.config.on_accept {
+                        let accepted = Accepted {
+                            conn: id,
+                            listener: on,
+                            sock: &sock,
+                            peer: addr,
+                            latency: listener
+                                .readable_since()
+                                .map_or_else(Duration::default, |since| since.elapsed()),
+                            backlog: listener.backlog(),
+                            open: self.conns.borrow().len(),
+                        };
+                        if !hook(&accepted) {
+                            debug!("#{}: {} turned away by on_accept", id, peer_name(addr));
+                            continue;
+                        }
+                    }
+                    info!("accepted #{}: {}{}", id, peer_name(addr), on_name);
+                    let token = self.conns.borrow_mut().insert(Conn {
+                        id,
+                        listener: on,
+                        peer: addr,
+                        opened: Instant::now(),
+                        task: None,
+                    });
+                    let name = format!("connection #{} {}", id, peer_name(addr));
+                    let task = spawner.spawn_named(name, self.connection(token, sock));
+                    self.conns.borrow_mut()[token].task = Some(task);
                 }
                 Err(e) => {
-                    warn!("{:?}", e);
+                    let kind = AcceptErrorKind::of(&e);
+                    let (min, max) = self.config.accept_backoff;
+                    let mut retry_in = match kind {
+                        AcceptErrorKind::Connection => None,
+                        _ => Some(backoff.map_or(min, |last| (last * 2).min(max))),
+                    };
+                    if kind == AcceptErrorKind::Descriptors
+                        && self.spare_fd.borrow_mut().take().is_some()
+                    {
+                        shed = true;
+                        retry_in = None;
+                    }
+                    if let Some(hook) = &self.config.on_accept_error {
+                        hook(&AcceptError {
+                            listener: on,
+                            error: &e,
+                            kind,
+                            retry_in,
+                        });
+                    }
+                    match retry_in {
+                        Some(wait) => warn!("accept{}: {}, retrying in {:?}", on_name, e, wait),
+                        None => debug!("accept{}: {}", on_name, e),
+                    }
+                    error::report(self.config.on_error.as_ref(), HttpError::Io(e));
+                    if let Some(wait) = retry_in {
+                        backoff = Some(wait);
+                        if let Either::Right(_) =
+                            future::select(time::sleep(wait), &mut shutdown).await
+                        {
+                            return;
+                        }
+                    }
                 }
             }
         }
     }
 
-    async fn connection(self: Rc<Self>, mut sock: TcpStream) {
-        if let Err(e) = self.connection_inner(&mut sock).await {
-            warn!("{:?}", e);
+    // opens the spare descriptor if `reserve_fd` is on and it isn't open
+    fn reserve_fd(&self) {
+        if !self.config.reserve_fd || self.spare_fd.borrow().is_some() {
+            return;
+        }
+        #[cfg(unix)]
+        match std::fs::File::open("/dev/null") {
+            Ok(file) => *self.spare_fd.borrow_mut() = Some(file),
+            Err(e) => debug!("reserving a file descriptor: {}", e),
         }
     }
 
-    async fn connection_inner(&self, sock: &mut TcpStream) -> io::Result<()> {
-        let mut buf = vec![0u8; 1024];
-        let len = sock.read(&mut buf).await?;
-        trace!(
-            "incoming message from {} ({} bytes):\n{}",
-            sock.peer_addr().unwrap(),
-            len,
-            String::from_utf8_lossy(&buf)
+    #[cfg(unix)]
+    async fn signals(&self, mut signals: Signals) {
+        while let Some(signal) = signals.recv().await {
+            for (_, hook) in self
+                .config
+                .signal_hooks
+                .iter()
+                .filter(|(s, _)| *s == signal)
+            {
+                hook();
+            }
+            if !self.config.shutdown_signals.contains(&signal) {
+                continue;
+            }
+            if self.config.shutdown.is_shutting_down() {
+                warn!("{:?} during shutdown, exiting", signal);
+                std::process::exit(1);
+            }
+            info!("{:?}: shutting down", signal);
+            self.config.shutdown.shutdown();
+        }
+    }
+
+    // in-flight requests finish first, then the hooks run, within the shutdown timeout
+    async fn shutdown(&self, hooks: Vec<ShutdownHook>) {
+        self.config.shutdown.triggered().await;
+        info!(
+            "shutting down: {} connections open, {} hooks",
+            self.conns.borrow().len(),
+            hooks.len()
         );
-        let req = Self::parse_header(&buf[..len]);
-        if let Some(req) = req {
-            let res = self.app.app(req).await;
-            dbg!(res.status_code);
-            Self::write_response(sock, &res).await?;
+        let idle = || {
+            future::poll_fn(|cx| {
+                if self.conns.borrow().is_empty() {
+                    Poll::Ready(())
+                } else {
+                    *self.idle_waker.borrow_mut() = Some(cx.waker().clone());
+                    Poll::Pending
+                }
+            })
+        };
+        let work = async {
+            idle().await;
+            future::join_all(hooks.into_iter().map(|hook| hook())).await;
+        };
+        futures::pin_mut!(work);
+        let deadline = time::sleep(self.config.shutdown_timeout);
+        if let Either::Right(_) = future::select(work, deadline).await {
+            let conns = self.conns.borrow();
+            warn!(
+                "shutdown timed out with {} connections open, aborting them",
+                conns.len()
+            );
+            for (_, conn) in conns.iter() {
+                if let Some(task) = &conn.task {
+                    task.abort();
+                }
+            }
+            drop(conns);
+            // each is dropped at its next poll, and closed then
+            idle().await;
         }
-        Ok(())
     }
 
-    fn parse_header(msg: &[u8]) -> Option<Request> {
-        let mut req = Request::empty();
-        let msg = String::from_utf8_lossy(msg);
-        for (i, s) in msg.lines().enumerate() {
-            if i == 0 {
-                let tokens: Vec<_> = s.split(' ').collect();
-                if tokens.len() != 3 {
-                    return None;
+    // serves the connection at `token` in `conns`, and removes it from there when it closes
+    async fn connection(&self, token: usize, sock: Socket) {
+        if let Some(metrics) = &self.config.metrics {
+            metrics.connection_opened();
+        }
+        let _closed = Closed {
+            server: self,
+            token,
+        };
+        let (id, on, peer) = {
+            let conns = self.conns.borrow();
+            (conns[token].id, conns[token].listener, conns[token].peer)
+        };
+        trace::connection(id, peer, self.serve_connection(id, on, peer, sock)).await;
+    }
+
+    async fn serve_connection(&self, id: usize, on: usize, peer: Option<SocketAddr>, sock: Socket) {
+        let mut sock = WireTrace::new(sock, id, self.config.wire_trace);
+        match self.connection_inner(id, on, peer, &mut sock).await {
+            Ok(Some((tx, read_buf))) => {
+                debug!("#{}: upgraded", id);
+                let sock = sock.into_inner();
+                let _ = tx.send(Upgraded { sock, read_buf });
+            }
+            Ok(None) => {
+                // the peer sees the end of the response at once, rather than when the drain
+                // below gives up; what it still sends is read so that closing doesn't reset
+                // the connection and discard the response before the peer has read it
+                if let Err(e) = sock.close().await {
+                    debug!("#{}: shutdown: {}", id, e);
+                }
+                drain(&mut sock, &mut Vec::new()).await;
+            }
+            Err(e) => {
+                warn!("#{}: {:?}", id, e);
+                error::report(self.config.on_error.as_ref(), HttpError::Io(e));
+            }
+        }
+    }
+
+    // hands back the upgrade sender and any unread bytes once a 101 response, or a 2xx to
+    // CONNECT, was sent. `on` is the listener it came in on.
+    async fn connection_inner<S: AsyncRead + AsyncWrite + Unpin>(
+        &self,
+        id: usize,
+        on: usize,
+        peer: Option<SocketAddr>,
+        sock: &mut S,
+    ) -> io::Result<Option<(oneshot::Sender<Upgraded>, Vec<u8>)>> {
+        #[cfg(feature = "secure-buffers")]
+        let mut buf = crate::zero::Zeroing(Vec::new());
+        #[cfg(not(feature = "secure-buffers"))]
+        let mut buf = Vec::new();
+        // the first request may also open h2 with its preface
+        let mut first = true;
+        loop {
+            let head_len = loop {
+                if first && self.http2(on) && buf.starts_with(b"PRI * HTTP/2.0") {
+                    return http2::serve(
+                        sock,
+                        &Screened(self, peer),
+                        &buf,
+                        self.config.max_body_len,
+                    )
+                    .await
+                    .map(|_| None);
+                }
+                match sniff(&buf) {
+                    Some(NotHttp::Tls) => {
+                        debug!("#{}: TLS handshake on a plaintext port", id);
+                        self.count_not_http("tls");
+                        return Ok(None);
+                    }
+                    Some(NotHttp::Garbage(reason)) => {
+                        debug!("#{}: not HTTP: {}", id, reason);
+                        self.count_not_http("garbage");
+                        let mut res = error::render(
+                            self.config.on_error.as_ref(),
+                            HttpError::BadRequest(reason),
+                        );
+                        res.set_header("Connection", "close".to_owned());
+                        Self::write_response(sock, &mut res, false, None).await?;
+                        return Ok(None);
+                    }
+                    None => {}
                 }
-                req.method = tokens[0].to_owned();
-                req.uri = tokens[1].to_owned();
-                req.http_version = tokens[2].to_owned();
-            } else {
-                let kv: Vec<_> = s.splitn(2, ':').map(|s| s.trim()).collect();
-                if kv.len() == 2 {
-                    req.set_header(&kv[0].to_lowercase(), kv[1].to_owned());
+                // over the limit however the head arrived, in one read or many
+                match find_header_end(&buf) {
+                    Some(end) if end <= MAX_HEAD_LEN => break end,
+                    Some(_) => return Ok(None),
+                    None => {}
                 }
+                if buf.len() > MAX_HEAD_LEN {
+                    return Ok(None);
+                }
+                let read = if first || !buf.is_empty() {
+                    fill(sock, &mut buf).await?
+                } else {
+                    self.await_request(sock, &mut buf).await?
+                };
+                if read == 0 {
+                    return Ok(None);
+                }
+            };
+            let req = parse_head(
+                &buf[..head_len],
+                self.config.strict_framing,
+                self.config.strict_utf8,
+            );
+            buf.drain(..head_len);
+            let mut req = match req {
+                Ok(req) => req,
+                Err(reason) => {
+                    debug!("#{}: bad request head: {}", id, reason);
+                    let mut res =
+                        error::render(self.config.on_error.as_ref(), HttpError::BadRequest(reason));
+                    res.set_header("Connection", "close".to_owned());
+                    Self::write_response(sock, &mut res, true, None).await?;
+                    return Ok(None);
+                }
+            };
+            req.peer_addr = peer;
+            req.client_addr = forwarded::client_addr(&req, &self.config.trusted_proxies);
+            let chunked = req.http_version() != "HTTP/1.0";
+            // HTTP/1.x only; h2 arrives with its preface or as an upgrade
+            if !req.http_version().starts_with("HTTP/1.") {
+                debug!("#{}: unsupported version {}", id, req.http_version());
+                let mut res = Response::with_status_code(StatusCode::HttpVersionNotSupported);
+                self.render(&mut res);
+                res.set_header("Connection", "close".to_owned());
+                return Self::write_response(sock, &mut res, false, None)
+                    .await
+                    .map(|_| None);
+            }
+            if let Some(mut res) = self.screen(&req) {
+                self.render(&mut res);
+                res.set_header("Connection", "close".to_owned());
+                return Self::write_response(sock, &mut res, chunked, None)
+                    .await
+                    .map(|_| None);
             }
+            if let Some(settings) = self.h2c_upgrade(on, &req) {
+                let mut res = Response::with_status_code(StatusCode::SwitchingProtocols);
+                res.set_header("Connection", "Upgrade".to_owned());
+                res.set_header("Upgrade", "h2c".to_owned());
+                Self::write_response(sock, &mut res, chunked, None).await?;
+                let max_body_len = self.config.max_body_len;
+                return http2::serve_upgrade(
+                    sock,
+                    &Screened(self, peer),
+                    req,
+                    &settings,
+                    &buf,
+                    max_body_len,
+                )
+                .await
+                .map(|_| None);
+            }
+            let _slot = match self.peer_slot(peer) {
+                Ok(slot) => slot,
+                Err(mut res) => {
+                    self.render(&mut res);
+                    res.set_header("Connection", "close".to_owned());
+                    return Self::write_response(sock, &mut res, chunked, None)
+                        .await
+                        .map(|_| None);
+                }
+            };
+            let mut charge = match self.config.memory.charge(head_len + buf.len()) {
+                Ok(charge) => charge,
+                Err(exceeded) => {
+                    debug!("#{}: shedding request, {:?} memory exceeded", id, exceeded);
+                    let mut res = Response::with_status_code(exceeded.status_code());
+                    self.render(&mut res);
+                    res.set_header("Connection", "close".to_owned());
+                    return Self::write_response(sock, &mut res, chunked, None)
+                        .await
+                        .map(|_| None);
+                }
+            };
+            let site = self.site(&req);
+            let (headroom, exceeded) = charge.headroom();
+            let body_limit = site.and_then(Site::body_limit).or(self.config.max_body_len);
+            let limit = match body_limit {
+                Some(limit) if limit <= headroom => Some(limit),
+                _ if headroom == usize::max_value() => None,
+                _ => Some(headroom),
+            };
+            let mut received = self.tracker(id, &req, Direction::Received);
+            let mut sent = self.tracker(id, &req, Direction::Sent);
+            let status = match read_body(sock, &mut buf, &mut req, limit, received.as_mut()).await?
+            {
+                BodyRead::Complete => None,
+                BodyRead::TooLarge if limit == Some(headroom) => Some(exceeded.status_code()),
+                BodyRead::TooLarge => Some(StatusCode::PayloadTooLarge),
+                BodyRead::Malformed(reason) => {
+                    debug!("#{}: bad request body: {}", id, reason);
+                    Some(StatusCode::BadRequest)
+                }
+            };
+            if let Some(status) = status {
+                // the rest of the body is never read, so the connection can't be reused
+                let mut res = Response::with_status_code(status);
+                self.render(&mut res);
+                res.set_header("Connection", "close".to_owned());
+                Self::write_response(sock, &mut res, chunked, None).await?;
+                return Ok(None);
+            }
+            // read_body kept within the headroom
+            let _ = charge.grow(req.body.len());
+            let charge = Rc::new(RefCell::new(charge));
+            req.charge = Some(Rc::clone(&charge));
+            if let Some(received) = &mut received {
+                received.finish();
+            }
+            let (upgrade_tx, on_upgrade) = OnUpgrade::pair();
+            req.on_upgrade = Some(on_upgrade);
+            let connect = req.method() == "CONNECT";
+            let head = req.method() == "HEAD";
+            // HTTP/1.1 connections stay open for further requests unless the client asks to
+            // close; HTTP/1.0 ones are closed after each response
+            let keep_alive = self.config.keep_alive_timeout > Duration::from_secs(0)
+                && req.http_version() == "HTTP/1.1"
+                && !header::has_token(req.header("connection"), "close");
+            let mut res = self.dispatch(site, req).await;
+            Self::charge_response(&mut charge.borrow_mut(), &mut res);
+            self.render(&mut res);
+            // the connection is a tunnel after a 2xx to CONNECT (RFC 7231 4.3.6)
+            let switched = res.status_code() == StatusCode::SwitchingProtocols
+                || (connect && res.status_code().code() / 100 == 2);
+            // a streaming body that can't be chunked is delimited by closing the connection
+            let close = !switched
+                && (!keep_alive
+                    || header::has_token(res.header("connection"), "close")
+                    || (res.is_streaming() && !chunked)
+                    || self.config.shutdown.is_shutting_down());
+            if close {
+                res.set_header("Connection", "close".to_owned());
+            }
+            if !switched {
+                frame(&mut res, head);
+            }
+            if let Some(sent) = &mut sent {
+                if !res.is_streaming() {
+                    sent.set_total(Some(res.body_len() as u64));
+                }
+            }
+            Self::write_response(sock, &mut res, chunked, sent.as_mut()).await?;
+            if let Some(sent) = &mut sent {
+                sent.finish();
+            }
+            if switched {
+                // the copy handed on is the upgraded protocol's to wipe
+                #[cfg(feature = "secure-buffers")]
+                let buf = buf.split_off(0);
+                return Ok(Some((upgrade_tx, buf)));
+            }
+            if close {
+                return Ok(None);
+            }
+            first = false;
         }
-        dbg!(&req.headers);
-        Some(req)
     }
 
-    async fn write_response(sock: &mut TcpStream, res: &Response) -> io::Result<()> {
-        let mut w = futures::io::BufWriter::new(sock);
-        let mut lines = vec![format!(
-            "HTTP/1.1 {} {}",
-            res.status_code().code(),
-            res.status_code().description()
-        )];
-        lines.extend(res.headers().iter().map(|(k, v)| format!("{}: {}", k, v)));
-        lines.push("".to_owned());
-        lines.push("".to_owned());
-        let header = lines.join("\r\n");
-        w.write_all(header.as_bytes()).await?;
-        w.write_all(res.body()).await?;
-        w.flush().await?;
+    // server-level checks made before the app sees a request
+    fn screen(&self, req: &Request) -> Option<Response> {
+        // HTTP/1.1 requires exactly one Host (RFC 7230 5.4); repeated ones were joined with commas
+        if req.http_version() == "HTTP/1.1"
+            && req.header("host").map_or(true, |host| host.contains(','))
+        {
+            debug!("missing or repeated Host header");
+            return Some(Response::with_status_code(StatusCode::BadRequest));
+        }
+        let hosts = &self.config.allowed_hosts;
+        if !hosts.is_empty() {
+            let host = host::request_host(req);
+            if !host
+                .as_ref()
+                .map_or(false, |h| hosts.iter().any(|p| p.matches(h)))
+            {
+                debug!("rejected host {:?}", host);
+                return Some(Response::with_status_code(StatusCode::MisdirectedRequest));
+            }
+        }
+        None
+    }
+
+    // the slot the response to a request from `peer` holds; see `max_in_flight_per_ip`
+    fn peer_slot(&self, peer: Option<SocketAddr>) -> Result<Option<PeerSlot>, Response> {
+        let (slots, peer) = match (&self.config.peer_slots, peer) {
+            (Some(slots), Some(peer)) => (slots, peer),
+            _ => return Ok(None),
+        };
+        match slots.acquire(peer.ip()) {
+            Some(slot) => Ok(Some(slot)),
+            None => Err(Response::with_status_code(StatusCode::TooManyRequests)),
+        }
+    }
+
+    // an over-budget response is replaced, freeing its body before it is written
+    fn charge_response(charge: &mut Charge, res: &mut Response) {
+        if let Err(exceeded) = charge.grow(res.body_len()) {
+            warn!(
+                "{}-byte response body over the {:?} memory budget",
+                res.body_len(),
+                exceeded
+            );
+            *res = Response::with_status_code(StatusCode::ServiceUnavailable);
+        }
+    }
+
+    fn site(&self, req: &Request) -> Option<&Site> {
+        let host = host::request_host(req)?;
+        self.config
+            .sites
+            .iter()
+            .filter(|(pattern, _)| pattern.matches(&host))
+            .max_by_key(|(pattern, _)| pattern.rank())
+            .map(|(_, site)| site)
+    }
+
+    fn count_not_http(&self, kind: &str) {
+        if let Some(metrics) = &self.config.metrics {
+            metrics.not_http(kind);
+        }
+    }
+
+    // error responses without a body are rendered by the `on_error` hook
+    fn render(&self, res: &mut Response) {
+        error::render_status(self.config.on_error.as_ref(), res);
+    }
+
+    // the app's response, with panics caught (see `Guarded`) inside the request's span
+    fn dispatch(
+        &self,
+        site: Option<&Site>,
+        req: Request,
+    ) -> Traced<Guarded<Dispatched<T::Output>>> {
+        let span = RequestSpan::new(&req);
+        let fut =
+            span.in_scope(|| panic::catch_unwind(AssertUnwindSafe(|| self.call_app(site, req))));
+        span.wrap(Guarded::new(fut, self.config.on_error.clone()))
+    }
+
+    fn call_app(&self, site: Option<&Site>, mut req: Request) -> Dispatched<T::Output> {
+        match site {
+            Some(site) => {
+                if site
+                    .body_limit()
+                    .map_or(false, |limit| req.body.len() > limit)
+                {
+                    let res = Response::with_status_code(StatusCode::PayloadTooLarge);
+                    return Either::Right(Box::pin(future::ready(res)));
+                }
+                req.document_root = site.root().map(Path::to_path_buf);
+                Either::Right(site.app().app(req))
+            }
+            None => Either::Left(self.app.app(req)),
+        }
+    }
+
+    fn tracker(&self, id: usize, req: &Request, direction: Direction) -> Option<Tracker<'_>> {
+        self.config
+            .progress
+            .as_ref()
+            .map(|(hook, interval)| Tracker::new(&**hook, *interval, id, req, direction))
+    }
+
+    fn http2(&self, on: usize) -> bool {
+        self.listeners
+            .get(on)
+            .and_then(|(_, config)| config.http2)
+            .unwrap_or(self.config.http2)
+    }
+
+    // reads the start of the next request on a kept-alive connection; 0 bytes when it stays
+    // idle past the keep-alive timeout or the server starts shutting down
+    async fn await_request<S: AsyncRead + Unpin>(
+        &self,
+        sock: &mut S,
+        buf: &mut Vec<u8>,
+    ) -> io::Result<usize> {
+        let read = time::timeout(self.config.keep_alive_timeout, fill(sock, buf));
+        futures::pin_mut!(read);
+        match future::select(read, self.config.shutdown.triggered()).await {
+            Either::Left((Ok(read), _)) => read,
+            _ => Ok(0),
+        }
+    }
+
+    // the decoded HTTP2-Settings when `req` asks for an h2c upgrade we can honor.
+    // requests with a body are served over HTTP/1.1 instead.
+    fn h2c_upgrade(&self, on: usize, req: &Request) -> Option<Vec<u8>> {
+        if !self.http2(on) || req.header("upgrade") != Some("h2c") {
+            return None;
+        }
+        let connection = req.header("connection")?.to_lowercase();
+        if !connection.split(',').any(|s| s.trim() == "upgrade") {
+            return None;
+        }
+        if req.header("content-length").map_or(false, |len| len != "0")
+            || req.header("transfer-encoding").is_some()
+        {
+            return None;
+        }
+        http2::decode_settings_header(req.header("http2-settings")?)
+    }
+
+    // a streaming body is sent chunked when `chunked` is set (HTTP/1.1 peers), otherwise it
+    // is delimited by closing the connection; each chunk is flushed as soon as it is produced
+    async fn write_response<S: AsyncWrite + Unpin>(
+        sock: &mut S,
+        res: &mut Response,
+        chunked: bool,
+        mut progress: Option<&mut Tracker<'_>>,
+    ) -> io::Result<()> {
+        let stream = res.take_stream();
+        let chunked = chunked && stream.is_some();
+        if chunked {
+            res.set_header("Transfer-Encoding", "chunked".to_owned());
+        }
+        // the head goes out with the body, or the body's first part
+        let head = res.head_bytes();
+        let mut written = 0;
+        write_all_vectored(sock, &[&head, res.body()], |n| {
+            let body =
+                (written + n).saturating_sub(head.len()) - written.saturating_sub(head.len());
+            written += n;
+            if let Some(progress) = progress.as_mut() {
+                progress.advance(body);
+            }
+        })
+        .await?;
+        if let Some(mut stream) = stream {
+            while let Some(chunk) = stream.next().await {
+                if chunk.is_empty() {
+                    continue;
+                }
+                if chunked {
+                    let size = format!("{:x}\r\n", chunk.len());
+                    write_all_vectored(sock, &[size.as_bytes(), &chunk, b"\r\n"], |_| ()).await?;
+                } else {
+                    write_all_vectored(sock, &[&chunk], |_| ()).await?;
+                }
+                if let Some(progress) = progress.as_mut() {
+                    progress.advance(chunk.len());
+                }
+            }
+            if chunked {
+                sock.write_all(b"0\r\n\r\n").await?;
+            }
+        }
+        sock.flush().await?;
         Ok(())
     }
 }
 
+// gives a response its Content-Length, so that another can follow it on the connection, and
+// drops the body of one that can't have any: to HEAD (keeping the length a GET would get), or
+// with a 1xx, 204 or 304 status
+fn frame(res: &mut Response, head: bool) {
+    let code = res.status_code().code();
+    let bodiless = code < 200 || code == 204 || code == 304;
+    if !bodiless
+        && !res.is_streaming()
+        && res.header("content-length").is_none()
+        && !(head && res.body().is_empty())
+    {
+        res.set_header("Content-Length", res.body_len().to_string());
+    }
+    if head || bodiless {
+        res.take_body();
+        res.take_stream();
+    }
+}
+
+// writes all of `bufs`, gathered into as few writes as the socket takes; `sent` is told the
+// bytes of each
+async fn write_all_vectored<S: AsyncWrite + Unpin>(
+    sock: &mut S,
+    bufs: &[&[u8]],
+    mut sent: impl FnMut(usize),
+) -> io::Result<()> {
+    let total: usize = bufs.iter().map(|buf| buf.len()).sum();
+    let mut written = 0;
+    while written < total {
+        // what is left of `bufs`
+        let mut skip = written;
+        let mut rest = Vec::with_capacity(bufs.len());
+        for buf in bufs {
+            if skip >= buf.len() {
+                skip -= buf.len();
+                continue;
+            }
+            rest.push(io::IoSlice::new(&buf[skip..]));
+            skip = 0;
+        }
+        let n = future::poll_fn(|cx| Pin::new(&mut *sock).poll_write_vectored(cx, &rest)).await?;
+        if n == 0 {
+            return Err(io::ErrorKind::WriteZero.into());
+        }
+        written += n;
+        sent(n);
+    }
+    Ok(())
+}
+
+// the server's app, or the app of the site the request was addressed to
+type Dispatched<F> = Either<F, LocalBoxFuture<'static, Response>>;
+
+// the app as seen by protocols that hand whole requests to it (h2): `screen` runs first
+struct Screened<'s, T>(&'s HttpServerInner<T>, Option<SocketAddr>);
+
+impl<'s, T: HttpApp> HttpApp for Screened<'s, T> {
+    type Output =
+        Either<future::Ready<Response>, Slotted<Charged<Traced<Guarded<Dispatched<T::Output>>>>>>;
+    fn app(&self, mut req: Request) -> Self::Output {
+        req.peer_addr = self.1;
+        req.client_addr = forwarded::client_addr(&req, &self.0.config.trusted_proxies);
+        if let Some(mut res) = self.0.screen(&req) {
+            self.0.render(&mut res);
+            return Either::Left(future::ready(res));
+        }
+        let slot = match self.0.peer_slot(self.1) {
+            Ok(slot) => slot,
+            Err(mut res) => {
+                self.0.render(&mut res);
+                return Either::Left(future::ready(res));
+            }
+        };
+        // h2 has buffered the body by now; the charge is held while the handler runs
+        let charge = match self.0.config.memory.charge(req.body.len()) {
+            Ok(charge) => Rc::new(RefCell::new(charge)),
+            Err(exceeded) => {
+                let mut res = Response::with_status_code(exceeded.status_code());
+                self.0.render(&mut res);
+                return Either::Left(future::ready(res));
+            }
+        };
+        req.charge = Some(Rc::clone(&charge));
+        let res = self.0.dispatch(self.0.site(&req), req);
+        Either::Right(Slotted::new(Charged::new(res, Some(charge)), slot))
+    }
+}
+
+enum NotHttp {
+    // a TLS ClientHello, from a client that expected TLS on this port; it can't read a
+    // plaintext response, so the connection is just closed
+    Tls,
+    Garbage(&'static str),
+}
+
+// rejects what can't be the start of an HTTP/1 request as soon as it arrives, rather than
+// waiting for a head that will never end: binary data, or a request line without a version
+// (HTTP/0.9), whose client waits for a response to that line alone
+fn sniff(buf: &[u8]) -> Option<NotHttp> {
+    if buf.first() == Some(&0x16) {
+        return Some(NotHttp::Tls);
+    }
+    let method_len = buf.iter().position(|&b| b == b' ').unwrap_or(buf.len());
+    if !buf[..method_len].iter().all(|&b| header::is_tchar(b)) {
+        return Some(NotHttp::Garbage("not an HTTP request"));
+    }
+    let line = &buf[..buf.iter().position(|&b| b == b'\n')?];
+    let version = line.rsplit(|&b| b == b' ').next().unwrap_or(b"");
+    if method_len == line.len() || !version.starts_with(b"HTTP/") {
+        return Some(NotHttp::Garbage("request line without HTTP version"));
+    }
+    None
+}
+
+// parses a request head, up to and including its empty line, as the server does with its
+// default settings; for benchmarks and tools working on raw requests
+pub fn parse_request_head(head: &[u8]) -> Result<Request, &'static str> {
+    parse_head(head, true, false)
+}
+
+// the length of the request head at the start of `buf`, once all of it is there
+pub fn request_head_len(buf: &[u8]) -> Option<usize> {
+    find_header_end(buf)
+}
+
+// parses a request head, ending with its empty line. ambiguous framing, the way request
+// smuggling slips a second request past a proxy, is an error: both Content-Length and
+// Transfer-Encoding, Content-Lengths that disagree or aren't plain digits, or a
+// Transfer-Encoding not ending in chunked. `strict` (`strict_framing`) also refuses bare LF
+// line ends, obs-fold continuation lines and malformed header lines, which are otherwise
+// tolerated. with `strict_utf8` the target must be visible ASCII and header values UTF-8
+// instead of being converted lossily.
+fn parse_head(msg: &[u8], strict: bool, strict_utf8: bool) -> Result<Request, &'static str> {
+    let mut req = Request::empty();
+    let msg = if strict_utf8 {
+        Cow::Borrowed(str::from_utf8(msg).map_err(|_| "head not UTF-8")?)
+    } else {
+        String::from_utf8_lossy(msg)
+    };
+    let mut lines = Vec::new();
+    // the head ends with a line end, nothing follows the last one
+    for line in msg[..msg.len().saturating_sub(1)].split('\n') {
+        if line.ends_with('\r') {
+            lines.push(&line[..line.len() - 1]);
+        } else if strict {
+            return Err("bare LF line end");
+        } else {
+            lines.push(line);
+        }
+    }
+    let mut lines = lines.into_iter().take_while(|line| !line.is_empty());
+    let tokens: Vec<_> = lines.next().unwrap_or("").split(' ').collect();
+    if tokens.len() != 3 || tokens.iter().any(|t| t.is_empty()) {
+        return Err("bad request line");
+    }
+    req.method = tokens[0].to_owned();
+    if strict_utf8 && !tokens[1].bytes().all(|b| b.is_ascii_graphic()) {
+        return Err("request target not visible ASCII");
+    }
+    req.uri = tokens[1].to_owned();
+    // HTTP/<digit>.<digit> (RFC 7230 2.6); majors other than 1 are refused by the caller
+    match tokens[2].as_bytes() {
+        [b'H', b'T', b'T', b'P', b'/', major, b'.', minor]
+            if major.is_ascii_digit() && minor.is_ascii_digit() => {}
+        _ => return Err("bad HTTP version"),
+    }
+    req.http_version = tokens[2].to_owned();
+    let mut last_name: Option<String> = None;
+    for line in lines {
+        if line.starts_with(' ') || line.starts_with('\t') {
+            if strict {
+                return Err("obs-fold line");
+            }
+            // a continuation of the previous value
+            if let Some(value) = last_name.as_ref().and_then(|n| req.headers.get_mut(n)) {
+                value.push(' ');
+                value.push_str(line.trim());
+            }
+            continue;
+        }
+        let colon = match line.find(':') {
+            Some(colon) => colon,
+            None if strict => return Err("header line without colon"),
+            None => continue,
+        };
+        let (name, value) = (&line[..colon], line[colon + 1..].trim());
+        if (strict || strict_utf8) && !header::valid_value(value) {
+            return Err("bad header value");
+        }
+        if strict && !header::valid_name(name) {
+            return Err("bad header");
+        }
+        let name = name.trim().to_lowercase();
+        // repeated fields are one comma-separated list
+        let value = match req.headers.get(&name) {
+            Some(prev) => format!("{}, {}", prev, value),
+            None => value.to_owned(),
+        };
+        // as received: `strict` checked it above, and lenient parsing keeps what it can
+        req.insert_header(&name, value);
+        last_name = Some(name);
+    }
+    if let Some(len) = req.header("content-length") {
+        // repeated or listed, the values must all be the same
+        let mut values = len.split(',').map(str::trim);
+        let first = values.next().unwrap_or("").to_owned();
+        if values.any(|v| v != first) {
+            return Err("conflicting Content-Length");
+        }
+        // digits only: `+5` or `0x5` would parse differently elsewhere in the chain
+        if first.is_empty() || !first.bytes().all(|b| b.is_ascii_digit()) {
+            return Err("bad Content-Length");
+        }
+        req.set_header("content-length", first);
+    }
+    if let Some(te) = req.header("transfer-encoding") {
+        let last = te.rsplit(',').next().unwrap_or("").trim();
+        if !last.eq_ignore_ascii_case("chunked") {
+            return Err("Transfer-Encoding not ending in chunked");
+        }
+        if req.header("content-length").is_some() {
+            if strict {
+                return Err("both Content-Length and Transfer-Encoding");
+            }
+            // chunked framing wins (RFC 7230 3.3.3)
+            req.remove_header("content-length");
+        }
+    }
+    trace!("headers {:?}", req.headers);
+    Ok(req)
+}
+
+// the length of the head, up to and including the empty line ending it. bare LF line ends
+// are found too, for `parse_head` to reject or accept.
+fn find_header_end(msg: &[u8]) -> Option<usize> {
+    msg.iter()
+        .enumerate()
+        .find_map(|(i, &b)| match (b, msg.get(i + 1), msg.get(i + 2)) {
+            (b'\n', Some(b'\r'), Some(b'\n')) => Some(i + 3),
+            (b'\n', Some(b'\n'), _) => Some(i + 2),
+            _ => None,
+        })
+}
+
+// appends whatever the next read returns to `buf`
+async fn fill<S: AsyncRead + Unpin>(sock: &mut S, buf: &mut Vec<u8>) -> io::Result<usize> {
+    let mut chunk = [0u8; 4096];
+    let len = sock.read(&mut chunk).await?;
+    buf.extend_from_slice(&chunk[..len]);
+    Ok(len)
+}
+
+async fn fill_to<S: AsyncRead + Unpin>(
+    sock: &mut S,
+    buf: &mut Vec<u8>,
+    len: usize,
+) -> io::Result<()> {
+    while buf.len() < len {
+        if fill(sock, buf).await? == 0 {
+            return Err(io::ErrorKind::UnexpectedEof.into());
+        }
+    }
+    Ok(())
+}
+
+// length of the first line in `buf` including its CRLF, reading more as needed.
+// None once `max` bytes have arrived without a CRLF.
+async fn read_line<S: AsyncRead + Unpin>(
+    sock: &mut S,
+    buf: &mut Vec<u8>,
+    max: usize,
+) -> io::Result<Option<usize>> {
+    loop {
+        if let Some(pos) = buf.windows(2).position(|w| w == b"\r\n") {
+            return Ok(Some(pos + 2).filter(|&len| len <= max));
+        }
+        if buf.len() >= max {
+            return Ok(None);
+        }
+        if fill(sock, buf).await? == 0 {
+            return Err(io::ErrorKind::UnexpectedEof.into());
+        }
+    }
+}
+
+// reads and drops what the peer is still sending after the last response, until it closes
+// its side. closing a socket with unread data resets the connection, which can discard the
+// response before the peer has read it.
+async fn drain<S: AsyncRead + Unpin>(sock: &mut S, buf: &mut Vec<u8>) {
+    let read = async {
+        let mut drained = buf.len();
+        buf.clear();
+        while drained < MAX_DRAIN_LEN {
+            match fill(sock, buf).await {
+                Ok(0) | Err(_) => break,
+                Ok(len) => drained += len,
+            }
+            buf.clear();
+        }
+    };
+    futures::pin_mut!(read);
+    future::select(read, time::sleep(DRAIN_TIME)).await;
+}
+
+enum BodyRead {
+    Complete,
+    // longer than the limit; the rest is left unread
+    TooLarge,
+    // bad framing, answered with 400
+    Malformed(&'static str),
+}
+
+// the size of a chunk from its size line (without CRLF). extensions are checked for stray
+// control characters but otherwise ignored.
+fn parse_chunk_size(line: &[u8]) -> Result<usize, &'static str> {
+    let (size, ext) = match line.iter().position(|&b| b == b';') {
+        Some(semi) => (&line[..semi], &line[semi + 1..]),
+        None => (line, &[][..]),
+    };
+    if ext.iter().any(|&b| (b < 0x20 && b != b'\t') || b == 0x7f) {
+        return Err("bad chunk extension");
+    }
+    let size = std::str::from_utf8(size)
+        .map_err(|_| "bad chunk size")?
+        .trim_matches(|c| c == ' ' || c == '\t');
+    if size.is_empty() || !size.bytes().all(|b| b.is_ascii_hexdigit()) {
+        return Err("bad chunk size");
+    }
+    if size.trim_start_matches('0').len() > MAX_CHUNK_SIZE_DIGITS {
+        return Err("chunk size too large");
+    }
+    match usize::from_str_radix(size, 16) {
+        Ok(size) if size <= MAX_CHUNK_SIZE => Ok(size),
+        _ => Err("chunk size too large"),
+    }
+}
+
+// reads the body framed by Content-Length or chunked Transfer-Encoding into `req`.
+// `buf` holds bytes already read past the header and keeps whatever follows the body.
+// size lines, extensions and trailers are bounded so a peer can't make us buffer without
+// end before a single body byte arrives.
+async fn read_body<S: AsyncRead + Unpin>(
+    sock: &mut S,
+    buf: &mut Vec<u8>,
+    req: &mut Request,
+    limit: Option<usize>,
+    mut progress: Option<&mut Tracker<'_>>,
+) -> io::Result<BodyRead> {
+    let limit = limit.unwrap_or(usize::max_value());
+    let chunked = req.header("transfer-encoding").map_or(false, |te| {
+        te.to_lowercase().trim_end().ends_with("chunked")
+    });
+    if chunked {
+        loop {
+            let line_len = match read_line(sock, buf, MAX_CHUNK_LINE).await? {
+                Some(len) => len,
+                None => return Ok(BodyRead::Malformed("chunk size line too long")),
+            };
+            let size = match parse_chunk_size(&buf[..line_len - 2]) {
+                Ok(size) => size,
+                Err(reason) => return Ok(BodyRead::Malformed(reason)),
+            };
+            buf.drain(..line_len);
+            if size > limit - req.body.len() {
+                return Ok(BodyRead::TooLarge);
+            }
+            if size == 0 {
+                // trailers are read and dropped
+                let mut trailers_len = 0;
+                loop {
+                    let max = MAX_TRAILERS_LEN - trailers_len;
+                    let line_len = match read_line(sock, buf, max).await? {
+                        Some(len) => len,
+                        None => return Ok(BodyRead::Malformed("trailers too long")),
+                    };
+                    buf.drain(..line_len);
+                    trailers_len += line_len;
+                    if line_len == 2 {
+                        return Ok(BodyRead::Complete);
+                    }
+                }
+            }
+            // taken into the body as it arrives rather than buffered whole
+            let mut left = size;
+            while left > 0 {
+                if buf.is_empty() && fill(sock, buf).await? == 0 {
+                    return Err(io::ErrorKind::UnexpectedEof.into());
+                }
+                let n = left.min(buf.len());
+                req.body.extend(buf.drain(..n));
+                left -= n;
+                if let Some(progress) = progress.as_mut() {
+                    progress.advance(n);
+                }
+            }
+            fill_to(sock, buf, 2).await?;
+            if &buf[..2] != b"\r\n" {
+                return Ok(BodyRead::Malformed("bad chunk terminator"));
+            }
+            buf.drain(..2);
+        }
+    }
+    let len = match req
+        .header("content-length")
+        .map(|len| len.trim().parse::<usize>())
+    {
+        Some(Ok(len)) => len,
+        Some(Err(_)) => return Ok(BodyRead::Malformed("bad content-length")),
+        None => return Ok(BodyRead::Complete),
+    };
+    if len > limit {
+        return Ok(BodyRead::TooLarge);
+    }
+    if let Some(progress) = progress.as_mut() {
+        progress.set_total(Some(len as u64));
+    }
+    while req.body.len() < len {
+        if buf.is_empty() && fill(sock, buf).await? == 0 {
+            return Err(io::ErrorKind::UnexpectedEof.into());
+        }
+        let n = (len - req.body.len()).min(buf.len());
+        req.body.extend(buf.drain(..n));
+        if let Some(progress) = progress.as_mut() {
+            progress.advance(n);
+        }
+    }
+    Ok(BodyRead::Complete)
+}
+
 #[derive(Default)]
 pub struct Request {
     method: String,
     uri: String,
     http_version: String,
     headers: HashMap<String, String>,
+    body: Vec<u8>,
+    on_upgrade: Option<OnUpgrade>,
+    document_root: Option<PathBuf>,
+    params: HashMap<String, String>,
+    session: Option<Session>,
+    peer_addr: Option<SocketAddr>,
+    // from forwarding headers, when the peer is a trusted proxy
+    client_addr: Option<IpAddr>,
+    request_id: Option<String>,
+    trace_context: Option<TraceContext>,
+    user: Option<String>,
+    // the memory the request holds, for requests the server read
+    charge: Option<SharedCharge>,
 }
 
 impl Request {
@@ -173,19 +1819,220 @@ impl Request {
         self.headers.get(key).map(|s| &**s)
     }
 
+    // like `Response::set_header`: control characters in `value` are replaced by spaces and
+    // a header with an invalid name is dropped, since the request may be sent on to another
+    // server
     pub fn set_header(&mut self, key: &str, value: String) -> Option<String> {
+        match header::validate(key, &value) {
+            Ok(()) => self.insert_header(key, value),
+            Err(header::InvalidHeader::Value(_)) => {
+                warn!("control characters in the value of {} replaced", key);
+                self.insert_header(key, header::sanitize_value(&value))
+            }
+            Err(e) => {
+                warn!("{}, header dropped", e);
+                None
+            }
+        }
+    }
+
+    pub fn try_set_header(
+        &mut self,
+        key: &str,
+        value: String,
+    ) -> Result<Option<String>, header::InvalidHeader> {
+        header::validate(key, &value)?;
+        Ok(self.insert_header(key, value))
+    }
+
+    fn insert_header(&mut self, key: &str, value: String) -> Option<String> {
         if let Some(v) = self.headers.get_mut(key) {
             Some(std::mem::replace(v, value))
         } else {
             self.headers.insert(key.to_owned(), value)
         }
     }
+
+    pub fn remove_header(&mut self, key: &str) -> Option<String> {
+        self.headers.remove(&key.to_lowercase())
+    }
+
+    pub fn body(&self) -> &[u8] {
+        &self.body
+    }
+
+    pub fn set_body(&mut self, body: Vec<u8>) {
+        self.body = body;
+    }
+
+    pub fn take_body(&mut self) -> Vec<u8> {
+        std::mem::replace(&mut self.body, Vec::new())
+    }
+
+    // how much more the request may hold within the server's memory budget, and what stops
+    // it first; unlimited for requests the server didn't read
+    pub(crate) fn memory_headroom(&self) -> (usize, Exceeded) {
+        match &self.charge {
+            Some(charge) => charge.borrow().headroom(),
+            None => (usize::max_value(), Exceeded::Request),
+        }
+    }
+
+    // as `set_body`, charging the new body to the budget in place of the old one
+    pub(crate) fn replace_body(&mut self, body: Vec<u8>) -> Result<(), Exceeded> {
+        if let Some(charge) = &self.charge {
+            let mut charge = charge.borrow_mut();
+            charge.grow(body.len())?;
+            charge.shrink(self.body.len());
+        }
+        self.body = body;
+        Ok(())
+    }
+
+    // the fields of an application/x-www-form-urlencoded body. the error's `status_code` is
+    // the answer for the client: 415 for another content type, 400 for a malformed body.
+    pub fn form(&self) -> Result<form::Form, form::FormError> {
+        form::from_request(self)
+    }
+
+    // the body deserialized from JSON. the error's `status_code` is the answer for the
+    // client: 415 when the Content-Type isn't JSON, 400 when the body doesn't parse as `T`.
+    #[cfg(feature = "json")]
+    pub fn json<T: serde::de::DeserializeOwned>(&self) -> Result<T, json::JsonError> {
+        json::from_request(self)
+    }
+
+    // the entry of `supported` (language tags such as "en-US") the client prefers, by its
+    // Accept-Language; None when it accepts none of them or sent no preference
+    pub fn negotiate_language<'a>(&self, supported: &[&'a str]) -> Option<&'a str> {
+        language::negotiate(self.header("accept-language")?, supported)
+    }
+
+    pub fn cookie(&self, name: &str) -> Option<String> {
+        self.cookies()
+            .into_iter()
+            .find(|(n, _)| n == name)
+            .map(|(_, v)| v)
+    }
+
+    pub fn cookies(&self) -> Vec<(String, String)> {
+        self.header("cookie").map(cookie::parse).unwrap_or_default()
+    }
+
+    // present below `SessionConfig::wrap`
+    pub fn session(&self) -> Option<&Session> {
+        self.session.as_ref()
+    }
+
+    // a segment captured by the matching `Router` pattern
+    pub fn param(&self, name: &str) -> Option<&str> {
+        self.params.get(name).map(|s| &**s)
+    }
+
+    pub fn params(&self) -> &HashMap<String, String> {
+        &self.params
+    }
+
+    pub fn set_params(&mut self, params: HashMap<String, String>) {
+        self.params = params;
+    }
+
+    // the address the request came from, the last proxy's if it passed through any; None for
+    // requests not read off a connection
+    pub fn peer_addr(&self) -> Option<SocketAddr> {
+        self.peer_addr
+    }
+
+    // the client's address as the trusted proxies in front of the server tell it (see
+    // `HttpServer::trusted_proxies`), or the peer's when it didn't come through one
+    pub fn client_addr(&self) -> Option<IpAddr> {
+        self.client_addr
+            .or_else(|| self.peer_addr.map(|addr| addr.ip()))
+    }
+
+    // set by the `request_id::RequestIds` middleware
+    pub fn request_id(&self) -> Option<&str> {
+        self.request_id.as_ref().map(|s| &**s)
+    }
+
+    // who the `auth` middleware authenticated the request as
+    pub fn user(&self) -> Option<&str> {
+        self.user.as_ref().map(|s| &**s)
+    }
+
+    // set by the `trace_context::TraceContexts` middleware; `inject` it into requests to
+    // other services
+    pub fn trace_context(&self) -> Option<&TraceContext> {
+        self.trace_context.as_ref()
+    }
+
+    // the document root of the site the request was addressed to, if it has one
+    pub fn document_root(&self) -> Option<&Path> {
+        self.document_root.as_ref().map(|p| &**p)
+    }
+
+    // the raw connection, once the handler has answered with 101 Switching Protocols (or a 2xx
+    // to CONNECT) and the response head is flushed; fails for other responses and for requests
+    // that arrived over h2
+    pub fn upgrade(&mut self) -> OnUpgrade {
+        self.on_upgrade.take().unwrap_or_else(OnUpgrade::none)
+    }
+
+    // a copy for handing the request to another app; the connection can only be upgraded
+    // through the original
+    pub(crate) fn duplicate(&self) -> Request {
+        Request {
+            method: self.method.clone(),
+            uri: self.uri.clone(),
+            http_version: self.http_version.clone(),
+            headers: self.headers.clone(),
+            body: self.body.clone(),
+            on_upgrade: None,
+            document_root: self.document_root.clone(),
+            params: self.params.clone(),
+            session: self.session.clone(),
+            peer_addr: self.peer_addr,
+            client_addr: self.client_addr,
+            request_id: self.request_id.clone(),
+            trace_context: self.trace_context.clone(),
+            user: self.user.clone(),
+            charge: self.charge.clone(),
+        }
+    }
+}
+
+#[cfg(feature = "secure-buffers")]
+impl Drop for Request {
+    fn drop(&mut self) {
+        use crate::zero::Zero;
+        self.uri.zero();
+        self.headers.zero();
+        self.body.zero();
+        self.params.zero();
+        self.user.zero();
+    }
 }
 
 pub struct Response {
     status_code: StatusCode,
     headers: HashMap<String, String>,
     body: Vec<u8>,
+    // Set-Cookie values, kept apart since each needs a header line of its own
+    cookies: Vec<String>,
+    // sent after `body`, chunk by chunk as the stream yields them
+    stream: Option<LocalBoxStream<'static, Vec<u8>>>,
+}
+
+// the body isn't wiped: what the app sends is rarely secret, and it can be large
+#[cfg(feature = "secure-buffers")]
+impl Drop for Response {
+    fn drop(&mut self) {
+        use crate::zero::Zero;
+        self.headers.zero();
+        for cookie in &mut self.cookies {
+            cookie.zero();
+        }
+    }
 }
 
 impl Response {
@@ -194,18 +2041,61 @@ impl Response {
             status_code,
             headers: HashMap::new(),
             body: Vec::new(),
+            cookies: Vec::new(),
+            stream: None,
         }
     }
 
+    pub fn with_stream<S>(status_code: StatusCode, stream: S) -> Response
+    where
+        S: Stream<Item = Vec<u8>> + 'static,
+    {
+        let mut res = Self::with_status_code(status_code);
+        res.set_stream(stream);
+        res
+    }
+
     pub fn ok() -> Response {
         Self::with_status_code(StatusCode::Ok)
     }
 
+    // 200 with `value` as its application/json body
+    #[cfg(feature = "json")]
+    pub fn json<T: serde::Serialize + ?Sized>(value: &T) -> Response {
+        json::response(value)
+    }
+
     pub fn status_code(&self) -> StatusCode {
         self.status_code
     }
 
+    // control characters in `value` are replaced by spaces, and a header with an invalid name
+    // is dropped, so user-influenced values can't split the response. use `try_set_header`
+    // to get an error instead.
     pub fn set_header(&mut self, key: &str, value: String) -> Option<String> {
+        match header::validate(key, &value) {
+            Ok(()) => self.insert_header(key, value),
+            Err(header::InvalidHeader::Value(_)) => {
+                warn!("control characters in the value of {} replaced", key);
+                self.insert_header(key, header::sanitize_value(&value))
+            }
+            Err(e) => {
+                warn!("{}, header dropped", e);
+                None
+            }
+        }
+    }
+
+    pub fn try_set_header(
+        &mut self,
+        key: &str,
+        value: String,
+    ) -> Result<Option<String>, header::InvalidHeader> {
+        header::validate(key, &value)?;
+        Ok(self.insert_header(key, value))
+    }
+
+    fn insert_header(&mut self, key: &str, value: String) -> Option<String> {
         if let Some(v) = self.headers.get_mut(key) {
             Some(std::mem::replace(v, value))
         } else {
@@ -217,6 +2107,46 @@ impl Response {
         &self.headers
     }
 
+    // looked up ignoring case, since apps set headers in whatever case they like
+    pub fn header(&self, key: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(k, _)| k.eq_ignore_ascii_case(key))
+            .map(|(_, v)| &**v)
+    }
+
+    pub fn remove_header(&mut self, key: &str) -> Option<String> {
+        let found = self
+            .headers
+            .keys()
+            .find(|k| k.eq_ignore_ascii_case(key))
+            .cloned()?;
+        self.headers.remove(&found)
+    }
+
+    pub fn add_cookie(&mut self, cookie: &Cookie) {
+        self.cookies
+            .push(header::sanitize_value(&cookie.to_header_value()));
+    }
+
+    pub fn cookies(&self) -> &[String] {
+        &self.cookies
+    }
+
+    // the HTTP/1.1 status line and header lines, ending with the empty line, as sent
+    pub fn head_bytes(&self) -> Vec<u8> {
+        let mut lines = vec![format!(
+            "HTTP/1.1 {} {}",
+            self.status_code().code(),
+            self.status_code().description()
+        )];
+        lines.extend(header::wire_fields(self.headers()).map(|(k, v)| format!("{}: {}", k, v)));
+        lines.extend(self.cookies().iter().map(|v| format!("Set-Cookie: {}", v)));
+        lines.push("".to_owned());
+        lines.push("".to_owned());
+        lines.join("\r\n").into_bytes()
+    }
+
     pub fn body(&self) -> &[u8] {
         &self.body
     }
@@ -224,6 +2154,29 @@ impl Response {
     pub fn body_len(&self) -> usize {
         self.body().len()
     }
+
+    pub fn set_body(&mut self, body: Vec<u8>) {
+        self.body = body;
+    }
+
+    pub fn take_body(&mut self) -> Vec<u8> {
+        std::mem::replace(&mut self.body, Vec::new())
+    }
+
+    pub fn set_stream<S>(&mut self, stream: S)
+    where
+        S: Stream<Item = Vec<u8>> + 'static,
+    {
+        self.stream = Some(stream.boxed_local());
+    }
+
+    pub fn is_streaming(&self) -> bool {
+        self.stream.is_some()
+    }
+
+    pub fn take_stream(&mut self) -> Option<LocalBoxStream<'static, Vec<u8>>> {
+        self.stream.take()
+    }
 }
 
 impl Extend<u8> for Response {
@@ -238,9 +2191,45 @@ impl<'a> Extend<&'a u8> for Response {
     }
 }
 
-#[derive(Clone, Copy, Debug)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
 pub enum StatusCode {
+    Continue = 100,
+    SwitchingProtocols = 101,
     Ok = 200,
+    Created = 201,
+    Accepted = 202,
+    NoContent = 204,
+    PartialContent = 206,
+    MultipleChoices = 300,
+    MovedPermanently = 301,
+    Found = 302,
+    SeeOther = 303,
+    NotModified = 304,
+    TemporaryRedirect = 307,
+    PermanentRedirect = 308,
+    BadRequest = 400,
+    Unauthorized = 401,
+    Forbidden = 403,
+    NotFound = 404,
+    MethodNotAllowed = 405,
+    NotAcceptable = 406,
+    ProxyAuthenticationRequired = 407,
+    RequestTimeout = 408,
+    Conflict = 409,
+    Gone = 410,
+    PreconditionFailed = 412,
+    PayloadTooLarge = 413,
+    UnsupportedMediaType = 415,
+    RangeNotSatisfiable = 416,
+    MisdirectedRequest = 421,
+    UnprocessableEntity = 422,
+    TooManyRequests = 429,
+    InternalServerError = 500,
+    NotImplemented = 501,
+    BadGateway = 502,
+    ServiceUnavailable = 503,
+    GatewayTimeout = 504,
+    HttpVersionNotSupported = 505,
 }
 
 impl StatusCode {
@@ -248,10 +2237,99 @@ impl StatusCode {
         self as u32
     }
 
+    // codes without a variant of their own become the first of their class, as RFC 7231 6
+    // has clients treat unrecognized ones; None outside 100-599
+    pub fn from_code(code: u16) -> Option<StatusCode> {
+        use StatusCode::*;
+        const ALL: &[StatusCode] = &[
+            Continue,
+            SwitchingProtocols,
+            Ok,
+            Created,
+            Accepted,
+            NoContent,
+            PartialContent,
+            MultipleChoices,
+            MovedPermanently,
+            Found,
+            SeeOther,
+            NotModified,
+            TemporaryRedirect,
+            PermanentRedirect,
+            BadRequest,
+            Unauthorized,
+            Forbidden,
+            NotFound,
+            MethodNotAllowed,
+            NotAcceptable,
+            ProxyAuthenticationRequired,
+            RequestTimeout,
+            Conflict,
+            Gone,
+            PreconditionFailed,
+            PayloadTooLarge,
+            UnsupportedMediaType,
+            RangeNotSatisfiable,
+            MisdirectedRequest,
+            UnprocessableEntity,
+            TooManyRequests,
+            InternalServerError,
+            NotImplemented,
+            BadGateway,
+            ServiceUnavailable,
+            GatewayTimeout,
+            HttpVersionNotSupported,
+        ];
+        let code = u32::from(code);
+        if code < 100 || code > 599 {
+            return None;
+        }
+        ALL.iter()
+            .find(|status| status.code() == code)
+            .or_else(|| ALL.iter().find(|status| status.code() == code / 100 * 100))
+            .cloned()
+    }
+
     pub fn description(self) -> &'static str {
         use StatusCode::*;
         match self {
+            Continue => "Continue",
+            SwitchingProtocols => "Switching Protocols",
             Ok => "OK",
+            Created => "Created",
+            Accepted => "Accepted",
+            NoContent => "No Content",
+            PartialContent => "Partial Content",
+            MultipleChoices => "Multiple Choices",
+            MovedPermanently => "Moved Permanently",
+            Found => "Found",
+            SeeOther => "See Other",
+            NotModified => "Not Modified",
+            TemporaryRedirect => "Temporary Redirect",
+            PermanentRedirect => "Permanent Redirect",
+            BadRequest => "Bad Request",
+            Unauthorized => "Unauthorized",
+            Forbidden => "Forbidden",
+            NotFound => "Not Found",
+            MethodNotAllowed => "Method Not Allowed",
+            NotAcceptable => "Not Acceptable",
+            ProxyAuthenticationRequired => "Proxy Authentication Required",
+            RequestTimeout => "Request Timeout",
+            Conflict => "Conflict",
+            Gone => "Gone",
+            PreconditionFailed => "Precondition Failed",
+            PayloadTooLarge => "Payload Too Large",
+            UnsupportedMediaType => "Unsupported Media Type",
+            RangeNotSatisfiable => "Range Not Satisfiable",
+            MisdirectedRequest => "Misdirected Request",
+            UnprocessableEntity => "Unprocessable Entity",
+            TooManyRequests => "Too Many Requests",
+            InternalServerError => "Internal Server Error",
+            NotImplemented => "Not Implemented",
+            BadGateway => "Bad Gateway",
+            ServiceUnavailable => "Service Unavailable",
+            GatewayTimeout => "Gateway Timeout",
+            HttpVersionNotSupported => "HTTP Version Not Supported",
         }
     }
 }
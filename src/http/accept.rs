@@ -0,0 +1,110 @@
+use super::ip_filter::IpFilter;
+use crate::net::Socket;
+use std::{io, net::SocketAddr, time::Duration};
+
+// settings of one of the server's listeners (see `HttpServer::listen`); those not set are
+// the server's
+#[derive(Clone, Default)]
+pub struct ListenerConfig {
+    pub(super) name: Option<String>,
+    pub(super) http2: Option<bool>,
+    pub(super) ip_filter: Option<IpFilter>,
+    pub(super) max_accepts_per_turn: Option<usize>,
+}
+
+impl ListenerConfig {
+    // for logs, e.g. "public" and "admin"
+    pub fn name(&mut self, name: &str) -> &mut Self {
+        self.name = Some(name.to_owned());
+        self
+    }
+
+    pub fn http2(&mut self, enabled: bool) -> &mut Self {
+        self.http2 = Some(enabled);
+        self
+    }
+
+    pub fn ip_filter(&mut self, filter: &IpFilter) -> &mut Self {
+        self.ip_filter = Some(filter.clone());
+        self
+    }
+
+    pub fn max_accepts_per_turn(&mut self, n: usize) -> &mut Self {
+        self.max_accepts_per_turn = Some(n.max(1));
+        self
+    }
+}
+
+// a connection just accepted, before anything is read from it; handed to the
+// `HttpServer::on_accept` hook
+#[derive(Debug)]
+pub struct Accepted<'a> {
+    pub conn: usize,
+    // the listener it came in on, 0 for `bind`'s and then in the order they were added
+    pub listener: usize,
+    pub sock: &'a Socket,
+    // None on a Unix socket
+    pub peer: Option<SocketAddr>,
+    // since the listener turned readable: at least this long the connection waited to be
+    // accepted, behind the ones accepted before it
+    pub latency: Duration,
+    // connections still waiting in the accept queue, where the OS tells
+    pub backlog: Option<usize>,
+    // connections being served, not counting this one
+    pub open: usize,
+}
+
+// false closes the connection at once
+pub type AcceptHook = Box<dyn Fn(&Accepted) -> bool>;
+
+// what a failed accept means for the accept loop
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum AcceptErrorKind {
+    // the connection was gone before it could be accepted, e.g. reset by the peer, or the
+    // accept was interrupted; the next one is accepted at once
+    Connection,
+    // the process or the system is out of file descriptors (EMFILE, ENFILE)
+    Descriptors,
+    // the kernel is out of memory for sockets (ENOBUFS, ENOMEM)
+    Memory,
+    // anything else
+    Other,
+}
+
+#[cfg(target_os = "linux")]
+const ENOBUFS: i32 = 105;
+#[cfg(not(target_os = "linux"))]
+const ENOBUFS: i32 = 55;
+
+impl AcceptErrorKind {
+    pub fn of(error: &io::Error) -> AcceptErrorKind {
+        match error.kind() {
+            io::ErrorKind::ConnectionAborted
+            | io::ErrorKind::ConnectionReset
+            | io::ErrorKind::Interrupted => return AcceptErrorKind::Connection,
+            _ => {}
+        }
+        // numbers shared by Linux, the BSDs and macOS but for ENOBUFS; EPROTO and EPERM (a
+        // firewall refused it) on Linux are errors of the connection, too
+        match error.raw_os_error() {
+            Some(23) | Some(24) => AcceptErrorKind::Descriptors,
+            Some(12) => AcceptErrorKind::Memory,
+            Some(code) if code == ENOBUFS => AcceptErrorKind::Memory,
+            #[cfg(target_os = "linux")]
+            Some(1) | Some(71) => AcceptErrorKind::Connection,
+            _ => AcceptErrorKind::Other,
+        }
+    }
+}
+
+// an accept that failed; handed to the `HttpServer::on_accept_error` hook
+#[derive(Debug)]
+pub struct AcceptError<'a> {
+    pub listener: usize,
+    pub error: &'a io::Error,
+    pub kind: AcceptErrorKind,
+    // how long the listener waits before accepting again; None when it goes on at once
+    pub retry_in: Option<Duration>,
+}
+
+pub type AcceptErrorHook = Box<dyn Fn(&AcceptError)>;
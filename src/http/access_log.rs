@@ -0,0 +1,239 @@
+use super::middleware::BoxedApp;
+use super::{date, HttpApp, Request, Response};
+use futures::future::{FutureExt, LocalBoxFuture};
+use log::*;
+use std::{
+    fs::OpenOptions,
+    io::{self, BufWriter, Write},
+    path::PathBuf,
+    rc::Rc,
+    sync::mpsc,
+    thread,
+    time::{Instant, SystemTime},
+};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum LogFormat {
+    // host - - [time] "request line" status bytes
+    Common,
+    // Common, then "referer" "user-agent"
+    Combined,
+}
+
+#[derive(Clone)]
+enum Sink {
+    Stderr,
+    File(PathBuf),
+    Callback(Rc<dyn Fn(&str)>),
+}
+
+// a line per request in Common or Combined Log Format, with the time taken to produce the
+// response appended in seconds. stderr and file sinks are written by a thread of their own,
+// so a slow disk never stalls the event loop; a callback is called on the event loop.
+#[derive(Clone)]
+pub struct AccessLog {
+    format: LogFormat,
+    latency: bool,
+    sink: Sink,
+}
+
+impl Default for AccessLog {
+    fn default() -> AccessLog {
+        AccessLog {
+            format: LogFormat::Combined,
+            latency: true,
+            sink: Sink::Stderr,
+        }
+    }
+}
+
+impl AccessLog {
+    pub fn new() -> AccessLog {
+        AccessLog::default()
+    }
+
+    pub fn format(&mut self, format: LogFormat) -> &mut Self {
+        self.format = format;
+        self
+    }
+
+    // append the seconds the app took to respond to each line; on by default. strict Common
+    // Log Format parsers want it off.
+    pub fn latency(&mut self, latency: bool) -> &mut Self {
+        self.latency = latency;
+        self
+    }
+
+    pub fn to_stderr(&mut self) -> &mut Self {
+        self.sink = Sink::Stderr;
+        self
+    }
+
+    // appended to, created if missing
+    pub fn to_file<P: Into<PathBuf>>(&mut self, path: P) -> &mut Self {
+        self.sink = Sink::File(path.into());
+        self
+    }
+
+    // each line, without line end
+    pub fn to_callback<F: Fn(&str) + 'static>(&mut self, f: F) -> &mut Self {
+        self.sink = Sink::Callback(Rc::new(f));
+        self
+    }
+
+    pub fn wrap<T>(&self, app: T) -> Logged
+    where
+        T: HttpApp + 'static,
+        T::Output: 'static,
+    {
+        let writer = match &self.sink {
+            Sink::Stderr => Writer::spawn(None),
+            Sink::File(path) => Writer::spawn(Some(path.clone())),
+            Sink::Callback(f) => Writer::Callback(Rc::clone(f)),
+        };
+        Logged {
+            config: Rc::new(self.clone()),
+            writer: Rc::new(writer),
+            next: BoxedApp::new(app),
+        }
+    }
+
+    fn line(&self, req: &Request, res: &Response, elapsed_secs: f64) -> String {
+        let host = req
+            .client_addr()
+            .map_or_else(|| "-".to_owned(), |ip| ip.to_string());
+        let bytes = if res.is_streaming() || res.body_len() == 0 {
+            "-".to_owned()
+        } else {
+            res.body_len().to_string()
+        };
+        let mut line = format!(
+            "{} - - [{}] \"{} {} {}\" {} {}",
+            host,
+            date::common_log(SystemTime::now()),
+            escape(req.method()),
+            escape(req.uri()),
+            escape(req.http_version()),
+            res.status_code() as u16,
+            bytes
+        );
+        if self.format == LogFormat::Combined {
+            line.push_str(&format!(
+                " \"{}\" \"{}\"",
+                escape(req.header("referer").unwrap_or("-")),
+                escape(req.header("user-agent").unwrap_or("-"))
+            ));
+        }
+        if self.latency {
+            line.push_str(&format!(" {:.3}", elapsed_secs));
+        }
+        line
+    }
+}
+
+enum Writer {
+    Thread(mpsc::Sender<String>),
+    Callback(Rc<dyn Fn(&str)>),
+}
+
+impl Writer {
+    // lines are written by the thread in batches, flushed whenever it runs out of lines
+    fn spawn(path: Option<PathBuf>) -> Writer {
+        let (tx, rx) = mpsc::channel::<String>();
+        let spawned = thread::Builder::new()
+            .name("access-log".to_owned())
+            .spawn(move || {
+                let out: Box<dyn Write> = match &path {
+                    Some(path) => match OpenOptions::new().create(true).append(true).open(path) {
+                        Ok(file) => Box::new(file),
+                        Err(e) => {
+                            error!("access log {}: {}", path.display(), e);
+                            return;
+                        }
+                    },
+                    None => Box::new(io::stderr()),
+                };
+                let mut out = BufWriter::new(out);
+                while let Ok(line) = rx.recv() {
+                    let mut res = writeln!(out, "{}", line);
+                    for line in rx.try_iter() {
+                        res = res.and_then(|_| writeln!(out, "{}", line));
+                    }
+                    if let Err(e) = res.and_then(|_| out.flush()) {
+                        error!("writing the access log: {}", e);
+                    }
+                }
+            });
+        if let Err(e) = spawned {
+            error!("spawning the access log thread: {}", e);
+        }
+        Writer::Thread(tx)
+    }
+
+    fn write(&self, line: String) {
+        match self {
+            // fails only once the thread is gone, which was logged already
+            Writer::Thread(tx) => {
+                let _ = tx.send(line);
+            }
+            Writer::Callback(f) => f(&line),
+        }
+    }
+}
+
+// quotes, backslashes and control characters in request fields are escaped, so a client
+// can't forge log lines
+fn escape(s: &str) -> String {
+    let mut out = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            c if c.is_control() => out.push_str(&format!("\\x{:02x}", c as u32)),
+            c => out.push(c),
+        }
+    }
+    out
+}
+
+// see `AccessLog::wrap`
+pub struct Logged {
+    config: Rc<AccessLog>,
+    writer: Rc<Writer>,
+    next: BoxedApp,
+}
+
+impl HttpApp for Logged {
+    type Output = LocalBoxFuture<'static, Response>;
+
+    fn app(&self, req: Request) -> Self::Output {
+        let start = Instant::now();
+        // the parts of the request the line needs, as `next` takes the request
+        let mut logged = Request::empty();
+        logged.method = req.method.clone();
+        logged.uri = req.uri.clone();
+        logged.http_version = req.http_version.clone();
+        logged.peer_addr = req.peer_addr;
+        logged.client_addr = req.client_addr;
+        for name in &["referer", "user-agent"] {
+            if let Some(value) = req.header(name) {
+                logged.set_header(name, value.to_owned());
+            }
+        }
+        let res = self.next.app(req);
+        let config = Rc::clone(&self.config);
+        let writer = Rc::clone(&self.writer);
+        async move {
+            let res = res.await;
+            let elapsed = start.elapsed();
+            let secs = elapsed.as_secs() as f64 + f64::from(elapsed.subsec_nanos()) / 1e9;
+            writer.write(config.line(&logged, &res, secs));
+            res
+        }
+        .boxed_local()
+    }
+
+    fn check(&self, problems: &mut Vec<String>) {
+        self.next.check(problems);
+    }
+}
@@ -0,0 +1,205 @@
+use super::base64;
+#[cfg(feature = "jwt")]
+use super::jwt::Jwt;
+use super::middleware::BoxedApp;
+use super::{HttpApp, Request, Response, StatusCode};
+use futures::future::{self, FutureExt, LocalBoxFuture};
+use log::*;
+use std::{rc::Rc, str};
+
+type Verify = Rc<dyn Fn(&str, &str) -> bool>;
+type Validate = Rc<dyn Fn(&str) -> Option<String>>;
+
+// HTTP Basic authentication (RFC 7617): `verify` gets the user name and password of every
+// request and decides whether they are valid. requests without valid credentials get a 401
+// challenging for them; the user name of the others is `Request::user`. only use it over
+// TLS, the password is sent in the clear.
+#[derive(Clone)]
+pub struct BasicAuth {
+    realm: String,
+    verify: Verify,
+}
+
+impl BasicAuth {
+    pub fn new<F>(realm: &str, verify: F) -> BasicAuth
+    where
+        F: Fn(&str, &str) -> bool + 'static,
+    {
+        BasicAuth {
+            realm: realm.to_owned(),
+            verify: Rc::new(verify),
+        }
+    }
+
+    pub fn wrap<T>(&self, app: T) -> Authenticated
+    where
+        T: HttpApp + 'static,
+        T::Output: 'static,
+    {
+        Authenticated {
+            scheme: Scheme::Basic(self.clone()),
+            next: BoxedApp::new(app),
+        }
+    }
+
+    fn authenticate(&self, req: &Request) -> Result<String, Response> {
+        let challenge = || {
+            unauthorized(format!(
+                "Basic realm={}, charset=\"UTF-8\"",
+                quote(&self.realm)
+            ))
+        };
+        let credentials = match credentials(req, "Basic") {
+            Some(credentials) => credentials,
+            None => return Err(challenge()),
+        };
+        let decoded = base64::decode(credentials)
+            .and_then(|decoded| String::from_utf8(decoded).ok())
+            .ok_or_else(|| Response::with_status_code(StatusCode::BadRequest))?;
+        let colon = decoded
+            .find(':')
+            .ok_or_else(|| Response::with_status_code(StatusCode::BadRequest))?;
+        let (user, password) = (&decoded[..colon], &decoded[colon + 1..]);
+        if (self.verify)(user, password) {
+            Ok(user.to_owned())
+        } else {
+            debug!("basic auth failed for {:?}", user);
+            Err(challenge())
+        }
+    }
+}
+
+// Bearer tokens (RFC 6750): `validate` gets the token of every request and returns the
+// subject it was issued to, which becomes `Request::user`, or None to refuse it with a 401
+#[derive(Clone)]
+pub struct BearerAuth {
+    realm: String,
+    validate: Validate,
+}
+
+impl BearerAuth {
+    pub fn new<F>(realm: &str, validate: F) -> BearerAuth
+    where
+        F: Fn(&str) -> Option<String> + 'static,
+    {
+        BearerAuth {
+            realm: realm.to_owned(),
+            validate: Rc::new(validate),
+        }
+    }
+
+    // tokens are JWTs verified by `jwt`; the subject is their `sub` claim, and tokens without
+    // a string one are refused
+    #[cfg(feature = "jwt")]
+    pub fn jwt(realm: &str, jwt: Jwt) -> BearerAuth {
+        BearerAuth::new(realm, move |token| match jwt.verify(token) {
+            Ok(claims) => {
+                let subject = claims["sub"].as_str().map(str::to_owned);
+                if subject.is_none() {
+                    debug!("rejected JWT without a subject");
+                }
+                subject
+            }
+            Err(e) => {
+                debug!("rejected JWT: {}", e);
+                None
+            }
+        })
+    }
+
+    pub fn wrap<T>(&self, app: T) -> Authenticated
+    where
+        T: HttpApp + 'static,
+        T::Output: 'static,
+    {
+        Authenticated {
+            scheme: Scheme::Bearer(self.clone()),
+            next: BoxedApp::new(app),
+        }
+    }
+
+    fn authenticate(&self, req: &Request) -> Result<String, Response> {
+        let realm = quote(&self.realm);
+        let token = match credentials(req, "Bearer") {
+            Some(token) => token,
+            None => return Err(unauthorized(format!("Bearer realm={}", realm))),
+        };
+        match (self.validate)(token) {
+            Some(subject) => Ok(subject),
+            None => Err(unauthorized(format!(
+                "Bearer realm={}, error=\"invalid_token\"",
+                realm
+            ))),
+        }
+    }
+}
+
+// the credentials after the scheme in the Authorization header, if it is `scheme`
+fn credentials<'r>(req: &'r Request, scheme: &str) -> Option<&'r str> {
+    let value = req.header("authorization")?.trim();
+    let space = value.find(' ')?;
+    if !value[..space].eq_ignore_ascii_case(scheme) {
+        return None;
+    }
+    Some(value[space..].trim()).filter(|credentials| !credentials.is_empty())
+}
+
+fn unauthorized(challenge: String) -> Response {
+    let mut res = Response::with_status_code(StatusCode::Unauthorized);
+    res.set_header("WWW-Authenticate", challenge);
+    res
+}
+
+// a quoted-string
+pub(super) fn quote(s: &str) -> String {
+    let mut out = String::with_capacity(s.len() + 2);
+    out.push('"');
+    for c in s.chars() {
+        if c == '"' || c == '\\' {
+            out.push('\\');
+        }
+        out.push(c);
+    }
+    out.push('"');
+    out
+}
+
+// compares in time depending only on the lengths, so a verifier comparing secrets doesn't
+// tell an attacker how much of a guess was right
+pub fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |diff, (x, y)| diff | (x ^ y)) == 0
+}
+
+#[derive(Clone)]
+enum Scheme {
+    Basic(BasicAuth),
+    Bearer(BearerAuth),
+}
+
+// see `BasicAuth::wrap` and `BearerAuth::wrap`
+pub struct Authenticated {
+    scheme: Scheme,
+    next: BoxedApp,
+}
+
+impl HttpApp for Authenticated {
+    type Output = LocalBoxFuture<'static, Response>;
+
+    fn app(&self, mut req: Request) -> Self::Output {
+        let user = match &self.scheme {
+            Scheme::Basic(basic) => basic.authenticate(&req),
+            Scheme::Bearer(bearer) => bearer.authenticate(&req),
+        };
+        match user {
+            Ok(user) => {
+                req.user = Some(user);
+                self.next.app(req)
+            }
+            Err(res) => future::ready(res).boxed_local(),
+        }
+    }
+
+    fn check(&self, problems: &mut Vec<String>) {
+        self.next.check(problems);
+    }
+}
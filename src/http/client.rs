@@ -0,0 +1,1029 @@
+// an HTTP/1.1 client on the crate's own sockets and resolver:
+//
+//     let res = Client::new().get("http://example.com/").send().await?;
+//     let body = res.bytes().await?;
+//
+// connections are kept open once a response has been read, for the next request to the same
+// host and port (see `Client::pool`), unless it switched protocols (see `into_upgraded`). the
+// body is read as the caller asks for it, so large downloads can be streamed with `chunk` or
+// `into_stream`. redirects are followed, and failed requests retried with a `Retry` policy.
+// `Client::pipeline` sends several requests on one connection without waiting for responses.
+// plain http only, there is no TLS.
+use super::trace_context::TraceContext;
+use super::upgrade::Upgraded;
+use super::{fill, fill_to, find_header_end, header, parse_chunk_size, read_line};
+use super::{MAX_CHUNK_LINE, MAX_TRAILERS_LEN};
+use crate::net::{
+    resolve::{self, Resolve},
+    Socket, TcpStream,
+};
+use crate::{random, time};
+use futures::prelude::*;
+use futures::stream::{self, LocalBoxStream};
+use log::*;
+use std::{
+    cell::RefCell,
+    collections::{HashMap, VecDeque},
+    error, fmt, fs,
+    io::{self, Seek},
+    rc::Rc,
+    time::{Duration, Instant},
+};
+
+// response heads grow with cookies, so the limit is well over the server's for requests
+const MAX_RESPONSE_HEAD: usize = 64 * 1024;
+// idle connections kept per host and port, and for how long; see `Client::pool`
+const MAX_IDLE_PER_HOST: usize = 32;
+const IDLE_TIMEOUT: Duration = Duration::from_secs(30);
+
+#[derive(Debug)]
+pub enum ClientError {
+    Url(url::ParseError),
+    // anything but http
+    Scheme(String),
+    Method(String),
+    Header(header::InvalidHeader),
+    // resolving the host or connecting to it failed; nothing was sent
+    Connect(io::Error),
+    Io(io::Error),
+    // still redirected after `Client::max_redirects` hops
+    Redirects(usize),
+    // no response head within `Client::timeout`
+    Timeout,
+    // what the server sent isn't an HTTP/1 response
+    Response(&'static str),
+    // `Client::pipeline` refused the requests; nothing was sent
+    Pipeline(&'static str),
+}
+
+impl fmt::Display for ClientError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ClientError::Url(e) => write!(f, "invalid URL: {}", e),
+            ClientError::Scheme(scheme) => write!(f, "unsupported scheme {:?}", scheme),
+            ClientError::Method(method) => write!(f, "invalid method {:?}", method),
+            ClientError::Header(e) => e.fmt(f),
+            ClientError::Connect(e) => write!(f, "connecting failed: {}", e),
+            ClientError::Io(e) => e.fmt(f),
+            ClientError::Redirects(hops) => write!(f, "still redirected after {} hops", hops),
+            ClientError::Timeout => f.write_str("timed out waiting for the response"),
+            ClientError::Response(reason) => write!(f, "bad response: {}", reason),
+            ClientError::Pipeline(reason) => write!(f, "can't pipeline {}", reason),
+        }
+    }
+}
+
+impl error::Error for ClientError {}
+
+impl From<io::Error> for ClientError {
+    fn from(e: io::Error) -> ClientError {
+        ClientError::Io(e)
+    }
+}
+
+impl From<url::ParseError> for ClientError {
+    fn from(e: url::ParseError) -> ClientError {
+        ClientError::Url(e)
+    }
+}
+
+// when and how often a failed request is tried again: requests that couldn't connect, and
+// idempotent ones (GET, HEAD, PUT, DELETE, OPTIONS, TRACE) that failed midway, timed out or
+// got a 5xx response. the waits between tries double from `backoff`, with jitter; a 5xx
+// response's Retry-After, in seconds, is honored up to the longest wait.
+#[derive(Clone, Debug)]
+pub struct Retry {
+    attempts: u32,
+    backoff: Duration,
+    max_backoff: Duration,
+}
+
+impl Retry {
+    // up to `attempts` tries in all, the first one included
+    pub fn new(attempts: u32) -> Retry {
+        Retry {
+            attempts: attempts.max(1),
+            backoff: Duration::from_millis(100),
+            max_backoff: Duration::from_secs(10),
+        }
+    }
+
+    // waits `initial` before the first retry and at most `max` before any; 100 milliseconds
+    // and 10 seconds by default
+    pub fn backoff(&mut self, initial: Duration, max: Duration) -> &mut Self {
+        self.backoff = initial;
+        self.max_backoff = max.max(initial);
+        self
+    }
+
+    // before try number `tries + 1`
+    fn delay(&self, tries: u32, retry_after: Option<Duration>) -> Duration {
+        if let Some(retry_after) = retry_after {
+            return retry_after.min(self.max_backoff);
+        }
+        let doubled = self.backoff * 2u32.pow((tries - 1).min(16));
+        random::jitter(doubled, 0.25).min(self.max_backoff)
+    }
+}
+
+// settings shared by the requests made through it; cheap to clone
+#[derive(Clone)]
+pub struct Client {
+    resolver: Option<Rc<dyn Resolve>>,
+    timeout: Option<Duration>,
+    user_agent: String,
+    max_redirects: usize,
+    retry: Option<Retry>,
+    pool: Rc<Pool>,
+}
+
+impl Default for Client {
+    fn default() -> Client {
+        Client {
+            resolver: None,
+            timeout: None,
+            user_agent: concat!("net_test3/", env!("CARGO_PKG_VERSION")).to_owned(),
+            max_redirects: 10,
+            retry: None,
+            pool: Rc::new(Pool::new(MAX_IDLE_PER_HOST, IDLE_TIMEOUT)),
+        }
+    }
+}
+
+impl Client {
+    pub fn new() -> Client {
+        Client::default()
+    }
+
+    // host names are looked up with `resolver` rather than the thread's default one
+    pub fn resolver<R: Resolve + 'static>(&mut self, resolver: R) -> &mut Self {
+        self.resolver = Some(Rc::new(resolver));
+        self
+    }
+
+    // how long resolving, connecting, sending the request and receiving the response head
+    // may take together, for each try and each redirect; reading the body isn't limited
+    pub fn timeout(&mut self, timeout: Duration) -> &mut Self {
+        self.timeout = Some(timeout);
+        self
+    }
+
+    pub fn user_agent(&mut self, user_agent: &str) -> &mut Self {
+        self.user_agent = user_agent.to_owned();
+        self
+    }
+
+    // redirects followed before giving up with `ClientError::Redirects`, 10 by default; with
+    // 0 redirect responses are returned like any other. 303 and a 301 or 302 to a POST are
+    // followed with a GET without the body, 307 and 308 with the same method and body.
+    // Authorization, Cookie and Proxy-Authorization headers aren't sent to other origins.
+    pub fn max_redirects(&mut self, hops: usize) -> &mut Self {
+        self.max_redirects = hops;
+        self
+    }
+
+    // requests aren't retried unless a policy is set
+    pub fn retry(&mut self, retry: &Retry) -> &mut Self {
+        self.retry = Some(retry.clone());
+        self
+    }
+
+    // connections kept open once a response has been read, for the next request to the same
+    // host and port: up to `max_idle` of them per host, each for up to `idle_timeout`; 32 for
+    // 30 seconds by default. with 0 every request gets a connection of its own, closed after
+    // the response. clones of a client share its connections, those made before this call
+    // excepted.
+    pub fn pool(&mut self, max_idle: usize, idle_timeout: Duration) -> &mut Self {
+        self.pool = Rc::new(Pool::new(max_idle, idle_timeout));
+        self
+    }
+
+    pub fn get(&self, url: &str) -> RequestBuilder {
+        self.request("GET", url)
+    }
+
+    pub fn post(&self, url: &str) -> RequestBuilder {
+        self.request("POST", url)
+    }
+
+    // sends `requests` back to back on one connection, then reads their responses in order
+    // (HTTP/1.1 pipelining). all but the last response are read whole before this returns.
+    // the requests must be idempotent and for one origin; they aren't redirected or retried,
+    // and the timeout covers the batch up to the last response head.
+    pub async fn pipeline(
+        &self,
+        requests: Vec<RequestBuilder>,
+    ) -> Result<Vec<ClientResponse>, ClientError> {
+        let mut batch = Vec::with_capacity(requests.len());
+        for req in requests {
+            if let Some(e) = req.error {
+                return Err(e);
+            }
+            let url = req.url.expect("no URL without an error");
+            if !is_idempotent(&req.method) {
+                return Err(ClientError::Pipeline("requests that aren't idempotent"));
+            }
+            if req
+                .headers
+                .iter()
+                .any(|(n, _)| n.eq_ignore_ascii_case("upgrade"))
+            {
+                return Err(ClientError::Pipeline("upgrades"));
+            }
+            batch.push((req.method, url, req.headers, req.body));
+        }
+        let endpoint = match batch.first() {
+            Some((_, url, _, _)) => endpoint(url)?,
+            None => return Ok(Vec::new()),
+        };
+        let mut prepared = Vec::with_capacity(batch.len());
+        for (i, (method, url, headers, body)) in batch.iter().enumerate() {
+            if url.origin() != batch[0].1.origin() {
+                return Err(ClientError::Pipeline("requests for several origins"));
+            }
+            let close = !self.pool.enabled() && i + 1 == batch.len();
+            prepared.push(outgoing(self, method, url, headers, body, close)?);
+        }
+        let exchange = exchange_all(self, &endpoint, &prepared);
+        match self.timeout {
+            Some(timeout) => time::timeout(timeout, exchange)
+                .await
+                .unwrap_or_else(|_| Err(ClientError::Timeout)),
+            None => exchange.await,
+        }
+    }
+
+    pub fn request(&self, method: &str, url: &str) -> RequestBuilder {
+        let mut error = None;
+        if !header::valid_name(method) {
+            error = Some(ClientError::Method(method.to_owned()));
+        }
+        let url = match url::Url::parse(url) {
+            Ok(url) => Some(url),
+            Err(e) => {
+                error.get_or_insert(e.into());
+                None
+            }
+        };
+        RequestBuilder {
+            client: self.clone(),
+            method: method.to_owned(),
+            url,
+            headers: Vec::new(),
+            body: Body::Empty,
+            error,
+        }
+    }
+}
+
+type Key = (String, u16);
+
+// idle connections by host and port, the most recently used last
+struct Pool {
+    max_idle: usize,
+    idle_timeout: Duration,
+    idle: RefCell<HashMap<Key, VecDeque<(TcpStream, Instant)>>>,
+}
+
+impl Pool {
+    fn new(max_idle: usize, idle_timeout: Duration) -> Pool {
+        Pool {
+            max_idle,
+            idle_timeout,
+            idle: RefCell::new(HashMap::new()),
+        }
+    }
+
+    fn enabled(&self) -> bool {
+        self.max_idle > 0
+    }
+
+    // the most recently used connection to `endpoint` that is still open
+    fn take(&self, endpoint: &Key) -> Option<TcpStream> {
+        let mut idle = self.idle.borrow_mut();
+        let conns = idle.get_mut(endpoint)?;
+        let now = time::now();
+        let mut found = None;
+        while let Some((sock, since)) = conns.pop_back() {
+            // the ones before it have been idle for longer still
+            if now.duration_since(since) >= self.idle_timeout {
+                conns.clear();
+                break;
+            }
+            if !sock.is_stale() {
+                found = Some(sock);
+                break;
+            }
+        }
+        if conns.is_empty() {
+            idle.remove(endpoint);
+        }
+        found
+    }
+
+    fn put(&self, endpoint: Key, sock: TcpStream) {
+        let mut idle = self.idle.borrow_mut();
+        let conns = idle.entry(endpoint).or_default();
+        let now = time::now();
+        while let Some((_, since)) = conns.front() {
+            if conns.len() < self.max_idle && now.duration_since(*since) < self.idle_timeout {
+                break;
+            }
+            conns.pop_front();
+        }
+        conns.push_back((sock, now));
+    }
+}
+
+enum Body {
+    Empty,
+    Bytes(Vec<u8>),
+    // from the offset where it was when added, for every try
+    File(fs::File, u64),
+}
+
+// a request being put together; `send` makes it. mistakes such as an invalid header are
+// reported by `send`, so calls can be chained.
+pub struct RequestBuilder {
+    client: Client,
+    method: String,
+    url: Option<url::Url>,
+    headers: Vec<(String, String)>,
+    body: Body,
+    error: Option<ClientError>,
+}
+
+impl RequestBuilder {
+    // replaces a header set before under the same name (in any case)
+    pub fn header(mut self, name: &str, value: &str) -> Self {
+        if let Err(e) = header::validate(name, value) {
+            self.error.get_or_insert(ClientError::Header(e));
+            return self;
+        }
+        self.headers.retain(|(n, _)| !n.eq_ignore_ascii_case(name));
+        self.headers.push((name.to_owned(), value.to_owned()));
+        self
+    }
+
+    pub fn body(mut self, body: Vec<u8>) -> Self {
+        self.body = Body::Bytes(body);
+        self
+    }
+
+    // the rest of `file`, from its current position, handed to the socket by the kernel
+    // where it can (see `TcpStream::send_file`)
+    pub fn body_file(mut self, file: fs::File) -> Self {
+        match (&file).seek(io::SeekFrom::Current(0)) {
+            Ok(start) => self.body = Body::File(file, start),
+            Err(e) => {
+                self.error.get_or_insert(e.into());
+            }
+        }
+        self
+    }
+
+    // passes the trace on: `context` should be the handler's, or a `child` of it
+    pub fn trace_context(mut self, context: &TraceContext) -> Self {
+        self = self.header("traceparent", &context.to_string());
+        match context.state() {
+            Some(state) => self.header("tracestate", state),
+            None => {
+                self.headers
+                    .retain(|(n, _)| !n.eq_ignore_ascii_case("tracestate"));
+                self
+            }
+        }
+    }
+
+    pub async fn send(self) -> Result<ClientResponse, ClientError> {
+        if let Some(e) = self.error {
+            return Err(e);
+        }
+        let client = self.client;
+        let (mut method, mut headers, mut body) = (self.method, self.headers, self.body);
+        let mut url = self.url.expect("no URL without an error");
+        let mut hops = 0;
+        loop {
+            let res = retry(&client, &method, &url, &headers, &body).await?;
+            let location = match res.header("location") {
+                Some(location) if is_redirect(res.status) && client.max_redirects > 0 => {
+                    location.to_owned()
+                }
+                _ => return Ok(res),
+            };
+            if hops == client.max_redirects {
+                return Err(ClientError::Redirects(hops));
+            }
+            hops += 1;
+            let next = url.join(&location)?;
+            debug!("{} {}: {} to {}", method, url, res.status, next);
+            // RFC 7231 6.4: what browsers do
+            if (res.status == 303 && method != "HEAD")
+                || ((res.status == 301 || res.status == 302) && method == "POST")
+            {
+                method = "GET".to_owned();
+                body = Body::Empty;
+                headers.retain(|(n, _)| !n.to_lowercase().starts_with("content-"));
+            }
+            if next.origin() != url.origin() {
+                headers.retain(|(n, _)| {
+                    !["authorization", "cookie", "proxy-authorization"]
+                        .iter()
+                        .any(|c| n.eq_ignore_ascii_case(c))
+                });
+            }
+            url = next;
+        }
+    }
+}
+
+fn is_redirect(status: u16) -> bool {
+    match status {
+        301 | 302 | 303 | 307 | 308 => true,
+        _ => false,
+    }
+}
+
+// safe to send twice (RFC 7231 4.2.2)
+fn is_idempotent(method: &str) -> bool {
+    match method {
+        "GET" | "HEAD" | "PUT" | "DELETE" | "OPTIONS" | "TRACE" => true,
+        _ => false,
+    }
+}
+
+// one request, tried as often as the client's `Retry` allows
+async fn retry(
+    client: &Client,
+    method: &str,
+    url: &url::Url,
+    headers: &[(String, String)],
+    body: &Body,
+) -> Result<ClientResponse, ClientError> {
+    let mut tries = 0;
+    loop {
+        tries += 1;
+        let exchange = exchange(client, method, url, headers, body);
+        let result = match client.timeout {
+            Some(timeout) => time::timeout(timeout, exchange)
+                .await
+                .unwrap_or_else(|_| Err(ClientError::Timeout)),
+            None => exchange.await,
+        };
+        let policy = match &client.retry {
+            Some(policy) if tries < policy.attempts => policy,
+            _ => return result,
+        };
+        let retry_after = match &result {
+            Err(ClientError::Connect(_)) => None,
+            Err(ClientError::Io(_)) | Err(ClientError::Timeout) if is_idempotent(method) => None,
+            Ok(res) if res.status >= 500 && is_idempotent(method) => res
+                .header("retry-after")
+                .and_then(|secs| secs.trim().parse().ok())
+                .map(Duration::from_secs),
+            _ => return result,
+        };
+        let delay = policy.delay(tries, retry_after);
+        match &result {
+            Ok(res) => debug!(
+                "{} {}: {}, retrying in {:?}",
+                method, url, res.status, delay
+            ),
+            Err(e) => debug!("{} {}: {}, retrying in {:?}", method, url, e, delay),
+        }
+        drop(result);
+        time::sleep(delay).await;
+    }
+}
+
+// a request ready to go out
+struct Outgoing<'a> {
+    method: &'a str,
+    url: &'a url::Url,
+    head: Vec<u8>,
+    body: &'a Body,
+    body_len: u64,
+}
+
+// the host and port `url` is served from, for connecting and pooling
+fn endpoint(url: &url::Url) -> Result<Key, ClientError> {
+    if url.scheme() != "http" {
+        return Err(ClientError::Scheme(url.scheme().to_owned()));
+    }
+    let host = url.host_str().ok_or(url::ParseError::EmptyHost)?;
+    Ok((host.to_owned(), url.port_or_known_default().unwrap_or(80)))
+}
+
+// `close` asks the server to close the connection after the response
+fn outgoing<'a>(
+    client: &Client,
+    method: &'a str,
+    url: &'a url::Url,
+    headers: &[(String, String)],
+    body: &'a Body,
+    close: bool,
+) -> Result<Outgoing<'a>, ClientError> {
+    let host = url.host_str().ok_or(url::ParseError::EmptyHost)?;
+    let body_len = match body {
+        Body::Empty => None,
+        Body::Bytes(bytes) => Some(bytes.len() as u64),
+        Body::File(file, start) => Some(file.metadata()?.len().saturating_sub(*start)),
+    };
+    let target = &url[url::Position::BeforePath..url::Position::AfterQuery];
+    let authority = match url.port() {
+        Some(port) => format!("{}:{}", host, port),
+        None => host.to_owned(),
+    };
+    let mut head = format!("{} {} HTTP/1.1\r\nHost: {}\r\n", method, target, authority);
+    let has = |name: &str| headers.iter().any(|(n, _)| n.eq_ignore_ascii_case(name));
+    if !has("user-agent") {
+        head.push_str(&format!("User-Agent: {}\r\n", client.user_agent));
+    }
+    // a request for another protocol keeps the connection for it if the server agrees
+    if has("upgrade") {
+        head.push_str("Connection: upgrade\r\n");
+    } else if close {
+        head.push_str("Connection: close\r\n");
+    }
+    // a POST or PUT without a body still says so, or the server waits for one
+    if let Some(len) = body_len.or_else(|| Some(0).filter(|_| method == "POST" || method == "PUT"))
+    {
+        head.push_str(&format!("Content-Length: {}\r\n", len));
+    }
+    for (name, value) in headers {
+        if ["content-length", "host", "connection"]
+            .iter()
+            .any(|n| name.eq_ignore_ascii_case(n))
+        {
+            continue;
+        }
+        head.push_str(&format!("{}: {}\r\n", name, value));
+    }
+    head.push_str("\r\n");
+    Ok(Outgoing {
+        method,
+        url,
+        head: head.into_bytes(),
+        body,
+        body_len: body_len.unwrap_or(0),
+    })
+}
+
+async fn exchange(
+    client: &Client,
+    method: &str,
+    url: &url::Url,
+    headers: &[(String, String)],
+    body: &Body,
+) -> Result<ClientResponse, ClientError> {
+    let endpoint = endpoint(url)?;
+    let req = outgoing(client, method, url, headers, body, !client.pool.enabled())?;
+    let mut responses = exchange_all(client, &endpoint, &[req]).await?;
+    Ok(responses.pop().expect("a response per request"))
+}
+
+// sends `requests` on one connection to `endpoint`, pooled or new, and reads their responses
+// in order: all but the last are read whole, the last is left for the caller to read
+async fn exchange_all(
+    client: &Client,
+    endpoint: &Key,
+    requests: &[Outgoing<'_>],
+) -> Result<Vec<ClientResponse>, ClientError> {
+    let idempotent = requests.iter().all(|req| is_idempotent(req.method));
+    let mut pooled = client.pool.take(endpoint);
+    let (mut conn, head) = loop {
+        let reused = pooled.is_some();
+        let mut sock = match pooled.take() {
+            Some(sock) => sock,
+            None => {
+                let resolver = client
+                    .resolver
+                    .clone()
+                    .unwrap_or_else(resolve::default_resolver);
+                TcpStream::connect_with(&*resolver, &endpoint.0, endpoint.1)
+                    .await
+                    .map_err(ClientError::Connect)?
+            }
+        };
+        let reuse = if reused { ", reused" } else { "" };
+        let via = format!("{:?}{}", sock.peer_addr(), reuse);
+        for req in requests {
+            debug!("{} {} via {}", req.method, req.url, via);
+        }
+        let sent = send(&mut sock, requests).await;
+        let mut conn = BodyReader::new(sock);
+        let result = match sent {
+            Ok(()) => conn.head().await,
+            Err(e) => Err(e),
+        };
+        match result {
+            Ok(head) => break (conn, head),
+            // the server closed the idle connection as the requests went out
+            Err(e) if reused && conn.buf.is_empty() && idempotent => {
+                debug!("{} on a reused connection, reconnecting", e);
+            }
+            Err(e) => return Err(e),
+        }
+    };
+    let mut first = Some(head);
+    let mut responses = Vec::with_capacity(requests.len());
+    for (i, req) in requests.iter().enumerate() {
+        let (version, status, reason, headers) = match first.take() {
+            Some(head) => head,
+            None => conn.head().await?,
+        };
+        let mut res = ClientResponse {
+            version,
+            status,
+            reason,
+            headers,
+            body: BodyReader::whole(Vec::new()),
+        };
+        let framing = res.framing(req.method)?;
+        if i + 1 < requests.len() {
+            conn.framing = framing;
+            let mut body = Vec::new();
+            while let Some(chunk) = conn.chunk().await? {
+                body.extend_from_slice(&chunk);
+            }
+            res.body = BodyReader::whole(body);
+            responses.push(res);
+            continue;
+        }
+        // a body delimited by the end of the connection leaves nothing to reuse
+        let reusable = match framing {
+            Framing::Close => false,
+            _ => client.pool.enabled() && res.keeps_alive(),
+        };
+        if reusable {
+            conn.pool = Some((Rc::clone(&client.pool), endpoint.clone()));
+        }
+        conn.framing = framing;
+        if let Framing::Done | Framing::Length(0) = conn.framing {
+            conn.finish();
+        }
+        res.body = conn;
+        responses.push(res);
+        return Ok(responses);
+    }
+    Ok(responses)
+}
+
+// writes `requests` back to back, in as few writes as their bodies allow
+async fn send(sock: &mut TcpStream, requests: &[Outgoing<'_>]) -> Result<(), ClientError> {
+    let mut out = Vec::new();
+    for req in requests {
+        out.extend_from_slice(&req.head);
+        let (file, start) = match req.body {
+            Body::Empty => continue,
+            Body::Bytes(bytes) => {
+                out.extend_from_slice(bytes);
+                continue;
+            }
+            Body::File(file, start) => (file, *start),
+        };
+        sock.write_all(&out).await?;
+        out.clear();
+        let mut offset = start;
+        let mut left = req.body_len;
+        while left > 0 {
+            let len = left.min(1 << 30) as usize;
+            let sent = sock.send_file(file, offset, len).await?;
+            if sent == 0 {
+                return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "file shrank").into());
+            }
+            offset += sent as u64;
+            left -= sent as u64;
+        }
+        (&*file).seek(io::SeekFrom::Start(offset))?;
+    }
+    sock.write_all(&out).await?;
+    sock.flush().await?;
+    Ok(())
+}
+
+type Head = (String, u16, String, Vec<(String, String)>);
+
+// the connection a body is read from, until it went back to the pool
+fn connected(sock: &mut Option<TcpStream>) -> io::Result<&mut TcpStream> {
+    sock.as_mut()
+        .ok_or_else(|| io::ErrorKind::NotConnected.into())
+}
+
+fn parse_head(head: &[u8]) -> Result<Head, ClientError> {
+    let head = String::from_utf8_lossy(head);
+    let mut lines = head
+        .split('\n')
+        .map(|line| line.trim_end_matches('\r'))
+        .take_while(|line| !line.is_empty());
+    let status_line = lines.next().unwrap_or("");
+    let mut parts = status_line.splitn(3, ' ');
+    let version = parts.next().unwrap_or("");
+    let status = parts.next().unwrap_or("");
+    if !version.starts_with("HTTP/1.") || status.len() != 3 {
+        return Err(ClientError::Response("bad status line"));
+    }
+    let status = status
+        .parse::<u16>()
+        .map_err(|_| ClientError::Response("bad status code"))?;
+    let reason = parts.next().unwrap_or("").to_owned();
+    let mut headers = Vec::new();
+    for line in lines {
+        let colon = line
+            .find(':')
+            .ok_or(ClientError::Response("header line without colon"))?;
+        let (name, value) = (&line[..colon], line[colon + 1..].trim());
+        if !header::valid_name(name) {
+            return Err(ClientError::Response("bad header"));
+        }
+        headers.push((name.to_lowercase(), value.to_owned()));
+    }
+    Ok((version.to_owned(), status, reason, headers))
+}
+
+enum Framing {
+    Length(u64),
+    // what is left of the current chunk; 0 between chunks
+    Chunked(u64),
+    // until the server closes the connection
+    Close,
+    Done,
+}
+
+struct BodyReader {
+    // None once the connection went back to the pool, and for a body read whole already
+    sock: Option<TcpStream>,
+    // read but not yet handed out
+    buf: Vec<u8>,
+    framing: Framing,
+    // where the connection goes once the body has been read, if it can take another request
+    pool: Option<(Rc<Pool>, Key)>,
+}
+
+impl BodyReader {
+    fn new(sock: TcpStream) -> BodyReader {
+        BodyReader {
+            sock: Some(sock),
+            buf: Vec::new(),
+            framing: Framing::Done,
+            pool: None,
+        }
+    }
+
+    fn whole(body: Vec<u8>) -> BodyReader {
+        BodyReader {
+            sock: None,
+            framing: Framing::Length(body.len() as u64),
+            buf: body,
+            pool: None,
+        }
+    }
+
+    // the next response head; interim responses (100 Continue, 103 Early Hints) are skipped
+    async fn head(&mut self) -> Result<Head, ClientError> {
+        loop {
+            let head_len = loop {
+                if let Some(end) = find_header_end(&self.buf) {
+                    break end;
+                }
+                if self.buf.len() > MAX_RESPONSE_HEAD {
+                    return Err(ClientError::Response("head too long"));
+                }
+                if self.read_more().await? == 0 {
+                    return Err(ClientError::Response(
+                        "connection closed before the head ended",
+                    ));
+                }
+            };
+            let head = parse_head(&self.buf[..head_len])?;
+            self.buf.drain(..head_len);
+            if head.1 >= 100 && head.1 < 200 && head.1 != 101 {
+                trace!("skipping interim {} response", head.1);
+                continue;
+            }
+            return Ok(head);
+        }
+    }
+
+    async fn read_more(&mut self) -> io::Result<usize> {
+        fill(connected(&mut self.sock)?, &mut self.buf).await
+    }
+
+    // the body has been read; the connection goes back to the pool if nothing came after it
+    fn finish(&mut self) {
+        self.framing = Framing::Done;
+        if let Some((pool, endpoint)) = self.pool.take() {
+            if let (Some(sock), true) = (self.sock.take(), self.buf.is_empty()) {
+                pool.put(endpoint, sock);
+            }
+        }
+    }
+
+    async fn chunk(&mut self) -> Result<Option<Vec<u8>>, ClientError> {
+        match self.framing {
+            Framing::Done => Ok(None),
+            Framing::Length(0) => {
+                self.finish();
+                Ok(None)
+            }
+            Framing::Length(left) => {
+                if self.buf.is_empty() && self.read_more().await? == 0 {
+                    return Err(io::Error::from(io::ErrorKind::UnexpectedEof).into());
+                }
+                let n = (self.buf.len() as u64).min(left) as usize;
+                self.framing = Framing::Length(left - n as u64);
+                Ok(Some(self.buf.drain(..n).collect()))
+            }
+            Framing::Close => {
+                if self.buf.is_empty() && self.read_more().await? == 0 {
+                    self.framing = Framing::Done;
+                    return Ok(None);
+                }
+                Ok(Some(std::mem::replace(&mut self.buf, Vec::new())))
+            }
+            Framing::Chunked(mut left) => {
+                if left == 0 {
+                    left = self.chunk_size().await? as u64;
+                    if left == 0 {
+                        self.trailers().await?;
+                        self.finish();
+                        return Ok(None);
+                    }
+                }
+                if self.buf.is_empty() && self.read_more().await? == 0 {
+                    return Err(io::Error::from(io::ErrorKind::UnexpectedEof).into());
+                }
+                let n = (self.buf.len() as u64).min(left) as usize;
+                let data = self.buf.drain(..n).collect();
+                left -= n as u64;
+                if left == 0 {
+                    fill_to(connected(&mut self.sock)?, &mut self.buf, 2).await?;
+                    if &self.buf[..2] != b"\r\n" {
+                        return Err(ClientError::Response("chunk without CRLF"));
+                    }
+                    self.buf.drain(..2);
+                }
+                self.framing = Framing::Chunked(left);
+                Ok(Some(data))
+            }
+        }
+    }
+
+    async fn chunk_size(&mut self) -> Result<usize, ClientError> {
+        let line_len = read_line(connected(&mut self.sock)?, &mut self.buf, MAX_CHUNK_LINE)
+            .await?
+            .ok_or(ClientError::Response("chunk size line too long"))?;
+        let size = parse_chunk_size(&self.buf[..line_len - 2]).map_err(ClientError::Response)?;
+        self.buf.drain(..line_len);
+        Ok(size)
+    }
+
+    // read and dropped
+    async fn trailers(&mut self) -> Result<(), ClientError> {
+        let mut trailers_len = 0;
+        loop {
+            let max = MAX_TRAILERS_LEN - trailers_len;
+            let line_len = read_line(connected(&mut self.sock)?, &mut self.buf, max)
+                .await?
+                .ok_or(ClientError::Response("trailers too long"))?;
+            self.buf.drain(..line_len);
+            trailers_len += line_len;
+            if line_len == 2 {
+                return Ok(());
+            }
+        }
+    }
+}
+
+pub struct ClientResponse {
+    version: String,
+    status: u16,
+    reason: String,
+    // names lowercased, in the order received
+    headers: Vec<(String, String)>,
+    body: BodyReader,
+}
+
+impl ClientResponse {
+    pub fn status(&self) -> u16 {
+        self.status
+    }
+
+    pub fn reason(&self) -> &str {
+        &self.reason
+    }
+
+    pub fn http_version(&self) -> &str {
+        &self.version
+    }
+
+    // the first field named `name`, in any case; see `header_all` for Set-Cookie and the like
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(n, _)| n.eq_ignore_ascii_case(name))
+            .map(|(_, v)| &**v)
+    }
+
+    pub fn header_all(&self, name: &str) -> Vec<&str> {
+        self.headers
+            .iter()
+            .filter(|(n, _)| n.eq_ignore_ascii_case(name))
+            .map(|(_, v)| &**v)
+            .collect()
+    }
+
+    pub fn headers(&self) -> &[(String, String)] {
+        &self.headers
+    }
+
+    // the next piece of the body as it arrives; None at its end
+    pub async fn chunk(&mut self) -> Result<Option<Vec<u8>>, ClientError> {
+        self.body.chunk().await
+    }
+
+    pub async fn bytes(mut self) -> Result<Vec<u8>, ClientError> {
+        let mut body = Vec::new();
+        while let Some(chunk) = self.chunk().await? {
+            body.extend_from_slice(&chunk);
+        }
+        Ok(body)
+    }
+
+    // invalid UTF-8 is replaced
+    pub async fn text(self) -> Result<String, ClientError> {
+        let bytes = self.bytes().await?;
+        Ok(String::from_utf8_lossy(&bytes).into_owned())
+    }
+
+    pub fn into_stream(self) -> LocalBoxStream<'static, Result<Vec<u8>, ClientError>> {
+        stream::unfold(Some(self.body), |body| async move {
+            let mut body = body?;
+            match body.chunk().await {
+                Ok(Some(chunk)) => Some((Ok(chunk), Some(body))),
+                Ok(None) => None,
+                Err(e) => Some((Err(e), None)),
+            }
+        })
+        .boxed_local()
+    }
+
+    // the connection of a 101 Switching Protocols response, for the protocol named in its
+    // Upgrade header; the request needs an Upgrade header of its own for the server to agree
+    pub fn into_upgraded(self) -> Result<Upgraded, ClientError> {
+        let sock = match (self.status, self.body.sock) {
+            (101, Some(sock)) => sock,
+            _ => return Err(ClientError::Response("not upgraded")),
+        };
+        Ok(Upgraded {
+            sock: Socket::Tcp(sock),
+            read_buf: self.body.buf,
+        })
+    }
+
+    // whether the server leaves the connection open for another request
+    fn keeps_alive(&self) -> bool {
+        self.version == "HTTP/1.1"
+            && self.status != 101
+            && !header::has_token(self.header("connection"), "close")
+    }
+
+    // how the body is delimited (RFC 7230 3.3.3)
+    fn framing(&self, method: &str) -> Result<Framing, ClientError> {
+        if method == "HEAD" || self.status == 204 || self.status == 304 || self.status < 200 {
+            return Ok(Framing::Done);
+        }
+        if let Some(te) = self.header("transfer-encoding") {
+            let last = te.rsplit(',').next().unwrap_or("").trim();
+            return Ok(if last.eq_ignore_ascii_case("chunked") {
+                Framing::Chunked(0)
+            } else {
+                Framing::Close
+            });
+        }
+        let lengths = self.header_all("content-length");
+        match lengths.first() {
+            Some(first) => {
+                let first = first.trim();
+                if lengths.iter().any(|len| len.trim() != first) {
+                    return Err(ClientError::Response("conflicting Content-Length"));
+                }
+                first
+                    .parse()
+                    .map(Framing::Length)
+                    .map_err(|_| ClientError::Response("bad Content-Length"))
+            }
+            None => Ok(Framing::Close),
+        }
+    }
+}
+
+impl fmt::Debug for ClientResponse {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ClientResponse")
+            .field("status", &self.status)
+            .field("reason", &self.reason)
+            .field("headers", &self.headers)
+            .finish()
+    }
+}
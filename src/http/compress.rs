@@ -0,0 +1,289 @@
+use super::middleware::BoxedApp;
+use super::{HttpApp, Request, Response, StatusCode};
+use flate2::write::{GzEncoder, ZlibEncoder};
+use futures::future::{FutureExt, LocalBoxFuture};
+use log::*;
+use std::{io::Write, rc::Rc};
+
+// media types that are compressed already, where another pass only costs time
+const PRECOMPRESSED: &[&str] = &[
+    "image/",
+    "audio/",
+    "video/",
+    "font/woff",
+    "font/woff2",
+    "application/zip",
+    "application/gzip",
+    "application/x-gzip",
+    "application/x-bzip2",
+    "application/x-xz",
+    "application/x-7z-compressed",
+    "application/x-rar-compressed",
+    "application/zstd",
+    "application/pdf",
+];
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Encoding {
+    Brotli,
+    Gzip,
+    Deflate,
+}
+
+impl Encoding {
+    pub fn name(self) -> &'static str {
+        match self {
+            Encoding::Brotli => "br",
+            Encoding::Gzip => "gzip",
+            Encoding::Deflate => "deflate",
+        }
+    }
+}
+
+// compresses response bodies the client accepts compressed. bodies below `min_size`,
+// streaming bodies, bodies that already have a Content-Encoding and bodies of
+// `PRECOMPRESSED` types are passed through.
+#[derive(Clone, Debug)]
+pub struct Compression {
+    min_size: usize,
+    level: u32,
+    brotli_quality: u32,
+    brotli_window: u32,
+    // in order of preference when the client likes several equally
+    encodings: Vec<Encoding>,
+}
+
+impl Default for Compression {
+    fn default() -> Compression {
+        Compression {
+            min_size: 1024,
+            level: 6,
+            // 11 compresses best but is far too slow for per-request use
+            brotli_quality: 5,
+            brotli_window: 22,
+            encodings: vec![Encoding::Brotli, Encoding::Gzip, Encoding::Deflate],
+        }
+    }
+}
+
+impl Compression {
+    pub fn new() -> Compression {
+        Compression::default()
+    }
+
+    pub fn min_size(&mut self, min_size: usize) -> &mut Self {
+        self.min_size = min_size;
+        self
+    }
+
+    // gzip and deflate: 0 (fastest) to 9 (smallest)
+    pub fn level(&mut self, level: u32) -> &mut Self {
+        self.level = level.min(9);
+        self
+    }
+
+    // 0 (fastest) to 11 (smallest)
+    pub fn brotli_quality(&mut self, quality: u32) -> &mut Self {
+        self.brotli_quality = quality.min(11);
+        self
+    }
+
+    // log2 of the sliding window, 10 to 24; larger windows find more matches in big bodies
+    // but cost the client more memory to decode
+    pub fn brotli_window(&mut self, lgwin: u32) -> &mut Self {
+        self.brotli_window = lgwin.max(10).min(24);
+        self
+    }
+
+    pub fn encodings(&mut self, encodings: &[Encoding]) -> &mut Self {
+        self.encodings = encodings.to_vec();
+        self
+    }
+
+    pub fn wrap<T>(&self, app: T) -> Compressed
+    where
+        T: HttpApp + 'static,
+        T::Output: 'static,
+    {
+        Compressed {
+            config: Rc::new(self.clone()),
+            next: BoxedApp::new(app),
+        }
+    }
+
+    fn compress(&self, res: &mut Response, accept: Option<&str>) {
+        if res.is_streaming() || res.header("content-encoding").is_some() {
+            return;
+        }
+        // the ranges are of the unencoded body; encoded, the parts couldn't be put together
+        if res.status_code() == StatusCode::PartialContent || res.header("content-range").is_some()
+        {
+            return;
+        }
+        let content_type = res.header("content-type").unwrap_or("").to_lowercase();
+        if PRECOMPRESSED.iter().any(|t| content_type.starts_with(t)) {
+            return;
+        }
+        // the body may differ by Accept-Encoding from here on, even when sent as is
+        add_vary(res, "Accept-Encoding");
+        if res.body_len() < self.min_size {
+            return;
+        }
+        let names = self.encodings.iter().map(|e| e.name()).collect::<Vec<_>>();
+        let chosen = match negotiate(accept.unwrap_or(""), &names) {
+            Some(name) => name,
+            None => return,
+        };
+        let encoding = self.encodings[names.iter().position(|&n| n == chosen).unwrap()];
+        match self.encode(encoding, res.body()) {
+            Ok(body) => {
+                if body.len() < res.body_len() {
+                    debug!(
+                        "{}: {} -> {} bytes",
+                        encoding.name(),
+                        res.body_len(),
+                        body.len()
+                    );
+                    res.set_body(body);
+                    res.remove_header("content-length");
+                    res.set_header("Content-Encoding", encoding.name().to_owned());
+                    weaken_etag(res);
+                }
+            }
+            Err(e) => warn!("{} failed: {}", encoding.name(), e),
+        }
+    }
+
+    // `body` in each of `encodings` that makes it smaller, for storing and picking from later
+    pub(crate) fn variants(&self, body: &[u8]) -> Vec<(Encoding, Vec<u8>)> {
+        if body.len() < self.min_size {
+            return Vec::new();
+        }
+        let mut variants = Vec::new();
+        for &encoding in &self.encodings {
+            match self.encode(encoding, body) {
+                Ok(encoded) if encoded.len() < body.len() => variants.push((encoding, encoded)),
+                Ok(_) => {}
+                Err(e) => warn!("{} failed: {}", encoding.name(), e),
+            }
+        }
+        variants
+    }
+
+    fn encode(&self, encoding: Encoding, body: &[u8]) -> std::io::Result<Vec<u8>> {
+        let level = flate2::Compression::new(self.level);
+        match encoding {
+            Encoding::Brotli => {
+                let mut w = brotli::CompressorWriter::new(
+                    Vec::new(),
+                    4096,
+                    self.brotli_quality,
+                    self.brotli_window,
+                );
+                w.write_all(body)?;
+                Ok(w.into_inner())
+            }
+            Encoding::Gzip => {
+                let mut w = GzEncoder::new(Vec::new(), level);
+                w.write_all(body)?;
+                w.finish()
+            }
+            Encoding::Deflate => {
+                let mut w = ZlibEncoder::new(Vec::new(), level);
+                w.write_all(body)?;
+                w.finish()
+            }
+        }
+    }
+}
+
+// see `Compression::wrap`
+pub struct Compressed {
+    config: Rc<Compression>,
+    next: BoxedApp,
+}
+
+impl HttpApp for Compressed {
+    type Output = LocalBoxFuture<'static, Response>;
+
+    fn app(&self, req: Request) -> Self::Output {
+        let accept = req.header("accept-encoding").map(str::to_owned);
+        let res = self.next.app(req);
+        let config = Rc::clone(&self.config);
+        async move {
+            let mut res = res.await;
+            config.compress(&mut res, accept.as_ref().map(|s| &**s));
+            res
+        }
+        .boxed_local()
+    }
+
+    fn check(&self, problems: &mut Vec<String>) {
+        self.next.check(problems);
+    }
+}
+
+// the coding in `supported` the Accept-Encoding value `accept` likes best (earlier entries of
+// `supported` win ties); None when it accepts none of them
+pub fn negotiate<'a>(accept: &str, supported: &[&'a str]) -> Option<&'a str> {
+    let mut wildcard = None;
+    let mut prefs = Vec::new();
+    for item in accept.split(',') {
+        let mut parts = item.split(';');
+        let coding = parts.next().unwrap_or("").trim().to_lowercase();
+        let q = parts
+            .filter_map(|p| {
+                let p = p.trim();
+                if p.starts_with("q=") {
+                    p[2..].trim().parse::<f32>().ok()
+                } else {
+                    None
+                }
+            })
+            .next()
+            .unwrap_or(1.0);
+        if coding == "*" {
+            wildcard = Some(q);
+        } else if !coding.is_empty() {
+            prefs.push((coding, q));
+        }
+    }
+    let mut best: Option<(&str, f32)> = None;
+    for &name in supported {
+        let q = match prefs.iter().find(|(coding, _)| coding == name) {
+            Some(&(_, q)) => q,
+            None => wildcard.unwrap_or(0.0),
+        };
+        if q > 0.0 && best.map_or(true, |(_, best_q)| q > best_q) {
+            best = Some((name, q));
+        }
+    }
+    best.map(|(name, _)| name)
+}
+
+// a strong ETag promises the same bytes, which an encoded body isn't; a weak one still
+// matches the unencoded body's in If-None-Match
+pub(crate) fn weaken_etag(res: &mut Response) {
+    let etag = match res.header("etag") {
+        Some(etag) if etag.starts_with('"') => format!("W/{}", etag),
+        _ => return,
+    };
+    res.remove_header("etag");
+    res.set_header("ETag", etag);
+}
+
+pub(crate) fn add_vary(res: &mut Response, field: &str) {
+    let vary = match res.header("vary") {
+        Some(vary)
+            if vary
+                .split(',')
+                .any(|v| v.trim().eq_ignore_ascii_case(field)) =>
+        {
+            return
+        }
+        Some(vary) => format!("{}, {}", vary, field),
+        None => field.to_owned(),
+    };
+    res.remove_header("vary");
+    res.set_header("Vary", vary);
+}
@@ -0,0 +1,218 @@
+use super::middleware::BoxedApp;
+use super::{base64, date, HttpApp, Request, Response, StatusCode};
+use futures::future::{FutureExt, LocalBoxFuture};
+use sha2::{Digest, Sha256};
+use std::{
+    fs::Metadata,
+    rc::Rc,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+// headers a 304 keeps from the response it stands in for
+const NOT_MODIFIED_HEADERS: &[&str] = &[
+    "cache-control",
+    "content-location",
+    "date",
+    "etag",
+    "expires",
+    "vary",
+];
+
+// a weak validator for a file, changing whenever its size or mtime does
+pub fn weak_etag(meta: &Metadata) -> String {
+    let mtime = meta
+        .modified()
+        .ok()
+        .and_then(|t| t.duration_since(UNIX_EPOCH).ok())
+        .unwrap_or_default();
+    format!(
+        "W/\"{:x}-{:x}.{:x}\"",
+        meta.len(),
+        mtime.as_secs(),
+        mtime.subsec_nanos()
+    )
+}
+
+// a strong validator derived from the bytes of a body
+pub fn strong_etag(body: &[u8]) -> String {
+    let digest = Sha256::digest(body);
+    format!("\"{}\"", base64::encode_url(&digest[..16]))
+}
+
+fn opaque(etag: &str) -> &str {
+    etag.trim().trim_start_matches("W/")
+}
+
+fn is_weak(etag: &str) -> bool {
+    etag.trim().starts_with("W/")
+}
+
+// whether `etag` is in the entity-tag list `header`. the strong comparison used by If-Match
+// never matches weak tags; the weak one used by If-None-Match ignores the W/ prefix.
+fn list_matches(header: &str, etag: Option<&str>, strong: bool) -> bool {
+    let etag = match etag {
+        Some(etag) => etag,
+        None => return false,
+    };
+    if header.trim() == "*" {
+        return true;
+    }
+    if strong && is_weak(etag) {
+        return false;
+    }
+    header
+        .split(',')
+        .any(|candidate| !(strong && is_weak(candidate)) && opaque(candidate) == opaque(etag))
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Precondition {
+    // serve the request as usual
+    Proceed,
+    // 304 for GET and HEAD
+    NotModified,
+    // 412 Precondition Failed
+    Failed,
+}
+
+// evaluates the conditional headers of `req` in the order of RFC 7232 section 6 against the
+// current validators of the target: `etag` (None when it doesn't exist) and `last_modified`.
+// handlers of unsafe methods call this before changing anything, so a stale If-Match fails
+// without side effects. dates that don't parse are ignored, as are date conditions whose
+// entity-tag counterpart is present.
+pub fn evaluate(
+    req: &Request,
+    etag: Option<&str>,
+    last_modified: Option<SystemTime>,
+) -> Precondition {
+    // HTTP-dates have whole seconds
+    let last_modified = last_modified.map(|t| {
+        let secs = t.duration_since(UNIX_EPOCH).unwrap_or_default().as_secs();
+        UNIX_EPOCH + Duration::from_secs(secs)
+    });
+    let get_or_head = req.method() == "GET" || req.method() == "HEAD";
+    if let Some(if_match) = req.header("if-match") {
+        if !list_matches(if_match, etag, true) {
+            return Precondition::Failed;
+        }
+    } else if let Some(since) = req.header("if-unmodified-since").and_then(date::parse) {
+        if last_modified.map_or(false, |modified| modified > since) {
+            return Precondition::Failed;
+        }
+    }
+    if let Some(if_none_match) = req.header("if-none-match") {
+        if list_matches(if_none_match, etag, false) {
+            return if get_or_head {
+                Precondition::NotModified
+            } else {
+                Precondition::Failed
+            };
+        }
+    } else if let Some(since) = req.header("if-modified-since").and_then(date::parse) {
+        if get_or_head && last_modified.map_or(false, |modified| modified <= since) {
+            return Precondition::NotModified;
+        }
+    }
+    Precondition::Proceed
+}
+
+// the 304 standing in for `res`, without a body
+pub fn not_modified(res: &Response) -> Response {
+    let mut not_modified = Response::with_status_code(StatusCode::NotModified);
+    for (name, value) in res.headers() {
+        if NOT_MODIFIED_HEADERS.contains(&&*name.to_lowercase()) {
+            not_modified.set_header(name, value.clone());
+        }
+    }
+    not_modified
+}
+
+pub fn precondition_failed() -> Response {
+    Response::with_status_code(StatusCode::PreconditionFailed)
+}
+
+// answers conditional requests for 200 responses against their ETag and Last-Modified: with
+// 304 when they are unchanged and 412 when a precondition fails. responses without an ETag
+// get a strong one hashed from their body unless `generate(false)`; streaming bodies are
+// passed through.
+#[derive(Clone, Debug)]
+pub struct ETags {
+    generate: bool,
+}
+
+impl Default for ETags {
+    fn default() -> ETags {
+        ETags { generate: true }
+    }
+}
+
+impl ETags {
+    pub fn new() -> ETags {
+        ETags::default()
+    }
+
+    pub fn generate(&mut self, generate: bool) -> &mut Self {
+        self.generate = generate;
+        self
+    }
+
+    pub fn wrap<T>(&self, app: T) -> Conditional
+    where
+        T: HttpApp + 'static,
+        T::Output: 'static,
+    {
+        Conditional {
+            config: Rc::new(self.clone()),
+            next: BoxedApp::new(app),
+        }
+    }
+}
+
+// see `ETags::wrap`
+pub struct Conditional {
+    config: Rc<ETags>,
+    next: BoxedApp,
+}
+
+impl HttpApp for Conditional {
+    type Output = LocalBoxFuture<'static, Response>;
+
+    fn app(&self, req: Request) -> Self::Output {
+        // only what `evaluate` looks at is kept
+        let mut head = Request::empty();
+        head.method = req.method().to_owned();
+        for &name in &[
+            "if-match",
+            "if-none-match",
+            "if-modified-since",
+            "if-unmodified-since",
+        ] {
+            if let Some(value) = req.header(name) {
+                head.set_header(name, value.to_owned());
+            }
+        }
+        let res = self.next.app(req);
+        let config = Rc::clone(&self.config);
+        async move {
+            let mut res = res.await;
+            if res.status_code() != StatusCode::Ok || res.is_streaming() {
+                return res;
+            }
+            if config.generate && res.header("etag").is_none() {
+                let etag = strong_etag(res.body());
+                res.set_header("ETag", etag);
+            }
+            let last_modified = res.header("last-modified").and_then(date::parse);
+            match evaluate(&head, res.header("etag"), last_modified) {
+                Precondition::Proceed => res,
+                Precondition::NotModified => not_modified(&res),
+                Precondition::Failed => precondition_failed(),
+            }
+        }
+        .boxed_local()
+    }
+
+    fn check(&self, problems: &mut Vec<String>) {
+        self.next.check(problems);
+    }
+}
@@ -0,0 +1,124 @@
+use std::time::Duration;
+
+// the name/value pairs of a `Cookie` request header, in order
+pub fn parse(header: &str) -> Vec<(String, String)> {
+    header
+        .split(';')
+        .filter_map(|pair| {
+            let mut kv = pair.splitn(2, '=');
+            let name = kv.next()?.trim();
+            let value = kv.next()?.trim().trim_matches('"');
+            if name.is_empty() {
+                None
+            } else {
+                Some((name.to_owned(), value.to_owned()))
+            }
+        })
+        .collect()
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SameSite {
+    Strict,
+    Lax,
+    None,
+}
+
+// a `Set-Cookie` value; see `Response::add_cookie`
+#[derive(Clone, Debug)]
+pub struct Cookie {
+    name: String,
+    value: String,
+    path: Option<String>,
+    domain: Option<String>,
+    max_age: Option<Duration>,
+    secure: bool,
+    http_only: bool,
+    same_site: Option<SameSite>,
+}
+
+impl Cookie {
+    pub fn new(name: &str, value: &str) -> Cookie {
+        Cookie {
+            name: name.to_owned(),
+            value: value.to_owned(),
+            path: None,
+            domain: None,
+            max_age: None,
+            secure: false,
+            http_only: false,
+            same_site: None,
+        }
+    }
+
+    // tells the client to drop the cookie
+    pub fn removal(name: &str) -> Cookie {
+        let mut cookie = Cookie::new(name, "");
+        cookie.max_age(Duration::from_secs(0));
+        cookie
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn value(&self) -> &str {
+        &self.value
+    }
+
+    pub fn path(&mut self, path: &str) -> &mut Self {
+        self.path = Some(path.to_owned());
+        self
+    }
+
+    pub fn domain(&mut self, domain: &str) -> &mut Self {
+        self.domain = Some(domain.to_owned());
+        self
+    }
+
+    pub fn max_age(&mut self, max_age: Duration) -> &mut Self {
+        self.max_age = Some(max_age);
+        self
+    }
+
+    pub fn secure(&mut self, secure: bool) -> &mut Self {
+        self.secure = secure;
+        self
+    }
+
+    pub fn http_only(&mut self, http_only: bool) -> &mut Self {
+        self.http_only = http_only;
+        self
+    }
+
+    pub fn same_site(&mut self, same_site: SameSite) -> &mut Self {
+        self.same_site = Some(same_site);
+        self
+    }
+
+    pub fn to_header_value(&self) -> String {
+        let mut out = format!("{}={}", self.name, self.value);
+        if let Some(path) = &self.path {
+            out.push_str(&format!("; Path={}", path));
+        }
+        if let Some(domain) = &self.domain {
+            out.push_str(&format!("; Domain={}", domain));
+        }
+        if let Some(max_age) = self.max_age {
+            out.push_str(&format!("; Max-Age={}", max_age.as_secs()));
+        }
+        if self.secure {
+            out.push_str("; Secure");
+        }
+        if self.http_only {
+            out.push_str("; HttpOnly");
+        }
+        match self.same_site {
+            Some(SameSite::Strict) => out.push_str("; SameSite=Strict"),
+            Some(SameSite::Lax) => out.push_str("; SameSite=Lax"),
+            Some(SameSite::None) => out.push_str("; SameSite=None"),
+            None => {}
+        }
+        out
+    }
+}
@@ -0,0 +1,161 @@
+// HTTP-dates (RFC 7231 7.1.1.1): IMF-fixdate is sent, the obsolete RFC 850 and asctime
+// formats are accepted too
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+const DAYS: [&str; 7] = ["Thu", "Fri", "Sat", "Sun", "Mon", "Tue", "Wed"];
+const MONTHS: [&str; 12] = [
+    "Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec",
+];
+
+// "Sun, 06 Nov 1994 08:49:37 GMT". times before 1970 are clamped to the epoch.
+pub fn format(time: SystemTime) -> String {
+    let secs = time
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs();
+    let days = secs / 86400;
+    let (year, month, day) = civil_from_days(days as i64);
+    let secs = secs % 86400;
+    format!(
+        "{}, {:02} {} {} {:02}:{:02}:{:02} GMT",
+        DAYS[(days % 7) as usize],
+        day,
+        MONTHS[month as usize - 1],
+        year,
+        secs / 3600,
+        secs / 60 % 60,
+        secs % 60
+    )
+}
+
+// "10/Oct/2000:13:55:36 +0000", as in Common Log Format lines
+pub fn common_log(time: SystemTime) -> String {
+    let secs = time
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs();
+    let (year, month, day) = civil_from_days((secs / 86400) as i64);
+    let secs = secs % 86400;
+    format!(
+        "{:02}/{}/{}:{:02}:{:02}:{:02} +0000",
+        day,
+        MONTHS[month as usize - 1],
+        year,
+        secs / 3600,
+        secs / 60 % 60,
+        secs % 60
+    )
+}
+
+pub fn now() -> String {
+    format(SystemTime::now())
+}
+
+// any of:
+//   Sun, 06 Nov 1994 08:49:37 GMT   (IMF-fixdate)
+//   Sunday, 06-Nov-94 08:49:37 GMT  (RFC 850)
+//   Sun Nov  6 08:49:37 1994        (asctime)
+// the weekday isn't checked against the date
+pub fn parse(s: &str) -> Option<SystemTime> {
+    let s = s.trim();
+    let (year, month, day, time) = match s.find(',') {
+        Some(comma) => {
+            let fields: Vec<&str> = s[comma + 1..].split_whitespace().collect();
+            match *fields {
+                [day, month, year, time, "GMT"] if year.len() == 4 => {
+                    (year.parse().ok()?, month_number(month)?, day, time)
+                }
+                [date, time, "GMT"] => {
+                    let parts: Vec<&str> = date.split('-').collect();
+                    match *parts {
+                        // two-digit years are taken to be within 50 years from now
+                        [day, month, year] if year.len() == 2 => {
+                            let year = two_digit_year(year.parse().ok()?);
+                            (year, month_number(month)?, day, time)
+                        }
+                        _ => return None,
+                    }
+                }
+                _ => return None,
+            }
+        }
+        None => {
+            let fields: Vec<&str> = s.split_whitespace().collect();
+            match *fields {
+                [_, month, day, time, year] if year.len() == 4 => {
+                    (year.parse().ok()?, month_number(month)?, day, time)
+                }
+                _ => return None,
+            }
+        }
+    };
+    let day = day.parse::<u32>().ok().filter(|_| day.len() <= 2)?;
+    let hms: Vec<u64> = time
+        .split(':')
+        .map(|n| if n.len() == 2 { n.parse().ok() } else { None })
+        .collect::<Option<_>>()?;
+    // 60 for leap seconds
+    if hms.len() != 3 || hms[0] > 23 || hms[1] > 59 || hms[2] > 60 {
+        return None;
+    }
+    if year < 1970 || day < 1 || day > days_in_month(year, month) {
+        return None;
+    }
+    let days = days_from_civil(year, month, day) as u64;
+    let secs = days * 86400 + hms[0] * 3600 + hms[1] * 60 + hms[2];
+    Some(UNIX_EPOCH + Duration::from_secs(secs))
+}
+
+fn month_number(name: &str) -> Option<u32> {
+    MONTHS.iter().position(|m| *m == name).map(|i| i as u32 + 1)
+}
+
+fn two_digit_year(yy: i64) -> i64 {
+    let this_year = civil_from_days(
+        (SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs()
+            / 86400) as i64,
+    )
+    .0;
+    let mut year = this_year - this_year % 100 + yy;
+    if year > this_year + 50 {
+        year -= 100;
+    }
+    year
+}
+
+fn days_in_month(year: i64, month: u32) -> u32 {
+    match month {
+        2 if year % 4 == 0 && (year % 100 != 0 || year % 400 == 0) => 29,
+        2 => 28,
+        4 | 6 | 9 | 11 => 30,
+        _ => 31,
+    }
+}
+
+// days since 1970-01-01 of a proleptic Gregorian date, and back
+// (http://howardhinnant.github.io/date_algorithms.html)
+fn days_from_civil(year: i64, month: u32, day: u32) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = if year >= 0 { year } else { year - 399 } / 400;
+    let yoe = year - era * 400;
+    let month = month as i64;
+    let doy = (153 * (if month > 2 { month - 3 } else { month + 9 }) + 2) / 5 + day as i64 - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    era * 146_097 + doe - 719_468
+}
+
+fn civil_from_days(days: i64) -> (i64, u32, u32) {
+    let z = days + 719_468;
+    let era = if z >= 0 { z } else { z - 146_096 } / 146_097;
+    let doe = z - era * 146_097;
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = (doy - (153 * mp + 2) / 5 + 1) as u32;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 } as u32;
+    let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+    (year, month, day)
+}
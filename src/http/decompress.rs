@@ -0,0 +1,161 @@
+use super::memory::Exceeded;
+use super::middleware::BoxedApp;
+use super::{HttpApp, Request, Response, StatusCode};
+use flate2::read::{GzDecoder, ZlibDecoder};
+use futures::future::{self, Either, LocalBoxFuture};
+use log::*;
+use std::{
+    io::{self, Read},
+    rc::Rc,
+};
+
+// inflates request bodies sent with `Content-Encoding: gzip` or `deflate` before the app sees
+// them. the inflated body may be at most `max_len` bytes and `max_ratio` times the size it
+// was sent with; bodies growing past either are answered with 413 so a small compressed
+// upload can't expand into gigabytes. the inflated body is charged to the server's memory
+// budget too, answering 413 or 503 as the server does for bodies read off the wire (see
+// `HttpServer::request_memory_limit`). unknown codings get 415, corrupt data 400.
+#[derive(Clone, Debug)]
+pub struct Decompression {
+    max_len: usize,
+    max_ratio: usize,
+}
+
+impl Default for Decompression {
+    fn default() -> Decompression {
+        Decompression {
+            max_len: 16 * 1024 * 1024,
+            max_ratio: 100,
+        }
+    }
+}
+
+enum Rejected {
+    Unsupported(String),
+    TooLarge,
+    Memory(Exceeded),
+    Corrupt(io::Error),
+}
+
+impl Decompression {
+    pub fn new() -> Decompression {
+        Decompression::default()
+    }
+
+    pub fn max_len(&mut self, bytes: usize) -> &mut Self {
+        self.max_len = bytes;
+        self
+    }
+
+    // 0 turns the ratio check off, leaving only `max_len`
+    pub fn max_ratio(&mut self, ratio: usize) -> &mut Self {
+        self.max_ratio = ratio;
+        self
+    }
+
+    pub fn wrap<T>(&self, app: T) -> Decompressed
+    where
+        T: HttpApp + 'static,
+        T::Output: 'static,
+    {
+        Decompressed {
+            config: Rc::new(self.clone()),
+            next: BoxedApp::new(app),
+        }
+    }
+
+    fn limit(&self, compressed_len: usize) -> usize {
+        match self.max_ratio {
+            0 => self.max_len,
+            ratio => self
+                .max_len
+                .min(compressed_len.saturating_mul(ratio).max(1024)),
+        }
+    }
+
+    fn decompress(&self, req: &mut Request) -> Result<(), Rejected> {
+        let coding = match req.header("content-encoding") {
+            Some(coding) => coding.trim().to_lowercase(),
+            None => return Ok(()),
+        };
+        let limit = self.limit(req.body().len());
+        // the compressed body is held while inflating, so it counts against the headroom
+        let (headroom, exceeded) = req.memory_headroom();
+        let inflated = match &*coding {
+            "identity" => {
+                req.remove_header("content-encoding");
+                return Ok(());
+            }
+            "gzip" | "x-gzip" => inflate(GzDecoder::new(req.body()), limit.min(headroom)),
+            "deflate" => inflate(ZlibDecoder::new(req.body()), limit.min(headroom)),
+            _ => return Err(Rejected::Unsupported(coding)),
+        };
+        let body = match inflated {
+            Err(Rejected::TooLarge) if headroom < limit => return Err(Rejected::Memory(exceeded)),
+            inflated => inflated?,
+        };
+        debug!("{}: {} -> {} bytes", coding, req.body().len(), body.len());
+        req.set_header("content-length", body.len().to_string());
+        req.replace_body(body).map_err(Rejected::Memory)?;
+        req.remove_header("content-encoding");
+        Ok(())
+    }
+}
+
+// reads one byte past `limit` to tell a body of exactly `limit` bytes from a longer one
+fn inflate<R: Read>(decoder: R, limit: usize) -> Result<Vec<u8>, Rejected> {
+    let mut body = Vec::new();
+    decoder
+        .take(limit as u64 + 1)
+        .read_to_end(&mut body)
+        .map_err(Rejected::Corrupt)?;
+    if body.len() > limit {
+        Err(Rejected::TooLarge)
+    } else {
+        Ok(body)
+    }
+}
+
+// see `Decompression::wrap`
+pub struct Decompressed {
+    config: Rc<Decompression>,
+    next: BoxedApp,
+}
+
+impl HttpApp for Decompressed {
+    type Output = Either<future::Ready<Response>, LocalBoxFuture<'static, Response>>;
+
+    fn app(&self, mut req: Request) -> Self::Output {
+        let status = match self.config.decompress(&mut req) {
+            Ok(()) => return Either::Right(self.next.app(req)),
+            Err(Rejected::Unsupported(coding)) => {
+                debug!("unsupported request Content-Encoding {:?}", coding);
+                StatusCode::UnsupportedMediaType
+            }
+            Err(Rejected::TooLarge) => {
+                debug!("decompressed request body over the limit");
+                StatusCode::PayloadTooLarge
+            }
+            Err(Rejected::Memory(exceeded)) => {
+                debug!(
+                    "decompressed request body over the {:?} memory budget",
+                    exceeded
+                );
+                exceeded.status_code()
+            }
+            Err(Rejected::Corrupt(e)) => {
+                debug!("bad compressed request body: {}", e);
+                StatusCode::BadRequest
+            }
+        };
+        let mut res = Response::with_status_code(status);
+        if let StatusCode::UnsupportedMediaType = status {
+            res.set_header("Accept-Encoding", "gzip, deflate".to_owned());
+        }
+        Either::Left(future::ready(res))
+    }
+
+    fn check(&self, problems: &mut Vec<String>) {
+        self.next.check(problems);
+    }
+}
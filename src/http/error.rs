@@ -0,0 +1,139 @@
+use super::{Response, StatusCode};
+use log::*;
+use std::{
+    any::Any,
+    future::Future,
+    io,
+    panic::{self, AssertUnwindSafe},
+    pin::Pin,
+    rc::Rc,
+    task::{Context, Poll},
+    thread,
+};
+
+// what went wrong, as handed to `HttpServer::on_error`
+#[derive(Debug)]
+pub enum HttpError {
+    // a request head that couldn't be parsed or was refused, and why
+    BadRequest(&'static str),
+    // the server or the app answered with this error status and an empty body
+    Status(StatusCode),
+    // the app panicked while handling a request, with the panic message
+    Panic(String),
+    // reading or writing a connection failed; there's no one left to answer
+    Io(io::Error),
+}
+
+impl HttpError {
+    // the status the client gets; None for I/O errors
+    pub fn status_code(&self) -> Option<StatusCode> {
+        match self {
+            HttpError::BadRequest(_) => Some(StatusCode::BadRequest),
+            HttpError::Status(status) => Some(*status),
+            HttpError::Panic(_) => Some(StatusCode::InternalServerError),
+            HttpError::Io(_) => None,
+        }
+    }
+}
+
+// returns the response to send instead of the built-in one, or None to keep it
+pub type ErrorHook = Rc<dyn Fn(&HttpError) -> Option<Response>>;
+
+// the response for `error`, from the hook if it renders one
+pub(crate) fn render(hook: Option<&ErrorHook>, error: HttpError) -> Response {
+    let default = Response::with_status_code(
+        error
+            .status_code()
+            .unwrap_or(StatusCode::InternalServerError),
+    );
+    match hook.and_then(|hook| hook(&error)) {
+        Some(res) => res,
+        None => default,
+    }
+}
+
+// an error response without a body is given one by the hook. headers the hook's response
+// doesn't set, such as `Allow` on a 405, are kept.
+pub(crate) fn render_status(hook: Option<&ErrorHook>, res: &mut Response) {
+    let hook = match hook {
+        Some(hook) => hook,
+        None => return,
+    };
+    if (res.status_code as u16) < 400 || res.body_len() > 0 || res.is_streaming() {
+        return;
+    }
+    if let Some(mut rendered) = hook(&HttpError::Status(res.status_code)) {
+        for (key, value) in res.headers.drain() {
+            if rendered.header(&key).is_none() {
+                rendered.insert_header(&key, value);
+            }
+        }
+        *res = rendered;
+    }
+}
+
+pub(crate) fn report(hook: Option<&ErrorHook>, error: HttpError) {
+    if let Some(hook) = hook {
+        let _ = hook(&error);
+    }
+}
+
+fn panic_message(payload: &(dyn Any + Send)) -> String {
+    match payload.downcast_ref::<&str>() {
+        Some(s) => (*s).to_owned(),
+        None => payload
+            .downcast_ref::<String>()
+            .cloned()
+            .unwrap_or_else(|| "Box<Any>".to_owned()),
+    }
+}
+
+// a handler's response future; a panic while it is polled (or while it was created) becomes
+// a 500, and error responses are rendered by the hook
+pub(crate) struct Guarded<F> {
+    fut: Option<F>,
+    panic: Option<String>,
+    hook: Option<ErrorHook>,
+}
+
+impl<F> Guarded<F> {
+    pub(crate) fn new(fut: thread::Result<F>, hook: Option<ErrorHook>) -> Guarded<F> {
+        let (fut, panic) = match fut {
+            Ok(fut) => (Some(fut), None),
+            Err(payload) => (None, Some(panic_message(&*payload))),
+        };
+        Guarded { fut, panic, hook }
+    }
+
+    fn panicked(&self, message: String) -> Response {
+        error!("request handler panicked: {}", message);
+        render(self.hook.as_ref(), HttpError::Panic(message))
+    }
+}
+
+impl<F: Future<Output = Response>> Future for Guarded<F> {
+    type Output = Response;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Response> {
+        // `fut` is structurally pinned: it is only ever dropped in place
+        let this = unsafe { self.get_unchecked_mut() };
+        if let Some(message) = this.panic.take() {
+            return Poll::Ready(this.panicked(message));
+        }
+        let fut = match &mut this.fut {
+            Some(fut) => unsafe { Pin::new_unchecked(fut) },
+            None => panic!("Guarded polled after completion"),
+        };
+        let mut res = match panic::catch_unwind(AssertUnwindSafe(|| fut.poll(cx))) {
+            Ok(Poll::Pending) => return Poll::Pending,
+            Ok(Poll::Ready(res)) => res,
+            Err(payload) => {
+                this.fut = None;
+                return Poll::Ready(this.panicked(panic_message(&*payload)));
+            }
+        };
+        this.fut = None;
+        render_status(this.hook.as_ref(), &mut res);
+        Poll::Ready(res)
+    }
+}
@@ -0,0 +1,310 @@
+use super::{form, Request, Response, StatusCode};
+use serde::de::{
+    self, value::Error, value::MapDeserializer, value::SeqDeserializer, DeserializeOwned,
+    IntoDeserializer, Unexpected, Visitor,
+};
+use std::fmt;
+use std::ops::Deref;
+
+// the query string deserialized into `T`, usually a struct with a field per parameter.
+// fields are parsed from text as their type needs; a parameter given several times fills a
+// `Vec` field, and `Option` fields may be missing.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Query<T>(pub T);
+
+// the router's captured path parameters deserialized into `T`: a struct or map by name, or
+// a bare value when the route captures exactly one parameter
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Path<T>(pub T);
+
+impl<T: DeserializeOwned> Query<T> {
+    pub fn from_request(req: &Request) -> Result<Query<T>, ExtractError> {
+        let query = req.uri().splitn(2, '?').nth(1).unwrap_or("");
+        let query = query.split('#').next().unwrap_or("");
+        let fields = form::parse(query.as_bytes())
+            .map_err(|e| ExtractError::new(Origin::Query, e.to_string()))?;
+        T::deserialize(Fields::new(fields.into_vec()))
+            .map(Query)
+            .map_err(|e| ExtractError::new(Origin::Query, e.to_string()))
+    }
+}
+
+impl<T: DeserializeOwned> Path<T> {
+    pub fn from_request(req: &Request) -> Result<Path<T>, ExtractError> {
+        let fields = req
+            .params()
+            .iter()
+            .map(|(name, value)| (name.clone(), value.clone()))
+            .collect();
+        T::deserialize(Fields::new(fields))
+            .map(Path)
+            .map_err(|e| ExtractError::new(Origin::Path, e.to_string()))
+    }
+}
+
+impl<T> Query<T> {
+    pub fn into_inner(self) -> T {
+        self.0
+    }
+}
+
+impl<T> Path<T> {
+    pub fn into_inner(self) -> T {
+        self.0
+    }
+}
+
+impl<T> Deref for Query<T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        &self.0
+    }
+}
+
+impl<T> Deref for Path<T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        &self.0
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Origin {
+    Query,
+    Path,
+}
+
+// the query string or path parameters didn't fit the type asked for; answered with 400
+#[derive(Clone, Debug)]
+pub struct ExtractError {
+    origin: Origin,
+    message: String,
+}
+
+impl ExtractError {
+    fn new(origin: Origin, message: String) -> ExtractError {
+        ExtractError { origin, message }
+    }
+
+    pub fn origin(&self) -> Origin {
+        self.origin
+    }
+
+    pub fn status_code(&self) -> StatusCode {
+        StatusCode::BadRequest
+    }
+
+    // the error response for the client, saying what was wrong
+    pub fn response(&self) -> Response {
+        let mut res = Response::with_status_code(self.status_code());
+        res.set_header("Content-Type", "text/plain; charset=utf-8".to_owned());
+        res.extend(format!("{}\n", self).bytes());
+        res
+    }
+}
+
+impl fmt::Display for ExtractError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.origin {
+            Origin::Query => write!(f, "bad query string: {}", self.message),
+            Origin::Path => write!(f, "bad path parameters: {}", self.message),
+        }
+    }
+}
+
+impl std::error::Error for ExtractError {}
+
+impl From<ExtractError> for Response {
+    fn from(e: ExtractError) -> Response {
+        e.response()
+    }
+}
+
+// name/value pairs as a map; repeated names are gathered, in order
+struct Fields(Vec<(String, Value)>);
+
+impl Fields {
+    fn new(pairs: Vec<(String, String)>) -> Fields {
+        let mut fields: Vec<(String, Value)> = Vec::new();
+        for (name, value) in pairs {
+            match fields.iter_mut().find(|(n, _)| *n == name) {
+                Some((_, values)) => values.0.push(value),
+                None => fields.push((name, Value(vec![value]))),
+            }
+        }
+        Fields(fields)
+    }
+
+    // a bare value stands for the only field there is
+    fn single(self) -> Result<Value, Error> {
+        let mut fields = self.0;
+        if fields.len() == 1 {
+            Ok(fields.pop().unwrap().1)
+        } else {
+            Err(de::Error::custom(format!(
+                "expected exactly one value, found {}",
+                fields.len()
+            )))
+        }
+    }
+}
+
+macro_rules! forward_to_single {
+    ($($method:ident)*) => {
+        $(
+            fn $method<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+                self.single()?.$method(visitor)
+            }
+        )*
+    };
+}
+
+impl<'de> de::Deserializer<'de> for Fields {
+    type Error = Error;
+
+    fn deserialize_any<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+        visitor.visit_map(MapDeserializer::new(self.0.into_iter()))
+    }
+
+    fn deserialize_newtype_struct<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        visitor: V,
+    ) -> Result<V::Value, Error> {
+        visitor.visit_newtype_struct(self)
+    }
+
+    fn deserialize_enum<V: Visitor<'de>>(
+        self,
+        name: &'static str,
+        variants: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value, Error> {
+        self.single()?.deserialize_enum(name, variants, visitor)
+    }
+
+    forward_to_single! {
+        deserialize_bool deserialize_i8 deserialize_i16 deserialize_i32 deserialize_i64
+        deserialize_u8 deserialize_u16 deserialize_u32 deserialize_u64 deserialize_f32
+        deserialize_f64 deserialize_char deserialize_str deserialize_string deserialize_bytes
+        deserialize_byte_buf
+    }
+
+    serde::forward_to_deserialize_any! {
+        option unit unit_struct seq tuple tuple_struct map struct identifier ignored_any
+        i128 u128
+    }
+}
+
+// the values given for one name, text to be parsed as the field's type needs
+struct Value(Vec<String>);
+
+impl Value {
+    // when one value is wanted, the last one given wins
+    fn last(mut self) -> String {
+        self.0.pop().unwrap_or_default()
+    }
+}
+
+impl<'de> IntoDeserializer<'de, Error> for Value {
+    type Deserializer = Value;
+
+    fn into_deserializer(self) -> Value {
+        self
+    }
+}
+
+macro_rules! parse_value {
+    ($($method:ident => $visit:ident,)*) => {
+        $(
+            fn $method<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+                let s = self.last();
+                match s.trim().parse() {
+                    Ok(v) => visitor.$visit(v),
+                    Err(_) => Err(de::Error::invalid_value(Unexpected::Str(&s), &visitor)),
+                }
+            }
+        )*
+    };
+}
+
+impl<'de> de::Deserializer<'de> for Value {
+    type Error = Error;
+
+    fn deserialize_any<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+        visitor.visit_string(self.last())
+    }
+
+    parse_value! {
+        deserialize_bool => visit_bool,
+        deserialize_i8 => visit_i8,
+        deserialize_i16 => visit_i16,
+        deserialize_i32 => visit_i32,
+        deserialize_i64 => visit_i64,
+        deserialize_u8 => visit_u8,
+        deserialize_u16 => visit_u16,
+        deserialize_u32 => visit_u32,
+        deserialize_u64 => visit_u64,
+        deserialize_f32 => visit_f32,
+        deserialize_f64 => visit_f64,
+        deserialize_char => visit_char,
+    }
+
+    fn deserialize_option<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+        visitor.visit_some(self)
+    }
+
+    fn deserialize_unit<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+        visitor.visit_unit()
+    }
+
+    fn deserialize_newtype_struct<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        visitor: V,
+    ) -> Result<V::Value, Error> {
+        visitor.visit_newtype_struct(self)
+    }
+
+    fn deserialize_seq<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+        let values = self.0.into_iter().map(|v| Value(vec![v]));
+        visitor.visit_seq(SeqDeserializer::new(values))
+    }
+
+    fn deserialize_tuple<V: Visitor<'de>>(
+        self,
+        _len: usize,
+        visitor: V,
+    ) -> Result<V::Value, Error> {
+        self.deserialize_seq(visitor)
+    }
+
+    fn deserialize_tuple_struct<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        _len: usize,
+        visitor: V,
+    ) -> Result<V::Value, Error> {
+        self.deserialize_seq(visitor)
+    }
+
+    // unit variants only, by name
+    fn deserialize_enum<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        _variants: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value, Error> {
+        visitor.visit_enum(self.last().into_deserializer())
+    }
+
+    fn deserialize_ignored_any<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+        visitor.visit_unit()
+    }
+
+    serde::forward_to_deserialize_any! {
+        i128 u128 str string bytes byte_buf unit_struct map struct identifier
+    }
+}
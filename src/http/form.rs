@@ -0,0 +1,114 @@
+use super::{Request, StatusCode};
+use std::fmt;
+
+// the fields of an application/x-www-form-urlencoded body, in order; names may repeat
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Form {
+    fields: Vec<(String, String)>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FormError {
+    // the body isn't application/x-www-form-urlencoded
+    ContentType,
+    // bad percent-encoding, or not UTF-8 once decoded
+    Malformed,
+}
+
+impl FormError {
+    pub fn status_code(self) -> StatusCode {
+        match self {
+            FormError::ContentType => StatusCode::UnsupportedMediaType,
+            FormError::Malformed => StatusCode::BadRequest,
+        }
+    }
+}
+
+impl fmt::Display for FormError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            FormError::ContentType => f.write_str("not a form-urlencoded body"),
+            FormError::Malformed => f.write_str("malformed form-urlencoded body"),
+        }
+    }
+}
+
+impl std::error::Error for FormError {}
+
+impl Form {
+    // the first value of `name`
+    pub fn get(&self, name: &str) -> Option<&str> {
+        self.fields
+            .iter()
+            .find(|(n, _)| n == name)
+            .map(|(_, v)| &**v)
+    }
+
+    pub fn get_all<'a>(&'a self, name: &'a str) -> impl Iterator<Item = &'a str> + 'a {
+        self.fields
+            .iter()
+            .filter(move |(n, _)| n == name)
+            .map(|(_, v)| &**v)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.fields.iter().map(|(n, v)| (&**n, &**v))
+    }
+
+    pub fn len(&self) -> usize {
+        self.fields.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.fields.is_empty()
+    }
+
+    pub fn into_vec(self) -> Vec<(String, String)> {
+        self.fields
+    }
+}
+
+pub fn from_request(req: &Request) -> Result<Form, FormError> {
+    let content_type = req.header("content-type").unwrap_or("");
+    let media_type = content_type.split(';').next().unwrap_or("").trim();
+    if !media_type.eq_ignore_ascii_case("application/x-www-form-urlencoded") {
+        return Err(FormError::ContentType);
+    }
+    parse(req.body())
+}
+
+// `a=1&b=x+y&c=%C3%A9` -> [("a", "1"), ("b", "x y"), ("c", "é")]. a field without `=` has
+// an empty value; empty fields are skipped.
+pub fn parse(body: &[u8]) -> Result<Form, FormError> {
+    let mut fields = Vec::new();
+    for field in body.split(|&b| b == b'&').filter(|f| !f.is_empty()) {
+        let (name, value) = match field.iter().position(|&b| b == b'=') {
+            Some(eq) => (&field[..eq], &field[eq + 1..]),
+            None => (field, &[][..]),
+        };
+        fields.push((decode(name)?, decode(value)?));
+    }
+    Ok(Form { fields })
+}
+
+fn decode(s: &[u8]) -> Result<String, FormError> {
+    let mut out = Vec::with_capacity(s.len());
+    let mut i = 0;
+    while i < s.len() {
+        match s[i] {
+            b'+' => out.push(b' '),
+            b'%' => {
+                let hex = s.get(i + 1..i + 3).ok_or(FormError::Malformed)?;
+                if !hex.iter().all(u8::is_ascii_hexdigit) {
+                    return Err(FormError::Malformed);
+                }
+                let hex = std::str::from_utf8(hex).unwrap();
+                out.push(u8::from_str_radix(hex, 16).unwrap());
+                i += 2;
+            }
+            b => out.push(b),
+        }
+        i += 1;
+    }
+    String::from_utf8(out).map_err(|_| FormError::Malformed)
+}
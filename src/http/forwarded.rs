@@ -0,0 +1,72 @@
+// who sent a request that came through proxies. each proxy adds the address it got the
+// request from to X-Forwarded-For, or a `for=` element to Forwarded (RFC 7239), so the peer
+// is the last proxy and the client is somewhere in the list. entries are only as good as
+// whoever added them, anyone can make up the first ones, so the list is walked back from
+// the peer while the addresses are those of trusted proxies (`HttpServer::trusted_proxies`);
+// the first one that isn't is the client.
+use super::ip_filter::Cidr;
+use super::Request;
+use std::net::{IpAddr, SocketAddr};
+
+// None when the peer isn't a trusted proxy or the headers don't name anyone past it
+pub(super) fn client_addr(req: &Request, trusted: &[Cidr]) -> Option<IpAddr> {
+    let is_trusted = |ip: IpAddr| trusted.iter().any(|net| net.contains(ip));
+    let peer = req.peer_addr()?.ip();
+    if !is_trusted(peer) {
+        return None;
+    }
+    // Forwarded replaces X-Forwarded-For where both are sent
+    let hops: Vec<Option<IpAddr>> = match req.header("forwarded") {
+        Some(forwarded) => forwarded.split(',').map(forwarded_for).collect(),
+        None => req
+            .header("x-forwarded-for")?
+            .split(',')
+            .map(|hop| parse_addr(hop.trim()))
+            .collect(),
+    };
+    let mut client = None;
+    for hop in hops.iter().rev() {
+        match hop {
+            Some(ip) => {
+                client = Some(*ip);
+                if !is_trusted(*ip) {
+                    break;
+                }
+            }
+            // `unknown`, obfuscated or garbled: what came before can't be told apart
+            None => break,
+        }
+    }
+    client
+}
+
+// the `for` parameter of one Forwarded element, `for=192.0.2.1;proto=https`
+fn forwarded_for(element: &str) -> Option<IpAddr> {
+    element.split(';').find_map(|pair| {
+        let eq = pair.find('=')?;
+        if !pair[..eq].trim().eq_ignore_ascii_case("for") {
+            return None;
+        }
+        let value = pair[eq + 1..].trim();
+        let value = if value.len() >= 2 && value.starts_with('"') && value.ends_with('"') {
+            &value[1..value.len() - 1]
+        } else {
+            value
+        };
+        parse_addr(value)
+    })
+}
+
+// `192.0.2.1`, `2001:db8::1`, and either with a port: `192.0.2.1:4711`, `[2001:db8::1]:4711`
+fn parse_addr(s: &str) -> Option<IpAddr> {
+    if let Ok(ip) = s.parse() {
+        return Some(ip);
+    }
+    if let Ok(addr) = s.parse::<SocketAddr>() {
+        return Some(addr.ip());
+    }
+    if s.starts_with('[') && s.ends_with(']') {
+        return s[1..s.len() - 1].parse().ok();
+    }
+    None
+}
@@ -0,0 +1,90 @@
+// header names and values as they may go on the wire: a CR or LF in a value would end the
+// header early and let whoever controls it inject headers or a whole response of their own
+use log::*;
+use std::{borrow::Cow, collections::HashMap, fmt};
+
+// RFC 7230 token characters
+pub(crate) fn is_tchar(b: u8) -> bool {
+    b.is_ascii_alphanumeric() || b"!#$%&'*+-.^_`|~".contains(&b)
+}
+
+pub fn valid_name(name: &str) -> bool {
+    !name.is_empty() && name.bytes().all(is_tchar)
+}
+
+// no control characters but horizontal tab
+pub fn valid_value(value: &str) -> bool {
+    value
+        .bytes()
+        .all(|b| b == b'\t' || (b >= 0x20 && b != 0x7f))
+}
+
+// `value` with each control character replaced by a space
+pub fn sanitize_value(value: &str) -> String {
+    value
+        .chars()
+        .map(|c| {
+            if c != '\t' && (c < ' ' || c == '\x7f') {
+                ' '
+            } else {
+                c
+            }
+        })
+        .collect()
+}
+
+// whether a comma-separated list such as Connection's has `token`, in any case
+pub(crate) fn has_token(value: Option<&str>, token: &str) -> bool {
+    value.map_or(false, |value| {
+        value
+            .split(',')
+            .any(|t| t.trim().eq_ignore_ascii_case(token))
+    })
+}
+
+// the fields of `headers` fit for the wire: values with control characters sanitized, fields
+// with invalid names dropped. `set_header` already sees to that, this is for whatever got
+// into a map some other way.
+pub(crate) fn wire_fields(
+    headers: &HashMap<String, String>,
+) -> impl Iterator<Item = (&str, Cow<'_, str>)> {
+    headers.iter().filter_map(|(name, value)| {
+        if !valid_name(name) {
+            warn!("invalid header name {:?} not sent", name);
+            None
+        } else if !valid_value(value) {
+            warn!("control characters in the value of {} replaced", name);
+            Some((&**name, Cow::Owned(sanitize_value(value))))
+        } else {
+            Some((&**name, Cow::Borrowed(&**value)))
+        }
+    })
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum InvalidHeader {
+    Name(String),
+    // the value is left out, it may be large or unprintable
+    Value(String),
+}
+
+impl fmt::Display for InvalidHeader {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            InvalidHeader::Name(name) => write!(f, "invalid header name {:?}", name),
+            InvalidHeader::Value(name) => write!(f, "invalid value for header {}", name),
+        }
+    }
+}
+
+impl std::error::Error for InvalidHeader {}
+
+pub fn validate(name: &str, value: &str) -> Result<(), InvalidHeader> {
+    if !valid_name(name) {
+        Err(InvalidHeader::Name(name.to_owned()))
+    } else if !valid_value(value) {
+        Err(InvalidHeader::Value(name.to_owned()))
+    } else {
+        Ok(())
+    }
+}
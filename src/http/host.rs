@@ -0,0 +1,90 @@
+use super::Request;
+use std::net::Ipv6Addr;
+
+// a `Host` pattern: an exact name, `*.example.com` for any subdomain of example.com, or `*`
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum HostPattern {
+    Any,
+    Exact(String),
+    // the part after `*`, including the leading dot
+    Suffix(String),
+}
+
+impl HostPattern {
+    pub fn new(pattern: &str) -> HostPattern {
+        let pattern = pattern.trim().to_lowercase();
+        if pattern == "*" {
+            HostPattern::Any
+        } else if pattern.starts_with("*.") {
+            HostPattern::Suffix(pattern[1..].to_owned())
+        } else if pattern.parse::<Ipv6Addr>().is_ok() {
+            // hosts come bracketed, as in `Host: [::1]:8080`
+            HostPattern::Exact(format!("[{}]", pattern))
+        } else {
+            HostPattern::Exact(strip_port(&pattern).to_owned())
+        }
+    }
+
+    // `host` is expected without port, as returned by `request_host`
+    pub fn matches(&self, host: &str) -> bool {
+        match self {
+            HostPattern::Any => true,
+            HostPattern::Exact(name) => host.eq_ignore_ascii_case(name),
+            HostPattern::Suffix(suffix) => {
+                host.len() > suffix.len() && host.to_lowercase().ends_with(&**suffix)
+            }
+        }
+    }
+
+    // when several patterns match, the highest rank wins: exact names, then the longest suffix
+    pub fn rank(&self) -> usize {
+        match self {
+            HostPattern::Any => 0,
+            HostPattern::Suffix(suffix) => suffix.len(),
+            HostPattern::Exact(_) => usize::max_value(),
+        }
+    }
+}
+
+impl From<&str> for HostPattern {
+    fn from(pattern: &str) -> HostPattern {
+        HostPattern::new(pattern)
+    }
+}
+
+// "example.com:8080" -> "example.com", "[::1]:80" -> "[::1]". a bare IPv6 address such as
+// "::1" has more than one colon and no port to strip.
+pub fn strip_port(host: &str) -> &str {
+    if host.starts_with('[') {
+        match host.find(']') {
+            Some(end) => &host[..=end],
+            None => host,
+        }
+    } else {
+        match host.rfind(':') {
+            Some(colon) if host.find(':') == Some(colon) => &host[..colon],
+            _ => host,
+        }
+    }
+}
+
+// the host a request is addressed to, lowercased and without port: the authority of an
+// absolute-form target (which overrides the Host header), otherwise the Host header
+pub fn request_host(req: &Request) -> Option<String> {
+    let uri = req.uri();
+    let authority = if uri.starts_with("http://") || uri.starts_with("https://") {
+        let rest = &uri[uri.find("//")? + 2..];
+        let end = rest.find(|c| c == '/' || c == '?' || c == '#');
+        let authority = &rest[..end.unwrap_or_else(|| rest.len())];
+        // drop userinfo
+        &authority[authority.rfind('@').map_or(0, |at| at + 1)..]
+    } else {
+        req.header("host")?
+    };
+    let host = strip_port(authority.trim());
+    if host.is_empty() {
+        None
+    } else {
+        Some(host.to_lowercase())
+    }
+}
@@ -0,0 +1,865 @@
+use super::{header, HttpApp, Request, Response, StatusCode, MAX_HEAD_LEN};
+use futures::prelude::*;
+use futures::stream::LocalBoxStream;
+use log::*;
+use std::{
+    collections::HashMap,
+    future::Future,
+    io,
+    pin::Pin,
+    task::{self, Context},
+};
+
+mod hpack;
+mod huffman;
+
+// protocol id a TLS acceptor negotiates through ALPN before handing the stream to `serve`
+pub const ALPN: &[u8] = b"h2";
+pub const PREFACE: &[u8] = b"PRI * HTTP/2.0\r\n\r\nSM\r\n\r\n";
+
+const DATA: u8 = 0x0;
+const HEADERS: u8 = 0x1;
+const PRIORITY: u8 = 0x2;
+const RST_STREAM: u8 = 0x3;
+const SETTINGS: u8 = 0x4;
+const PUSH_PROMISE: u8 = 0x5;
+const PING: u8 = 0x6;
+const GOAWAY: u8 = 0x7;
+const WINDOW_UPDATE: u8 = 0x8;
+const CONTINUATION: u8 = 0x9;
+
+const FLAG_END_STREAM: u8 = 0x1;
+const FLAG_ACK: u8 = 0x1;
+const FLAG_END_HEADERS: u8 = 0x4;
+const FLAG_PADDED: u8 = 0x8;
+const FLAG_PRIORITY: u8 = 0x20;
+
+const NO_ERROR: u32 = 0x0;
+const PROTOCOL_ERROR: u32 = 0x1;
+const FLOW_CONTROL_ERROR: u32 = 0x3;
+const STREAM_CLOSED: u32 = 0x5;
+const FRAME_SIZE_ERROR: u32 = 0x6;
+const REFUSED_STREAM: u32 = 0x7;
+const COMPRESSION_ERROR: u32 = 0x9;
+const ENHANCE_YOUR_CALM: u32 = 0xb;
+
+const SETTINGS_ENABLE_PUSH: u16 = 0x2;
+const SETTINGS_MAX_CONCURRENT_STREAMS: u16 = 0x3;
+const SETTINGS_INITIAL_WINDOW_SIZE: u16 = 0x4;
+const SETTINGS_MAX_FRAME_SIZE: u16 = 0x5;
+
+const DEFAULT_WINDOW: i64 = 65_535;
+const MAX_WINDOW: i64 = (1 << 31) - 1;
+const DEFAULT_MAX_FRAME: usize = 16_384;
+const HEADER_TABLE_SIZE: usize = 4096;
+const MAX_CONCURRENT_STREAMS: usize = 128;
+// streams the peer may reset before their responses, beyond as many as were answered, before
+// the connection is closed: opening and resetting streams at once keeps handlers busy for
+// nothing
+const MAX_RESETS: usize = 2 * MAX_CONCURRENT_STREAMS;
+// stop reading and producing DATA while this much output is still unsent
+const WRITE_HIGH_WATER: usize = 64 * 1024;
+// the body bound when the server sets none, as request bodies are buffered whole before their
+// handler runs
+pub const DEFAULT_MAX_BODY_LEN: usize = 1024 * 1024;
+
+// serves an h2 connection; `prelude` holds bytes already read from `io`
+// (the client preface when the protocol was detected by prior knowledge). streams sending
+// more than `max_body_len` bytes of body, `DEFAULT_MAX_BODY_LEN` if None, get 413 and are
+// reset.
+pub async fn serve<S, T>(
+    io: S,
+    app: &T,
+    prelude: &[u8],
+    max_body_len: Option<usize>,
+) -> io::Result<()>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+    T: HttpApp,
+{
+    let mut conn = Connection::new(io, prelude, max_body_len);
+    conn.process_or_fail(app);
+    future::poll_fn(|cx| conn.poll_run(cx, app)).await
+}
+
+// continues a connection upgraded from HTTP/1.1 (`Upgrade: h2c`) after the 101 response was sent.
+// `req` becomes stream 1 and `settings` is the decoded HTTP2-Settings header.
+pub async fn serve_upgrade<S, T>(
+    io: S,
+    app: &T,
+    req: Request,
+    settings: &[u8],
+    prelude: &[u8],
+    max_body_len: Option<usize>,
+) -> io::Result<()>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+    T: HttpApp,
+{
+    let mut conn = Connection::new(io, prelude, max_body_len);
+    if conn.apply_settings(settings).is_err() {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "invalid HTTP2-Settings",
+        ));
+    }
+    conn.open_stream(1, req);
+    conn.dispatch(app, 1);
+    conn.process_or_fail(app);
+    future::poll_fn(|cx| conn.poll_run(cx, app)).await
+}
+
+// decodes the token68 value of an HTTP2-Settings header (unpadded base64url)
+pub fn decode_settings_header(value: &str) -> Option<Vec<u8>> {
+    super::base64::decode(value)
+}
+
+struct Connection<S, F> {
+    io: S,
+    rbuf: Vec<u8>,
+    wbuf: Vec<u8>,
+    preface_done: bool,
+    decoder: hpack::Decoder,
+    peer: Settings,
+    streams: HashMap<u32, StreamState>,
+    // handlers still running, all polled on every pass as they share the connection's waker
+    tasks: Vec<StreamTask<F>>,
+    send_window: i64,
+    last_stream_id: u32,
+    continuation: Option<HeaderBlock>,
+    // streams reset by the peer before they were answered, and streams answered
+    resets: usize,
+    answered: usize,
+    going_away: bool,
+    eof: bool,
+    needs_flush: bool,
+    max_body_len: usize,
+    // the window each stream starts with for sending us its body
+    stream_recv_window: i64,
+}
+
+struct Settings {
+    initial_window_size: i64,
+    max_frame_size: usize,
+}
+
+struct HeaderBlock {
+    stream_id: u32,
+    end_stream: bool,
+    block: Vec<u8>,
+}
+
+struct StreamState {
+    // the request while its body is still being received
+    req: Option<Request>,
+    head: bool,
+    send_window: i64,
+    recv_window: i64,
+    // response body waiting for flow control window
+    pending: Option<Outgoing>,
+}
+
+struct Outgoing {
+    buf: Vec<u8>,
+    pos: usize,
+    // more of the body still to come from a streaming response
+    stream: Option<LocalBoxStream<'static, Vec<u8>>>,
+}
+
+struct StreamTask<F> {
+    id: u32,
+    fut: Pin<Box<F>>,
+}
+
+impl<F: Future<Output = Response>> Future for StreamTask<F> {
+    type Output = Response;
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context) -> task::Poll<Self::Output> {
+        self.fut.as_mut().poll(cx)
+    }
+}
+
+// frames as received, HPACK-encoded headers included
+#[cfg(feature = "secure-buffers")]
+impl<S, F> Drop for Connection<S, F> {
+    fn drop(&mut self) {
+        use crate::zero::Zero;
+        self.rbuf.zero();
+        if let Some(continuation) = &mut self.continuation {
+            continuation.block.zero();
+        }
+    }
+}
+
+impl<S, F> Connection<S, F>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+    F: Future<Output = Response>,
+{
+    fn new(io: S, prelude: &[u8], max_body_len: Option<usize>) -> Self {
+        let max_body_len = max_body_len.unwrap_or(DEFAULT_MAX_BODY_LEN);
+        let stream_recv_window = recv_allowance(max_body_len, 0);
+        let mut conn = Connection {
+            io,
+            rbuf: prelude.to_vec(),
+            wbuf: Vec::new(),
+            preface_done: false,
+            decoder: hpack::Decoder::new(HEADER_TABLE_SIZE),
+            peer: Settings {
+                initial_window_size: DEFAULT_WINDOW,
+                max_frame_size: DEFAULT_MAX_FRAME,
+            },
+            streams: HashMap::new(),
+            tasks: Vec::new(),
+            send_window: DEFAULT_WINDOW,
+            last_stream_id: 0,
+            continuation: None,
+            resets: 0,
+            answered: 0,
+            going_away: false,
+            eof: false,
+            needs_flush: false,
+            max_body_len,
+            stream_recv_window,
+        };
+        let mut payload = Vec::new();
+        payload.extend_from_slice(&SETTINGS_MAX_CONCURRENT_STREAMS.to_be_bytes());
+        payload.extend_from_slice(&(MAX_CONCURRENT_STREAMS as u32).to_be_bytes());
+        payload.extend_from_slice(&SETTINGS_ENABLE_PUSH.to_be_bytes());
+        payload.extend_from_slice(&0u32.to_be_bytes());
+        payload.extend_from_slice(&SETTINGS_INITIAL_WINDOW_SIZE.to_be_bytes());
+        payload.extend_from_slice(&(stream_recv_window as u32).to_be_bytes());
+        conn.write_frame(SETTINGS, 0, 0, &payload);
+        conn
+    }
+
+    fn poll_run<T: HttpApp<Output = F>>(
+        &mut self,
+        cx: &mut Context,
+        app: &T,
+    ) -> task::Poll<io::Result<()>> {
+        loop {
+            let mut progress = false;
+
+            if !self.eof && self.wbuf.len() < WRITE_HIGH_WATER {
+                let mut buf = [0u8; 8192];
+                match Pin::new(&mut self.io).poll_read(cx, &mut buf) {
+                    task::Poll::Ready(Ok(0)) => {
+                        self.eof = true;
+                        progress = true;
+                    }
+                    task::Poll::Ready(Ok(len)) => {
+                        self.rbuf.extend_from_slice(&buf[..len]);
+                        self.process_or_fail(app);
+                        progress = true;
+                    }
+                    task::Poll::Ready(Err(e)) => return task::Poll::Ready(Err(e)),
+                    task::Poll::Pending => {}
+                }
+            }
+
+            let mut i = 0;
+            while i < self.tasks.len() {
+                if let task::Poll::Ready(res) = Pin::new(&mut self.tasks[i]).poll(cx) {
+                    let id = self.tasks.swap_remove(i).id;
+                    self.respond(id, res);
+                    progress = true;
+                } else {
+                    i += 1;
+                }
+            }
+
+            self.flush_data(cx);
+
+            while !self.wbuf.is_empty() {
+                match Pin::new(&mut self.io).poll_write(cx, &self.wbuf) {
+                    task::Poll::Ready(Ok(0)) => {
+                        return task::Poll::Ready(Err(io::ErrorKind::WriteZero.into()))
+                    }
+                    task::Poll::Ready(Ok(len)) => {
+                        self.wbuf.drain(..len);
+                        self.needs_flush = true;
+                        progress = true;
+                    }
+                    task::Poll::Ready(Err(e)) => return task::Poll::Ready(Err(e)),
+                    task::Poll::Pending => break,
+                }
+            }
+            if self.wbuf.is_empty() && self.needs_flush {
+                match Pin::new(&mut self.io).poll_flush(cx) {
+                    task::Poll::Ready(Ok(())) => self.needs_flush = false,
+                    task::Poll::Ready(Err(e)) => return task::Poll::Ready(Err(e)),
+                    task::Poll::Pending => {}
+                }
+            }
+
+            let idle = self.streams.is_empty() && self.tasks.is_empty();
+            if (self.eof || self.going_away) && idle && self.wbuf.is_empty() && !self.needs_flush {
+                return task::Poll::Ready(Ok(()));
+            }
+            if !progress {
+                return task::Poll::Pending;
+            }
+        }
+    }
+
+    fn process_or_fail<T: HttpApp<Output = F>>(&mut self, app: &T) {
+        if let Err(code) = self.process(app) {
+            debug!("h2 connection error {:#x}", code);
+            self.write_goaway(code);
+            self.eof = true;
+            self.going_away = true;
+            self.streams.clear();
+            self.tasks.clear();
+        }
+    }
+
+    // handles every complete frame in `rbuf`; an error is a connection error code
+    fn process<T: HttpApp<Output = F>>(&mut self, app: &T) -> Result<(), u32> {
+        if !self.preface_done {
+            let len = self.rbuf.len().min(PREFACE.len());
+            if self.rbuf[..len] != PREFACE[..len] {
+                return Err(PROTOCOL_ERROR);
+            }
+            if len < PREFACE.len() {
+                return Ok(());
+            }
+            self.rbuf.drain(..PREFACE.len());
+            self.preface_done = true;
+        }
+        while self.rbuf.len() >= 9 {
+            let len = (self.rbuf[0] as usize) << 16
+                | (self.rbuf[1] as usize) << 8
+                | self.rbuf[2] as usize;
+            if len > DEFAULT_MAX_FRAME {
+                return Err(FRAME_SIZE_ERROR);
+            }
+            if self.rbuf.len() < 9 + len {
+                break;
+            }
+            let typ = self.rbuf[3];
+            let flags = self.rbuf[4];
+            let id = read_u31(&self.rbuf[5..9]);
+            let payload = self.rbuf[9..9 + len].to_vec();
+            self.rbuf.drain(..9 + len);
+            trace!(
+                "h2 frame type {} flags {:#x} stream {} ({} bytes)",
+                typ,
+                flags,
+                id,
+                len
+            );
+            self.frame(app, typ, flags, id, payload)?;
+        }
+        Ok(())
+    }
+
+    fn frame<T: HttpApp<Output = F>>(
+        &mut self,
+        app: &T,
+        typ: u8,
+        flags: u8,
+        id: u32,
+        mut payload: Vec<u8>,
+    ) -> Result<(), u32> {
+        if let Some(ref cont) = self.continuation {
+            if typ != CONTINUATION || id != cont.stream_id {
+                return Err(PROTOCOL_ERROR);
+            }
+        }
+        match typ {
+            DATA => {
+                if id == 0 {
+                    return Err(PROTOCOL_ERROR);
+                }
+                let len = payload.len() as u32;
+                if flags & FLAG_PADDED != 0 {
+                    strip_padding(&mut payload)?;
+                }
+                // the streams' windows bound what is buffered; holding the connection's back
+                // until handlers take their bodies would let half-sent streams starve each other
+                if len > 0 {
+                    self.write_window_update(0, len);
+                }
+                let end_stream = flags & FLAG_END_STREAM != 0;
+                let max_body_len = self.max_body_len;
+                let (too_large, overflowed) = match self.streams.get_mut(&id) {
+                    Some(StreamState {
+                        req: Some(req),
+                        recv_window,
+                        ..
+                    }) => {
+                        *recv_window -= len as i64;
+                        (
+                            req.body.len() + payload.len() > max_body_len,
+                            *recv_window < 0,
+                        )
+                    }
+                    _ => (false, false),
+                };
+                if too_large {
+                    // answered at once; the reset tells the client to stop sending the rest
+                    debug!("h2 stream {}: request body too large", id);
+                    self.respond(id, Response::with_status_code(StatusCode::PayloadTooLarge));
+                    self.write_rst_stream(id, NO_ERROR);
+                    return Ok(());
+                }
+                if overflowed {
+                    debug!("h2 stream {}: sent past its window", id);
+                    self.close_stream(id);
+                    self.write_rst_stream(id, FLOW_CONTROL_ERROR);
+                    return Ok(());
+                }
+                match self.streams.get_mut(&id) {
+                    Some(StreamState {
+                        req: Some(req),
+                        recv_window,
+                        ..
+                    }) => {
+                        req.body.extend_from_slice(&payload);
+                        if end_stream {
+                            self.dispatch(app, id);
+                        } else {
+                            // the handler takes the body whole, so the window only opens for
+                            // what the bound still allows, e.g. the padding of this frame
+                            let allowance = recv_allowance(max_body_len, req.body.len());
+                            if allowance > *recv_window {
+                                let inc = allowance - *recv_window;
+                                *recv_window = allowance;
+                                self.write_window_update(id, inc as u32);
+                            }
+                        }
+                    }
+                    _ if id > self.last_stream_id => return Err(PROTOCOL_ERROR),
+                    _ => self.write_rst_stream(id, STREAM_CLOSED),
+                }
+            }
+            HEADERS => {
+                if id == 0 || id % 2 == 0 {
+                    return Err(PROTOCOL_ERROR);
+                }
+                if flags & FLAG_PADDED != 0 {
+                    strip_padding(&mut payload)?;
+                }
+                if flags & FLAG_PRIORITY != 0 {
+                    if payload.len() < 5 {
+                        return Err(FRAME_SIZE_ERROR);
+                    }
+                    payload.drain(..5);
+                }
+                let block = HeaderBlock {
+                    stream_id: id,
+                    end_stream: flags & FLAG_END_STREAM != 0,
+                    block: payload,
+                };
+                if flags & FLAG_END_HEADERS != 0 {
+                    self.header_block(app, block)?;
+                } else {
+                    self.continuation = Some(block);
+                }
+            }
+            CONTINUATION => {
+                let mut block = self.continuation.take().ok_or(PROTOCOL_ERROR)?;
+                // compressed, the head is smaller than an HTTP/1 one may be
+                if block.block.len() + payload.len() > MAX_HEAD_LEN {
+                    debug!("h2 stream {}: header block too long", id);
+                    return Err(ENHANCE_YOUR_CALM);
+                }
+                block.block.extend_from_slice(&payload);
+                if flags & FLAG_END_HEADERS != 0 {
+                    self.header_block(app, block)?;
+                } else {
+                    self.continuation = Some(block);
+                }
+            }
+            PRIORITY => {}
+            RST_STREAM => {
+                if id == 0 {
+                    return Err(PROTOCOL_ERROR);
+                }
+                if payload.len() != 4 {
+                    return Err(FRAME_SIZE_ERROR);
+                }
+                if self.streams.contains_key(&id) {
+                    self.close_stream(id);
+                    self.resets += 1;
+                    if self.resets > self.answered + MAX_RESETS {
+                        debug!("h2: {} streams reset by the peer", self.resets);
+                        return Err(ENHANCE_YOUR_CALM);
+                    }
+                }
+            }
+            SETTINGS => {
+                if id != 0 {
+                    return Err(PROTOCOL_ERROR);
+                }
+                if flags & FLAG_ACK != 0 {
+                    if !payload.is_empty() {
+                        return Err(FRAME_SIZE_ERROR);
+                    }
+                } else {
+                    self.apply_settings(&payload)?;
+                    self.write_frame(SETTINGS, FLAG_ACK, 0, &[]);
+                }
+            }
+            PUSH_PROMISE => return Err(PROTOCOL_ERROR),
+            PING => {
+                if id != 0 {
+                    return Err(PROTOCOL_ERROR);
+                }
+                if payload.len() != 8 {
+                    return Err(FRAME_SIZE_ERROR);
+                }
+                if flags & FLAG_ACK == 0 {
+                    self.write_frame(PING, FLAG_ACK, 0, &payload);
+                }
+            }
+            GOAWAY => {
+                self.going_away = true;
+            }
+            WINDOW_UPDATE => {
+                if payload.len() != 4 {
+                    return Err(FRAME_SIZE_ERROR);
+                }
+                let inc = read_u31(&payload) as i64;
+                if id == 0 {
+                    if inc == 0 {
+                        return Err(PROTOCOL_ERROR);
+                    }
+                    self.send_window += inc;
+                    if self.send_window > MAX_WINDOW {
+                        return Err(FLOW_CONTROL_ERROR);
+                    }
+                } else if let Some(stream) = self.streams.get_mut(&id) {
+                    stream.send_window += inc;
+                    if inc == 0 || stream.send_window > MAX_WINDOW {
+                        let code = if inc == 0 {
+                            PROTOCOL_ERROR
+                        } else {
+                            FLOW_CONTROL_ERROR
+                        };
+                        self.close_stream(id);
+                        self.write_rst_stream(id, code);
+                    }
+                }
+            }
+            _ => {}
+        }
+        Ok(())
+    }
+
+    fn header_block<T: HttpApp<Output = F>>(
+        &mut self,
+        app: &T,
+        block: HeaderBlock,
+    ) -> Result<(), u32> {
+        // always decode, even for refused streams, so the hpack state stays in sync
+        let fields = self.decoder.decode(&block.block).ok_or(COMPRESSION_ERROR)?;
+        let id = block.stream_id;
+        if let Some(stream) = self.streams.get_mut(&id) {
+            // trailers: accepted and dropped, they must end the stream
+            if stream.req.is_none() || !block.end_stream {
+                return Err(PROTOCOL_ERROR);
+            }
+            self.dispatch(app, id);
+            return Ok(());
+        }
+        if id <= self.last_stream_id {
+            return Err(PROTOCOL_ERROR);
+        }
+        self.last_stream_id = id;
+        if self.going_away {
+            return Ok(());
+        }
+        // handlers count until they return, even for streams closed since
+        if self.streams.len().max(self.tasks.len()) >= MAX_CONCURRENT_STREAMS {
+            self.write_rst_stream(id, REFUSED_STREAM);
+            return Ok(());
+        }
+        match build_request(fields) {
+            Some(req) => {
+                self.open_stream(id, req);
+                if block.end_stream {
+                    self.dispatch(app, id);
+                }
+            }
+            None => self.write_rst_stream(id, PROTOCOL_ERROR),
+        }
+        Ok(())
+    }
+
+    fn apply_settings(&mut self, payload: &[u8]) -> Result<(), u32> {
+        if payload.len() % 6 != 0 {
+            return Err(FRAME_SIZE_ERROR);
+        }
+        for setting in payload.chunks(6) {
+            let key = u16::from_be_bytes([setting[0], setting[1]]);
+            let value = u32::from_be_bytes([setting[2], setting[3], setting[4], setting[5]]);
+            match key {
+                SETTINGS_ENABLE_PUSH if value > 1 => return Err(PROTOCOL_ERROR),
+                SETTINGS_INITIAL_WINDOW_SIZE => {
+                    if value as i64 > MAX_WINDOW {
+                        return Err(FLOW_CONTROL_ERROR);
+                    }
+                    let delta = value as i64 - self.peer.initial_window_size;
+                    self.peer.initial_window_size = value as i64;
+                    for stream in self.streams.values_mut() {
+                        stream.send_window += delta;
+                        if stream.send_window > MAX_WINDOW {
+                            return Err(FLOW_CONTROL_ERROR);
+                        }
+                    }
+                }
+                SETTINGS_MAX_FRAME_SIZE => {
+                    if value < DEFAULT_MAX_FRAME as u32 || value > 0xff_ffff {
+                        return Err(PROTOCOL_ERROR);
+                    }
+                    self.peer.max_frame_size = value as usize;
+                }
+                // our encoder never uses the dynamic table (SETTINGS_HEADER_TABLE_SIZE)
+                // and we never push (SETTINGS_MAX_CONCURRENT_STREAMS)
+                _ => {}
+            }
+        }
+        Ok(())
+    }
+
+    fn open_stream(&mut self, id: u32, req: Request) {
+        self.last_stream_id = self.last_stream_id.max(id);
+        let head = req.method() == "HEAD";
+        self.streams.insert(
+            id,
+            StreamState {
+                req: Some(req),
+                head,
+                send_window: self.peer.initial_window_size,
+                recv_window: self.stream_recv_window,
+                pending: None,
+            },
+        );
+    }
+
+    // and drops its handler, if it is still running
+    fn close_stream(&mut self, id: u32) {
+        self.streams.remove(&id);
+        self.tasks.retain(|task| task.id != id);
+    }
+
+    fn dispatch<T: HttpApp<Output = F>>(&mut self, app: &T, id: u32) {
+        if let Some(req) = self.streams.get_mut(&id).and_then(|s| s.req.take()) {
+            debug!("h2 stream {}: {} {}", id, req.method(), req.uri());
+            self.tasks.push(StreamTask {
+                id,
+                fut: Box::pin(app.app(req)),
+            });
+        }
+    }
+
+    fn respond(&mut self, id: u32, mut res: Response) {
+        let head = match self.streams.get(&id) {
+            Some(stream) => stream.head,
+            // closed while the handler was running
+            None => return,
+        };
+        self.answered += 1;
+        let body_stream = res.take_stream();
+        let status = res.status_code().code().to_string();
+        // unknown up front for streaming bodies, which end with an empty END_STREAM frame
+        let content_length = match body_stream {
+            Some(_) => None,
+            None => Some(res.body.len().to_string()),
+        };
+        let mut block = Vec::new();
+        let fields = header::wire_fields(res.headers())
+            .map(|(k, v)| (k.to_lowercase(), v))
+            .filter(|(k, _)| !is_connection_header(k) && k != "content-length")
+            .collect::<Vec<_>>();
+        hpack::encode(
+            std::iter::once((":status", &*status))
+                .chain(fields.iter().map(|(k, v)| (&**k, &**v)))
+                .chain(res.cookies().iter().map(|v| ("set-cookie", &**v)))
+                .chain(content_length.iter().map(|len| ("content-length", &**len))),
+            &mut block,
+        );
+        let body = std::mem::replace(&mut res.body, Vec::new());
+        let end_stream = head || (body.is_empty() && body_stream.is_none());
+        self.write_headers(id, &block, end_stream);
+        if end_stream {
+            self.streams.remove(&id);
+        } else if let Some(stream) = self.streams.get_mut(&id) {
+            stream.pending = Some(Outgoing {
+                buf: body,
+                pos: 0,
+                stream: body_stream,
+            });
+        }
+    }
+
+    // writes pending response bodies as far as the flow control windows allow
+    fn flush_data(&mut self, cx: &mut Context) {
+        let mut finished = Vec::new();
+        for (&id, stream) in self.streams.iter_mut() {
+            if let Some(out) = &mut stream.pending {
+                while self.wbuf.len() < WRITE_HIGH_WATER {
+                    if out.pos == out.buf.len() {
+                        let next = match &mut out.stream {
+                            Some(body) => body.as_mut().poll_next(cx),
+                            None => {
+                                finished.push(id);
+                                break;
+                            }
+                        };
+                        match next {
+                            task::Poll::Ready(Some(chunk)) => {
+                                out.buf = chunk;
+                                out.pos = 0;
+                                continue;
+                            }
+                            task::Poll::Ready(None) => {
+                                write_frame(&mut self.wbuf, DATA, FLAG_END_STREAM, id, &[]);
+                                finished.push(id);
+                            }
+                            task::Poll::Pending => {}
+                        }
+                        break;
+                    }
+                    let window = stream.send_window.min(self.send_window);
+                    if window <= 0 {
+                        break;
+                    }
+                    let len = (out.buf.len() - out.pos)
+                        .min(self.peer.max_frame_size)
+                        .min(window as usize);
+                    let end = out.pos + len;
+                    let flags = if end == out.buf.len() && out.stream.is_none() {
+                        FLAG_END_STREAM
+                    } else {
+                        0
+                    };
+                    write_frame(&mut self.wbuf, DATA, flags, id, &out.buf[out.pos..end]);
+                    out.pos = end;
+                    stream.send_window -= len as i64;
+                    self.send_window -= len as i64;
+                }
+            }
+        }
+        for id in finished {
+            self.streams.remove(&id);
+        }
+    }
+
+    fn write_frame(&mut self, typ: u8, flags: u8, id: u32, payload: &[u8]) {
+        write_frame(&mut self.wbuf, typ, flags, id, payload);
+    }
+
+    fn write_headers(&mut self, id: u32, block: &[u8], end_stream: bool) {
+        let mut chunks = block.chunks(self.peer.max_frame_size).peekable();
+        let mut typ = HEADERS;
+        let mut flags = if end_stream { FLAG_END_STREAM } else { 0 };
+        if chunks.peek().is_none() {
+            self.write_frame(HEADERS, flags | FLAG_END_HEADERS, id, &[]);
+        }
+        while let Some(chunk) = chunks.next() {
+            if chunks.peek().is_none() {
+                flags |= FLAG_END_HEADERS;
+            }
+            write_frame(&mut self.wbuf, typ, flags, id, chunk);
+            typ = CONTINUATION;
+            flags = 0;
+        }
+    }
+
+    fn write_window_update(&mut self, id: u32, inc: u32) {
+        self.write_frame(WINDOW_UPDATE, 0, id, &inc.to_be_bytes());
+    }
+
+    fn write_rst_stream(&mut self, id: u32, code: u32) {
+        self.write_frame(RST_STREAM, 0, id, &code.to_be_bytes());
+    }
+
+    fn write_goaway(&mut self, code: u32) {
+        let mut payload = self.last_stream_id.to_be_bytes().to_vec();
+        payload.extend_from_slice(&code.to_be_bytes());
+        self.write_frame(GOAWAY, 0, 0, &payload);
+    }
+}
+
+fn write_frame(dst: &mut Vec<u8>, typ: u8, flags: u8, id: u32, payload: &[u8]) {
+    let len = payload.len() as u32;
+    dst.extend_from_slice(&len.to_be_bytes()[1..]);
+    dst.push(typ);
+    dst.push(flags);
+    dst.extend_from_slice(&(id & 0x7fff_ffff).to_be_bytes());
+    dst.extend_from_slice(payload);
+}
+
+// the window for a stream that has sent `received` bytes of body: one byte past the bound, so
+// that a body over it is refused with 413 instead of stalling
+fn recv_allowance(max_body_len: usize, received: usize) -> i64 {
+    (max_body_len.saturating_add(1) - received).min(MAX_WINDOW as usize) as i64
+}
+
+fn read_u31(b: &[u8]) -> u32 {
+    u32::from_be_bytes([b[0], b[1], b[2], b[3]]) & 0x7fff_ffff
+}
+
+fn strip_padding(payload: &mut Vec<u8>) -> Result<(), u32> {
+    let pad = *payload.first().ok_or(FRAME_SIZE_ERROR)? as usize;
+    if pad + 1 > payload.len() {
+        return Err(PROTOCOL_ERROR);
+    }
+    payload.truncate(payload.len() - pad);
+    payload.remove(0);
+    Ok(())
+}
+
+fn is_connection_header(name: &str) -> bool {
+    match name {
+        "connection" | "keep-alive" | "proxy-connection" | "transfer-encoding" | "upgrade" => true,
+        _ => false,
+    }
+}
+
+fn build_request(fields: Vec<hpack::Field>) -> Option<Request> {
+    let mut req = Request::empty();
+    req.http_version = "HTTP/2.0".to_owned();
+    let mut authority = None;
+    let mut scheme = false;
+    let mut regular = false;
+    for (name, value) in fields {
+        let name = String::from_utf8(name).ok()?;
+        let value = String::from_utf8_lossy(&value).into_owned();
+        if name.starts_with(':') {
+            if regular {
+                return None;
+            }
+            match &*name {
+                ":method" => req.method = value,
+                ":path" => req.uri = value,
+                ":scheme" => scheme = true,
+                ":authority" => authority = Some(value),
+                _ => return None,
+            }
+        } else {
+            if name.bytes().any(|b| b.is_ascii_uppercase()) || is_connection_header(&name) {
+                return None;
+            }
+            regular = true;
+            // h2 splits cookies into separate fields
+            let sep = if name == "cookie" { "; " } else { ", " };
+            let value = match req.header(&name) {
+                Some(prev) => format!("{}{}{}", prev, sep, value),
+                None => value,
+            };
+            req.set_header(&name, value);
+        }
+    }
+    if req.method.is_empty() || (req.method != "CONNECT" && (req.uri.is_empty() || !scheme)) {
+        return None;
+    }
+    if let Some(authority) = authority {
+        if req.header("host").is_none() {
+            req.set_header("host", authority);
+        }
+    }
+    Some(req)
+}
@@ -0,0 +1,247 @@
+use super::huffman;
+use std::collections::VecDeque;
+
+pub const STATIC_TABLE: [(&str, &str); 61] = [
+    (":authority", ""),
+    (":method", "GET"),
+    (":method", "POST"),
+    (":path", "/"),
+    (":path", "/index.html"),
+    (":scheme", "http"),
+    (":scheme", "https"),
+    (":status", "200"),
+    (":status", "204"),
+    (":status", "206"),
+    (":status", "304"),
+    (":status", "400"),
+    (":status", "404"),
+    (":status", "500"),
+    ("accept-charset", ""),
+    ("accept-encoding", "gzip, deflate"),
+    ("accept-language", ""),
+    ("accept-ranges", ""),
+    ("accept", ""),
+    ("access-control-allow-origin", ""),
+    ("age", ""),
+    ("allow", ""),
+    ("authorization", ""),
+    ("cache-control", ""),
+    ("content-disposition", ""),
+    ("content-encoding", ""),
+    ("content-language", ""),
+    ("content-length", ""),
+    ("content-location", ""),
+    ("content-range", ""),
+    ("content-type", ""),
+    ("cookie", ""),
+    ("date", ""),
+    ("etag", ""),
+    ("expect", ""),
+    ("expires", ""),
+    ("from", ""),
+    ("host", ""),
+    ("if-match", ""),
+    ("if-modified-since", ""),
+    ("if-none-match", ""),
+    ("if-range", ""),
+    ("if-unmodified-since", ""),
+    ("last-modified", ""),
+    ("link", ""),
+    ("location", ""),
+    ("max-forwards", ""),
+    ("proxy-authenticate", ""),
+    ("proxy-authorization", ""),
+    ("range", ""),
+    ("referer", ""),
+    ("refresh", ""),
+    ("retry-after", ""),
+    ("server", ""),
+    ("set-cookie", ""),
+    ("strict-transport-security", ""),
+    ("transfer-encoding", ""),
+    ("user-agent", ""),
+    ("vary", ""),
+    ("via", ""),
+    ("www-authenticate", ""),
+];
+
+pub type Field = (Vec<u8>, Vec<u8>);
+
+pub struct Decoder {
+    entries: VecDeque<Field>,
+    size: usize,
+    max_size: usize,
+    // upper bound advertised in our SETTINGS_HEADER_TABLE_SIZE
+    limit: usize,
+}
+
+impl Decoder {
+    pub fn new(limit: usize) -> Decoder {
+        Decoder {
+            entries: VecDeque::new(),
+            size: 0,
+            max_size: limit,
+            limit,
+        }
+    }
+
+    pub fn decode(&mut self, mut src: &[u8]) -> Option<Vec<Field>> {
+        let mut fields = Vec::new();
+        while let Some(&b) = src.first() {
+            if b & 0x80 != 0 {
+                let idx = decode_int(&mut src, 7)?;
+                let (name, value) = self.entry(idx)?;
+                fields.push((name.to_vec(), value.to_vec()));
+            } else if b & 0x40 != 0 {
+                let field = self.literal(&mut src, 6)?;
+                self.insert(field.clone());
+                fields.push(field);
+            } else if b & 0x20 != 0 {
+                let size = decode_int(&mut src, 5)?;
+                if size > self.limit {
+                    return None;
+                }
+                self.max_size = size;
+                self.evict(0);
+            } else {
+                // without indexing and never indexed are the same to a decoder
+                fields.push(self.literal(&mut src, 4)?);
+            }
+        }
+        Some(fields)
+    }
+
+    fn entry(&self, idx: usize) -> Option<(&[u8], &[u8])> {
+        if idx == 0 {
+            None
+        } else if idx <= STATIC_TABLE.len() {
+            let (name, value) = STATIC_TABLE[idx - 1];
+            Some((name.as_bytes(), value.as_bytes()))
+        } else {
+            self.entries
+                .get(idx - STATIC_TABLE.len() - 1)
+                .map(|(name, value)| (&**name, &**value))
+        }
+    }
+
+    fn literal(&self, src: &mut &[u8], prefix: u8) -> Option<Field> {
+        let idx = decode_int(src, prefix)?;
+        let name = if idx == 0 {
+            decode_string(src)?
+        } else {
+            self.entry(idx)?.0.to_vec()
+        };
+        let value = decode_string(src)?;
+        Some((name, value))
+    }
+
+    fn insert(&mut self, field: Field) {
+        let size = entry_size(&field);
+        self.evict(size);
+        if size <= self.max_size {
+            self.size += size;
+            self.entries.push_front(field);
+        }
+    }
+
+    // evicts old entries until `additional` more bytes fit in the table
+    fn evict(&mut self, additional: usize) {
+        while self.size + additional > self.max_size {
+            match self.entries.pop_back() {
+                Some(field) => self.size -= entry_size(&field),
+                None => break,
+            }
+        }
+    }
+}
+
+fn entry_size((name, value): &Field) -> usize {
+    name.len() + value.len() + 32
+}
+
+fn decode_int(src: &mut &[u8], prefix: u8) -> Option<usize> {
+    let mask = (1u8 << prefix) - 1;
+    let (&first, rest) = src.split_first()?;
+    *src = rest;
+    let mut value = (first & mask) as usize;
+    if value < mask as usize {
+        return Some(value);
+    }
+    let mut shift = 0;
+    loop {
+        let (&b, rest) = src.split_first()?;
+        *src = rest;
+        if shift > 28 {
+            return None;
+        }
+        value += ((b & 0x7f) as usize) << shift;
+        shift += 7;
+        if b & 0x80 == 0 {
+            return Some(value);
+        }
+    }
+}
+
+fn decode_string(src: &mut &[u8]) -> Option<Vec<u8>> {
+    let huffman = src.first()? & 0x80 != 0;
+    let len = decode_int(src, 7)?;
+    if src.len() < len {
+        return None;
+    }
+    let (s, rest) = src.split_at(len);
+    *src = rest;
+    if huffman {
+        huffman::decode(s)
+    } else {
+        Some(s.to_vec())
+    }
+}
+
+// encodes without touching the dynamic table (so the peer's table size setting never matters):
+// exact static matches are indexed, everything else is a literal without indexing.
+pub fn encode<'a, I: IntoIterator<Item = (&'a str, &'a str)>>(fields: I, dst: &mut Vec<u8>) {
+    for (name, value) in fields {
+        let mut name_idx = 0;
+        let mut exact = None;
+        for (i, &(n, v)) in STATIC_TABLE.iter().enumerate() {
+            if n == name {
+                if v == value {
+                    exact = Some(i + 1);
+                    break;
+                }
+                if name_idx == 0 {
+                    name_idx = i + 1;
+                }
+            }
+        }
+        if let Some(idx) = exact {
+            encode_int(dst, idx, 7, 0x80);
+        } else {
+            encode_int(dst, name_idx, 4, 0x00);
+            if name_idx == 0 {
+                encode_string(dst, name.as_bytes());
+            }
+            encode_string(dst, value.as_bytes());
+        }
+    }
+}
+
+fn encode_int(dst: &mut Vec<u8>, mut value: usize, prefix: u8, flags: u8) {
+    let mask = (1usize << prefix) - 1;
+    if value < mask {
+        dst.push(flags | value as u8);
+        return;
+    }
+    dst.push(flags | mask as u8);
+    value -= mask;
+    while value >= 0x80 {
+        dst.push((value & 0x7f) as u8 | 0x80);
+        value >>= 7;
+    }
+    dst.push(value as u8);
+}
+
+fn encode_string(dst: &mut Vec<u8>, s: &[u8]) {
+    encode_int(dst, s.len(), 7, 0x00);
+    dst.extend_from_slice(s);
+}
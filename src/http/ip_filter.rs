@@ -0,0 +1,194 @@
+// allowing and denying clients by address. `HttpServer::ip_filter` closes connections from
+// refused peers right after accept, before a byte is read; `IpFilter::wrap` answers 403 to
+// refused clients (`Request::client_addr`, so those behind trusted proxies too) in front of
+// one app, e.g. an admin router.
+use super::middleware::BoxedApp;
+use super::{HttpApp, Request, Response, StatusCode};
+use futures::future::{self, FutureExt, LocalBoxFuture};
+use log::*;
+use std::{
+    error, fmt,
+    net::{IpAddr, Ipv4Addr, Ipv6Addr},
+    str::FromStr,
+};
+
+// a network in CIDR notation, `10.0.0.0/8` or `fd00::/8`; a bare address is a network of one
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Cidr {
+    addr: IpAddr,
+    prefix: u8,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct CidrError(String);
+
+impl fmt::Display for CidrError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "invalid CIDR {:?}", self.0)
+    }
+}
+
+impl error::Error for CidrError {}
+
+impl Cidr {
+    // the bits of `addr` past `prefix` are ignored
+    pub fn new(addr: IpAddr, prefix: u8) -> Result<Cidr, CidrError> {
+        let max = match addr {
+            IpAddr::V4(_) => 32,
+            IpAddr::V6(_) => 128,
+        };
+        if prefix > max {
+            return Err(CidrError(format!("{}/{}", addr, prefix)));
+        }
+        // `::ffff:10.0.0.0/104` is `10.0.0.0/8`, as the peers it should match are canonical
+        let (addr, prefix) = match canonical(addr) {
+            IpAddr::V4(v4) if addr.is_ipv6() && prefix >= 96 => (IpAddr::V4(v4), prefix - 96),
+            _ => (addr, prefix),
+        };
+        Ok(Cidr { addr, prefix })
+    }
+
+    pub fn contains(&self, ip: IpAddr) -> bool {
+        match (self.addr, canonical(ip)) {
+            (IpAddr::V4(net), IpAddr::V4(ip)) => prefix_eq(
+                u128::from(u32::from(net)),
+                u128::from(u32::from(ip)),
+                32,
+                self.prefix,
+            ),
+            (IpAddr::V6(net), IpAddr::V6(ip)) => {
+                prefix_eq(u128::from(net), u128::from(ip), 128, self.prefix)
+            }
+            _ => false,
+        }
+    }
+}
+
+impl FromStr for Cidr {
+    type Err = CidrError;
+
+    fn from_str(s: &str) -> Result<Cidr, CidrError> {
+        let err = || CidrError(s.to_owned());
+        let s = s.trim();
+        let (addr, prefix) = match s.find('/') {
+            Some(slash) => (&s[..slash], Some(&s[slash + 1..])),
+            None => (s, None),
+        };
+        let addr = addr.parse::<IpAddr>().map_err(|_| err())?;
+        let prefix = match prefix {
+            Some(prefix) if !prefix.is_empty() && prefix.bytes().all(|b| b.is_ascii_digit()) => {
+                prefix.parse::<u8>().map_err(|_| err())?
+            }
+            Some(_) => return Err(err()),
+            None if addr.is_ipv4() => 32,
+            None => 128,
+        };
+        Cidr::new(addr, prefix).map_err(|_| err())
+    }
+}
+
+impl fmt::Display for Cidr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}/{}", self.addr, self.prefix)
+    }
+}
+
+// IPv4-mapped IPv6 addresses, as peers on a dual-stack socket appear, as plain IPv4
+fn canonical(ip: IpAddr) -> IpAddr {
+    match ip {
+        IpAddr::V6(v6) => match v6.segments() {
+            [0, 0, 0, 0, 0, 0xffff, hi, lo] => {
+                IpAddr::V4(Ipv4Addr::from((u32::from(hi) << 16) | u32::from(lo)))
+            }
+            _ => IpAddr::V6(v6),
+        },
+        v4 => v4,
+    }
+}
+
+// whether the first `prefix` of the `width` low bits of `a` and `b` are equal
+fn prefix_eq(a: u128, b: u128, width: u32, prefix: u8) -> bool {
+    let shift = width - u32::from(prefix);
+    shift >= width || (a >> shift) == (b >> shift)
+}
+
+// a peer is refused when it is in a denied network, or when there are allowed networks and
+// it is in none of them; denials win
+#[derive(Clone, Debug, Default)]
+pub struct IpFilter {
+    allow: Vec<Cidr>,
+    deny: Vec<Cidr>,
+}
+
+impl IpFilter {
+    pub fn new() -> IpFilter {
+        IpFilter::default()
+    }
+
+    pub fn allow(&mut self, cidr: Cidr) -> &mut Self {
+        self.allow.push(cidr);
+        self
+    }
+
+    pub fn deny(&mut self, cidr: Cidr) -> &mut Self {
+        self.deny.push(cidr);
+        self
+    }
+
+    // loopback, both families
+    pub fn allow_loopback(&mut self) -> &mut Self {
+        self.allow(Cidr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 0)), 8).unwrap())
+            .allow(Cidr::new(IpAddr::V6(Ipv6Addr::LOCALHOST), 128).unwrap())
+    }
+
+    pub fn permits(&self, ip: IpAddr) -> bool {
+        if self.deny.iter().any(|cidr| cidr.contains(ip)) {
+            return false;
+        }
+        self.allow.is_empty() || self.allow.iter().any(|cidr| cidr.contains(ip))
+    }
+
+    pub fn wrap<T>(&self, app: T) -> IpFiltered
+    where
+        T: HttpApp + 'static,
+        T::Output: 'static,
+    {
+        IpFiltered {
+            filter: self.clone(),
+            next: BoxedApp::new(app),
+        }
+    }
+}
+
+// see `IpFilter::wrap`
+pub struct IpFiltered {
+    filter: IpFilter,
+    next: BoxedApp,
+}
+
+impl HttpApp for IpFiltered {
+    type Output = LocalBoxFuture<'static, Response>;
+
+    fn app(&self, req: Request) -> Self::Output {
+        // requests without a peer, e.g. made up in tests, are only let through an empty filter
+        let permitted = match req.client_addr() {
+            Some(ip) => self.filter.permits(ip),
+            None => self.filter.allow.is_empty() && self.filter.deny.is_empty(),
+        };
+        if permitted {
+            self.next.app(req)
+        } else {
+            debug!(
+                "{:?} refused {} {}",
+                req.client_addr(),
+                req.method(),
+                req.uri()
+            );
+            future::ready(Response::with_status_code(StatusCode::Forbidden)).boxed_local()
+        }
+    }
+
+    fn check(&self, problems: &mut Vec<String>) {
+        self.next.check(problems);
+    }
+}
@@ -0,0 +1,86 @@
+use super::{Request, Response, StatusCode};
+use serde::{de::DeserializeOwned, Serialize};
+use std::fmt;
+
+#[derive(Debug)]
+pub enum JsonError {
+    // the body isn't application/json (or a +json type)
+    ContentType,
+    // malformed JSON, or JSON of the wrong shape
+    Body(serde_json::Error),
+}
+
+impl JsonError {
+    pub fn status_code(&self) -> StatusCode {
+        match self {
+            JsonError::ContentType => StatusCode::UnsupportedMediaType,
+            JsonError::Body(_) => StatusCode::BadRequest,
+        }
+    }
+
+    // the error response for the client, saying what was wrong
+    pub fn response(&self) -> Response {
+        let mut res = Response::with_status_code(self.status_code());
+        res.set_header("Content-Type", "text/plain; charset=utf-8".to_owned());
+        res.extend(format!("{}\n", self).bytes());
+        res
+    }
+}
+
+impl fmt::Display for JsonError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            JsonError::ContentType => f.write_str("expected Content-Type: application/json"),
+            JsonError::Body(e) => write!(f, "bad JSON body: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for JsonError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            JsonError::ContentType => None,
+            JsonError::Body(e) => Some(e),
+        }
+    }
+}
+
+impl From<JsonError> for Response {
+    fn from(e: JsonError) -> Response {
+        e.response()
+    }
+}
+
+pub fn is_json(content_type: &str) -> bool {
+    let media_type = content_type
+        .split(';')
+        .next()
+        .unwrap_or("")
+        .trim()
+        .to_lowercase();
+    media_type == "application/json"
+        || (media_type.starts_with("application/") && media_type.ends_with("+json"))
+}
+
+pub fn from_request<T: DeserializeOwned>(req: &Request) -> Result<T, JsonError> {
+    if !req.header("content-type").map_or(false, is_json) {
+        return Err(JsonError::ContentType);
+    }
+    serde_json::from_slice(req.body()).map_err(JsonError::Body)
+}
+
+// 200 with `value` serialized; 500 if it can't be, e.g. for a map with non-string keys
+pub fn response<T: Serialize + ?Sized>(value: &T) -> Response {
+    match serde_json::to_vec(value) {
+        Ok(body) => {
+            let mut res = Response::ok();
+            res.set_header("Content-Type", "application/json".to_owned());
+            res.set_body(body);
+            res
+        }
+        Err(e) => {
+            log::error!("serializing a JSON response: {}", e);
+            Response::with_status_code(StatusCode::InternalServerError)
+        }
+    }
+}
@@ -0,0 +1,147 @@
+// JSON Web Tokens (RFC 7519) signed with HS256, the shared-secret kind, as Bearer tokens;
+// see `auth::BearerAuth::jwt`
+use super::base64;
+use hmac::{Hmac, KeyInit, Mac};
+use serde_json::Value;
+use sha2::Sha256;
+use std::{
+    error, fmt,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+type HmacSha256 = Hmac<Sha256>;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum JwtError {
+    Malformed,
+    // an `alg` other than HS256; "none" in particular is never accepted
+    Algorithm,
+    Signature,
+    Expired,
+    NotYetValid,
+    Issuer,
+    Audience,
+}
+
+impl fmt::Display for JwtError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            JwtError::Malformed => "malformed token",
+            JwtError::Algorithm => "unsupported algorithm",
+            JwtError::Signature => "bad signature",
+            JwtError::Expired => "token expired",
+            JwtError::NotYetValid => "token not yet valid",
+            JwtError::Issuer => "wrong issuer",
+            JwtError::Audience => "wrong audience",
+        })
+    }
+}
+
+impl error::Error for JwtError {}
+
+// checks the signature and the `exp` and `nbf` claims, and `iss` and `aud` when configured
+#[derive(Clone)]
+pub struct Jwt {
+    key: Vec<u8>,
+    issuer: Option<String>,
+    audience: Option<String>,
+    leeway: Duration,
+}
+
+impl Jwt {
+    pub fn hs256(secret: &[u8]) -> Jwt {
+        Jwt {
+            key: secret.to_vec(),
+            issuer: None,
+            audience: None,
+            leeway: Duration::from_secs(60),
+        }
+    }
+
+    pub fn issuer(&mut self, issuer: &str) -> &mut Self {
+        self.issuer = Some(issuer.to_owned());
+        self
+    }
+
+    // the `aud` claim must be, or contain, `audience`
+    pub fn audience(&mut self, audience: &str) -> &mut Self {
+        self.audience = Some(audience.to_owned());
+        self
+    }
+
+    // clock skew allowed for `exp` and `nbf`; a minute by default
+    pub fn leeway(&mut self, leeway: Duration) -> &mut Self {
+        self.leeway = leeway;
+        self
+    }
+
+    // the claims of a valid token
+    pub fn verify(&self, token: &str) -> Result<Value, JwtError> {
+        let parts = token.split('.').collect::<Vec<_>>();
+        if parts.len() != 3 {
+            return Err(JwtError::Malformed);
+        }
+        let header = decode_json(parts[0])?;
+        if header["alg"] != "HS256" {
+            return Err(JwtError::Algorithm);
+        }
+        let signature = base64::decode(parts[2]).ok_or(JwtError::Malformed)?;
+        let mut mac = <HmacSha256 as KeyInit>::new_from_slice(&self.key).unwrap();
+        mac.update(parts[0].as_bytes());
+        mac.update(b".");
+        mac.update(parts[1].as_bytes());
+        mac.verify_slice(&signature)
+            .map_err(|_| JwtError::Signature)?;
+        let claims = decode_json(parts[1])?;
+
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs();
+        let leeway = self.leeway.as_secs();
+        if let Some(exp) = claims.get("exp") {
+            let exp = exp.as_u64().ok_or(JwtError::Malformed)?;
+            if now > exp.saturating_add(leeway) {
+                return Err(JwtError::Expired);
+            }
+        }
+        if let Some(nbf) = claims.get("nbf") {
+            let nbf = nbf.as_u64().ok_or(JwtError::Malformed)?;
+            if now.saturating_add(leeway) < nbf {
+                return Err(JwtError::NotYetValid);
+            }
+        }
+        if let Some(issuer) = &self.issuer {
+            if claims["iss"] != **issuer {
+                return Err(JwtError::Issuer);
+            }
+        }
+        if let Some(audience) = &self.audience {
+            let matches = match &claims["aud"] {
+                Value::String(aud) => aud == audience,
+                Value::Array(auds) => auds.iter().any(|aud| aud == &**audience),
+                _ => false,
+            };
+            if !matches {
+                return Err(JwtError::Audience);
+            }
+        }
+        Ok(claims)
+    }
+}
+
+#[cfg(feature = "secure-buffers")]
+impl Drop for Jwt {
+    fn drop(&mut self) {
+        use crate::zero::Zero;
+        self.key.zero();
+    }
+}
+
+fn decode_json(part: &str) -> Result<Value, JwtError> {
+    let bytes = base64::decode(part).ok_or(JwtError::Malformed)?;
+    match serde_json::from_slice(&bytes) {
+        Ok(value @ Value::Object(_)) => Ok(value),
+        _ => Err(JwtError::Malformed),
+    }
+}
@@ -0,0 +1,76 @@
+// Accept-Language (RFC 7231 5.3.5) with the matching of RFC 4647
+
+// the language ranges of an Accept-Language value, most preferred first. ranges with equal
+// weight keep their order; q=0 ranges are kept, they exclude a language.
+pub fn parse(header: &str) -> Vec<(String, f32)> {
+    let mut ranges: Vec<(String, f32)> = header
+        .split(',')
+        .filter_map(|item| {
+            let mut parts = item.split(';');
+            let range = parts.next()?.trim().to_lowercase();
+            if range.is_empty() {
+                return None;
+            }
+            let q = parts
+                .filter_map(|p| {
+                    let p = p.trim();
+                    if p.starts_with("q=") {
+                        p[2..].trim().parse::<f32>().ok()
+                    } else {
+                        None
+                    }
+                })
+                .next()
+                .unwrap_or(1.0)
+                .max(0.0)
+                .min(1.0);
+            Some((range, q))
+        })
+        .collect();
+    // stable, so ties stay in header order
+    ranges.sort_by(|a, b| b.1.partial_cmp(&a.1).unwrap());
+    ranges
+}
+
+// whether `range` covers `tag`: "en" covers "en" and "en-us" but not "eng"
+fn covers(range: &str, tag: &str) -> bool {
+    let tag = tag.to_lowercase();
+    tag == range || (tag.starts_with(range) && tag[range.len()..].starts_with('-'))
+}
+
+// the tag in `supported` the Accept-Language value `header` likes best. a range covers its
+// subtags ("en" takes "en-US"); failing that it is shortened ("de-CH" takes "de"). earlier
+// entries of `supported` win ties, and "*" takes the first one not excluded with q=0.
+pub fn negotiate<'a>(header: &str, supported: &[&'a str]) -> Option<&'a str> {
+    let ranges = parse(header);
+    let excluded = |tag: &str| {
+        ranges
+            .iter()
+            .any(|(range, q)| *q == 0.0 && range != "*" && covers(range, tag))
+    };
+    for (range, q) in &ranges {
+        if *q == 0.0 {
+            break;
+        }
+        if range == "*" {
+            if let Some(tag) = supported.iter().find(|tag| !excluded(tag)) {
+                return Some(tag);
+            }
+            continue;
+        }
+        let mut range = &range[..];
+        loop {
+            if let Some(tag) = supported
+                .iter()
+                .find(|tag| covers(range, tag) && !excluded(tag))
+            {
+                return Some(tag);
+            }
+            match range.rfind('-') {
+                Some(dash) => range = &range[..dash],
+                None => break,
+            }
+        }
+    }
+    None
+}
@@ -0,0 +1,145 @@
+use super::StatusCode;
+use std::{
+    cell::{Cell, RefCell},
+    future::Future,
+    pin::Pin,
+    rc::Rc,
+    task::{Context, Poll},
+};
+
+// approximate memory held by in-flight requests: head buffers, request bodies and
+// response bodies. limits of 0 mean unlimited.
+#[derive(Debug, Default)]
+pub struct Budget {
+    per_request: Cell<usize>,
+    global: Cell<usize>,
+    in_use: Cell<usize>,
+    peak: Cell<usize>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Exceeded {
+    // this request alone is over its limit
+    Request,
+    // the server as a whole is out of budget; the request is shed
+    Global,
+}
+
+impl Exceeded {
+    pub fn status_code(self) -> StatusCode {
+        match self {
+            Exceeded::Request => StatusCode::PayloadTooLarge,
+            Exceeded::Global => StatusCode::ServiceUnavailable,
+        }
+    }
+}
+
+impl Budget {
+    pub(crate) fn set_per_request(&self, limit: usize) {
+        self.per_request.set(limit);
+    }
+
+    pub(crate) fn set_global(&self, limit: usize) {
+        self.global.set(limit);
+    }
+
+    pub fn in_use(&self) -> usize {
+        self.in_use.get()
+    }
+
+    pub fn peak(&self) -> usize {
+        self.peak.get()
+    }
+
+    fn global_left(&self) -> usize {
+        match self.global.get() {
+            0 => usize::max_value(),
+            limit => limit.saturating_sub(self.in_use.get()),
+        }
+    }
+
+    pub(crate) fn charge(self: &Rc<Self>, bytes: usize) -> Result<Charge, Exceeded> {
+        let mut charge = Charge {
+            budget: Rc::clone(self),
+            bytes: 0,
+        };
+        charge.grow(bytes)?;
+        Ok(charge)
+    }
+}
+
+// bytes held by one request, given back when dropped
+#[derive(Debug)]
+pub(crate) struct Charge {
+    budget: Rc<Budget>,
+    bytes: usize,
+}
+
+impl Charge {
+    // how much more this request may take, and what stops it first
+    pub(crate) fn headroom(&self) -> (usize, Exceeded) {
+        let request_left = match self.budget.per_request.get() {
+            0 => usize::max_value(),
+            limit => limit.saturating_sub(self.bytes),
+        };
+        let global_left = self.budget.global_left();
+        if global_left < request_left {
+            (global_left, Exceeded::Global)
+        } else {
+            (request_left, Exceeded::Request)
+        }
+    }
+
+    pub(crate) fn grow(&mut self, bytes: usize) -> Result<(), Exceeded> {
+        let (left, exceeded) = self.headroom();
+        if bytes > left {
+            return Err(exceeded);
+        }
+        self.bytes += bytes;
+        let in_use = self.budget.in_use.get() + bytes;
+        self.budget.in_use.set(in_use);
+        self.budget.peak.set(self.budget.peak.get().max(in_use));
+        Ok(())
+    }
+
+    pub(crate) fn shrink(&mut self, bytes: usize) {
+        let bytes = bytes.min(self.bytes);
+        self.bytes -= bytes;
+        self.budget.in_use.set(self.budget.in_use.get() - bytes);
+    }
+}
+
+impl Drop for Charge {
+    fn drop(&mut self) {
+        let bytes = self.bytes;
+        self.shrink(bytes);
+    }
+}
+
+// a request's charge, shared with the `Request` so middleware holding more for it (an
+// inflated body, say) is accounted too
+pub(crate) type SharedCharge = Rc<RefCell<Charge>>;
+
+// `F`, holding on to a charge until it completes
+pub(crate) struct Charged<F> {
+    fut: F,
+    _charge: Option<SharedCharge>,
+}
+
+impl<F> Charged<F> {
+    pub(crate) fn new(fut: F, charge: Option<SharedCharge>) -> Charged<F> {
+        Charged {
+            fut,
+            _charge: charge,
+        }
+    }
+}
+
+impl<F: Future> Future for Charged<F> {
+    type Output = F::Output;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<F::Output> {
+        // `fut` is structurally pinned: it is never moved out of `self`
+        unsafe { self.map_unchecked_mut(|this| &mut this.fut) }.poll(cx)
+    }
+}
@@ -0,0 +1,635 @@
+use super::middleware::BoxedApp;
+use super::{HttpApp, Request, Response, StatusCode};
+use crate::{reactor, runner};
+use futures::future::{self, FutureExt, LocalBoxFuture};
+use futures::stream::StreamExt;
+use std::{
+    cell::{Cell, RefCell},
+    collections::BTreeMap,
+    fmt::Write,
+    rc::Rc,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+    time::{Duration, Instant},
+};
+
+// Prometheus' default buckets, in seconds
+const DEFAULT_BUCKETS: &[f64] = &[
+    0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0,
+];
+
+// request counts by status, a latency histogram, requests and connections in flight and
+// body bytes in and out, together with the runner's and the reactor's counters, in the
+// Prometheus text format. `wrap` measures the app and answers `GET /metrics` itself;
+// `handler` serves the same page from anywhere else. connections are counted once the
+// metrics are passed to `HttpServer::metrics`. counters and histograms of the app's own are
+// exported too once registered. `runtime_path` adds a page listing the server thread's
+// tasks, for finding stuck connections.
+#[derive(Clone)]
+pub struct Metrics(Rc<Registry>);
+
+struct Registry {
+    path: RefCell<Option<String>>,
+    runtime_path: RefCell<Option<String>>,
+    requests: RefCell<BTreeMap<u16, u64>>,
+    latency: RefCell<Histogram>,
+    custom: RefCell<Vec<(String, String, Custom)>>,
+    in_flight: Cell<i64>,
+    connections: Cell<i64>,
+    connections_total: Cell<u64>,
+    not_http: RefCell<BTreeMap<String, u64>>,
+    bytes_in: Cell<u64>,
+    bytes_out: Rc<Cell<u64>>,
+}
+
+enum Custom {
+    Counter(Counter),
+    Histogram(Histogram),
+}
+
+// a count that only goes up. clones share it, and it can be bumped from any thread.
+#[derive(Clone, Debug, Default)]
+pub struct Counter(Arc<AtomicU64>);
+
+impl Counter {
+    pub fn new() -> Counter {
+        Counter::default()
+    }
+
+    pub fn inc(&self) {
+        self.add(1);
+    }
+
+    pub fn add(&self, n: u64) {
+        self.0.fetch_add(n, Ordering::Relaxed);
+    }
+
+    pub fn get(&self) -> u64 {
+        self.0.load(Ordering::Relaxed)
+    }
+}
+
+// counts of observed values by bucket, e.g. timings in seconds. clones share the counts, and
+// observing takes no lock, so it can be done from any thread.
+#[derive(Clone, Debug)]
+pub struct Histogram(Arc<HistogramInner>);
+
+#[derive(Debug)]
+struct HistogramInner {
+    bounds: Vec<f64>,
+    // per bucket, not cumulative; the last one is +Inf
+    counts: Vec<AtomicU64>,
+    // the bits of an f64
+    sum: AtomicU64,
+}
+
+impl Histogram {
+    // `bounds` are the upper bounds of the buckets, ascending; a +Inf one is implied
+    pub fn new(bounds: &[f64]) -> Histogram {
+        assert!(
+            bounds.windows(2).all(|pair| pair[0] < pair[1]),
+            "histogram bounds not ascending: {:?}",
+            bounds
+        );
+        Histogram(Arc::new(HistogramInner {
+            bounds: bounds.to_vec(),
+            counts: (0..=bounds.len()).map(|_| AtomicU64::new(0)).collect(),
+            sum: AtomicU64::new(0f64.to_bits()),
+        }))
+    }
+
+    // with Prometheus' default bounds, 5ms to 10s
+    pub fn seconds() -> Histogram {
+        Histogram::new(DEFAULT_BUCKETS)
+    }
+
+    pub fn observe(&self, value: f64) {
+        let inner = &self.0;
+        let bucket = inner
+            .bounds
+            .iter()
+            .position(|&bound| value <= bound)
+            .unwrap_or_else(|| inner.bounds.len());
+        inner.counts[bucket].fetch_add(1, Ordering::Relaxed);
+        let mut sum = inner.sum.load(Ordering::Relaxed);
+        loop {
+            let new = (f64::from_bits(sum) + value).to_bits();
+            match inner
+                .sum
+                .compare_exchange_weak(sum, new, Ordering::Relaxed, Ordering::Relaxed)
+            {
+                Ok(_) => break,
+                Err(current) => sum = current,
+            }
+        }
+    }
+
+    // in seconds
+    pub fn observe_duration(&self, duration: Duration) {
+        self.observe(seconds(duration));
+    }
+
+    // observes the time until the timer is dropped
+    pub fn start_timer(&self) -> HistogramTimer {
+        HistogramTimer {
+            histogram: self.clone(),
+            start: Instant::now(),
+        }
+    }
+
+    // the upper bound of every bucket with the number of values at or below it, +Inf last
+    pub fn buckets(&self) -> Vec<(f64, u64)> {
+        let inner = &self.0;
+        let mut cumulative = 0;
+        inner
+            .counts
+            .iter()
+            .enumerate()
+            .map(|(i, count)| {
+                cumulative += count.load(Ordering::Relaxed);
+                let bound = inner.bounds.get(i).cloned().unwrap_or(std::f64::INFINITY);
+                (bound, cumulative)
+            })
+            .collect()
+    }
+
+    pub fn count(&self) -> u64 {
+        self.0
+            .counts
+            .iter()
+            .map(|count| count.load(Ordering::Relaxed))
+            .sum()
+    }
+
+    pub fn sum(&self) -> f64 {
+        f64::from_bits(self.0.sum.load(Ordering::Relaxed))
+    }
+
+    fn render(&self, out: &mut String, name: &str) {
+        let buckets = self.buckets();
+        for (bound, cumulative) in &buckets {
+            let le = if bound.is_infinite() {
+                "+Inf".to_owned()
+            } else {
+                bound.to_string()
+            };
+            let _ = writeln!(out, "{}_bucket{{le=\"{}\"}} {}", name, le, cumulative);
+        }
+        let _ = writeln!(out, "{}_sum {}", name, self.sum());
+        // from the same snapshot as the buckets, so +Inf and the count agree
+        let count = buckets.last().map_or(0, |&(_, cumulative)| cumulative);
+        let _ = writeln!(out, "{}_count {}", name, count);
+    }
+}
+
+// see `Histogram::start_timer`
+pub struct HistogramTimer {
+    histogram: Histogram,
+    start: Instant,
+}
+
+impl HistogramTimer {
+    // observes now rather than when dropped
+    pub fn stop(self) {}
+}
+
+impl Drop for HistogramTimer {
+    fn drop(&mut self) {
+        self.histogram.observe_duration(self.start.elapsed());
+    }
+}
+
+impl Default for Metrics {
+    fn default() -> Metrics {
+        Metrics(Rc::new(Registry {
+            path: RefCell::new(Some("/metrics".to_owned())),
+            runtime_path: RefCell::new(None),
+            requests: RefCell::new(BTreeMap::new()),
+            latency: RefCell::new(Histogram::seconds()),
+            custom: RefCell::new(Vec::new()),
+            in_flight: Cell::new(0),
+            connections: Cell::new(0),
+            connections_total: Cell::new(0),
+            not_http: RefCell::new(BTreeMap::new()),
+            bytes_in: Cell::new(0),
+            bytes_out: Rc::new(Cell::new(0)),
+        }))
+    }
+}
+
+impl Metrics {
+    pub fn new() -> Metrics {
+        Metrics::default()
+    }
+
+    // where `wrap` serves the metrics; None leaves every request to the app
+    pub fn path(&mut self, path: Option<&str>) -> &mut Self {
+        *self.0.path.borrow_mut() = path.map(str::to_owned);
+        self
+    }
+
+    // where `wrap` serves the task dump, e.g. `/__runtime`; off by default, as it shows
+    // what the server is doing to anyone who asks
+    pub fn runtime_path(&mut self, path: Option<&str>) -> &mut Self {
+        *self.0.runtime_path.borrow_mut() = path.map(str::to_owned);
+        self
+    }
+
+    // upper bounds of the latency buckets in seconds, ascending; resets the histogram
+    pub fn buckets(&mut self, bounds: &[f64]) -> &mut Self {
+        *self.0.latency.borrow_mut() = Histogram::new(bounds);
+        self
+    }
+
+    // exports `counter` under `name`, which must be a valid Prometheus metric name
+    pub fn register_counter(&self, name: &str, help: &str, counter: &Counter) {
+        self.register(name, help, Custom::Counter(counter.clone()));
+    }
+
+    // exports `histogram` under `name`, e.g. `checkout_duration_seconds`
+    pub fn register_histogram(&self, name: &str, help: &str, histogram: &Histogram) {
+        self.register(name, help, Custom::Histogram(histogram.clone()));
+    }
+
+    fn register(&self, name: &str, help: &str, metric: Custom) {
+        let valid = name.chars().enumerate().all(|(i, c)| {
+            c.is_ascii_alphabetic() || c == '_' || c == ':' || (i > 0 && c.is_ascii_digit())
+        });
+        assert!(valid && !name.is_empty(), "invalid metric name {:?}", name);
+        let mut custom = self.0.custom.borrow_mut();
+        assert!(
+            custom.iter().all(|(existing, _, _)| existing != name),
+            "metric {} registered twice",
+            name
+        );
+        custom.push((name.to_owned(), help.to_owned(), metric));
+    }
+
+    pub fn wrap<T>(&self, app: T) -> Measured
+    where
+        T: HttpApp + 'static,
+        T::Output: 'static,
+    {
+        Measured {
+            metrics: self.clone(),
+            next: BoxedApp::new(app),
+        }
+    }
+
+    // an app answering every request with the metrics page
+    pub fn handler(&self) -> MetricsHandler {
+        MetricsHandler(self.clone())
+    }
+
+    // an app answering every request with the task dump; see `runtime_path`
+    pub fn runtime_handler(&self) -> RuntimeHandler {
+        RuntimeHandler
+    }
+
+    pub(crate) fn connection_opened(&self) {
+        let registry = &self.0;
+        registry.connections.set(registry.connections.get() + 1);
+        registry
+            .connections_total
+            .set(registry.connections_total.get() + 1);
+    }
+
+    pub(crate) fn connection_closed(&self) {
+        let registry = &self.0;
+        registry.connections.set(registry.connections.get() - 1);
+    }
+
+    // a connection closed for sending something other than HTTP
+    pub(crate) fn not_http(&self, kind: &str) {
+        *self
+            .0
+            .not_http
+            .borrow_mut()
+            .entry(kind.to_owned())
+            .or_insert(0) += 1;
+    }
+
+    fn response(&self) -> Response {
+        let mut res = Response::ok();
+        res.set_header(
+            "Content-Type",
+            "text/plain; version=0.0.4; charset=utf-8".to_owned(),
+        );
+        res.set_body(self.render().into_bytes());
+        res
+    }
+
+    // the text exposition format
+    pub fn render(&self) -> String {
+        let registry = &self.0;
+        let mut out = String::new();
+        header(
+            &mut out,
+            "http_requests_total",
+            "counter",
+            "Requests answered, by status.",
+        );
+        for (status, count) in &*registry.requests.borrow() {
+            let _ = writeln!(
+                out,
+                "http_requests_total{{status=\"{}\"}} {}",
+                status, count
+            );
+        }
+
+        header(
+            &mut out,
+            "http_not_http_connections_total",
+            "counter",
+            "Connections rejected for not speaking HTTP, by kind.",
+        );
+        for (kind, count) in &*registry.not_http.borrow() {
+            let _ = writeln!(
+                out,
+                "http_not_http_connections_total{{kind=\"{}\"}} {}",
+                kind, count
+            );
+        }
+
+        header(
+            &mut out,
+            "http_request_duration_seconds",
+            "histogram",
+            "Time from the request reaching the app to its response.",
+        );
+        registry
+            .latency
+            .borrow()
+            .render(&mut out, "http_request_duration_seconds");
+
+        let reactor = reactor::stats();
+        let executor = runner::stats();
+        let samples: &[(&str, &str, &str, String)] = &[
+            (
+                "http_requests_in_flight",
+                "gauge",
+                "Requests the app is handling.",
+                registry.in_flight.get().to_string(),
+            ),
+            (
+                "http_connections_open",
+                "gauge",
+                "Connections being served.",
+                registry.connections.get().to_string(),
+            ),
+            (
+                "http_connections_total",
+                "counter",
+                "Connections accepted.",
+                registry.connections_total.get().to_string(),
+            ),
+            (
+                "http_request_body_bytes_total",
+                "counter",
+                "Request body bytes received.",
+                registry.bytes_in.get().to_string(),
+            ),
+            (
+                "http_response_body_bytes_total",
+                "counter",
+                "Response body bytes produced, streamed ones included.",
+                registry.bytes_out.get().to_string(),
+            ),
+            (
+                "executor_tasks",
+                "gauge",
+                "Tasks spawned on the server thread that haven't finished.",
+                executor.tasks.to_string(),
+            ),
+            (
+                "executor_queued_tasks",
+                "gauge",
+                "Tasks waiting for their next poll.",
+                executor.queued.to_string(),
+            ),
+            (
+                "executor_polls_total",
+                "counter",
+                "Times a task was polled.",
+                executor.polls.to_string(),
+            ),
+            (
+                "executor_poll_seconds_total",
+                "counter",
+                "Time spent polling tasks.",
+                seconds(executor.busy).to_string(),
+            ),
+            (
+                "executor_longest_poll_seconds",
+                "gauge",
+                "The longest a single poll has taken; every other task waited meanwhile.",
+                seconds(executor.longest_poll).to_string(),
+            ),
+            (
+                "reactor_turns_total",
+                "counter",
+                "Times the reactor polled for events.",
+                reactor.turns.to_string(),
+            ),
+            (
+                "reactor_events_total",
+                "counter",
+                "I/O events the reactor dispatched.",
+                reactor.events.to_string(),
+            ),
+            (
+                "reactor_registered",
+                "gauge",
+                "Sockets and other handles registered with the reactor.",
+                reactor.registered.to_string(),
+            ),
+            (
+                "reactor_timers",
+                "gauge",
+                "Timers pending in the reactor.",
+                reactor.timers.to_string(),
+            ),
+            (
+                "reactor_poll_seconds_total",
+                "counter",
+                "Time the reactor spent waiting for events.",
+                seconds(reactor.poll_time).to_string(),
+            ),
+            (
+                "reactor_dispatch_seconds_total",
+                "counter",
+                "Time between polls, spent waking and running tasks.",
+                seconds(reactor.dispatch_time).to_string(),
+            ),
+        ];
+        for (name, kind, help, value) in samples {
+            header(&mut out, name, kind, help);
+            let _ = writeln!(out, "{} {}", name, value);
+        }
+
+        for (name, help, metric) in &*registry.custom.borrow() {
+            match metric {
+                Custom::Counter(counter) => {
+                    header(&mut out, name, "counter", help);
+                    let _ = writeln!(out, "{} {}", name, counter.get());
+                }
+                Custom::Histogram(histogram) => {
+                    header(&mut out, name, "histogram", help);
+                    histogram.render(&mut out, name);
+                }
+            }
+        }
+        out
+    }
+}
+
+fn seconds(duration: Duration) -> f64 {
+    duration.as_secs() as f64 + f64::from(duration.subsec_nanos()) / 1e9
+}
+
+fn header(out: &mut String, name: &str, kind: &str, help: &str) {
+    let _ = writeln!(out, "# HELP {} {}", name, help);
+    let _ = writeln!(out, "# TYPE {} {}", name, kind);
+}
+
+// see `Metrics::wrap`
+pub struct Measured {
+    metrics: Metrics,
+    next: BoxedApp,
+}
+
+impl HttpApp for Measured {
+    type Output = LocalBoxFuture<'static, Response>;
+
+    fn app(&self, req: Request) -> Self::Output {
+        let registry = Rc::clone(&self.metrics.0);
+        let scrape = req.method() == "GET"
+            && registry.path.borrow().as_ref().map(|s| &**s) == Some(req.uri());
+        if scrape {
+            return future::ready(self.metrics.response()).boxed_local();
+        }
+        let dump = req.method() == "GET"
+            && registry.runtime_path.borrow().as_ref().map(|s| &**s) == Some(req.uri());
+        if dump {
+            return future::ready(runtime_response()).boxed_local();
+        }
+        let start = Instant::now();
+        registry.in_flight.set(registry.in_flight.get() + 1);
+        registry
+            .bytes_in
+            .set(registry.bytes_in.get() + req.body.len() as u64);
+        let res = self.next.app(req);
+        async move {
+            let mut res = res.await;
+            registry.in_flight.set(registry.in_flight.get() - 1);
+            registry.latency.borrow().observe_duration(start.elapsed());
+            *registry
+                .requests
+                .borrow_mut()
+                .entry(res.status_code() as u16)
+                .or_insert(0) += 1;
+            registry
+                .bytes_out
+                .set(registry.bytes_out.get() + res.body.len() as u64);
+            if let Some(stream) = res.stream.take() {
+                let bytes_out = Rc::clone(&registry.bytes_out);
+                let counted = stream.inspect(move |chunk| {
+                    bytes_out.set(bytes_out.get() + chunk.len() as u64);
+                });
+                res.stream = Some(counted.boxed_local());
+            }
+            res
+        }
+        .boxed_local()
+    }
+
+    fn check(&self, problems: &mut Vec<String>) {
+        self.next.check(problems);
+    }
+}
+
+// see `Metrics::handler`
+pub struct MetricsHandler(Metrics);
+
+impl HttpApp for MetricsHandler {
+    type Output = future::Ready<Response>;
+
+    fn app(&self, req: Request) -> Self::Output {
+        if req.method() != "GET" && req.method() != "HEAD" {
+            let mut res = Response::with_status_code(StatusCode::MethodNotAllowed);
+            res.set_header("Allow", "GET, HEAD".to_owned());
+            return future::ready(res);
+        }
+        future::ready(self.0.response())
+    }
+}
+
+// see `Metrics::runtime_handler`
+pub struct RuntimeHandler;
+
+impl HttpApp for RuntimeHandler {
+    type Output = future::Ready<Response>;
+
+    fn app(&self, req: Request) -> Self::Output {
+        if req.method() != "GET" && req.method() != "HEAD" {
+            let mut res = Response::with_status_code(StatusCode::MethodNotAllowed);
+            res.set_header("Allow", "GET, HEAD".to_owned());
+            return future::ready(res);
+        }
+        future::ready(runtime_response())
+    }
+}
+
+fn runtime_response() -> Response {
+    let mut res = Response::ok();
+    res.set_header("Content-Type", "text/plain; charset=utf-8".to_owned());
+    res.set_header("Cache-Control", "no-store".to_owned());
+    res.set_body(render_runtime().into_bytes());
+    res
+}
+
+// the runner's totals, then a line per task, oldest first, by name if it has one
+fn render_runtime() -> String {
+    let stats = runner::stats();
+    let mut out = String::new();
+    let _ = writeln!(
+        out,
+        "{} tasks, {} queued; {} polls, {:.1}/s, {:.3}s busy, longest {:.6}s",
+        stats.tasks,
+        stats.queued,
+        stats.polls,
+        stats.polls_per_second(),
+        seconds(stats.busy),
+        seconds(stats.longest_poll)
+    );
+    let _ = writeln!(
+        out,
+        "\n{:>8} {:<8} {:>10} {:>8} {:>10} {:>10}  name or future",
+        "task", "state", "age", "polls", "last poll", "busy"
+    );
+    for task in runner::dump() {
+        let state = match task.state {
+            runner::TaskState::Running => "running",
+            runner::TaskState::Queued => "queued",
+            runner::TaskState::Idle => "idle",
+        };
+        let since_poll = task.since_poll.map_or_else(
+            || "never".to_owned(),
+            |since| format!("{:.3}s", seconds(since)),
+        );
+        let _ = writeln!(
+            out,
+            "{:>8} {:<8} {:>9.3}s {:>8} {:>10} {:>9.6}s  {}",
+            task.key,
+            state,
+            seconds(task.age),
+            task.polls,
+            since_poll,
+            seconds(task.busy),
+            task.name.as_ref().map_or(task.future, |name| &name[..])
+        );
+    }
+    out
+}
@@ -0,0 +1,180 @@
+use super::{HttpApp, Request, Response, StatusCode};
+use futures::future::{FutureExt, LocalBoxFuture};
+use std::{future::Future, rc::Rc};
+
+// an app behind a shared pointer, so apps of different types can be stored side by side
+#[derive(Clone)]
+pub struct BoxedApp(Rc<dyn DynApp>);
+
+trait DynApp {
+    fn dyn_app(&self, req: Request) -> LocalBoxFuture<'static, Response>;
+    fn dyn_check(&self, problems: &mut Vec<String>);
+}
+
+impl<T> DynApp for T
+where
+    T: HttpApp,
+    T::Output: 'static,
+{
+    fn dyn_app(&self, req: Request) -> LocalBoxFuture<'static, Response> {
+        self.app(req).boxed_local()
+    }
+
+    fn dyn_check(&self, problems: &mut Vec<String>) {
+        self.check(problems)
+    }
+}
+
+impl BoxedApp {
+    pub fn new<T>(app: T) -> BoxedApp
+    where
+        T: HttpApp + 'static,
+        T::Output: 'static,
+    {
+        BoxedApp(Rc::new(app))
+    }
+}
+
+impl HttpApp for BoxedApp {
+    type Output = LocalBoxFuture<'static, Response>;
+    fn app(&self, req: Request) -> Self::Output {
+        self.0.dyn_app(req)
+    }
+
+    fn check(&self, problems: &mut Vec<String>) {
+        self.0.dyn_check(problems)
+    }
+}
+
+// the rest of the stack below a middleware
+#[derive(Clone)]
+pub struct Next(BoxedApp);
+
+impl Next {
+    pub fn run(&self, req: Request) -> LocalBoxFuture<'static, Response> {
+        self.0.app(req)
+    }
+}
+
+// `f` sees every request first and decides whether and how to pass it on to `next`
+pub struct Middleware<F> {
+    f: F,
+    next: BoxedApp,
+}
+
+impl<F> Middleware<F> {
+    pub fn new<T>(app: T, f: F) -> Middleware<F>
+    where
+        T: HttpApp + 'static,
+        T::Output: 'static,
+    {
+        Middleware {
+            f,
+            next: BoxedApp::new(app),
+        }
+    }
+}
+
+impl<F, Fut> HttpApp for Middleware<F>
+where
+    F: Fn(Request, Next) -> Fut,
+    Fut: Future<Output = Response>,
+{
+    type Output = Fut;
+    fn app(&self, req: Request) -> Fut {
+        (self.f)(req, Next(self.next.clone()))
+    }
+
+    fn check(&self, problems: &mut Vec<String>) {
+        self.next.check(problems)
+    }
+}
+
+// `first` answers unless it declines, by default with a 404, in which case `second` gets
+// the request; e.g. dynamic routes first, static files second. the request is copied for
+// `second`, body included, and only `first` can upgrade the connection.
+pub struct OrElse<P> {
+    first: BoxedApp,
+    second: BoxedApp,
+    declined: Rc<P>,
+}
+
+impl<P> HttpApp for OrElse<P>
+where
+    P: Fn(&Response) -> bool + 'static,
+{
+    type Output = LocalBoxFuture<'static, Response>;
+    fn app(&self, req: Request) -> Self::Output {
+        let fallback = req.duplicate();
+        let res = self.first.app(req);
+        let second = self.second.clone();
+        let declined = Rc::clone(&self.declined);
+        async move {
+            let res = res.await;
+            if declined(&res) {
+                second.app(fallback).await
+            } else {
+                res
+            }
+        }
+        .boxed_local()
+    }
+
+    fn check(&self, problems: &mut Vec<String>) {
+        self.first.check(problems);
+        self.second.check(problems);
+    }
+}
+
+fn not_found(res: &Response) -> bool {
+    res.status_code() == StatusCode::NotFound
+}
+
+pub trait HttpAppExt: HttpApp + Sized {
+    fn boxed(self) -> BoxedApp
+    where
+        Self: 'static,
+        Self::Output: 'static,
+    {
+        BoxedApp::new(self)
+    }
+
+    fn with<F, Fut>(self, f: F) -> Middleware<F>
+    where
+        Self: 'static,
+        Self::Output: 'static,
+        F: Fn(Request, Next) -> Fut,
+        Fut: Future<Output = Response>,
+    {
+        Middleware::new(self, f)
+    }
+
+    // `other` handles the requests this app answers with 404
+    fn or_else<T>(self, other: T) -> OrElse<fn(&Response) -> bool>
+    where
+        Self: 'static,
+        Self::Output: 'static,
+        T: HttpApp + 'static,
+        T::Output: 'static,
+    {
+        self.or_else_if(other, not_found)
+    }
+
+    // `other` handles the requests whose response from this app `declined` returns true for
+    fn or_else_if<T, P>(self, other: T, declined: P) -> OrElse<P>
+    where
+        Self: 'static,
+        Self::Output: 'static,
+        T: HttpApp + 'static,
+        T::Output: 'static,
+        P: Fn(&Response) -> bool + 'static,
+    {
+        OrElse {
+            first: BoxedApp::new(self),
+            second: BoxedApp::new(other),
+            declined: Rc::new(declined),
+        }
+    }
+}
+
+impl<T: HttpApp> HttpAppExt for T {}
@@ -0,0 +1,277 @@
+use super::Request;
+use futures::io::{AsyncRead, AsyncReadExt};
+use std::{collections::HashMap, io};
+
+// the head of every part together
+const MAX_PART_HEAD: usize = 8 * 1024;
+const READ_CHUNK: usize = 8 * 1024;
+
+// the boundary parameter of a multipart Content-Type value
+pub fn boundary(content_type: &str) -> Option<String> {
+    let mut params = split_params(content_type).into_iter();
+    if !params.next()?.1.to_lowercase().starts_with("multipart/") {
+        return None;
+    }
+    params
+        .find(|(name, _)| name == "boundary")
+        .map(|(_, value)| value)
+        .filter(|b| !b.is_empty() && b.len() <= 70)
+}
+
+// `value; a=1; b="x;y"` -> [("", "value"), ("a", "1"), ("b", "x;y")], names lowercased
+fn split_params(value: &str) -> Vec<(String, String)> {
+    let mut params = Vec::new();
+    let mut chars = value.chars().peekable();
+    let mut first = true;
+    loop {
+        while chars.peek().map_or(false, |c| c.is_whitespace()) {
+            chars.next();
+        }
+        if chars.peek().is_none() {
+            return params;
+        }
+        let mut name = String::new();
+        let mut value = String::new();
+        let mut in_name = !first;
+        let mut quoted = false;
+        let mut was_quoted = false;
+        while let Some(c) = chars.next() {
+            match c {
+                '"' if quoted => {
+                    quoted = false;
+                    // anything up to the next ';' is dropped
+                    while chars.peek().map_or(false, |&c| c != ';') {
+                        chars.next();
+                    }
+                }
+                '"' if !in_name && value.trim().is_empty() => {
+                    quoted = true;
+                    was_quoted = true;
+                    value.clear();
+                }
+                '\\' if quoted => value.extend(chars.next()),
+                ';' if !quoted => break,
+                '=' if in_name => in_name = false,
+                _ if in_name => name.push(c),
+                _ => value.push(c),
+            }
+        }
+        let value = if was_quoted {
+            value
+        } else {
+            value.trim().to_owned()
+        };
+        params.push((name.trim().to_lowercase(), value));
+        first = false;
+    }
+}
+
+#[derive(Debug, PartialEq, Eq)]
+enum State {
+    // before the first delimiter
+    Preamble,
+    // in the body of a part, or between the headers of the next one and a delimiter
+    Body,
+    // right after a delimiter
+    Delimiter,
+    Done,
+}
+
+// reads the parts of a multipart body from `R` one after the other; only a part's head and
+// a few kilobytes of its body are held at a time, so it works as well over an upload being
+// received as over the buffered `Request::body`.
+pub struct Multipart<R> {
+    reader: R,
+    buf: Vec<u8>,
+    // CRLF "--" boundary
+    delimiter: Vec<u8>,
+    state: State,
+    eof: bool,
+}
+
+impl<'a> Multipart<&'a [u8]> {
+    // the parts of a buffered request body; None unless the Content-Type is multipart with
+    // a boundary
+    pub fn from_request(req: &'a Request) -> Option<Multipart<&'a [u8]>> {
+        let boundary = boundary(req.header("content-type")?)?;
+        Some(Multipart::new(req.body(), &boundary))
+    }
+}
+
+impl<R: AsyncRead + Unpin> Multipart<R> {
+    pub fn new(reader: R, boundary: &str) -> Multipart<R> {
+        Multipart {
+            reader,
+            // the first delimiter may start the body, without a CRLF before it
+            buf: b"\r\n".to_vec(),
+            delimiter: format!("\r\n--{}", boundary).into_bytes(),
+            state: State::Preamble,
+            eof: false,
+        }
+    }
+
+    // the next part, skipping whatever of the current one wasn't read; None after the last
+    pub async fn next_part(&mut self) -> io::Result<Option<Part<'_, R>>> {
+        loop {
+            match self.state {
+                State::Done => return Ok(None),
+                State::Preamble | State::Body => while self.chunk().await?.is_some() {},
+                State::Delimiter => break,
+            }
+        }
+        // "--" after the delimiter closes the body; otherwise optional padding, then CRLF
+        self.fill_to(2).await?;
+        if &self.buf[..2] == b"--" {
+            self.state = State::Done;
+            return Ok(None);
+        }
+        let head_len = loop {
+            if let Some(pos) = find(&self.buf, b"\r\n\r\n") {
+                break pos + 4;
+            }
+            if self.buf.len() > MAX_PART_HEAD {
+                return Err(invalid_data("part head too long"));
+            }
+            self.fill().await?;
+        };
+        let head = String::from_utf8_lossy(&self.buf[..head_len]).into_owned();
+        self.buf.drain(..head_len);
+        let mut lines = head.split("\r\n");
+        if !lines
+            .next()
+            .unwrap_or("")
+            .trim_matches(|c| c == ' ' || c == '\t')
+            .is_empty()
+        {
+            return Err(invalid_data("bad multipart delimiter"));
+        }
+        let mut headers = HashMap::new();
+        for line in lines.filter(|line| !line.is_empty()) {
+            let colon = line
+                .find(':')
+                .ok_or_else(|| invalid_data("bad part header"))?;
+            headers.insert(
+                line[..colon].trim().to_lowercase(),
+                line[colon + 1..].trim().to_owned(),
+            );
+        }
+        self.state = State::Body;
+        Ok(Some(Part {
+            multipart: self,
+            headers,
+        }))
+    }
+
+    // the next piece of the current part's body, None at its end
+    async fn chunk(&mut self) -> io::Result<Option<Vec<u8>>> {
+        if self.state == State::Delimiter || self.state == State::Done {
+            return Ok(None);
+        }
+        loop {
+            if let Some(pos) = find(&self.buf, &self.delimiter) {
+                if pos > 0 {
+                    return Ok(Some(self.buf.drain(..pos).collect()));
+                }
+                self.buf.drain(..self.delimiter.len());
+                self.state = State::Delimiter;
+                return Ok(None);
+            }
+            // the tail may be the start of a delimiter split across reads
+            let safe = self.buf.len().saturating_sub(self.delimiter.len() - 1);
+            if safe >= READ_CHUNK || (safe > 0 && self.eof) {
+                return Ok(Some(self.buf.drain(..safe).collect()));
+            }
+            self.fill().await?;
+        }
+    }
+
+    async fn fill(&mut self) -> io::Result<()> {
+        if self.eof {
+            return Err(io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                "multipart body ended early",
+            ));
+        }
+        let mut chunk = [0; READ_CHUNK];
+        match self.reader.read(&mut chunk).await? {
+            0 => self.eof = true,
+            n => self.buf.extend_from_slice(&chunk[..n]),
+        }
+        Ok(())
+    }
+
+    async fn fill_to(&mut self, len: usize) -> io::Result<()> {
+        while self.buf.len() < len {
+            self.fill().await?;
+        }
+        Ok(())
+    }
+}
+
+// a part of a multipart body, read before moving on with `Multipart::next_part`
+pub struct Part<'m, R> {
+    multipart: &'m mut Multipart<R>,
+    headers: HashMap<String, String>,
+}
+
+impl<'m, R: AsyncRead + Unpin> Part<'m, R> {
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers.get(&name.to_lowercase()).map(|s| &**s)
+    }
+
+    pub fn headers(&self) -> &HashMap<String, String> {
+        &self.headers
+    }
+
+    // the form field, from Content-Disposition
+    pub fn name(&self) -> Option<String> {
+        self.disposition_param("name")
+    }
+
+    // the name of an uploaded file as the client sent it; never use it as a path unchecked
+    pub fn filename(&self) -> Option<String> {
+        self.disposition_param("filename")
+    }
+
+    pub fn content_type(&self) -> Option<&str> {
+        self.header("content-type")
+    }
+
+    fn disposition_param(&self, name: &str) -> Option<String> {
+        split_params(self.header("content-disposition")?)
+            .into_iter()
+            .skip(1)
+            .find(|(n, _)| n == name)
+            .map(|(_, value)| value)
+    }
+
+    // the next piece of the body, None at its end
+    pub async fn chunk(&mut self) -> io::Result<Option<Vec<u8>>> {
+        self.multipart.chunk().await
+    }
+
+    // the rest of the body at once; longer bodies than `limit` are an error
+    pub async fn bytes(mut self, limit: usize) -> io::Result<Vec<u8>> {
+        let mut body = Vec::new();
+        while let Some(chunk) = self.chunk().await? {
+            if chunk.len() > limit - body.len() {
+                return Err(invalid_data("part too long"));
+            }
+            body.extend(chunk);
+        }
+        Ok(body)
+    }
+
+    pub async fn text(self, limit: usize) -> io::Result<String> {
+        String::from_utf8(self.bytes(limit).await?)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+    }
+}
+
+fn find(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    haystack.windows(needle.len()).position(|w| w == needle)
+}
+
+fn invalid_data(msg: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
@@ -0,0 +1,110 @@
+use super::Response;
+use futures::stream::StreamExt;
+use log::*;
+use std::{
+    cell::RefCell,
+    collections::HashMap,
+    future::Future,
+    net::IpAddr,
+    pin::Pin,
+    rc::Rc,
+    task::{Context, Poll},
+};
+
+// responses in flight per client IP, for `HttpServer::max_in_flight_per_ip`. a response is in
+// flight from dispatch until its body is written, so a slow streaming download keeps its
+// slot for as long as it lasts.
+pub(crate) struct PeerSlots {
+    max: usize,
+    in_flight: RefCell<HashMap<IpAddr, usize>>,
+}
+
+impl PeerSlots {
+    pub(crate) fn new(max: usize) -> PeerSlots {
+        PeerSlots {
+            max,
+            in_flight: RefCell::new(HashMap::new()),
+        }
+    }
+
+    // None once `ip` has `max` responses in flight
+    pub(crate) fn acquire(self: &Rc<Self>, ip: IpAddr) -> Option<PeerSlot> {
+        let mut in_flight = self.in_flight.borrow_mut();
+        let count = in_flight.entry(ip).or_insert(0);
+        if *count >= self.max {
+            debug!("{} has {} responses in flight", ip, count);
+            return None;
+        }
+        *count += 1;
+        Some(PeerSlot {
+            slots: Rc::clone(self),
+            ip,
+        })
+    }
+}
+
+pub(crate) struct PeerSlot {
+    slots: Rc<PeerSlots>,
+    ip: IpAddr,
+}
+
+impl PeerSlot {
+    // a streaming body holds on to the slot until the stream is done or dropped
+    pub(crate) fn hold_for(self, res: &mut Response) {
+        if let Some(stream) = res.stream.take() {
+            // dropped together with the stream
+            let slot = self;
+            res.stream = Some(
+                stream
+                    .map(move |chunk| {
+                        let _held = &slot;
+                        chunk
+                    })
+                    .boxed_local(),
+            );
+        }
+    }
+}
+
+impl Drop for PeerSlot {
+    fn drop(&mut self) {
+        let mut in_flight = self.slots.in_flight.borrow_mut();
+        if let Some(count) = in_flight.get_mut(&self.ip) {
+            *count -= 1;
+            if *count == 0 {
+                in_flight.remove(&self.ip);
+            }
+        }
+    }
+}
+
+// `F`'s response, holding the slot until the response is sent
+pub(crate) struct Slotted<F> {
+    fut: F,
+    slot: Option<PeerSlot>,
+}
+
+impl<F> Slotted<F> {
+    pub(crate) fn new(fut: F, slot: Option<PeerSlot>) -> Slotted<F> {
+        Slotted { fut, slot }
+    }
+}
+
+impl<F: Future<Output = Response>> Future for Slotted<F> {
+    type Output = Response;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Response> {
+        // `fut` is structurally pinned: it is never moved out of `self`
+        let this = unsafe { self.get_unchecked_mut() };
+        let fut = unsafe { Pin::new_unchecked(&mut this.fut) };
+        match fut.poll(cx) {
+            Poll::Ready(mut res) => {
+                if let Some(slot) = this.slot.take() {
+                    slot.hold_for(&mut res);
+                }
+                Poll::Ready(res)
+            }
+            Poll::Pending => Poll::Pending,
+        }
+    }
+}
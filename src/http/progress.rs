@@ -0,0 +1,100 @@
+use super::Request;
+use std::time::{Duration, Instant};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Direction {
+    Received,
+    Sent,
+}
+
+// a sample of a request or response body transfer, handed to the `HttpServer::on_progress` hook
+#[derive(Debug)]
+pub struct Progress<'a> {
+    pub conn: usize,
+    pub method: &'a str,
+    pub uri: &'a str,
+    pub direction: Direction,
+    pub bytes: u64,
+    pub total: Option<u64>,
+    pub elapsed: Duration,
+    pub done: bool,
+}
+
+impl Progress<'_> {
+    // bytes per second since the transfer started
+    pub fn rate(&self) -> f64 {
+        let secs = self.elapsed.as_secs_f64();
+        if secs > 0.0 {
+            self.bytes as f64 / secs
+        } else {
+            0.0
+        }
+    }
+}
+
+pub type ProgressHook = Box<dyn Fn(&Progress)>;
+
+pub(crate) struct Tracker<'a> {
+    hook: &'a dyn Fn(&Progress),
+    interval: u64,
+    conn: usize,
+    method: String,
+    uri: String,
+    direction: Direction,
+    total: Option<u64>,
+    bytes: u64,
+    reported: u64,
+    start: Instant,
+}
+
+impl<'a> Tracker<'a> {
+    pub(crate) fn new(
+        hook: &'a dyn Fn(&Progress),
+        interval: u64,
+        conn: usize,
+        req: &Request,
+        direction: Direction,
+    ) -> Tracker<'a> {
+        Tracker {
+            hook,
+            interval,
+            conn,
+            method: req.method().to_owned(),
+            uri: req.uri().to_owned(),
+            direction,
+            total: None,
+            bytes: 0,
+            reported: 0,
+            start: Instant::now(),
+        }
+    }
+
+    pub(crate) fn set_total(&mut self, total: Option<u64>) {
+        self.total = total;
+    }
+
+    pub(crate) fn advance(&mut self, len: usize) {
+        self.bytes += len as u64;
+        if self.bytes - self.reported >= self.interval {
+            self.report(false);
+        }
+    }
+
+    pub(crate) fn finish(&mut self) {
+        self.report(true);
+    }
+
+    fn report(&mut self, done: bool) {
+        self.reported = self.bytes;
+        (self.hook)(&Progress {
+            conn: self.conn,
+            method: &self.method,
+            uri: &self.uri,
+            direction: self.direction,
+            bytes: self.bytes,
+            total: self.total,
+            elapsed: self.start.elapsed(),
+            done,
+        });
+    }
+}
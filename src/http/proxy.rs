@@ -0,0 +1,756 @@
+// a reverse proxy: requests are forwarded with `client::Client` to one of a set of upstream
+// servers, and their responses streamed back.
+//
+//     let mut proxy = Proxy::new();
+//     proxy
+//         .upstream("http://10.0.0.1:8080".parse()?)
+//         .upstream("http://10.0.0.2:8080".parse()?)
+//         .balance(Balance::LeastConnections)
+//         .health_check("/healthz", Duration::from_secs(5));
+//     HttpServer::bind(&addr, proxy)?.run()
+//
+// an upstream leaves the rotation after `max_fails` failed requests in a row, for
+// `fail_timeout`, and while its active health checks fail. a request that couldn't connect
+// is tried on the next upstream, since nothing was sent.
+//
+// an `Upgrade` request (WebSocket, say) is passed on as one; if the upstream switches
+// protocols, so does the proxy, and the bytes of both connections are copied across until
+// they are closed.
+//
+// `Connect` makes the server a forward proxy instead, tunneling CONNECT requests to the host
+// they name.
+use super::auth::quote;
+use super::client::{Client, ClientError, ClientResponse};
+use super::middleware::BoxedApp;
+use super::upgrade::{OnUpgrade, Upgraded};
+use super::{base64, HttpApp, Request, Response, StatusCode};
+use crate::net::{
+    resolve::{self, Resolve},
+    Socket, TcpStream,
+};
+use crate::{runner, time};
+use futures::future::{self, FutureExt, LocalBoxFuture};
+use futures::prelude::*;
+use futures::ready;
+use log::*;
+use std::{
+    cell::Cell,
+    io,
+    net::Shutdown,
+    pin::Pin,
+    rc::{Rc, Weak},
+    task::{Context, Poll},
+    time::{Duration, Instant},
+};
+
+// headers about one connection rather than the message (RFC 7230 6.1), not forwarded
+const HOP_BY_HOP: &[&str] = &[
+    "connection",
+    "keep-alive",
+    "proxy-authenticate",
+    "proxy-authorization",
+    "proxy-connection",
+    "te",
+    "trailer",
+    "transfer-encoding",
+    "upgrade",
+];
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Balance {
+    // each upstream in turn
+    RoundRobin,
+    // the upstream with the fewest requests in flight, bodies still streaming included
+    LeastConnections,
+}
+
+struct Upstream {
+    base: url::Url,
+    active: Cell<usize>,
+    // failed requests in a row
+    fails: Cell<u32>,
+    // out of the rotation until then
+    down_until: Cell<Option<Instant>>,
+    // failed its last health check
+    unhealthy: Cell<bool>,
+}
+
+impl Upstream {
+    fn available(&self, now: Instant) -> bool {
+        !self.unhealthy.get() && self.down_until.get().map_or(true, |until| now >= until)
+    }
+}
+
+pub struct Proxy {
+    inner: Rc<Inner>,
+}
+
+// shared with the requests in flight and the health checks
+struct Inner {
+    upstreams: Vec<Upstream>,
+    balance: Balance,
+    // where round robin goes on from
+    next: Cell<usize>,
+    max_fails: u32,
+    fail_timeout: Duration,
+    health_check: Option<(String, Duration)>,
+    checking: Cell<bool>,
+    client: Client,
+}
+
+impl Default for Proxy {
+    fn default() -> Proxy {
+        let mut client = Client::new();
+        // passed on for the client to follow
+        client.max_redirects(0).timeout(Duration::from_secs(30));
+        Proxy {
+            inner: Rc::new(Inner {
+                upstreams: Vec::new(),
+                balance: Balance::RoundRobin,
+                next: Cell::new(0),
+                max_fails: 3,
+                fail_timeout: Duration::from_secs(10),
+                health_check: None,
+                checking: Cell::new(false),
+                client,
+            }),
+        }
+    }
+}
+
+impl Proxy {
+    pub fn new() -> Proxy {
+        Proxy::default()
+    }
+
+    fn config(&mut self) -> &mut Inner {
+        Rc::get_mut(&mut self.inner).expect("Proxy configured after it began serving")
+    }
+
+    // requests go to `base` with their path and query appended to its path; http only
+    pub fn upstream(&mut self, base: url::Url) -> &mut Self {
+        self.config().upstreams.push(Upstream {
+            base,
+            active: Cell::new(0),
+            fails: Cell::new(0),
+            down_until: Cell::new(None),
+            unhealthy: Cell::new(false),
+        });
+        self
+    }
+
+    // round robin by default
+    pub fn balance(&mut self, balance: Balance) -> &mut Self {
+        self.config().balance = balance;
+        self
+    }
+
+    // an upstream is left out for `fail_timeout` after `max_fails` requests to it in a row
+    // couldn't connect, failed midway or timed out, then given another chance; 3 and 10
+    // seconds by default, and 0 never leaves one out
+    pub fn passive_checks(&mut self, max_fails: u32, fail_timeout: Duration) -> &mut Self {
+        let config = self.config();
+        config.max_fails = max_fails;
+        config.fail_timeout = fail_timeout;
+        self
+    }
+
+    // GETs `path`, under its base, on every upstream every `interval`; one not answering 2xx
+    // or 3xx is left out until it does
+    pub fn health_check(&mut self, path: &str, interval: Duration) -> &mut Self {
+        self.config().health_check = Some((path.to_owned(), interval));
+        self
+    }
+
+    // how long an upstream may take to answer with a response head, 30 seconds by default;
+    // 504 Gateway Timeout after that
+    pub fn timeout(&mut self, timeout: Duration) -> &mut Self {
+        self.config().client.timeout(timeout);
+        self
+    }
+}
+
+impl Inner {
+    // the next upstream to try, other than those in `tried`
+    fn pick(&self, tried: &[usize]) -> Option<usize> {
+        let now = time::now();
+        let len = self.upstreams.len();
+        let start = self.next.get();
+        self.next.set(start.wrapping_add(1));
+        let mut candidates = (0..len)
+            .map(|i| start.wrapping_add(i) % len)
+            .filter(|i| !tried.contains(i) && self.upstreams[*i].available(now));
+        match self.balance {
+            Balance::RoundRobin => candidates.next(),
+            // the first of the least busy, so ties take turns
+            Balance::LeastConnections => candidates.min_by_key(|&i| self.upstreams[i].active.get()),
+        }
+    }
+
+    fn succeeded(&self, upstream: &Upstream) {
+        upstream.fails.set(0);
+        upstream.down_until.set(None);
+    }
+
+    fn failed(&self, upstream: &Upstream) {
+        let fails = upstream.fails.get() + 1;
+        upstream.fails.set(fails);
+        if self.max_fails > 0 && fails >= self.max_fails {
+            warn!(
+                "proxy: {} failed {} times in a row, out for {:?}",
+                upstream.base, fails, self.fail_timeout
+            );
+            upstream
+                .down_until
+                .set(Some(time::now() + self.fail_timeout));
+        }
+    }
+}
+
+impl HttpApp for Proxy {
+    type Output = LocalBoxFuture<'static, Response>;
+
+    fn app(&self, req: Request) -> Self::Output {
+        if let Some((path, interval)) = &self.inner.health_check {
+            if !self.inner.checking.replace(true) {
+                let checks = health_checks(Rc::downgrade(&self.inner), path.clone(), *interval);
+                runner::spawn_local_named("proxy health checks", checks);
+            }
+        }
+        forward(Rc::clone(&self.inner), req).boxed_local()
+    }
+
+    fn check(&self, problems: &mut Vec<String>) {
+        if self.inner.upstreams.is_empty() {
+            problems.push("proxy without upstreams".to_owned());
+        }
+        for upstream in &self.inner.upstreams {
+            if upstream.base.scheme() != "http" {
+                problems.push(format!(
+                    "proxy upstream {}: only http is supported",
+                    upstream.base
+                ));
+            }
+        }
+    }
+}
+
+// counts a request in flight to an upstream until dropped, with the response body
+struct InFlight(Rc<Inner>, usize);
+
+impl InFlight {
+    fn new(inner: &Rc<Inner>, i: usize) -> InFlight {
+        let active = &inner.upstreams[i].active;
+        active.set(active.get() + 1);
+        InFlight(Rc::clone(inner), i)
+    }
+}
+
+impl Drop for InFlight {
+    fn drop(&mut self) {
+        let active = &self.0.upstreams[self.1].active;
+        active.set(active.get() - 1);
+    }
+}
+
+// `base` with `uri`'s path and query appended to its path
+fn target(base: &url::Url, uri: &str) -> Result<url::Url, url::ParseError> {
+    let path = if uri.starts_with('/') {
+        uri.to_owned()
+    } else {
+        // absolute-form
+        let url = url::Url::parse(uri)?;
+        url[url::Position::BeforePath..url::Position::AfterQuery].to_owned()
+    };
+    let prefix = base.path().trim_end_matches('/');
+    url::Url::parse(&format!(
+        "{}{}{}",
+        &base[..url::Position::BeforePath],
+        prefix,
+        path
+    ))
+}
+
+fn is_hop_by_hop(name: &str, connection: &[String]) -> bool {
+    HOP_BY_HOP.iter().any(|h| name.eq_ignore_ascii_case(h))
+        || connection.iter().any(|c| name.eq_ignore_ascii_case(c))
+}
+
+// the header names listed in Connection, which are hop-by-hop too
+fn connection_options(value: Option<&str>) -> Vec<String> {
+    value
+        .unwrap_or("")
+        .split(',')
+        .map(|option| option.trim().to_lowercase())
+        .filter(|option| !option.is_empty())
+        .collect()
+}
+
+async fn forward(inner: Rc<Inner>, mut req: Request) -> Response {
+    let body = req.take_body();
+    let connection = connection_options(req.header("connection"));
+    let upgrade = req
+        .header("upgrade")
+        .filter(|_| connection.iter().any(|c| c == "upgrade"))
+        .map(str::to_owned);
+    let on_upgrade = req.upgrade();
+    let mut tried = Vec::new();
+    loop {
+        let i = match inner.pick(&tried) {
+            Some(i) => i,
+            None if tried.is_empty() => {
+                warn!("proxy: no upstream available for {}", req.uri());
+                return Response::with_status_code(StatusCode::ServiceUnavailable);
+            }
+            None => return Response::with_status_code(StatusCode::BadGateway),
+        };
+        tried.push(i);
+        let upstream = &inner.upstreams[i];
+        let url = match target(&upstream.base, req.uri()) {
+            Ok(url) => url,
+            Err(e) => {
+                debug!("proxy: {}: {}", req.uri(), e);
+                return Response::with_status_code(StatusCode::BadRequest);
+            }
+        };
+        let mut upstream_req = inner.client.request(req.method(), url.as_str());
+        for (name, value) in &req.headers {
+            if name != "host" && name != "content-length" && !is_hop_by_hop(name, &connection) {
+                upstream_req = upstream_req.header(name, value);
+            }
+        }
+        if let Some(upgrade) = &upgrade {
+            upstream_req = upstream_req.header("Upgrade", upgrade);
+        }
+        // the upstream learns who the proxy got the request from
+        if let Some(peer) = req.peer_addr() {
+            let forwarded_for = match req.header("x-forwarded-for") {
+                Some(hops) => format!("{}, {}", hops, peer.ip()),
+                None => peer.ip().to_string(),
+            };
+            upstream_req = upstream_req.header("X-Forwarded-For", &forwarded_for);
+        }
+        if !body.is_empty() || req.header("content-length").is_some() {
+            upstream_req = upstream_req.body(body.clone());
+        }
+        let in_flight = InFlight::new(&inner, i);
+        match upstream_req.send().await {
+            Ok(res) => {
+                inner.succeeded(upstream);
+                if res.status() == 101 {
+                    if upgrade.is_none() {
+                        warn!("proxy: {} switched protocols unasked", upstream.base);
+                        return Response::with_status_code(StatusCode::BadGateway);
+                    }
+                    return switch(res, on_upgrade, in_flight);
+                }
+                return respond(req.method(), res, in_flight);
+            }
+            // nothing was sent, so another upstream can have it
+            Err(ClientError::Connect(e)) => {
+                debug!("proxy: {}: {}", upstream.base, e);
+                inner.failed(upstream);
+            }
+            Err(ClientError::Timeout) => {
+                warn!("proxy: {} timed out on {}", upstream.base, req.uri());
+                inner.failed(upstream);
+                return Response::with_status_code(StatusCode::GatewayTimeout);
+            }
+            Err(e) => {
+                warn!("proxy: {} on {}: {}", upstream.base, req.uri(), e);
+                inner.failed(upstream);
+                return Response::with_status_code(StatusCode::BadGateway);
+            }
+        }
+    }
+}
+
+// the upstream's response as the proxy's, its body streamed through
+fn respond(method: &str, upstream: ClientResponse, in_flight: InFlight) -> Response {
+    let status = StatusCode::from_code(upstream.status()).unwrap_or(StatusCode::BadGateway);
+    let mut res = Response::with_status_code(status);
+    let connection = connection_options(upstream.header("connection"));
+    for (name, value) in upstream.headers() {
+        if is_hop_by_hop(name, &connection) || name == "content-length" {
+            continue;
+        }
+        if name == "set-cookie" {
+            res.cookies.push(value.clone());
+            continue;
+        }
+        let value = match res.header(name) {
+            Some(prev) => format!("{}, {}", prev, value),
+            None => value.clone(),
+        };
+        res.set_header(name, value);
+    }
+    let status = upstream.status();
+    if method == "HEAD" || status == 204 || status == 304 {
+        return res;
+    }
+    let base = in_flight.0.upstreams[in_flight.1].base.clone();
+    let body = upstream
+        .into_stream()
+        .take_while(move |chunk| {
+            if let Err(e) = chunk {
+                warn!("proxy: {} body: {}", base, e);
+            }
+            future::ready(chunk.is_ok())
+        })
+        .map(move |chunk| {
+            // until the body is done
+            let _ = &in_flight;
+            chunk.unwrap_or_default()
+        });
+    res.set_stream(body);
+    res
+}
+
+// the upstream's 101 response as the proxy's, and a task tunneling the client's connection
+// to the upstream's once it is sent
+fn switch(upstream: ClientResponse, on_upgrade: OnUpgrade, in_flight: InFlight) -> Response {
+    let mut res = Response::with_status_code(StatusCode::SwitchingProtocols);
+    let connection = connection_options(upstream.header("connection"));
+    for (name, value) in upstream.headers() {
+        if !is_hop_by_hop(name, &connection) && name != "content-length" {
+            res.set_header(name, value.clone());
+        }
+    }
+    let protocol = upstream.header("upgrade").unwrap_or("").to_owned();
+    res.set_header("Upgrade", protocol);
+    res.set_header("Connection", "Upgrade".to_owned());
+    let upstream = upstream.into_upgraded().expect("a 101 response");
+    runner::spawn_local_named("proxy upgrade", async move {
+        let base = in_flight.0.upstreams[in_flight.1].base.to_string();
+        splice(on_upgrade, upstream, &base).await;
+    });
+    res
+}
+
+// tunnels the client's connection to `to` once it is handed over
+async fn splice(on_upgrade: OnUpgrade, to: Upgraded, name: &str) {
+    let from = match on_upgrade.await {
+        Ok(from) => from,
+        Err(e) => {
+            debug!("proxy: {}: {}", name, e);
+            return;
+        }
+    };
+    match tunnel(from, to).await {
+        Ok((sent, received)) => debug!(
+            "proxy: tunnel to {} closed, {} bytes sent, {} received",
+            name, sent, received
+        ),
+        Err(e) => debug!("proxy: tunnel to {}: {}", name, e),
+    }
+}
+
+// copies each connection's bytes to the other, starting with those already read off them. a
+// side that has finished sending gets the other's write side shut down, so the tunnel ends
+// once both have; an error on either ends it at once. returns the bytes sent each way.
+async fn tunnel(client: Upgraded, upstream: Upgraded) -> io::Result<(u64, u64)> {
+    let (mut client_sock, mut upstream_sock) = (client.sock, upstream.sock);
+    let mut up = Pipe::new(client.read_buf);
+    let mut down = Pipe::new(upstream.read_buf);
+    future::poll_fn(|cx| {
+        let up_done = up.poll_copy(cx, &mut client_sock, &mut upstream_sock)?;
+        let down_done = down.poll_copy(cx, &mut upstream_sock, &mut client_sock)?;
+        if up_done.is_ready() && down_done.is_ready() {
+            Poll::Ready(Ok((up.copied, down.copied)))
+        } else {
+            Poll::Pending
+        }
+    })
+    .await
+}
+
+type Verify = Rc<dyn Fn(&str, &str) -> bool>;
+
+// CONNECT tunnels (RFC 7231 4.3.6) for a forward proxy: the server connects to the host and
+// port a CONNECT request names, answers 200 and copies bytes both ways, so clients reach
+// TLS sites through it. other requests go to the wrapped app.
+//
+//     let mut connect = Connect::new();
+//     connect.auth("proxy", |user, password| user == "alice" && password == "secret");
+//     HttpServer::bind(&addr, connect.wrap(app))?.run()
+//
+// only port 443 by default, so the proxy can't be used to reach mail or other plaintext
+// services; anyone who can reach the server can use it unless `auth` is set.
+#[derive(Clone)]
+pub struct Connect {
+    ports: Vec<u16>,
+    auth: Option<(String, Verify)>,
+    resolver: Option<Rc<dyn Resolve>>,
+    timeout: Duration,
+}
+
+impl Default for Connect {
+    fn default() -> Connect {
+        Connect {
+            ports: vec![443],
+            auth: None,
+            resolver: None,
+            timeout: Duration::from_secs(10),
+        }
+    }
+}
+
+impl Connect {
+    pub fn new() -> Connect {
+        Connect::default()
+    }
+
+    // the ports tunnels may go to; empty for any
+    pub fn ports(&mut self, ports: &[u16]) -> &mut Self {
+        self.ports = ports.to_vec();
+        self
+    }
+
+    // Basic credentials in Proxy-Authorization, checked by `verify`; a CONNECT without valid
+    // ones gets a 407 challenging for them
+    pub fn auth<F>(&mut self, realm: &str, verify: F) -> &mut Self
+    where
+        F: Fn(&str, &str) -> bool + 'static,
+    {
+        self.auth = Some((realm.to_owned(), Rc::new(verify)));
+        self
+    }
+
+    // for the hosts asked for; the thread's default resolver otherwise
+    pub fn resolver<R: Resolve + 'static>(&mut self, resolver: R) -> &mut Self {
+        self.resolver = Some(Rc::new(resolver));
+        self
+    }
+
+    // how long connecting may take, 10 seconds by default; 504 Gateway Timeout after that
+    pub fn timeout(&mut self, timeout: Duration) -> &mut Self {
+        self.timeout = timeout;
+        self
+    }
+
+    pub fn wrap<T>(&self, app: T) -> Tunneling
+    where
+        T: HttpApp + 'static,
+        T::Output: 'static,
+    {
+        Tunneling {
+            connect: Rc::new(self.clone()),
+            next: BoxedApp::new(app),
+        }
+    }
+
+    // the user, or the 407 for a request without valid credentials
+    fn authenticate(&self, req: &Request) -> Result<Option<String>, Response> {
+        let (realm, verify) = match &self.auth {
+            Some(auth) => auth,
+            None => return Ok(None),
+        };
+        let challenge = || {
+            let mut res = Response::with_status_code(StatusCode::ProxyAuthenticationRequired);
+            res.set_header(
+                "Proxy-Authenticate",
+                format!("Basic realm={}", quote(realm)),
+            );
+            res
+        };
+        let value = req.header("proxy-authorization").unwrap_or("").trim();
+        let credentials = match value.find(' ') {
+            Some(space) if value[..space].eq_ignore_ascii_case("Basic") => value[space..].trim(),
+            _ => return Err(challenge()),
+        };
+        let decoded = base64::decode(credentials)
+            .and_then(|decoded| String::from_utf8(decoded).ok())
+            .ok_or_else(|| Response::with_status_code(StatusCode::BadRequest))?;
+        let colon = decoded
+            .find(':')
+            .ok_or_else(|| Response::with_status_code(StatusCode::BadRequest))?;
+        let (user, password) = (&decoded[..colon], &decoded[colon + 1..]);
+        if verify(user, password) {
+            Ok(Some(user.to_owned()))
+        } else {
+            debug!("proxy: CONNECT auth failed for {:?}", user);
+            Err(challenge())
+        }
+    }
+}
+
+// see `Connect::wrap`
+pub struct Tunneling {
+    connect: Rc<Connect>,
+    next: BoxedApp,
+}
+
+impl HttpApp for Tunneling {
+    type Output = LocalBoxFuture<'static, Response>;
+
+    fn app(&self, mut req: Request) -> Self::Output {
+        if req.method() != "CONNECT" {
+            return self.next.app(req);
+        }
+        match self.connect.authenticate(&req) {
+            Ok(user) => req.user = user,
+            Err(res) => return future::ready(res).boxed_local(),
+        }
+        let (host, port) = match authority(req.uri()) {
+            Some(authority) => authority,
+            None => {
+                debug!("proxy: CONNECT to {:?}", req.uri());
+                return future::ready(Response::with_status_code(StatusCode::BadRequest))
+                    .boxed_local();
+            }
+        };
+        let connect = &self.connect;
+        if !connect.ports.is_empty() && !connect.ports.contains(&port) {
+            debug!("proxy: CONNECT to port {} refused", port);
+            return future::ready(Response::with_status_code(StatusCode::Forbidden)).boxed_local();
+        }
+        let resolver = connect
+            .resolver
+            .clone()
+            .unwrap_or_else(resolve::default_resolver);
+        let timeout = connect.timeout;
+        let on_upgrade = req.upgrade();
+        async move {
+            let name = format!("{}:{}", host, port);
+            let connecting = TcpStream::connect_with(&*resolver, &host, port);
+            let sock = match time::timeout(timeout, connecting).await {
+                Ok(Ok(sock)) => sock,
+                Ok(Err(e)) => {
+                    debug!("proxy: CONNECT to {}: {}", name, e);
+                    return Response::with_status_code(StatusCode::BadGateway);
+                }
+                Err(_) => {
+                    debug!("proxy: CONNECT to {} timed out", name);
+                    return Response::with_status_code(StatusCode::GatewayTimeout);
+                }
+            };
+            let to = Upgraded {
+                sock: Socket::Tcp(sock),
+                read_buf: Vec::new(),
+            };
+            runner::spawn_local_named("proxy tunnel", async move {
+                splice(on_upgrade, to, &name).await
+            });
+            Response::ok()
+        }
+        .boxed_local()
+    }
+
+    fn check(&self, problems: &mut Vec<String>) {
+        self.next.check(problems);
+    }
+}
+
+// the host and port of an authority-form request target, `host:port` or `[v6]:port`
+fn authority(target: &str) -> Option<(String, u16)> {
+    let colon = target.rfind(':')?;
+    let (host, port) = (&target[..colon], &target[colon + 1..]);
+    if !port.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+    let port = port.parse().ok().filter(|&port| port != 0)?;
+    let host = if host.starts_with('[') && host.ends_with(']') {
+        &host[1..host.len() - 1]
+    } else {
+        host
+    };
+    if host.is_empty() || host.contains(|c: char| c == '/' || c == '@' || c == '[' || c == ']') {
+        return None;
+    }
+    Some((host.to_owned(), port))
+}
+
+const PIPE_BUF: usize = 16 * 1024;
+
+// one direction of a tunnel
+struct Pipe {
+    buf: Vec<u8>,
+    // what of `buf` is still to be written
+    start: usize,
+    end: usize,
+    read_done: bool,
+    done: bool,
+    copied: u64,
+}
+
+impl Pipe {
+    fn new(mut buf: Vec<u8>) -> Pipe {
+        let end = buf.len();
+        let len = buf.len().max(PIPE_BUF);
+        buf.resize(len, 0);
+        Pipe {
+            buf,
+            start: 0,
+            end,
+            read_done: false,
+            done: false,
+            copied: 0,
+        }
+    }
+
+    fn poll_copy(
+        &mut self,
+        cx: &mut Context,
+        from: &mut Socket,
+        to: &mut Socket,
+    ) -> Poll<io::Result<()>> {
+        if self.done {
+            return Poll::Ready(Ok(()));
+        }
+        loop {
+            while self.start < self.end {
+                let n = ready!(Pin::new(&mut *to).poll_write(cx, &self.buf[self.start..self.end]))?;
+                if n == 0 {
+                    return Poll::Ready(Err(io::ErrorKind::WriteZero.into()));
+                }
+                self.start += n;
+                self.copied += n as u64;
+            }
+            if self.read_done {
+                ready!(Pin::new(&mut *to).poll_flush(cx))?;
+                // the peer may be gone already
+                let _ = to.shutdown(Shutdown::Write);
+                self.done = true;
+                return Poll::Ready(Ok(()));
+            }
+            let n = ready!(Pin::new(&mut *from).poll_read(cx, &mut self.buf))?;
+            self.start = 0;
+            self.end = n;
+            self.read_done = n == 0;
+        }
+    }
+}
+
+async fn health_checks(inner: Weak<Inner>, path: String, interval: Duration) {
+    let mut interval = time::interval(interval);
+    loop {
+        interval.tick().await;
+        let inner = match inner.upgrade() {
+            Some(inner) => inner,
+            None => return,
+        };
+        let (client, path) = (&inner.client, &path);
+        let checks = inner.upstreams.iter().map(|upstream| async move {
+            let healthy = match target(&upstream.base, path) {
+                Ok(url) => match client.get(url.as_str()).send().await {
+                    Ok(res) => res.status() >= 200 && res.status() < 400,
+                    Err(e) => {
+                        debug!("proxy: health check of {}: {}", upstream.base, e);
+                        false
+                    }
+                },
+                Err(_) => false,
+            };
+            if upstream.unhealthy.replace(!healthy) == healthy {
+                if healthy {
+                    info!("proxy: {} is healthy again", upstream.base);
+                    upstream.fails.set(0);
+                    upstream.down_until.set(None);
+                } else {
+                    warn!("proxy: {} failed its health check", upstream.base);
+                }
+            }
+        });
+        future::join_all(checks).await;
+    }
+}
@@ -0,0 +1,259 @@
+use super::middleware::BoxedApp;
+use super::{HttpApp, Request, Response, StatusCode};
+use crate::random;
+use futures::future::{FutureExt, LocalBoxFuture};
+
+// more ranges than this in one request are answered with the whole body, as overlapping or
+// tiny ranges only make the response bigger
+const MAX_RANGES: usize = 16;
+
+// an inclusive span of byte offsets
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ByteRange {
+    pub start: u64,
+    pub end: u64,
+}
+
+impl ByteRange {
+    pub fn len(self) -> u64 {
+        self.end - self.start + 1
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Requested {
+    // no usable Range header, or an If-Range that no longer matches
+    Whole,
+    Ranges(Vec<ByteRange>),
+    // 416 Range Not Satisfiable
+    Unsatisfiable,
+}
+
+// parses a `Range` value against a body of `len` bytes. syntax errors and units other than
+// bytes yield `Whole`, since a server may ignore a Range it doesn't understand. overlapping
+// and adjacent ranges are merged, in offset order; ranges adding up to more than the body,
+// like `0-,0-`, get the whole body instead (RFC 7233, section 6.1).
+pub fn parse(value: &str, len: u64) -> Requested {
+    let value = value.trim();
+    if !value.starts_with("bytes=") {
+        return Requested::Whole;
+    }
+    let mut ranges = Vec::new();
+    for spec in value[6..].split(',') {
+        let spec = spec.trim();
+        if spec.is_empty() {
+            continue;
+        }
+        let dash = match spec.find('-') {
+            Some(dash) => dash,
+            None => return Requested::Whole,
+        };
+        let (first, last) = (spec[..dash].trim(), spec[dash + 1..].trim());
+        let range = if first.is_empty() {
+            // the last `last` bytes
+            let suffix = match last.parse::<u64>() {
+                Ok(suffix) => suffix,
+                Err(_) => return Requested::Whole,
+            };
+            if suffix == 0 || len == 0 {
+                continue;
+            }
+            ByteRange {
+                start: len.saturating_sub(suffix),
+                end: len - 1,
+            }
+        } else {
+            let start = match first.parse::<u64>() {
+                Ok(start) => start,
+                Err(_) => return Requested::Whole,
+            };
+            let end = if last.is_empty() {
+                u64::max_value()
+            } else {
+                match last.parse::<u64>() {
+                    Ok(end) if end >= start => end,
+                    _ => return Requested::Whole,
+                }
+            };
+            if start >= len {
+                continue;
+            }
+            ByteRange {
+                start,
+                end: end.min(len - 1),
+            }
+        };
+        ranges.push(range);
+    }
+    if ranges.is_empty() {
+        return Requested::Unsatisfiable;
+    }
+    if ranges.len() > MAX_RANGES || ranges.iter().map(|r| r.len()).sum::<u64>() > len {
+        return Requested::Whole;
+    }
+    ranges.sort_by_key(|r| r.start);
+    let mut merged: Vec<ByteRange> = Vec::with_capacity(ranges.len());
+    for range in ranges {
+        match merged.last_mut() {
+            Some(last) if range.start <= last.end + 1 => last.end = last.end.max(range.end),
+            _ => merged.push(range),
+        }
+    }
+    Requested::Ranges(merged)
+}
+
+// the ranges `req` asks for out of a `len`-byte representation. `etag` and `last_modified`
+// are the validators sent with it; an `If-Range` matching neither gets the whole body.
+pub fn requested(
+    req: &Request,
+    len: u64,
+    etag: Option<&str>,
+    last_modified: Option<&str>,
+) -> Requested {
+    if req.method() != "GET" {
+        return Requested::Whole;
+    }
+    let range = match req.header("range") {
+        Some(range) => range,
+        None => return Requested::Whole,
+    };
+    if let Some(if_range) = req.header("if-range") {
+        let if_range = if_range.trim();
+        let matches = if if_range.starts_with('"') {
+            // only strong validators
+            etag.map_or(false, |etag| etag == if_range)
+        } else {
+            last_modified.map_or(false, |date| date == if_range)
+        };
+        if !matches {
+            return Requested::Whole;
+        }
+    }
+    parse(range, len)
+}
+
+pub fn unsatisfiable(len: u64) -> Response {
+    let mut res = Response::with_status_code(StatusCode::RangeNotSatisfiable);
+    res.set_header("Content-Range", format!("bytes */{}", len));
+    res
+}
+
+// a 206 response carrying `parts`, the bytes of each of `ranges` in order. a single range is
+// sent as is; several go in a multipart/byteranges body, each part with `content_type`.
+pub fn partial(
+    ranges: &[ByteRange],
+    parts: Vec<Vec<u8>>,
+    len: u64,
+    content_type: Option<&str>,
+) -> Response {
+    let mut res = Response::with_status_code(StatusCode::PartialContent);
+    if ranges.len() == 1 {
+        let range = ranges[0];
+        res.set_header(
+            "Content-Range",
+            format!("bytes {}-{}/{}", range.start, range.end, len),
+        );
+        if let Some(content_type) = content_type {
+            res.set_header("Content-Type", content_type.to_owned());
+        }
+        res.set_body(parts.into_iter().next().unwrap_or_default());
+        return res;
+    }
+    let boundary = random::boundary();
+    res.set_header(
+        "Content-Type",
+        format!("multipart/byteranges; boundary={}", boundary),
+    );
+    for (range, part) in ranges.iter().zip(parts) {
+        res.extend(format!("\r\n--{}\r\n", boundary).bytes());
+        if let Some(content_type) = content_type {
+            res.extend(format!("Content-Type: {}\r\n", content_type).bytes());
+        }
+        res.extend(
+            format!(
+                "Content-Range: bytes {}-{}/{}\r\n\r\n",
+                range.start, range.end, len
+            )
+            .bytes(),
+        );
+        res.extend(&part);
+    }
+    res.extend(format!("\r\n--{}--\r\n", boundary).bytes());
+    res
+}
+
+// serves ranges of the in-memory bodies of 200 responses to GET requests and advertises
+// `Accept-Ranges: bytes` on them. streaming bodies are passed through. it belongs outside
+// `Compression` so the ranges refer to the bytes actually sent.
+#[derive(Clone, Debug, Default)]
+pub struct RangeRequests {}
+
+impl RangeRequests {
+    pub fn new() -> RangeRequests {
+        RangeRequests::default()
+    }
+
+    pub fn wrap<T>(&self, app: T) -> Ranged
+    where
+        T: HttpApp + 'static,
+        T::Output: 'static,
+    {
+        Ranged {
+            next: BoxedApp::new(app),
+        }
+    }
+}
+
+// see `RangeRequests::wrap`
+pub struct Ranged {
+    next: BoxedApp,
+}
+
+impl HttpApp for Ranged {
+    type Output = LocalBoxFuture<'static, Response>;
+
+    fn app(&self, req: Request) -> Self::Output {
+        // only what `requested` looks at is kept
+        let mut head = Request::empty();
+        head.method = req.method().to_owned();
+        for &name in &["range", "if-range"] {
+            if let Some(value) = req.header(name) {
+                head.set_header(name, value.to_owned());
+            }
+        }
+        let res = self.next.app(req);
+        async move {
+            let mut res = res.await;
+            if res.status_code() != StatusCode::Ok || res.is_streaming() {
+                return res;
+            }
+            res.set_header("Accept-Ranges", "bytes".to_owned());
+            let len = res.body_len() as u64;
+            let ranges =
+                match requested(&head, len, res.header("etag"), res.header("last-modified")) {
+                    Requested::Whole => return res,
+                    Requested::Unsatisfiable => return unsatisfiable(len),
+                    Requested::Ranges(ranges) => ranges,
+                };
+            let body = res.body();
+            let parts = ranges
+                .iter()
+                .map(|r| body[r.start as usize..=r.end as usize].to_vec())
+                .collect();
+            let mut partial = partial(&ranges, parts, len, res.header("content-type"));
+            // validators and the like stay; the body headers are the partial's own
+            for (name, value) in res.headers() {
+                let lower = name.to_lowercase();
+                if !lower.starts_with("content-") && partial.header(&lower).is_none() {
+                    partial.set_header(name, value.clone());
+                }
+            }
+            partial
+        }
+        .boxed_local()
+    }
+
+    fn check(&self, problems: &mut Vec<String>) {
+        self.next.check(problems);
+    }
+}
@@ -0,0 +1,164 @@
+use super::middleware::BoxedApp;
+use super::{HttpApp, Request, Response, StatusCode};
+use crate::{runner, time};
+use futures::future::{self, FutureExt, LocalBoxFuture};
+use log::*;
+use std::{
+    cell::{Cell, RefCell},
+    collections::HashMap,
+    rc::{Rc, Weak},
+    time::{Duration, Instant},
+};
+
+type KeyFn = Rc<dyn Fn(&Request) -> Option<String>>;
+
+// token buckets per client: each holds up to `burst` tokens and gains `rate` a second; a
+// request takes one, and one arriving at an empty bucket gets 429 Too Many Requests with
+// Retry-After. clients are told apart by IP unless `key` says otherwise. buckets that have
+// filled up again are dropped by a timer, so idle clients cost nothing.
+#[derive(Clone)]
+pub struct RateLimit {
+    rate: f64,
+    burst: f64,
+    key: KeyFn,
+}
+
+impl RateLimit {
+    // `rate` requests a second on average, `burst` of them at once
+    pub fn new(rate: f64, burst: u32) -> RateLimit {
+        assert!(rate > 0.0, "RateLimit with a rate of {}", rate);
+        RateLimit {
+            rate,
+            burst: f64::from(burst.max(1)),
+            key: Rc::new(|req: &Request| req.client_addr().map(|ip| ip.to_string())),
+        }
+    }
+
+    // the bucket a request draws from, e.g. an API key or `Request::user`; requests it
+    // returns None for aren't limited
+    pub fn key<F>(&mut self, key: F) -> &mut Self
+    where
+        F: Fn(&Request) -> Option<String> + 'static,
+    {
+        self.key = Rc::new(key);
+        self
+    }
+
+    pub fn wrap<T>(&self, app: T) -> RateLimited
+    where
+        T: HttpApp + 'static,
+        T::Output: 'static,
+    {
+        RateLimited {
+            config: self.clone(),
+            buckets: Rc::new(Buckets::default()),
+            next: BoxedApp::new(app),
+        }
+    }
+
+    // how long a bucket takes to fill up from empty
+    fn fill_time(&self) -> Duration {
+        let secs = self.burst / self.rate;
+        Duration::new(secs as u64, (secs.fract() * 1e9) as u32)
+    }
+}
+
+#[derive(Default)]
+struct Buckets {
+    map: RefCell<HashMap<String, Bucket>>,
+    sweeping: Cell<bool>,
+}
+
+struct Bucket {
+    tokens: f64,
+    updated: Instant,
+}
+
+impl Bucket {
+    fn refill(&mut self, config: &RateLimit, now: Instant) {
+        let elapsed = now.duration_since(self.updated);
+        let secs = elapsed.as_secs() as f64 + f64::from(elapsed.subsec_nanos()) / 1e9;
+        self.tokens = (self.tokens + secs * config.rate).min(config.burst);
+        self.updated = now;
+    }
+}
+
+impl Buckets {
+    // takes a token, or tells how long until there is one
+    fn take(&self, config: &RateLimit, key: String) -> Result<(), Duration> {
+        let now = time::now();
+        let mut map = self.map.borrow_mut();
+        let bucket = map.entry(key).or_insert(Bucket {
+            tokens: config.burst,
+            updated: now,
+        });
+        bucket.refill(config, now);
+        if bucket.tokens >= 1.0 {
+            bucket.tokens -= 1.0;
+            Ok(())
+        } else {
+            let secs = (1.0 - bucket.tokens) / config.rate;
+            Err(Duration::new(secs as u64, (secs.fract() * 1e9) as u32))
+        }
+    }
+
+    // drops the buckets that are full again, every time one could have filled up; ends
+    // with the middleware
+    async fn sweep(buckets: Weak<Buckets>, config: RateLimit) {
+        let period = config.fill_time().max(Duration::from_secs(1));
+        let mut interval = time::interval_at(time::now() + period, period);
+        loop {
+            interval.tick().await;
+            let buckets = match buckets.upgrade() {
+                Some(buckets) => buckets,
+                None => return,
+            };
+            let now = time::now();
+            let mut map = buckets.map.borrow_mut();
+            map.retain(|_, bucket| {
+                bucket.refill(&config, now);
+                bucket.tokens < config.burst
+            });
+            trace!("rate limit: {} clients with spent tokens", map.len());
+        }
+    }
+}
+
+// see `RateLimit::wrap`
+pub struct RateLimited {
+    config: RateLimit,
+    buckets: Rc<Buckets>,
+    next: BoxedApp,
+}
+
+impl HttpApp for RateLimited {
+    type Output = LocalBoxFuture<'static, Response>;
+
+    fn app(&self, req: Request) -> Self::Output {
+        let key = match (self.config.key)(&req) {
+            Some(key) => key,
+            None => return self.next.app(req),
+        };
+        if !self.buckets.sweeping.replace(true) {
+            runner::spawn_local_named(
+                "rate limit sweep",
+                Buckets::sweep(Rc::downgrade(&self.buckets), self.config.clone()),
+            );
+        }
+        match self.buckets.take(&self.config, key) {
+            Ok(()) => self.next.app(req),
+            Err(wait) => {
+                debug!("rate limited {} {}", req.method(), req.uri());
+                let mut res = Response::with_status_code(StatusCode::TooManyRequests);
+                // whole seconds, rounded up
+                let secs = wait.as_secs() + if wait.subsec_nanos() > 0 { 1 } else { 0 };
+                res.set_header("Retry-After", secs.to_string());
+                future::ready(res).boxed_local()
+            }
+        }
+    }
+
+    fn check(&self, problems: &mut Vec<String>) {
+        self.next.check(problems);
+    }
+}
@@ -0,0 +1,149 @@
+use super::middleware::BoxedApp;
+use super::{HttpApp, Request, Response};
+use crate::random;
+use futures::future::{FutureExt, LocalBoxFuture};
+use std::{
+    cell::RefCell,
+    future::Future,
+    pin::Pin,
+    rc::Rc,
+    task::{Context, Poll},
+};
+
+// inbound IDs longer than this, or with anything but visible ASCII, are replaced
+const MAX_INBOUND_LEN: usize = 128;
+
+thread_local! {
+    static CURRENT: RefCell<Option<String>> = RefCell::new(None);
+}
+
+// the ID of the request whose handler is running on this thread, for log formats:
+//
+//     env_logger::Builder::from_default_env()
+//         .format(|buf, record| {
+//             let id = request_id::current().unwrap_or_else(|| "-".to_owned());
+//             writeln!(buf, "{} [{}] {}", record.level(), id, record.args())
+//         })
+//         .init();
+pub fn current() -> Option<String> {
+    CURRENT.with(|current| current.borrow().clone())
+}
+
+// gives every request an ID, available as `Request::request_id` and sent back in the
+// response header; records logged while the handler runs can include it via `current`.
+// an ID the client (or a proxy in front) sent in the header is kept, unless `trust_inbound`
+// is off.
+#[derive(Clone, Debug)]
+pub struct RequestIds {
+    header: String,
+    trust_inbound: bool,
+}
+
+impl Default for RequestIds {
+    fn default() -> RequestIds {
+        RequestIds {
+            header: "X-Request-Id".to_owned(),
+            trust_inbound: true,
+        }
+    }
+}
+
+impl RequestIds {
+    pub fn new() -> RequestIds {
+        RequestIds::default()
+    }
+
+    pub fn header(&mut self, name: &str) -> &mut Self {
+        self.header = name.to_owned();
+        self
+    }
+
+    pub fn trust_inbound(&mut self, trust: bool) -> &mut Self {
+        self.trust_inbound = trust;
+        self
+    }
+
+    pub fn wrap<T>(&self, app: T) -> WithRequestIds
+    where
+        T: HttpApp + 'static,
+        T::Output: 'static,
+    {
+        WithRequestIds {
+            config: Rc::new(self.clone()),
+            next: BoxedApp::new(app),
+        }
+    }
+
+    fn id_for(&self, req: &Request) -> String {
+        match req.header(&self.header.to_lowercase()) {
+            Some(id) if self.trust_inbound && valid(id) => id.to_owned(),
+            _ => random::id(),
+        }
+    }
+}
+
+fn valid(id: &str) -> bool {
+    !id.is_empty() && id.len() <= MAX_INBOUND_LEN && id.bytes().all(|b| b.is_ascii_graphic())
+}
+
+// see `RequestIds::wrap`
+pub struct WithRequestIds {
+    config: Rc<RequestIds>,
+    next: BoxedApp,
+}
+
+impl HttpApp for WithRequestIds {
+    type Output = LocalBoxFuture<'static, Response>;
+
+    fn app(&self, mut req: Request) -> Self::Output {
+        let id = self.config.id_for(&req);
+        req.request_id = Some(id.clone());
+        let res = Scoped::new(id.clone(), || self.next.app(req));
+        let config = Rc::clone(&self.config);
+        async move {
+            let mut res = res.await;
+            res.set_header(&config.header, id);
+            res
+        }
+        .boxed_local()
+    }
+
+    fn check(&self, problems: &mut Vec<String>) {
+        self.next.check(problems);
+    }
+}
+
+// `current` is the request's ID while the handler's future is polled (and created)
+struct Scoped {
+    id: Option<String>,
+    fut: LocalBoxFuture<'static, Response>,
+}
+
+impl Scoped {
+    fn new<F>(id: String, make: F) -> Scoped
+    where
+        F: FnOnce() -> LocalBoxFuture<'static, Response>,
+    {
+        let mut id = Some(id);
+        let fut = in_scope(&mut id, make);
+        Scoped { id, fut }
+    }
+}
+
+// swaps `id` in as the current ID for the call and back out after it
+fn in_scope<T, F: FnOnce() -> T>(id: &mut Option<String>, f: F) -> T {
+    let outer = CURRENT.with(|current| current.replace(id.take()));
+    let out = f();
+    *id = CURRENT.with(|current| current.replace(outer));
+    out
+}
+
+impl Future for Scoped {
+    type Output = Response;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Response> {
+        let this = self.get_mut();
+        let fut = &mut this.fut;
+        in_scope(&mut this.id, || fut.as_mut().poll(cx))
+    }
+}
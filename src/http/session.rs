@@ -0,0 +1,346 @@
+use super::base64;
+use super::cookie::{Cookie, SameSite};
+use super::middleware::BoxedApp;
+use super::{HttpApp, Request, Response};
+use futures::future::{FutureExt, LocalBoxFuture};
+use hmac::{Hmac, KeyInit, Mac};
+use log::*;
+use sha2::Sha256;
+use std::{
+    cell::RefCell,
+    collections::HashMap,
+    rc::Rc,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+type HmacSha256 = Hmac<Sha256>;
+
+const MIN_KEY_LEN: usize = 32;
+// browsers drop larger cookies
+const MAX_COOKIE_LEN: usize = 4096;
+
+// the session map of one client, shared between the handler and the middleware that
+// writes it back into the cookie
+#[derive(Clone, Default)]
+pub struct Session(Rc<RefCell<State>>);
+
+#[derive(Default)]
+struct State {
+    data: HashMap<String, String>,
+    changed: bool,
+}
+
+#[cfg(feature = "secure-buffers")]
+impl Drop for State {
+    fn drop(&mut self) {
+        use crate::zero::Zero;
+        self.data.zero();
+    }
+}
+
+impl Session {
+    pub fn get(&self, key: &str) -> Option<String> {
+        self.0.borrow().data.get(key).cloned()
+    }
+
+    pub fn set(&self, key: &str, value: String) {
+        let mut state = self.0.borrow_mut();
+        if state.data.get(key) != Some(&value) {
+            state.data.insert(key.to_owned(), value);
+            state.changed = true;
+        }
+    }
+
+    pub fn remove(&self, key: &str) -> Option<String> {
+        let mut state = self.0.borrow_mut();
+        let old = state.data.remove(key);
+        state.changed |= old.is_some();
+        old
+    }
+
+    pub fn clear(&self) {
+        let mut state = self.0.borrow_mut();
+        state.changed |= !state.data.is_empty();
+        state.data.clear();
+    }
+
+    pub fn is_changed(&self) -> bool {
+        self.0.borrow().changed
+    }
+
+    fn from_map(data: HashMap<String, String>) -> Session {
+        Session(Rc::new(RefCell::new(State {
+            data,
+            changed: false,
+        })))
+    }
+}
+
+// how session cookies are named, protected and scoped. the key signs (and, with the
+// `cookie-encryption` feature, encrypts) the cookie; it has to stay the same across restarts.
+pub struct SessionConfig {
+    sign_key: Vec<u8>,
+    #[cfg(feature = "cookie-encryption")]
+    encrypt_key: Option<Vec<u8>>,
+    key_len: usize,
+    cookie_name: String,
+    path: String,
+    max_age: Option<Duration>,
+    secure: bool,
+    same_site: SameSite,
+}
+
+impl SessionConfig {
+    pub fn new(key: &[u8]) -> SessionConfig {
+        SessionConfig {
+            sign_key: derive_key(key, b"session-sign"),
+            #[cfg(feature = "cookie-encryption")]
+            encrypt_key: None,
+            key_len: key.len(),
+            cookie_name: "session".to_owned(),
+            path: "/".to_owned(),
+            max_age: None,
+            secure: false,
+            same_site: SameSite::Lax,
+        }
+    }
+
+    pub fn cookie_name(&mut self, name: &str) -> &mut Self {
+        self.cookie_name = name.to_owned();
+        self
+    }
+
+    pub fn path(&mut self, path: &str) -> &mut Self {
+        self.path = path.to_owned();
+        self
+    }
+
+    // without one the cookie lasts until the browser closes. with one, cookies issued longer
+    // ago are refused as well, so a copy kept past its expiry doesn't bring the session back.
+    pub fn max_age(&mut self, max_age: Duration) -> &mut Self {
+        self.max_age = Some(max_age);
+        self
+    }
+
+    pub fn secure(&mut self, secure: bool) -> &mut Self {
+        self.secure = secure;
+        self
+    }
+
+    pub fn same_site(&mut self, same_site: SameSite) -> &mut Self {
+        self.same_site = same_site;
+        self
+    }
+
+    // hide the session contents from the client, not just protect them from tampering
+    #[cfg(feature = "cookie-encryption")]
+    pub fn encrypt(&mut self, key: &[u8]) -> &mut Self {
+        self.encrypt_key = Some(derive_key(key, b"session-encrypt"));
+        self
+    }
+
+    // `app` with `Request::session` available
+    pub fn wrap<T>(&self, app: T) -> Sessions
+    where
+        T: HttpApp + 'static,
+        T::Output: 'static,
+    {
+        Sessions {
+            config: Rc::new(self.clone()),
+            next: BoxedApp::new(app),
+        }
+    }
+
+    fn load(&self, req: &Request) -> Session {
+        let value = match req.cookie(&self.cookie_name) {
+            Some(value) => value,
+            None => return Session::default(),
+        };
+        match self.open(&value) {
+            Some(payload) => {
+                Session::from_map(url::form_urlencoded::parse(&payload).into_owned().collect())
+            }
+            None => {
+                debug!("ignoring invalid session cookie");
+                Session::default()
+            }
+        }
+    }
+
+    fn store(&self, session: &Session) -> Cookie {
+        let state = session.0.borrow();
+        let mut cookie = if state.data.is_empty() {
+            Cookie::removal(&self.cookie_name)
+        } else {
+            let payload = url::form_urlencoded::Serializer::new(String::new())
+                .extend_pairs(state.data.iter())
+                .finish();
+            let value = self.seal(payload.as_bytes());
+            if value.len() > MAX_COOKIE_LEN {
+                warn!(
+                    "session cookie is {} bytes, browsers may drop it",
+                    value.len()
+                );
+            }
+            let mut cookie = Cookie::new(&self.cookie_name, &value);
+            if let Some(max_age) = self.max_age {
+                cookie.max_age(max_age);
+            }
+            cookie
+        };
+        cookie
+            .path(&self.path)
+            .http_only(true)
+            .secure(self.secure)
+            .same_site(self.same_site);
+        cookie
+    }
+
+    // "<issued>.<payload>.<mac>": the time the cookie was issued in seconds since the epoch,
+    // then payload and mac in base64url. the mac covers the cookie name so values can't be
+    // moved between cookies.
+    fn seal(&self, payload: &[u8]) -> String {
+        #[cfg(feature = "cookie-encryption")]
+        let payload = &match &self.encrypt_key {
+            Some(key) => encrypt(key, self.cookie_name.as_bytes(), payload),
+            None => payload.to_vec(),
+        };
+        let body = format!("{}.{}", unix_now(), base64::encode_url(payload));
+        let tag = self.mac(&body).finalize().into_bytes();
+        format!("{}.{}", body, base64::encode_url(&tag))
+    }
+
+    fn open(&self, value: &str) -> Option<Vec<u8>> {
+        let dot = value.rfind('.')?;
+        let (body, tag) = (&value[..dot], &value[dot + 1..]);
+        self.mac(body).verify_slice(&base64::decode(tag)?).ok()?;
+        let dot = body.find('.')?;
+        let issued: u64 = body[..dot].parse().ok()?;
+        if let Some(max_age) = self.max_age {
+            if unix_now() > issued.saturating_add(max_age.as_secs()) {
+                debug!("session cookie issued at {} has expired", issued);
+                return None;
+            }
+        }
+        let payload = base64::decode(&body[dot + 1..])?;
+        #[cfg(feature = "cookie-encryption")]
+        let payload = match &self.encrypt_key {
+            Some(key) => decrypt(key, self.cookie_name.as_bytes(), &payload)?,
+            None => payload,
+        };
+        Some(payload)
+    }
+
+    fn mac(&self, body: &str) -> HmacSha256 {
+        let mut mac = <HmacSha256 as KeyInit>::new_from_slice(&self.sign_key).unwrap();
+        mac.update(self.cookie_name.as_bytes());
+        mac.update(b"=");
+        mac.update(body.as_bytes());
+        mac
+    }
+}
+
+impl Clone for SessionConfig {
+    fn clone(&self) -> SessionConfig {
+        SessionConfig {
+            sign_key: self.sign_key.clone(),
+            #[cfg(feature = "cookie-encryption")]
+            encrypt_key: self.encrypt_key.clone(),
+            key_len: self.key_len,
+            cookie_name: self.cookie_name.clone(),
+            path: self.path.clone(),
+            max_age: self.max_age,
+            secure: self.secure,
+            same_site: self.same_site,
+        }
+    }
+}
+
+#[cfg(feature = "secure-buffers")]
+impl Drop for SessionConfig {
+    fn drop(&mut self) {
+        use crate::zero::Zero;
+        self.sign_key.zero();
+        #[cfg(feature = "cookie-encryption")]
+        self.encrypt_key.zero();
+    }
+}
+
+fn unix_now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+}
+
+// separate keys for separate purposes, all from the one configured secret
+fn derive_key(key: &[u8], purpose: &[u8]) -> Vec<u8> {
+    let mut mac = <HmacSha256 as KeyInit>::new_from_slice(key).unwrap();
+    mac.update(purpose);
+    mac.finalize().into_bytes().to_vec()
+}
+
+#[cfg(feature = "cookie-encryption")]
+fn encrypt(key: &[u8], aad: &[u8], payload: &[u8]) -> Vec<u8> {
+    use chacha20poly1305::aead::{Aead, Generate, Payload};
+    use chacha20poly1305::{XChaCha20Poly1305, XNonce};
+    let cipher = <XChaCha20Poly1305 as KeyInit>::new_from_slice(key).unwrap();
+    let nonce = XNonce::generate();
+    let mut out = nonce.to_vec();
+    out.extend(
+        cipher
+            .encrypt(&nonce, Payload { msg: payload, aad })
+            .expect("session cookie encryption"),
+    );
+    out
+}
+
+#[cfg(feature = "cookie-encryption")]
+fn decrypt(key: &[u8], aad: &[u8], sealed: &[u8]) -> Option<Vec<u8>> {
+    use chacha20poly1305::aead::{Aead, Payload};
+    use chacha20poly1305::{XChaCha20Poly1305, XNonce};
+    use std::convert::TryFrom;
+    const NONCE_LEN: usize = 24;
+    if sealed.len() < NONCE_LEN {
+        return None;
+    }
+    let cipher = <XChaCha20Poly1305 as KeyInit>::new_from_slice(key).unwrap();
+    let nonce = XNonce::try_from(&sealed[..NONCE_LEN]).ok()?;
+    let msg = &sealed[NONCE_LEN..];
+    cipher.decrypt(&nonce, Payload { msg, aad }).ok()
+}
+
+// see `SessionConfig::wrap`
+pub struct Sessions {
+    config: Rc<SessionConfig>,
+    next: BoxedApp,
+}
+
+impl HttpApp for Sessions {
+    type Output = LocalBoxFuture<'static, Response>;
+
+    fn app(&self, mut req: Request) -> Self::Output {
+        let session = self.config.load(&req);
+        req.session = Some(session.clone());
+        let res = self.next.app(req);
+        let config = Rc::clone(&self.config);
+        async move {
+            let mut res = res.await;
+            if session.is_changed() {
+                res.add_cookie(&config.store(&session));
+            }
+            res
+        }
+        .boxed_local()
+    }
+
+    fn check(&self, problems: &mut Vec<String>) {
+        if self.config.key_len < MIN_KEY_LEN {
+            problems.push(format!(
+                "session key is {} bytes, at least {} are needed",
+                self.config.key_len, MIN_KEY_LEN
+            ));
+        }
+        self.next.check(problems);
+    }
+}
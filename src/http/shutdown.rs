@@ -0,0 +1,67 @@
+use crate::{reactor, time};
+use futures::future::LocalBoxFuture;
+use log::*;
+use std::{
+    cell::{Cell, RefCell},
+    future::Future,
+    pin::Pin,
+    rc::Rc,
+    task::{Context, Poll, Waker},
+};
+
+pub(crate) type ShutdownHook = Box<dyn FnOnce() -> LocalBoxFuture<'static, ()>>;
+
+// starts the graceful shutdown of the server it came from, from a handler or any other task
+// on the server's thread; see `HttpServer::shutdown_handle`
+#[derive(Clone, Default)]
+pub struct ShutdownHandle(Rc<State>);
+
+#[derive(Default)]
+struct State {
+    triggered: Cell<bool>,
+    wakers: RefCell<Vec<Waker>>,
+}
+
+impl ShutdownHandle {
+    // no more connections are accepted; `run` returns once the open ones are done and the
+    // `on_shutdown` hooks have run, or when the shutdown timeout passes. later calls have no
+    // effect.
+    pub fn shutdown(&self) {
+        if self.0.triggered.replace(true) {
+            return;
+        }
+        info!("shutdown requested");
+        for waker in self.0.wakers.borrow_mut().drain(..) {
+            // a timer that is already due wakes the task and ends the reactor's wait for
+            // events, which a plain wake may leave blocked
+            reactor::add_timer(time::now(), waker);
+        }
+    }
+
+    pub fn is_shutting_down(&self) -> bool {
+        self.0.triggered.get()
+    }
+
+    // completes once `shutdown` was called
+    pub(crate) fn triggered(&self) -> Triggered {
+        Triggered(Rc::clone(&self.0))
+    }
+}
+
+pub(crate) struct Triggered(Rc<State>);
+
+impl Future for Triggered {
+    type Output = ();
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<()> {
+        if self.0.triggered.get() {
+            Poll::Ready(())
+        } else {
+            let mut wakers = self.0.wakers.borrow_mut();
+            if !wakers.iter().any(|w| w.will_wake(cx.waker())) {
+                wakers.push(cx.waker().clone());
+            }
+            Poll::Pending
+        }
+    }
+}
@@ -0,0 +1,177 @@
+use super::middleware::{BoxedApp, Middleware, Next};
+use super::{base64, HttpApp, Request, Response};
+use crate::static_router;
+use std::{
+    future::Future,
+    path::{Path, PathBuf},
+};
+
+// the settings for one hostname (or pattern) served by `HttpServer::site`
+pub struct Site {
+    app: BoxedApp,
+    root: Option<PathBuf>,
+    body_limit: Option<usize>,
+    tls: Option<TlsFiles>,
+}
+
+// PEM certificate chain and private key presented for a site's hostnames
+#[derive(Clone, Debug)]
+pub struct TlsFiles {
+    pub cert: PathBuf,
+    pub key: PathBuf,
+}
+
+impl Site {
+    pub fn new<T>(app: T) -> Site
+    where
+        T: HttpApp + 'static,
+        T::Output: 'static,
+    {
+        Site {
+            app: BoxedApp::new(app),
+            root: None,
+            body_limit: None,
+            tls: None,
+        }
+    }
+
+    // files under `root`, served by the static router
+    pub fn static_files<P: Into<PathBuf>>(root: P) -> Site {
+        let mut site = Site::new(static_router::static_router);
+        site.document_root(root);
+        site
+    }
+
+    // handed to the app as `Request::document_root`
+    pub fn document_root<P: Into<PathBuf>>(&mut self, root: P) -> &mut Self {
+        self.root = Some(root.into());
+        self
+    }
+
+    // larger request bodies get 413 Payload Too Large
+    pub fn max_body_len(&mut self, len: usize) -> &mut Self {
+        self.body_limit = Some(len);
+        self
+    }
+
+    // picked by SNI once the server terminates TLS; plaintext listeners ignore it
+    pub fn tls<P: Into<PathBuf>, Q: Into<PathBuf>>(&mut self, cert: P, key: Q) -> &mut Self {
+        self.tls = Some(TlsFiles {
+            cert: cert.into(),
+            key: key.into(),
+        });
+        self
+    }
+
+    // middleware added last sees requests first
+    pub fn wrap<F, Fut>(&mut self, f: F) -> &mut Self
+    where
+        F: Fn(Request, Next) -> Fut + 'static,
+        Fut: Future<Output = Response> + 'static,
+    {
+        self.app = BoxedApp::new(Middleware::new(self.app.clone(), f));
+        self
+    }
+
+    pub fn app(&self) -> &BoxedApp {
+        &self.app
+    }
+
+    pub fn root(&self) -> Option<&Path> {
+        self.root.as_ref().map(|p| &**p)
+    }
+
+    pub fn body_limit(&self) -> Option<usize> {
+        self.body_limit
+    }
+
+    pub fn tls_files(&self) -> Option<&TlsFiles> {
+        self.tls.as_ref()
+    }
+
+    pub fn check(&self, problems: &mut Vec<String>) {
+        if let Some(root) = &self.root {
+            if let Err(e) = std::fs::read_dir(root) {
+                problems.push(format!("document root {}: {}", root.display(), e));
+            }
+        }
+        if let Some(tls) = &self.tls {
+            check_pem(&tls.cert, "CERTIFICATE", problems);
+            check_pem(&tls.key, "PRIVATE KEY", problems);
+        }
+        self.app.check(problems);
+    }
+}
+
+// `path` is readable and holds at least one PEM block whose label ends with `label`, and
+// every block in it decodes to a DER SEQUENCE, as certificates and keys are
+fn check_pem(path: &Path, label: &str, problems: &mut Vec<String>) {
+    let pem = match std::fs::read_to_string(path) {
+        Ok(pem) => pem,
+        Err(e) => {
+            problems.push(format!("{}: {}", path.display(), e));
+            return;
+        }
+    };
+    let mut found = false;
+    let mut lines = pem.lines().map(str::trim);
+    while let Some(line) = lines.next() {
+        if !line.starts_with("-----BEGIN ") || !line.ends_with("-----") {
+            continue;
+        }
+        let kind = &line[11..line.len() - 5];
+        let end = format!("-----END {}-----", kind);
+        let mut body = String::new();
+        let mut terminated = false;
+        for line in &mut lines {
+            if line == end {
+                terminated = true;
+                break;
+            }
+            // headers of encrypted keys, such as Proc-Type
+            if !line.contains(':') {
+                body.push_str(line);
+            }
+        }
+        if !terminated {
+            problems.push(format!("{}: unterminated {} block", path.display(), kind));
+            return;
+        }
+        match base64::decode(&body) {
+            Some(ref der) if is_der_sequence(der) => {}
+            _ => {
+                problems.push(format!(
+                    "{}: {} block is not valid DER",
+                    path.display(),
+                    kind
+                ));
+                return;
+            }
+        }
+        found |= kind.ends_with(label);
+    }
+    if !found {
+        problems.push(format!("{}: no PEM {} found", path.display(), label));
+    }
+}
+
+// a DER SEQUENCE whose encoded length covers exactly `der`
+fn is_der_sequence(der: &[u8]) -> bool {
+    if der.len() < 2 || der[0] != 0x30 {
+        return false;
+    }
+    let (header, len) = match der[1] {
+        len if len < 0x80 => (2, len as usize),
+        long => {
+            let n = (long & 0x7f) as usize;
+            if n == 0 || n > 4 || der.len() < 2 + n {
+                return false;
+            }
+            let len = der[2..2 + n]
+                .iter()
+                .fold(0usize, |len, &b| len << 8 | b as usize);
+            (2 + n, len)
+        }
+    };
+    der.len() - header == len
+}
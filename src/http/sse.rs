@@ -0,0 +1,143 @@
+use super::{Response, StatusCode};
+use crate::time::{self, Sleep};
+use futures::prelude::*;
+use std::{
+    pin::Pin,
+    task::{Context, Poll},
+    time::Duration,
+};
+
+// comment lines sent while the event stream is idle, so proxies don't time the connection out
+// and a vanished client shows up as a failed write
+pub const DEFAULT_KEEP_ALIVE: Duration = Duration::from_secs(15);
+
+// one `text/event-stream` message
+#[derive(Clone, Debug, Default)]
+pub struct Event {
+    event: Option<String>,
+    id: Option<String>,
+    retry: Option<Duration>,
+    data: String,
+}
+
+impl Event {
+    pub fn data<D: Into<String>>(data: D) -> Event {
+        Event {
+            data: data.into(),
+            ..Event::default()
+        }
+    }
+
+    pub fn event(mut self, name: &str) -> Event {
+        self.event = Some(single_line(name));
+        self
+    }
+
+    pub fn id(mut self, id: &str) -> Event {
+        self.id = Some(single_line(id));
+        self
+    }
+
+    // how long the client waits before reconnecting
+    pub fn retry(mut self, retry: Duration) -> Event {
+        self.retry = Some(retry);
+        self
+    }
+
+    pub fn encode(&self) -> Vec<u8> {
+        let mut out = String::new();
+        if let Some(event) = &self.event {
+            out.push_str(&format!("event: {}\n", event));
+        }
+        if let Some(id) = &self.id {
+            out.push_str(&format!("id: {}\n", id));
+        }
+        if let Some(retry) = self.retry {
+            out.push_str(&format!("retry: {}\n", retry.as_millis()));
+        }
+        // a line break inside the data would end the field, so each line gets its own; the
+        // client breaks lines at CRLF, a lone CR or a lone LF
+        for line in self
+            .data
+            .split("\r\n")
+            .flat_map(|l| l.split(|c| c == '\r' || c == '\n'))
+        {
+            out.push_str("data: ");
+            out.push_str(line);
+            out.push('\n');
+        }
+        out.push('\n');
+        out.into_bytes()
+    }
+}
+
+fn single_line(s: &str) -> String {
+    s.chars().filter(|&c| c != '\r' && c != '\n').collect()
+}
+
+// the encoded events of `S`, with a keep-alive comment whenever it stays quiet for too long
+pub struct SseBody<S> {
+    events: S,
+    keep_alive: Option<(Duration, Sleep)>,
+}
+
+impl<S: Stream<Item = Event> + Unpin> SseBody<S> {
+    pub fn new(events: S, keep_alive: Option<Duration>) -> SseBody<S> {
+        SseBody {
+            events,
+            keep_alive: keep_alive.map(|interval| (interval, time::sleep(interval))),
+        }
+    }
+}
+
+impl<S: Stream<Item = Event> + Unpin> Stream for SseBody<S> {
+    type Item = Vec<u8>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Vec<u8>>> {
+        let this = &mut *self;
+        let ready = match Pin::new(&mut this.events).poll_next(cx) {
+            Poll::Ready(Some(event)) => Some(event.encode()),
+            Poll::Ready(None) => return Poll::Ready(None),
+            Poll::Pending => None,
+        };
+        match &mut this.keep_alive {
+            Some((interval, sleep)) => {
+                if ready.is_some() {
+                    sleep.reset(time::now() + *interval);
+                } else if Pin::new(&mut *sleep).poll(cx).is_ready() {
+                    sleep.reset(time::now() + *interval);
+                    return Poll::Ready(Some(b":\n\n".to_vec()));
+                }
+            }
+            None => {}
+        }
+        match ready {
+            Some(bytes) => Poll::Ready(Some(bytes)),
+            None => Poll::Pending,
+        }
+    }
+}
+
+impl Response {
+    // a `text/event-stream` response sending the events of `events` as they come,
+    // with a keep-alive comment every 15 seconds of silence
+    pub fn sse<S>(events: S) -> Response
+    where
+        S: Stream<Item = Event> + 'static,
+    {
+        Self::sse_keep_alive(events, Some(DEFAULT_KEEP_ALIVE))
+    }
+
+    pub fn sse_keep_alive<S>(events: S, keep_alive: Option<Duration>) -> Response
+    where
+        S: Stream<Item = Event> + 'static,
+    {
+        let body = SseBody::new(events.boxed_local(), keep_alive);
+        let mut res = Response::with_stream(StatusCode::Ok, body);
+        res.set_header("Content-Type", "text/event-stream".to_owned());
+        res.set_header("Cache-Control", "no-cache".to_owned());
+        // nginx buffers proxied responses unless told otherwise
+        res.set_header("X-Accel-Buffering", "no".to_owned());
+        res
+    }
+}
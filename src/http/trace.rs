@@ -0,0 +1,105 @@
+// spans for `tracing` subscribers, built with the `tracing` feature: one per connection and
+// one per request, recording method, path, status and duration. without the feature these
+// add nothing.
+use super::{Request, Response};
+use std::{
+    future::Future,
+    net::SocketAddr,
+    pin::Pin,
+    task::{Context, Poll},
+};
+#[cfg(feature = "tracing")]
+use {std::time::Instant, tracing::field};
+
+#[cfg(feature = "tracing")]
+pub(crate) fn connection<F: Future>(
+    id: usize,
+    peer: Option<SocketAddr>,
+    fut: F,
+) -> impl Future<Output = F::Output> {
+    let peer = peer.map_or_else(|| "-".to_owned(), |addr| addr.to_string());
+    tracing::Instrument::instrument(fut, tracing::info_span!("connection", id, peer = %peer))
+}
+
+#[cfg(not(feature = "tracing"))]
+pub(crate) fn connection<F: Future>(_id: usize, _peer: Option<SocketAddr>, fut: F) -> F {
+    fut
+}
+
+// the span of one request, entered while its handler is created and polled
+pub(crate) struct RequestSpan {
+    #[cfg(feature = "tracing")]
+    span: tracing::Span,
+    #[cfg(feature = "tracing")]
+    start: Instant,
+}
+
+impl RequestSpan {
+    #[cfg(feature = "tracing")]
+    pub(crate) fn new(req: &Request) -> RequestSpan {
+        let path = req.uri().split('?').next().unwrap_or("");
+        let span = tracing::info_span!(
+            "request",
+            method = req.method(),
+            path,
+            status = field::Empty,
+            duration_us = field::Empty,
+        );
+        RequestSpan {
+            span,
+            start: Instant::now(),
+        }
+    }
+
+    #[cfg(not(feature = "tracing"))]
+    pub(crate) fn new(_req: &Request) -> RequestSpan {
+        RequestSpan {}
+    }
+
+    pub(crate) fn in_scope<T, F: FnOnce() -> T>(&self, f: F) -> T {
+        #[cfg(feature = "tracing")]
+        let _enter = self.span.enter();
+        f()
+    }
+
+    pub(crate) fn wrap<F>(self, fut: F) -> Traced<F> {
+        Traced { fut, span: self }
+    }
+
+    #[cfg(feature = "tracing")]
+    fn finish(&self, res: &Response) {
+        let elapsed = self.start.elapsed();
+        let micros = elapsed.as_secs() * 1_000_000 + u64::from(elapsed.subsec_micros());
+        let status = res.status_code() as u16;
+        self.span.record("status", &status);
+        self.span.record("duration_us", &micros);
+        tracing::debug!(status, duration_us = micros, "responded");
+    }
+
+    #[cfg(not(feature = "tracing"))]
+    fn finish(&self, _res: &Response) {}
+}
+
+// a handler's response future inside its request's span
+pub(crate) struct Traced<F> {
+    fut: F,
+    span: RequestSpan,
+}
+
+impl<F: Future<Output = Response>> Future for Traced<F> {
+    type Output = Response;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Response> {
+        // `fut` is structurally pinned
+        let this = unsafe { self.get_unchecked_mut() };
+        let fut = unsafe { Pin::new_unchecked(&mut this.fut) };
+        let span = &this.span;
+        span.in_scope(|| {
+            let res = fut.poll(cx);
+            if let Poll::Ready(res) = &res {
+                span.finish(res);
+            }
+            res
+        })
+    }
+}
@@ -0,0 +1,215 @@
+// W3C Trace Context: the `traceparent` and `tracestate` headers carrying a distributed trace
+// from service to service. `TraceContexts` picks up the context a request arrived with (or
+// starts a trace) and makes it available as `Request::trace_context`; `inject` passes it on
+// in requests the handler sends to other services.
+use super::middleware::BoxedApp;
+use super::{HttpApp, Request, Response};
+use crate::random;
+use futures::future::LocalBoxFuture;
+use log::*;
+use std::fmt;
+
+// the longest tracestate passed on; longer ones are dropped, as the spec allows
+const MAX_STATE_LEN: usize = 512;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct TraceContext {
+    trace_id: u128,
+    // the span that made the request this context came with, or ours once `child` is taken
+    parent_id: u64,
+    flags: u8,
+    state: Option<String>,
+}
+
+impl TraceContext {
+    // a new trace, sampled
+    pub fn new() -> TraceContext {
+        TraceContext {
+            trace_id: nonzero_u128(),
+            parent_id: nonzero_u64(),
+            flags: 1,
+            state: None,
+        }
+    }
+
+    // the context of the request; None if it has none or a malformed `traceparent`, in
+    // which case `tracestate` is ignored too
+    pub fn from_request(req: &Request) -> Option<TraceContext> {
+        let mut context = TraceContext::parse(req.header("traceparent")?)?;
+        context.state = req
+            .header("tracestate")
+            .map(str::trim)
+            .filter(|state| !state.is_empty() && state.len() <= MAX_STATE_LEN)
+            .map(str::to_owned);
+        Some(context)
+    }
+
+    // a `traceparent` value: version-traceid-parentid-flags, lowercase hex
+    pub fn parse(traceparent: &str) -> Option<TraceContext> {
+        let traceparent = traceparent.trim();
+        let fields = traceparent.splitn(5, '-').collect::<Vec<_>>();
+        if fields.len() < 4 || !fields[..4].iter().all(|f| is_lower_hex(f)) {
+            return None;
+        }
+        let (version, trace_id, parent_id, flags) = (fields[0], fields[1], fields[2], fields[3]);
+        // version 00 has exactly four fields; later versions may add more after them
+        if version.len() != 2 || version == "ff" || (version == "00" && fields.len() > 4) {
+            return None;
+        }
+        if trace_id.len() != 32 || parent_id.len() != 16 || flags.len() != 2 {
+            return None;
+        }
+        let trace_id = u128::from_str_radix(trace_id, 16).ok()?;
+        let parent_id = u64::from_str_radix(parent_id, 16).ok()?;
+        if trace_id == 0 || parent_id == 0 {
+            return None;
+        }
+        Some(TraceContext {
+            trace_id,
+            parent_id,
+            flags: u8::from_str_radix(flags, 16).ok()?,
+            state: None,
+        })
+    }
+
+    // the same trace, continued by a new span of ours
+    pub fn child(&self) -> TraceContext {
+        TraceContext {
+            parent_id: nonzero_u64(),
+            ..self.clone()
+        }
+    }
+
+    pub fn trace_id(&self) -> String {
+        format!("{:032x}", self.trace_id)
+    }
+
+    pub fn span_id(&self) -> String {
+        format!("{:016x}", self.parent_id)
+    }
+
+    pub fn sampled(&self) -> bool {
+        self.flags & 1 != 0
+    }
+
+    pub fn set_sampled(&mut self, sampled: bool) {
+        self.flags = if sampled {
+            self.flags | 1
+        } else {
+            self.flags & !1
+        };
+    }
+
+    // the vendor entries passed along unchanged
+    pub fn state(&self) -> Option<&str> {
+        self.state.as_ref().map(|s| &**s)
+    }
+
+    // sets `traceparent` and `tracestate` on a request to another service
+    pub fn inject(&self, req: &mut Request) {
+        req.set_header("traceparent", self.to_string());
+        match &self.state {
+            Some(state) => {
+                req.set_header("tracestate", state.clone());
+            }
+            None => {
+                req.remove_header("tracestate");
+            }
+        }
+    }
+}
+
+impl Default for TraceContext {
+    fn default() -> TraceContext {
+        TraceContext::new()
+    }
+}
+
+// the `traceparent` value
+impl fmt::Display for TraceContext {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "00-{:032x}-{:016x}-{:02x}",
+            self.trace_id, self.parent_id, self.flags
+        )
+    }
+}
+
+fn is_lower_hex(s: &str) -> bool {
+    s.bytes()
+        .all(|b| b.is_ascii_digit() || (b'a'..=b'f').contains(&b))
+}
+
+fn nonzero_u64() -> u64 {
+    loop {
+        let n = random::u64();
+        if n != 0 {
+            return n;
+        }
+    }
+}
+
+fn nonzero_u128() -> u128 {
+    u128::from(random::u64()) << 64 | u128::from(nonzero_u64())
+}
+
+// gives every request a trace context: the one it arrived with, continued by a span for
+// this server, or a new trace
+#[derive(Clone, Debug, Default)]
+pub struct TraceContexts {
+    start_unsampled: bool,
+}
+
+impl TraceContexts {
+    pub fn new() -> TraceContexts {
+        TraceContexts::default()
+    }
+
+    // traces started here are marked unsampled; those arriving keep their flag
+    pub fn start_unsampled(&mut self, unsampled: bool) -> &mut Self {
+        self.start_unsampled = unsampled;
+        self
+    }
+
+    pub fn wrap<T>(&self, app: T) -> WithTraceContext
+    where
+        T: HttpApp + 'static,
+        T::Output: 'static,
+    {
+        WithTraceContext {
+            config: self.clone(),
+            next: BoxedApp::new(app),
+        }
+    }
+}
+
+// see `TraceContexts::wrap`
+pub struct WithTraceContext {
+    config: TraceContexts,
+    next: BoxedApp,
+}
+
+impl HttpApp for WithTraceContext {
+    type Output = LocalBoxFuture<'static, Response>;
+
+    fn app(&self, mut req: Request) -> Self::Output {
+        let context = match TraceContext::from_request(&req) {
+            Some(context) => context.child(),
+            None => {
+                if req.header("traceparent").is_some() {
+                    debug!("ignoring malformed traceparent");
+                }
+                let mut context = TraceContext::new();
+                context.set_sampled(!self.config.start_unsampled);
+                context
+            }
+        };
+        req.trace_context = Some(context);
+        self.next.app(req)
+    }
+
+    fn check(&self, problems: &mut Vec<String>) {
+        self.next.check(problems);
+    }
+}
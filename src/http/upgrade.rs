@@ -0,0 +1,54 @@
+use crate::net::Socket;
+use futures::channel::oneshot;
+use std::{
+    future::Future,
+    io,
+    pin::Pin,
+    task::{Context, Poll},
+};
+
+// the connection of a request answered with 101 Switching Protocols, or a 2xx to CONNECT, taken
+// out of HTTP processing
+#[derive(Debug)]
+pub struct Upgraded {
+    pub sock: Socket,
+    // bytes the client sent after the request head, already read off `sock`
+    pub read_buf: Vec<u8>,
+}
+
+// returned by `Request::upgrade`; resolves once the response switching protocols has been flushed
+pub struct OnUpgrade {
+    rx: Option<oneshot::Receiver<Upgraded>>,
+}
+
+impl OnUpgrade {
+    pub(crate) fn pair() -> (oneshot::Sender<Upgraded>, OnUpgrade) {
+        let (tx, rx) = oneshot::channel();
+        (tx, OnUpgrade { rx: Some(rx) })
+    }
+
+    pub(crate) fn none() -> OnUpgrade {
+        OnUpgrade { rx: None }
+    }
+}
+
+impl Future for OnUpgrade {
+    type Output = io::Result<Upgraded>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let rx = match &mut self.rx {
+            Some(rx) => rx,
+            None => return Poll::Ready(Err(not_upgraded())),
+        };
+        match Pin::new(rx).poll(cx) {
+            Poll::Ready(Ok(upgraded)) => Poll::Ready(Ok(upgraded)),
+            // the response wasn't a 101, or the connection failed before it was sent
+            Poll::Ready(Err(_)) => Poll::Ready(Err(not_upgraded())),
+            Poll::Pending => Poll::Pending,
+        }
+    }
+}
+
+fn not_upgraded() -> io::Error {
+    io::Error::new(io::ErrorKind::Other, "connection was not upgraded")
+}
@@ -1,9 +1,22 @@
 #![feature(async_await)]
 #![feature(async_closure)]
 
+pub mod codec;
 pub mod fs;
 pub mod http;
+mod json;
 pub mod net;
+pub mod random;
 pub mod reactor;
+pub mod router;
 pub mod runner;
+pub mod runtime;
+#[cfg(unix)]
+pub mod signal;
 pub mod static_router;
+#[cfg(feature = "test-util")]
+pub mod test_util;
+pub mod time;
+pub mod wire;
+#[cfg(feature = "secure-buffers")]
+mod zero;
@@ -23,6 +23,10 @@ fn main() -> std::io::Result<()> {
         res
     })?;
     */
+    // dry run: validate the configuration and exit
+    if std::env::args().any(|arg| arg == "--check") {
+        return http.check();
+    }
     info!("http server listening on {}", &addr);
     http.run();
     Ok(())
@@ -1,31 +1,197 @@
-use crate::reactor;
+use crate::reactor::{self, Ready};
 use futures::prelude::*;
 use log::*;
-use mio::*;
 use std::io::{self, prelude::*};
 use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
 use std::pin::Pin;
+use std::rc::Rc;
 use std::task;
+use std::time::{Duration, Instant};
+
+pub mod resolve;
+#[cfg(unix)]
+pub mod systemd;
 
 pub struct TcpListener {
     listener: mio::net::TcpListener,
     reactor: reactor::ReactorHandle,
+    // set on accepted sockets; see `ListenerBuilder`
+    accepted: SocketOptions,
+}
+
+// a TCP listener with socket options chosen before it binds, e.g. SO_REUSEPORT so that
+// several worker processes can listen on one port, the kernel spreading connections over
+// them. `TcpListener::bind` is `ListenerBuilder::new().bind(addr)`.
+#[derive(Clone, Debug)]
+pub struct ListenerBuilder {
+    reuse_address: bool,
+    reuse_port: bool,
+    backlog: u32,
+    only_v6: Option<bool>,
+    accepted: SocketOptions,
+}
+
+impl Default for ListenerBuilder {
+    fn default() -> ListenerBuilder {
+        ListenerBuilder {
+            // on Windows SO_REUSEADDR lets another socket steal the port
+            reuse_address: cfg!(unix),
+            reuse_port: false,
+            backlog: 1024,
+            only_v6: None,
+            accepted: SocketOptions::default(),
+        }
+    }
+}
+
+impl ListenerBuilder {
+    pub fn new() -> ListenerBuilder {
+        ListenerBuilder::default()
+    }
+
+    // SO_REUSEADDR, binding while connections of a previous process are in TIME_WAIT; on by
+    // default on Unix
+    pub fn reuse_address(&mut self, reuse: bool) -> &mut Self {
+        self.reuse_address = reuse;
+        self
+    }
+
+    // SO_REUSEPORT, binding a port other sockets with the option are bound to; Unix only,
+    // `bind` fails elsewhere
+    pub fn reuse_port(&mut self, reuse: bool) -> &mut Self {
+        self.reuse_port = reuse;
+        self
+    }
+
+    // connections the OS queues until they are accepted, capped by it (somaxconn on Linux)
+    pub fn backlog(&mut self, backlog: u32) -> &mut Self {
+        self.backlog = backlog;
+        self
+    }
+
+    // IPV6_V6ONLY for an IPv6 address: whether `[::]` leaves IPv4 to another socket rather
+    // than taking IPv4 peers as mapped addresses; the OS default when unset
+    pub fn only_v6(&mut self, only: bool) -> &mut Self {
+        self.only_v6 = Some(only);
+        self
+    }
+
+    // TCP_NODELAY on accepted sockets
+    pub fn nodelay(&mut self, nodelay: bool) -> &mut Self {
+        self.accepted.nodelay(nodelay);
+        self
+    }
+
+    // TCP keepalive probes on accepted sockets once they have been idle for `idle`
+    pub fn keepalive(&mut self, idle: Duration) -> &mut Self {
+        self.accepted.keepalive(Some(idle));
+        self
+    }
+
+    // all the options for accepted sockets at once
+    pub fn accepted(&mut self, options: &SocketOptions) -> &mut Self {
+        self.accepted = options.clone();
+        self
+    }
+
+    pub fn bind(&self, addr: &SocketAddr) -> io::Result<TcpListener> {
+        let mut listener = TcpListener::from_std(self.listen(addr)?)?;
+        listener.accepted = self.accepted.clone();
+        Ok(listener)
+    }
+
+    // binds `addr` as `bind` would and releases it at once: a dry run that leaves the port
+    // free for the server it checks for
+    pub fn check(&self, addr: &SocketAddr) -> io::Result<()> {
+        self.listen(addr).map(drop)
+    }
+
+    fn listen(&self, addr: &SocketAddr) -> io::Result<std::net::TcpListener> {
+        let builder = match addr {
+            SocketAddr::V4(_) => net2::TcpBuilder::new_v4()?,
+            SocketAddr::V6(_) => net2::TcpBuilder::new_v6()?,
+        };
+        builder.reuse_address(self.reuse_address)?;
+        if self.reuse_port {
+            reuse_port(&builder)?;
+        }
+        if let (Some(only), SocketAddr::V6(_)) = (self.only_v6, addr) {
+            builder.only_v6(only)?;
+        }
+        builder.bind(addr)?;
+        let backlog = self.backlog.min(i32::max_value() as u32) as i32;
+        builder.listen(backlog)
+    }
+}
+
+#[cfg(unix)]
+fn reuse_port(builder: &net2::TcpBuilder) -> io::Result<()> {
+    use net2::unix::UnixTcpBuilderExt;
+    builder.reuse_port(true).map(|_| ())
+}
+
+#[cfg(not(unix))]
+fn reuse_port(_builder: &net2::TcpBuilder) -> io::Result<()> {
+    Err(io::Error::new(
+        io::ErrorKind::InvalidInput,
+        "SO_REUSEPORT is Unix only",
+    ))
 }
 
 impl TcpListener {
     pub fn bind(addr: &SocketAddr) -> io::Result<TcpListener> {
-        let listener = mio::net::TcpListener::bind(addr)?;
+        ListenerBuilder::new().bind(addr)
+    }
+
+    // a socket bound elsewhere, e.g. inherited from a service manager (see `systemd`); it is
+    // made non-blocking
+    pub fn from_std(listener: std::net::TcpListener) -> io::Result<TcpListener> {
+        listener.set_nonblocking(true)?;
+        TcpListener::from_mio(mio::net::TcpListener::from_std(listener))
+    }
+
+    // takes over `fd`, which must be a listening TCP socket nothing else owns
+    #[cfg(unix)]
+    pub unsafe fn from_raw_fd(fd: std::os::unix::io::RawFd) -> io::Result<TcpListener> {
+        use std::os::unix::io::FromRawFd;
+        TcpListener::from_std(std::net::TcpListener::from_raw_fd(fd))
+    }
+
+    fn from_mio(mut listener: mio::net::TcpListener) -> io::Result<TcpListener> {
         let tcp = TcpListener {
-            reactor: reactor::register(&listener, Ready::readable())?,
+            reactor: reactor::register(&mut listener, Ready::readable())?,
             listener,
+            accepted: SocketOptions::default(),
         };
         Ok(tcp)
     }
 
+    pub fn local_addr(&self) -> io::Result<SocketAddr> {
+        self.listener.local_addr()
+    }
+
+    // when connections started waiting to be accepted; None once they all are
+    pub fn readable_since(&self) -> Option<Instant> {
+        self.reactor.readable_since()
+    }
+
+    // connections waiting in the accept queue, where the OS tells: Linux does in TCP_INFO
+    pub fn backlog(&self) -> Option<usize> {
+        backlog(&self.listener)
+    }
+
     pub async fn accept(&self) -> io::Result<(TcpStream, SocketAddr)> {
         futures::future::poll_fn(|cx| self.poll_accept(cx)).await
     }
 
+    // the accepted connections as a stream that never ends, for stream combinators, e.g.
+    // `take_until(shutdown)` or `for_each_concurrent`. a failed accept is an item too: the
+    // stream goes on after it.
+    pub fn incoming(&self) -> Incoming<'_> {
+        Incoming { listener: self }
+    }
+
     pub fn poll_accept(
         &self,
         cx: &mut task::Context,
@@ -34,19 +200,123 @@ impl TcpListener {
             match self.listener.accept() {
                 Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => {
                     self.reactor.remove_readiness(Ready::readable());
-                    self.reactor.set_read_waker(cx.waker().clone());
+                    self.reactor.add_read_waker(cx.waker().clone());
                     task::Poll::Pending
                 }
-                Ok((sock, addr)) => task::Poll::Ready(Ok((TcpStream::from_mio(sock)?, addr))),
+                Ok((sock, addr)) => {
+                    let sock = TcpStream::from_mio(sock)?;
+                    // failing only for a peer that is gone already, which the first read sees
+                    if let Err(e) = self.accepted.apply(&sock) {
+                        debug!("setting options for {}: {}", addr, e);
+                    }
+                    task::Poll::Ready(Ok((sock, addr)))
+                }
                 Err(e) => task::Poll::Ready(Err(e)),
             }
         } else {
-            self.reactor.set_read_waker(cx.waker().clone());
+            self.reactor.add_read_waker(cx.waker().clone());
             task::Poll::Pending
         }
     }
 }
 
+impl Drop for TcpListener {
+    fn drop(&mut self) {
+        let _ = self.reactor.deregister(&mut self.listener);
+    }
+}
+
+// see `TcpListener::incoming`
+pub struct Incoming<'a> {
+    listener: &'a TcpListener,
+}
+
+impl Stream for Incoming<'_> {
+    type Item = io::Result<TcpStream>;
+
+    fn poll_next(
+        self: Pin<&mut Self>,
+        cx: &mut task::Context,
+    ) -> task::Poll<Option<io::Result<TcpStream>>> {
+        self.listener
+            .poll_accept(cx)
+            .map(|accepted| Some(accepted.map(|(sock, _)| sock)))
+    }
+}
+
+// options to set on TCP sockets, e.g. every one a server accepts; those not set are left as
+// the OS has them
+#[derive(Clone, Debug, Default)]
+pub struct SocketOptions {
+    nodelay: Option<bool>,
+    keepalive: Option<Option<Duration>>,
+    ttl: Option<u32>,
+    linger: Option<Option<Duration>>,
+    recv_buffer_size: Option<usize>,
+    send_buffer_size: Option<usize>,
+}
+
+impl SocketOptions {
+    pub fn new() -> SocketOptions {
+        SocketOptions::default()
+    }
+
+    pub fn nodelay(&mut self, nodelay: bool) -> &mut Self {
+        self.nodelay = Some(nodelay);
+        self
+    }
+
+    // None turns keepalive probes off
+    pub fn keepalive(&mut self, idle: Option<Duration>) -> &mut Self {
+        self.keepalive = Some(idle);
+        self
+    }
+
+    pub fn ttl(&mut self, ttl: u32) -> &mut Self {
+        self.ttl = Some(ttl);
+        self
+    }
+
+    // None turns lingering off
+    pub fn linger(&mut self, linger: Option<Duration>) -> &mut Self {
+        self.linger = Some(linger);
+        self
+    }
+
+    pub fn recv_buffer_size(&mut self, size: usize) -> &mut Self {
+        self.recv_buffer_size = Some(size);
+        self
+    }
+
+    pub fn send_buffer_size(&mut self, size: usize) -> &mut Self {
+        self.send_buffer_size = Some(size);
+        self
+    }
+
+    // stops at the first option that can't be set
+    pub fn apply(&self, sock: &TcpStream) -> io::Result<()> {
+        if let Some(nodelay) = self.nodelay {
+            sock.set_nodelay(nodelay)?;
+        }
+        if let Some(idle) = self.keepalive {
+            sock.set_keepalive(idle)?;
+        }
+        if let Some(ttl) = self.ttl {
+            sock.set_ttl(ttl)?;
+        }
+        if let Some(linger) = self.linger {
+            sock.set_linger(linger)?;
+        }
+        if let Some(size) = self.recv_buffer_size {
+            sock.set_recv_buffer_size(size)?;
+        }
+        if let Some(size) = self.send_buffer_size {
+            sock.set_send_buffer_size(size)?;
+        }
+        Ok(())
+    }
+}
+
 #[derive(Debug)]
 pub struct TcpStream {
     sock: mio::net::TcpStream,
@@ -54,83 +324,1071 @@ pub struct TcpStream {
 }
 
 impl TcpStream {
-    pub fn from_mio(sock: mio::net::TcpStream) -> io::Result<TcpStream> {
+    pub fn from_mio(mut sock: mio::net::TcpStream) -> io::Result<TcpStream> {
         let tcp = TcpStream {
-            reactor: reactor::register(&sock, Ready::readable() | Ready::writable())?,
+            reactor: reactor::register(&mut sock, Ready::readable() | Ready::writable())?,
             sock,
         };
         Ok(tcp)
     }
 
+    // resolves once the non-blocking connect has finished, with the error SO_ERROR holds if it
+    // failed, e.g. ConnectionRefused
+    pub async fn connect(addr: &SocketAddr) -> io::Result<TcpStream> {
+        let tcp = TcpStream::from_mio(mio::net::TcpStream::connect(*addr)?)?;
+        futures::future::poll_fn(|cx| tcp.poll_connected(cx)).await?;
+        Ok(tcp)
+    }
+
+    // `host` is a name or an IP literal; names go through the thread's default resolver
+    pub async fn connect_host(host: &str, port: u16) -> io::Result<TcpStream> {
+        let resolver = resolve::default_resolver();
+        TcpStream::connect_with(&*resolver, host, port).await
+    }
+
+    // tries every address `host` resolves to in turn, failing with the last error
+    pub async fn connect_with(
+        resolver: &dyn resolve::Resolve,
+        host: &str,
+        port: u16,
+    ) -> io::Result<TcpStream> {
+        let mut last_err = None;
+        for addr in resolve::resolve(resolver, host, port).await? {
+            match TcpStream::connect(&addr).await {
+                Ok(tcp) => return Ok(tcp),
+                Err(e) => {
+                    debug!("connect {}: {}", addr, e);
+                    last_err = Some(e);
+                }
+            }
+        }
+        Err(last_err.unwrap_or_else(|| io::ErrorKind::NotFound.into()))
+    }
+
+    // a non-blocking connect has finished once the socket turns writable
+    fn poll_connected(&self, cx: &mut task::Context) -> task::Poll<io::Result<()>> {
+        if self.reactor.readiness().is_writable() {
+            match self.sock.take_error()? {
+                Some(e) => task::Poll::Ready(Err(e)),
+                None => task::Poll::Ready(Ok(())),
+            }
+        } else {
+            self.reactor.add_write_waker(cx.waker().clone());
+            task::Poll::Pending
+        }
+    }
+
     pub fn peer_addr(&self) -> io::Result<std::net::SocketAddr> {
         self.sock.peer_addr()
     }
-}
 
-impl AsyncRead for TcpStream {
-    fn poll_read(
-        mut self: Pin<&mut Self>,
+    // whether a connection left idle can't take another request: the peer closed it, or sent
+    // something nobody asked for. for pools of keep-alive connections; never waits.
+    pub(crate) fn is_stale(&self) -> bool {
+        match self.sock.peek(&mut [0]) {
+            Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => false,
+            _ => true,
+        }
+    }
+
+    // `Shutdown::Write` sends FIN while reads go on, as closing the stream (`AsyncWriteExt::
+    // close`) does; the peer reads the end of the stream, e.g. of a request framed by it,
+    // and can still answer. shutting down never waits, so this isn't async.
+    pub fn shutdown(&self, how: std::net::Shutdown) -> io::Result<()> {
+        self.sock.shutdown(how)
+    }
+
+    // TCP_NODELAY: small writes go out at once rather than waiting to be coalesced
+    pub fn set_nodelay(&self, nodelay: bool) -> io::Result<()> {
+        self.sock.set_nodelay(nodelay)
+    }
+
+    pub fn nodelay(&self) -> io::Result<bool> {
+        self.sock.nodelay()
+    }
+
+    // probes a peer that has been silent for `idle`, so that dead connections get noticed;
+    // None turns probing off
+    pub fn set_keepalive(&self, idle: Option<Duration>) -> io::Result<()> {
+        with_std(&self.sock, |sock| {
+            net2::TcpStreamExt::set_keepalive(sock, idle)
+        })
+    }
+
+    pub fn keepalive(&self) -> io::Result<Option<Duration>> {
+        with_std(&self.sock, |sock| net2::TcpStreamExt::keepalive(sock))
+    }
+
+    pub fn set_ttl(&self, ttl: u32) -> io::Result<()> {
+        self.sock.set_ttl(ttl)
+    }
+
+    pub fn ttl(&self) -> io::Result<u32> {
+        self.sock.ttl()
+    }
+
+    // SO_LINGER: how long closing waits for unsent data; `Some(Duration::from_secs(0))`
+    // resets the connection instead
+    pub fn set_linger(&self, linger: Option<Duration>) -> io::Result<()> {
+        with_std(&self.sock, |sock| {
+            net2::TcpStreamExt::set_linger(sock, linger)
+        })
+    }
+
+    pub fn linger(&self) -> io::Result<Option<Duration>> {
+        with_std(&self.sock, |sock| net2::TcpStreamExt::linger(sock))
+    }
+
+    // SO_RCVBUF; the OS may round or double it
+    pub fn set_recv_buffer_size(&self, size: usize) -> io::Result<()> {
+        with_std(&self.sock, |sock| {
+            net2::TcpStreamExt::set_recv_buffer_size(sock, size)
+        })
+    }
+
+    pub fn recv_buffer_size(&self) -> io::Result<usize> {
+        with_std(&self.sock, |sock| {
+            net2::TcpStreamExt::recv_buffer_size(sock)
+        })
+    }
+
+    // SO_SNDBUF; the OS may round or double it
+    pub fn set_send_buffer_size(&self, size: usize) -> io::Result<()> {
+        with_std(&self.sock, |sock| {
+            net2::TcpStreamExt::set_send_buffer_size(sock, size)
+        })
+    }
+
+    pub fn send_buffer_size(&self) -> io::Result<usize> {
+        with_std(&self.sock, |sock| {
+            net2::TcpStreamExt::send_buffer_size(sock)
+        })
+    }
+
+    // writes up to `len` bytes of `file` from `offset`, handed from the page cache to the
+    // socket without a copy through user space where the OS allows (sendfile on Linux).
+    // resolves to the bytes sent, 0 at the end of the file.
+    pub async fn send_file(
+        &self,
+        file: &std::fs::File,
+        offset: u64,
+        len: usize,
+    ) -> io::Result<usize> {
+        futures::future::poll_fn(|cx| self.poll_send_file(cx, file, offset, len)).await
+    }
+
+    pub fn poll_send_file(
+        &self,
         cx: &mut task::Context,
-        buf: &mut [u8],
+        file: &std::fs::File,
+        offset: u64,
+        len: usize,
     ) -> task::Poll<io::Result<usize>> {
-        trace!("poll_read");
-        if self.reactor.readiness().is_readable() {
-            match self.sock.read(buf) {
+        trace!("poll_send_file ({} at {})", len, offset);
+        if self.reactor.readiness().is_writable() {
+            match send_file(&self.sock, file, offset, len) {
                 Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => {
-                    self.reactor.remove_readiness(Ready::readable());
-                    self.reactor.set_read_waker(cx.waker().clone());
+                    self.reactor.remove_readiness(Ready::writable());
+                    self.reactor.add_write_waker(cx.waker().clone());
                     task::Poll::Pending
                 }
-                res => {
-                    self.reactor.reset_read_waker();
-                    task::Poll::Ready(res)
-                }
+                res => task::Poll::Ready(res),
             }
         } else {
-            self.reactor.set_read_waker(cx.waker().clone());
+            self.reactor.add_write_waker(cx.waker().clone());
             task::Poll::Pending
         }
     }
 }
 
-impl AsyncWrite for TcpStream {
-    fn poll_write(
-        mut self: Pin<&mut Self>,
+#[cfg(all(target_os = "linux", target_pointer_width = "64"))]
+fn send_file(
+    sock: &mio::net::TcpStream,
+    file: &std::fs::File,
+    offset: u64,
+    len: usize,
+) -> io::Result<usize> {
+    use std::os::unix::io::AsRawFd;
+    extern "C" {
+        fn sendfile(out_fd: i32, in_fd: i32, offset: *mut i64, count: usize) -> isize;
+    }
+    let mut offset = offset as i64;
+    let sent = unsafe { sendfile(sock.as_raw_fd(), file.as_raw_fd(), &mut offset, len) };
+    if sent < 0 {
+        Err(io::Error::last_os_error())
+    } else {
+        Ok(sent as usize)
+    }
+}
+
+// a bounded read and write where sendfile isn't available
+#[cfg(not(all(target_os = "linux", target_pointer_width = "64")))]
+fn send_file(
+    mut sock: &mio::net::TcpStream,
+    mut file: &std::fs::File,
+    offset: u64,
+    len: usize,
+) -> io::Result<usize> {
+    let mut buf = vec![0; len.min(64 * 1024)];
+    file.seek(io::SeekFrom::Start(offset))?;
+    let read = file.read(&mut buf)?;
+    sock.write(&buf[..read])
+}
+
+// closing a stream whose peer is gone already is no error: there is no one left to tell
+fn shutdown_write(res: io::Result<()>) -> io::Result<()> {
+    match res {
+        Err(ref e) if e.kind() == io::ErrorKind::NotConnected => Ok(()),
+        res => res,
+    }
+}
+
+// mio leaves out the less common socket options; net2 sets them on a std stream, borrowed
+// over the same socket
+#[cfg(unix)]
+fn with_std<T>(sock: &mio::net::TcpStream, op: impl FnOnce(&std::net::TcpStream) -> T) -> T {
+    use std::os::unix::io::{AsRawFd, FromRawFd};
+    // borrowed: the fd stays mio's to close
+    let std =
+        std::mem::ManuallyDrop::new(unsafe { std::net::TcpStream::from_raw_fd(sock.as_raw_fd()) });
+    op(&std)
+}
+
+#[cfg(windows)]
+fn with_std<T>(sock: &mio::net::TcpStream, op: impl FnOnce(&std::net::TcpStream) -> T) -> T {
+    use std::os::windows::io::{AsRawSocket, FromRawSocket};
+    let std = std::mem::ManuallyDrop::new(unsafe {
+        std::net::TcpStream::from_raw_socket(sock.as_raw_socket())
+    });
+    op(&std)
+}
+
+#[cfg(target_os = "linux")]
+fn backlog(listener: &mio::net::TcpListener) -> Option<usize> {
+    use std::os::unix::io::AsRawFd;
+    const IPPROTO_TCP: i32 = 6;
+    const TCP_INFO: i32 = 11;
+    extern "C" {
+        fn getsockopt(fd: i32, level: i32, name: i32, value: *mut u8, len: *mut u32) -> i32;
+    }
+    // struct tcp_info: eight u8 fields, then u32s; for a listening socket tcpi_unacked,
+    // the seventh field, counts the connections waiting in the accept queue
+    let mut info = [0u32; 64];
+    let mut len = std::mem::size_of_val(&info) as u32;
+    let ret = unsafe {
+        getsockopt(
+            listener.as_raw_fd(),
+            IPPROTO_TCP,
+            TCP_INFO,
+            info.as_mut_ptr() as *mut u8,
+            &mut len,
+        )
+    };
+    if ret != 0 || len < 28 {
+        return None;
+    }
+    Some(info[6] as usize)
+}
+
+#[cfg(not(target_os = "linux"))]
+fn backlog(_listener: &mio::net::TcpListener) -> Option<usize> {
+    None
+}
+
+// reads and writes take `&self`, so that the halves of a split stream share it
+impl TcpStream {
+    fn poll_read_shared(
+        &self,
+        cx: &mut task::Context,
+        buf: &mut [u8],
+    ) -> task::Poll<io::Result<usize>> {
+        trace!("poll_read");
+        self.poll_io(cx, Ready::readable(), |mut sock| sock.read(buf))
+    }
+
+    fn poll_read_vectored_shared(
+        &self,
+        cx: &mut task::Context,
+        bufs: &mut [io::IoSliceMut],
+    ) -> task::Poll<io::Result<usize>> {
+        trace!("poll_read_vectored ({} buffers)", bufs.len());
+        self.poll_io(cx, Ready::readable(), |mut sock| sock.read_vectored(bufs))
+    }
+
+    // reads into `buf` what a read would, but leaves it to be read again, e.g. to tell a TLS
+    // handshake from plain HTTP before handing the stream on. resolves once there is at
+    // least a byte, or the end of the stream (0).
+    pub async fn peek(&self, buf: &mut [u8]) -> io::Result<usize> {
+        futures::future::poll_fn(|cx| self.poll_peek(cx, buf)).await
+    }
+
+    pub fn poll_peek(
+        &self,
+        cx: &mut task::Context,
+        buf: &mut [u8],
+    ) -> task::Poll<io::Result<usize>> {
+        trace!("poll_peek");
+        self.poll_io(cx, Ready::readable(), |sock| sock.peek(buf))
+    }
+
+    fn poll_write_shared(
+        &self,
         cx: &mut task::Context,
         buf: &[u8],
     ) -> task::Poll<io::Result<usize>> {
         trace!("poll_write ({})", buf.len());
-        if self.reactor.readiness().is_writable() {
-            match self.sock.write(buf) {
-                Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => {
-                    self.reactor.remove_readiness(Ready::writable());
-                    self.reactor.set_write_waker(cx.waker().clone());
-                    task::Poll::Pending
-                }
-                res => {
-                    self.reactor.reset_write_waker();
-                    task::Poll::Ready(res)
-                }
+        self.poll_io(cx, Ready::writable(), |mut sock| sock.write(buf))
+    }
+
+    fn poll_write_vectored_shared(
+        &self,
+        cx: &mut task::Context,
+        bufs: &[io::IoSlice],
+    ) -> task::Poll<io::Result<usize>> {
+        trace!(
+            "poll_write_vectored ({})",
+            bufs.iter().map(|buf| buf.len()).sum::<usize>()
+        );
+        self.poll_io(cx, Ready::writable(), |mut sock| sock.write_vectored(bufs))
+    }
+
+    // runs `op`, a non-blocking read or write, once the socket is ready for it
+    fn poll_io<T>(
+        &self,
+        cx: &mut task::Context,
+        interest: Ready,
+        mut op: impl FnMut(&mio::net::TcpStream) -> io::Result<T>,
+    ) -> task::Poll<io::Result<T>> {
+        let reactor = &self.reactor;
+        let set_waker = || {
+            if interest.is_readable() {
+                reactor.add_read_waker(cx.waker().clone());
+            } else {
+                reactor.add_write_waker(cx.waker().clone());
             }
-        } else {
-            self.reactor.set_write_waker(cx.waker().clone());
-            task::Poll::Pending
+        };
+        if !reactor.readiness().contains(interest) {
+            set_waker();
+            return task::Poll::Pending;
+        }
+        match op(&self.sock) {
+            Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => {
+                reactor.remove_readiness(interest);
+                set_waker();
+                task::Poll::Pending
+            }
+            res => task::Poll::Ready(res),
         }
     }
 
+    fn poll_close_shared(&self) -> task::Poll<io::Result<()>> {
+        task::Poll::Ready(shutdown_write(
+            self.sock.shutdown(std::net::Shutdown::Write),
+        ))
+    }
+
+    // halves borrowing the stream, to read and write at once within one task, e.g. in
+    // `future::join`
+    pub fn split(&self) -> (ReadHalf<'_>, WriteHalf<'_>) {
+        (ReadHalf(self), WriteHalf(self))
+    }
+
+    // halves owning the stream between them, for a reading and a writing task. each wakes
+    // its task alone, as the reactor keeps a read and a write waker per socket. the socket
+    // closes when both are dropped.
+    pub fn into_split(self) -> (OwnedReadHalf, OwnedWriteHalf) {
+        let tcp = Rc::new(self);
+        (OwnedReadHalf(Rc::clone(&tcp)), OwnedWriteHalf(tcp))
+    }
+}
+
+impl AsyncRead for TcpStream {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut task::Context,
+        buf: &mut [u8],
+    ) -> task::Poll<io::Result<usize>> {
+        self.poll_read_shared(cx, buf)
+    }
+
+    fn poll_read_vectored(
+        self: Pin<&mut Self>,
+        cx: &mut task::Context,
+        bufs: &mut [io::IoSliceMut],
+    ) -> task::Poll<io::Result<usize>> {
+        self.poll_read_vectored_shared(cx, bufs)
+    }
+}
+
+impl AsyncWrite for TcpStream {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut task::Context,
+        buf: &[u8],
+    ) -> task::Poll<io::Result<usize>> {
+        self.poll_write_shared(cx, buf)
+    }
+
+    fn poll_write_vectored(
+        self: Pin<&mut Self>,
+        cx: &mut task::Context,
+        bufs: &[io::IoSlice],
+    ) -> task::Poll<io::Result<usize>> {
+        self.poll_write_vectored_shared(cx, bufs)
+    }
+
     fn poll_flush(self: Pin<&mut Self>, _cx: &mut task::Context) -> task::Poll<io::Result<()>> {
         task::Poll::Ready(Ok(()))
     }
 
     fn poll_close(self: Pin<&mut Self>, _cx: &mut task::Context) -> task::Poll<io::Result<()>> {
-        self.reactor.reset_write_waker();
-        task::Poll::Ready(Ok(()))
+        self.poll_close_shared()
     }
 }
 
-impl Drop for TcpStream {
-    fn drop(&mut self) {
-        trace!("TcpStream dropped");
-        let _ = self.reactor.deregister(&self.sock);
+// see `TcpStream::split`
+pub struct ReadHalf<'a>(&'a TcpStream);
+
+// see `TcpStream::split`
+pub struct WriteHalf<'a>(&'a TcpStream);
+
+// see `TcpStream::into_split`
+#[derive(Debug)]
+pub struct OwnedReadHalf(Rc<TcpStream>);
+
+// see `TcpStream::into_split`
+#[derive(Debug)]
+pub struct OwnedWriteHalf(Rc<TcpStream>);
+
+impl OwnedReadHalf {
+    // the stream back, or the halves if they weren't split from one stream
+    pub fn reunite(self, write: OwnedWriteHalf) -> Result<TcpStream, (Self, OwnedWriteHalf)> {
+        if !Rc::ptr_eq(&self.0, &write.0) {
+            return Err((self, write));
+        }
+        drop(write);
+        Ok(Rc::try_unwrap(self.0).expect("both halves were given"))
+    }
+
+    pub fn peer_addr(&self) -> io::Result<SocketAddr> {
+        self.0.peer_addr()
+    }
+}
+
+impl OwnedWriteHalf {
+    pub fn peer_addr(&self) -> io::Result<SocketAddr> {
+        self.0.peer_addr()
+    }
+
+    // `Shutdown::Write`: the peer reads the end of the stream while this side still reads
+    pub fn shutdown(&self) -> io::Result<()> {
+        self.0.shutdown(std::net::Shutdown::Write)
+    }
+}
+
+impl AsyncRead for ReadHalf<'_> {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut task::Context,
+        buf: &mut [u8],
+    ) -> task::Poll<io::Result<usize>> {
+        self.0.poll_read_shared(cx, buf)
+    }
+
+    fn poll_read_vectored(
+        self: Pin<&mut Self>,
+        cx: &mut task::Context,
+        bufs: &mut [io::IoSliceMut],
+    ) -> task::Poll<io::Result<usize>> {
+        self.0.poll_read_vectored_shared(cx, bufs)
+    }
+}
+
+impl AsyncRead for OwnedReadHalf {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut task::Context,
+        buf: &mut [u8],
+    ) -> task::Poll<io::Result<usize>> {
+        self.0.poll_read_shared(cx, buf)
+    }
+
+    fn poll_read_vectored(
+        self: Pin<&mut Self>,
+        cx: &mut task::Context,
+        bufs: &mut [io::IoSliceMut],
+    ) -> task::Poll<io::Result<usize>> {
+        self.0.poll_read_vectored_shared(cx, bufs)
+    }
+}
+
+impl AsyncWrite for WriteHalf<'_> {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut task::Context,
+        buf: &[u8],
+    ) -> task::Poll<io::Result<usize>> {
+        self.0.poll_write_shared(cx, buf)
+    }
+
+    fn poll_write_vectored(
+        self: Pin<&mut Self>,
+        cx: &mut task::Context,
+        bufs: &[io::IoSlice],
+    ) -> task::Poll<io::Result<usize>> {
+        self.0.poll_write_vectored_shared(cx, bufs)
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, _cx: &mut task::Context) -> task::Poll<io::Result<()>> {
+        task::Poll::Ready(Ok(()))
+    }
+
+    fn poll_close(self: Pin<&mut Self>, _cx: &mut task::Context) -> task::Poll<io::Result<()>> {
+        self.0.poll_close_shared()
+    }
+}
+
+impl AsyncWrite for OwnedWriteHalf {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut task::Context,
+        buf: &[u8],
+    ) -> task::Poll<io::Result<usize>> {
+        self.0.poll_write_shared(cx, buf)
+    }
+
+    fn poll_write_vectored(
+        self: Pin<&mut Self>,
+        cx: &mut task::Context,
+        bufs: &[io::IoSlice],
+    ) -> task::Poll<io::Result<usize>> {
+        self.0.poll_write_vectored_shared(cx, bufs)
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, _cx: &mut task::Context) -> task::Poll<io::Result<()>> {
+        task::Poll::Ready(Ok(()))
+    }
+
+    fn poll_close(self: Pin<&mut Self>, _cx: &mut task::Context) -> task::Poll<io::Result<()>> {
+        self.0.poll_close_shared()
+    }
+}
+
+impl Drop for TcpStream {
+    fn drop(&mut self) {
+        trace!("TcpStream dropped");
+        let _ = self.reactor.deregister(&mut self.sock);
+    }
+}
+
+// datagrams to and from any peer, or, once `connect`ed, one peer with `send` and `recv`
+pub struct UdpSocket {
+    sock: mio::net::UdpSocket,
+    reactor: reactor::ReactorHandle,
+}
+
+impl UdpSocket {
+    pub fn bind(addr: &SocketAddr) -> io::Result<UdpSocket> {
+        UdpSocket::from_mio(mio::net::UdpSocket::bind(*addr)?)
+    }
+
+    // made non-blocking
+    pub fn from_std(sock: std::net::UdpSocket) -> io::Result<UdpSocket> {
+        sock.set_nonblocking(true)?;
+        UdpSocket::from_mio(mio::net::UdpSocket::from_std(sock))
+    }
+
+    fn from_mio(mut sock: mio::net::UdpSocket) -> io::Result<UdpSocket> {
+        let udp = UdpSocket {
+            reactor: reactor::register(&mut sock, Ready::readable() | Ready::writable())?,
+            sock,
+        };
+        Ok(udp)
+    }
+
+    pub fn local_addr(&self) -> io::Result<SocketAddr> {
+        self.sock.local_addr()
+    }
+
+    // sets the peer `send` sends to, and the only one `recv` takes datagrams from
+    pub fn connect(&self, addr: &SocketAddr) -> io::Result<()> {
+        self.sock.connect(*addr)
+    }
+
+    pub fn set_broadcast(&self, on: bool) -> io::Result<()> {
+        self.sock.set_broadcast(on)
+    }
+
+    // resolves to the bytes sent, all of `buf` or an error, as datagrams aren't split
+    pub async fn send_to(&self, buf: &[u8], target: &SocketAddr) -> io::Result<usize> {
+        futures::future::poll_fn(|cx| self.poll_send_to(cx, buf, target)).await
+    }
+
+    pub fn poll_send_to(
+        &self,
+        cx: &mut task::Context,
+        buf: &[u8],
+        target: &SocketAddr,
+    ) -> task::Poll<io::Result<usize>> {
+        self.poll_io(cx, Ready::writable(), |sock| sock.send_to(buf, *target))
+    }
+
+    // a datagram longer than `buf` is cut short, the rest discarded
+    pub async fn recv_from(&self, buf: &mut [u8]) -> io::Result<(usize, SocketAddr)> {
+        futures::future::poll_fn(|cx| self.poll_recv_from(cx, buf)).await
+    }
+
+    pub fn poll_recv_from(
+        &self,
+        cx: &mut task::Context,
+        buf: &mut [u8],
+    ) -> task::Poll<io::Result<(usize, SocketAddr)>> {
+        self.poll_io(cx, Ready::readable(), |sock| sock.recv_from(buf))
+    }
+
+    pub async fn send(&self, buf: &[u8]) -> io::Result<usize> {
+        futures::future::poll_fn(|cx| self.poll_io(cx, Ready::writable(), |sock| sock.send(buf)))
+            .await
+    }
+
+    pub async fn recv(&self, buf: &mut [u8]) -> io::Result<usize> {
+        futures::future::poll_fn(|cx| self.poll_io(cx, Ready::readable(), |sock| sock.recv(buf)))
+            .await
+    }
+
+    // runs `op`, a non-blocking send or receive, once the socket is ready for it
+    fn poll_io<T>(
+        &self,
+        cx: &mut task::Context,
+        interest: Ready,
+        mut op: impl FnMut(&mio::net::UdpSocket) -> io::Result<T>,
+    ) -> task::Poll<io::Result<T>> {
+        let reactor = &self.reactor;
+        let set_waker = || {
+            if interest.is_readable() {
+                reactor.add_read_waker(cx.waker().clone());
+            } else {
+                reactor.add_write_waker(cx.waker().clone());
+            }
+        };
+        if !reactor.readiness().contains(interest) {
+            set_waker();
+            return task::Poll::Pending;
+        }
+        match op(&self.sock) {
+            Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => {
+                reactor.remove_readiness(interest);
+                set_waker();
+                task::Poll::Pending
+            }
+            res => task::Poll::Ready(res),
+        }
+    }
+}
+
+impl Drop for UdpSocket {
+    fn drop(&mut self) {
+        trace!("UdpSocket dropped");
+        let _ = self.reactor.deregister(&mut self.sock);
+    }
+}
+
+// a listening Unix domain socket, e.g. for serving behind a web server on the same host
+#[cfg(unix)]
+pub struct UnixListener {
+    listener: std::os::unix::net::UnixListener,
+    reactor: reactor::ReactorHandle,
+    // the socket file `bind` made, removed again on drop
+    path: Option<PathBuf>,
+}
+
+#[cfg(unix)]
+impl UnixListener {
+    // fails if `path` exists; see `HttpServer::bind_unix` for replacing stale sockets
+    pub fn bind<P: AsRef<Path>>(path: P) -> io::Result<UnixListener> {
+        let path = path.as_ref();
+        let mut listener = UnixListener::from_std(std::os::unix::net::UnixListener::bind(path)?)?;
+        listener.path = Some(path.to_path_buf());
+        Ok(listener)
+    }
+
+    // a socket bound elsewhere, e.g. inherited from a service manager; it is made
+    // non-blocking
+    pub fn from_std(listener: std::os::unix::net::UnixListener) -> io::Result<UnixListener> {
+        listener.set_nonblocking(true)?;
+        let reactor = reactor::register(&listener, Ready::readable())?;
+        Ok(UnixListener {
+            listener,
+            reactor,
+            path: None,
+        })
+    }
+
+    // takes over `fd`, which must be a listening Unix socket nothing else owns
+    pub unsafe fn from_raw_fd(fd: std::os::unix::io::RawFd) -> io::Result<UnixListener> {
+        use std::os::unix::io::FromRawFd;
+        UnixListener::from_std(std::os::unix::net::UnixListener::from_raw_fd(fd))
+    }
+
+    pub fn local_addr(&self) -> io::Result<std::os::unix::net::SocketAddr> {
+        self.listener.local_addr()
+    }
+
+    // sets the permissions of the socket's file, e.g. 0o660 to let only a group connect
+    pub fn set_mode(&self, mode: u32) -> io::Result<()> {
+        use std::os::unix::fs::PermissionsExt;
+        let addr = self.listener.local_addr()?;
+        let path = addr.as_pathname().ok_or_else(|| {
+            io::Error::new(io::ErrorKind::InvalidInput, "unix socket without a path")
+        })?;
+        std::fs::set_permissions(path, std::fs::Permissions::from_mode(mode))
+    }
+
+    // when connections started waiting to be accepted; None once they all are
+    pub fn readable_since(&self) -> Option<Instant> {
+        self.reactor.readable_since()
+    }
+
+    pub async fn accept(&self) -> io::Result<(UnixStream, std::os::unix::net::SocketAddr)> {
+        futures::future::poll_fn(|cx| self.poll_accept(cx)).await
+    }
+
+    pub fn poll_accept(
+        &self,
+        cx: &mut task::Context,
+    ) -> task::Poll<io::Result<(UnixStream, std::os::unix::net::SocketAddr)>> {
+        if self.reactor.readiness().is_readable() {
+            match self.listener.accept() {
+                Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => {
+                    self.reactor.remove_readiness(Ready::readable());
+                    self.reactor.add_read_waker(cx.waker().clone());
+                    task::Poll::Pending
+                }
+                Ok((sock, addr)) => task::Poll::Ready(Ok((UnixStream::from_std(sock)?, addr))),
+                Err(e) => task::Poll::Ready(Err(e)),
+            }
+        } else {
+            self.reactor.add_read_waker(cx.waker().clone());
+            task::Poll::Pending
+        }
+    }
+}
+
+#[cfg(unix)]
+impl Drop for UnixListener {
+    fn drop(&mut self) {
+        let _ = self.reactor.deregister(&self.listener);
+        if let Some(path) = &self.path {
+            let _ = std::fs::remove_file(path);
+        }
+    }
+}
+
+#[cfg(unix)]
+#[derive(Debug)]
+pub struct UnixStream {
+    sock: std::os::unix::net::UnixStream,
+    reactor: reactor::ReactorHandle,
+}
+
+#[cfg(unix)]
+impl UnixStream {
+    // made non-blocking
+    pub fn from_std(sock: std::os::unix::net::UnixStream) -> io::Result<UnixStream> {
+        sock.set_nonblocking(true)?;
+        let reactor = reactor::register(&sock, Ready::readable() | Ready::writable())?;
+        Ok(UnixStream { sock, reactor })
+    }
+
+    // connecting to a local socket doesn't wait on a network: it succeeds or fails at once
+    pub async fn connect<P: AsRef<Path>>(path: P) -> io::Result<UnixStream> {
+        UnixStream::from_std(std::os::unix::net::UnixStream::connect(path)?)
+    }
+
+    pub fn peer_addr(&self) -> io::Result<std::os::unix::net::SocketAddr> {
+        self.sock.peer_addr()
+    }
+
+    // `Shutdown::Write` ends what the peer reads while reads go on
+    pub fn shutdown(&self, how: std::net::Shutdown) -> io::Result<()> {
+        self.sock.shutdown(how)
+    }
+
+    // see `TcpStream::peek`
+    pub async fn peek(&self, buf: &mut [u8]) -> io::Result<usize> {
+        futures::future::poll_fn(|cx| self.poll_peek(cx, buf)).await
+    }
+
+    pub fn poll_peek(
+        &self,
+        cx: &mut task::Context,
+        buf: &mut [u8],
+    ) -> task::Poll<io::Result<usize>> {
+        use std::os::unix::io::AsRawFd;
+        const MSG_PEEK: i32 = 2;
+        extern "C" {
+            fn recv(fd: i32, buf: *mut u8, len: usize, flags: i32) -> isize;
+        }
+        self.poll_io(cx, Ready::readable(), |sock| {
+            let read = unsafe { recv(sock.as_raw_fd(), buf.as_mut_ptr(), buf.len(), MSG_PEEK) };
+            if read < 0 {
+                Err(io::Error::last_os_error())
+            } else {
+                Ok(read as usize)
+            }
+        })
+    }
+
+    // runs `op`, a non-blocking read or write, once the socket is ready for it
+    fn poll_io<T>(
+        &self,
+        cx: &mut task::Context,
+        interest: Ready,
+        mut op: impl FnMut(&std::os::unix::net::UnixStream) -> io::Result<T>,
+    ) -> task::Poll<io::Result<T>> {
+        let reactor = &self.reactor;
+        let set_waker = || {
+            if interest.is_readable() {
+                reactor.add_read_waker(cx.waker().clone());
+            } else {
+                reactor.add_write_waker(cx.waker().clone());
+            }
+        };
+        if !reactor.readiness().contains(interest) {
+            set_waker();
+            return task::Poll::Pending;
+        }
+        match op(&self.sock) {
+            Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => {
+                reactor.remove_readiness(interest);
+                set_waker();
+                task::Poll::Pending
+            }
+            res => task::Poll::Ready(res),
+        }
+    }
+}
+
+#[cfg(unix)]
+impl AsyncRead for UnixStream {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut task::Context,
+        buf: &mut [u8],
+    ) -> task::Poll<io::Result<usize>> {
+        trace!("poll_read (unix)");
+        self.poll_io(cx, Ready::readable(), |mut sock| sock.read(buf))
+    }
+
+    fn poll_read_vectored(
+        self: Pin<&mut Self>,
+        cx: &mut task::Context,
+        bufs: &mut [io::IoSliceMut],
+    ) -> task::Poll<io::Result<usize>> {
+        self.poll_io(cx, Ready::readable(), |mut sock| sock.read_vectored(bufs))
+    }
+}
+
+#[cfg(unix)]
+impl AsyncWrite for UnixStream {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut task::Context,
+        buf: &[u8],
+    ) -> task::Poll<io::Result<usize>> {
+        trace!("poll_write (unix, {})", buf.len());
+        self.poll_io(cx, Ready::writable(), |mut sock| sock.write(buf))
+    }
+
+    fn poll_write_vectored(
+        self: Pin<&mut Self>,
+        cx: &mut task::Context,
+        bufs: &[io::IoSlice],
+    ) -> task::Poll<io::Result<usize>> {
+        self.poll_io(cx, Ready::writable(), |mut sock| sock.write_vectored(bufs))
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, _cx: &mut task::Context) -> task::Poll<io::Result<()>> {
+        task::Poll::Ready(Ok(()))
+    }
+
+    fn poll_close(self: Pin<&mut Self>, _cx: &mut task::Context) -> task::Poll<io::Result<()>> {
+        task::Poll::Ready(shutdown_write(
+            self.sock.shutdown(std::net::Shutdown::Write),
+        ))
+    }
+}
+
+#[cfg(unix)]
+impl Drop for UnixStream {
+    fn drop(&mut self) {
+        trace!("UnixStream dropped");
+        let _ = self.reactor.deregister(&self.sock);
+    }
+}
+
+// a connection over TCP or a Unix socket, as `HttpServer` accepts them
+#[derive(Debug)]
+pub enum Socket {
+    Tcp(TcpStream),
+    #[cfg(unix)]
+    Unix(UnixStream),
+}
+
+impl Socket {
+    pub fn shutdown(&self, how: std::net::Shutdown) -> io::Result<()> {
+        match self {
+            Socket::Tcp(sock) => sock.shutdown(how),
+            #[cfg(unix)]
+            Socket::Unix(sock) => sock.shutdown(how),
+        }
+    }
+
+    // see `TcpStream::peek`
+    pub async fn peek(&self, buf: &mut [u8]) -> io::Result<usize> {
+        futures::future::poll_fn(|cx| self.poll_peek(cx, buf)).await
+    }
+
+    pub fn poll_peek(
+        &self,
+        cx: &mut task::Context,
+        buf: &mut [u8],
+    ) -> task::Poll<io::Result<usize>> {
+        match self {
+            Socket::Tcp(sock) => sock.poll_peek(cx, buf),
+            #[cfg(unix)]
+            Socket::Unix(sock) => sock.poll_peek(cx, buf),
+        }
+    }
+}
+
+impl AsyncRead for Socket {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut task::Context,
+        buf: &mut [u8],
+    ) -> task::Poll<io::Result<usize>> {
+        match self.get_mut() {
+            Socket::Tcp(sock) => Pin::new(sock).poll_read(cx, buf),
+            #[cfg(unix)]
+            Socket::Unix(sock) => Pin::new(sock).poll_read(cx, buf),
+        }
+    }
+
+    fn poll_read_vectored(
+        self: Pin<&mut Self>,
+        cx: &mut task::Context,
+        bufs: &mut [io::IoSliceMut],
+    ) -> task::Poll<io::Result<usize>> {
+        match self.get_mut() {
+            Socket::Tcp(sock) => Pin::new(sock).poll_read_vectored(cx, bufs),
+            #[cfg(unix)]
+            Socket::Unix(sock) => Pin::new(sock).poll_read_vectored(cx, bufs),
+        }
+    }
+}
+
+impl AsyncWrite for Socket {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut task::Context,
+        buf: &[u8],
+    ) -> task::Poll<io::Result<usize>> {
+        match self.get_mut() {
+            Socket::Tcp(sock) => Pin::new(sock).poll_write(cx, buf),
+            #[cfg(unix)]
+            Socket::Unix(sock) => Pin::new(sock).poll_write(cx, buf),
+        }
+    }
+
+    fn poll_write_vectored(
+        self: Pin<&mut Self>,
+        cx: &mut task::Context,
+        bufs: &[io::IoSlice],
+    ) -> task::Poll<io::Result<usize>> {
+        match self.get_mut() {
+            Socket::Tcp(sock) => Pin::new(sock).poll_write_vectored(cx, bufs),
+            #[cfg(unix)]
+            Socket::Unix(sock) => Pin::new(sock).poll_write_vectored(cx, bufs),
+        }
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut task::Context) -> task::Poll<io::Result<()>> {
+        match self.get_mut() {
+            Socket::Tcp(sock) => Pin::new(sock).poll_flush(cx),
+            #[cfg(unix)]
+            Socket::Unix(sock) => Pin::new(sock).poll_flush(cx),
+        }
+    }
+
+    fn poll_close(self: Pin<&mut Self>, cx: &mut task::Context) -> task::Poll<io::Result<()>> {
+        match self.get_mut() {
+            Socket::Tcp(sock) => Pin::new(sock).poll_close(cx),
+            #[cfg(unix)]
+            Socket::Unix(sock) => Pin::new(sock).poll_close(cx),
+        }
+    }
+}
+
+// a listening socket of either kind, as `HttpServer` serves on
+pub enum Listener {
+    Tcp(TcpListener),
+    #[cfg(unix)]
+    Unix(UnixListener),
+}
+
+impl Listener {
+    // the peer address is None for Unix sockets
+    pub fn poll_accept(
+        &self,
+        cx: &mut task::Context,
+    ) -> task::Poll<io::Result<(Socket, Option<SocketAddr>)>> {
+        match self {
+            Listener::Tcp(tcp) => tcp
+                .poll_accept(cx)
+                .map_ok(|(sock, addr)| (Socket::Tcp(sock), Some(addr))),
+            #[cfg(unix)]
+            Listener::Unix(unix) => unix
+                .poll_accept(cx)
+                .map_ok(|(sock, _)| (Socket::Unix(sock), None)),
+        }
+    }
+
+    pub fn readable_since(&self) -> Option<Instant> {
+        match self {
+            Listener::Tcp(tcp) => tcp.readable_since(),
+            #[cfg(unix)]
+            Listener::Unix(unix) => unix.readable_since(),
+        }
+    }
+
+    pub fn backlog(&self) -> Option<usize> {
+        match self {
+            Listener::Tcp(tcp) => tcp.backlog(),
+            #[cfg(unix)]
+            Listener::Unix(_) => None,
+        }
+    }
+
+    // fails if the socket is unusable
+    pub(crate) fn check(&self) -> io::Result<()> {
+        match self {
+            Listener::Tcp(tcp) => tcp.local_addr().map(drop),
+            #[cfg(unix)]
+            Listener::Unix(unix) => unix.local_addr().map(drop),
+        }
+    }
+}
+
+impl From<TcpListener> for Listener {
+    fn from(listener: TcpListener) -> Listener {
+        Listener::Tcp(listener)
+    }
+}
+
+#[cfg(unix)]
+impl From<UnixListener> for Listener {
+    fn from(listener: UnixListener) -> Listener {
+        Listener::Unix(listener)
     }
 }
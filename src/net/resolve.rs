@@ -0,0 +1,157 @@
+use crate::{reactor, runner::blocking};
+use futures::future::{FutureExt, LocalBoxFuture};
+use std::{
+    cell::RefCell,
+    collections::HashMap,
+    future::Future,
+    io,
+    net::{IpAddr, SocketAddr, ToSocketAddrs},
+    pin::Pin,
+    rc::Rc,
+    sync::mpsc,
+    task::{Context, Poll},
+};
+
+// turns a host name into addresses; `TcpStream::connect_host` goes through the default one,
+// which `set_default_resolver` swaps for the current thread (service discovery, tests)
+pub trait Resolve {
+    // every address `host` resolves to, with `port` filled in
+    fn resolve(
+        &self,
+        host: &str,
+        port: u16,
+    ) -> LocalBoxFuture<'static, io::Result<Vec<SocketAddr>>>;
+}
+
+impl<R: Resolve + ?Sized> Resolve for Rc<R> {
+    fn resolve(
+        &self,
+        host: &str,
+        port: u16,
+    ) -> LocalBoxFuture<'static, io::Result<Vec<SocketAddr>>> {
+        (**self).resolve(host, port)
+    }
+}
+
+thread_local! {
+    static DEFAULT: RefCell<Rc<dyn Resolve>> = RefCell::new(Rc::new(SystemResolver));
+}
+
+pub fn default_resolver() -> Rc<dyn Resolve> {
+    DEFAULT.with(|resolver| Rc::clone(&*resolver.borrow()))
+}
+
+pub fn set_default_resolver<R: Resolve + 'static>(resolver: R) {
+    DEFAULT.with(|default| *default.borrow_mut() = Rc::new(resolver));
+}
+
+// IP literals (bracketed or not) never reach the resolver
+pub async fn resolve(resolver: &dyn Resolve, host: &str, port: u16) -> io::Result<Vec<SocketAddr>> {
+    let literal = host.trim_start_matches('[').trim_end_matches(']');
+    if let Ok(ip) = literal.parse::<IpAddr>() {
+        return Ok(vec![SocketAddr::new(ip, port)]);
+    }
+    let addrs = resolver.resolve(host, port).await?;
+    if addrs.is_empty() {
+        Err(not_found(host))
+    } else {
+        Ok(addrs)
+    }
+}
+
+fn not_found(host: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::NotFound, format!("{}: no addresses", host))
+}
+
+// the system resolver (getaddrinfo), run on the `runner::blocking` pool so lookups don't stall
+// the reactor; a burst of them waits in its queue once the pool's threads are all busy
+#[derive(Clone, Copy, Debug, Default)]
+pub struct SystemResolver;
+
+impl Resolve for SystemResolver {
+    fn resolve(
+        &self,
+        host: &str,
+        port: u16,
+    ) -> LocalBoxFuture<'static, io::Result<Vec<SocketAddr>>> {
+        let (reactor, waker) = match reactor::register_waker() {
+            Ok(pair) => pair,
+            Err(e) => return futures::future::ready(Err(e)).boxed_local(),
+        };
+        let (tx, rx) = mpsc::channel();
+        let host = host.to_owned();
+        blocking::spawn(move || {
+            let res = (&*host, port).to_socket_addrs().map(Iterator::collect);
+            // the result has to be there before the reactor wakes the lookup
+            let _ = tx.send(res);
+            waker.wake();
+        });
+        Lookup { reactor, rx }.boxed_local()
+    }
+}
+
+struct Lookup {
+    reactor: reactor::ReactorHandle,
+    rx: mpsc::Receiver<io::Result<Vec<SocketAddr>>>,
+}
+
+impl Future for Lookup {
+    type Output = io::Result<Vec<SocketAddr>>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        match self.rx.try_recv() {
+            Ok(res) => Poll::Ready(res),
+            Err(mpsc::TryRecvError::Empty) => {
+                self.reactor.add_read_waker(cx.waker().clone());
+                Poll::Pending
+            }
+            Err(mpsc::TryRecvError::Disconnected) => {
+                Poll::Ready(Err(io::Error::new(io::ErrorKind::Other, "lookup panicked")))
+            }
+        }
+    }
+}
+
+// a fixed map from names to addresses, for tests and static service discovery.
+// names not in the map fail with NotFound unless a fallback resolver is set.
+#[derive(Default)]
+pub struct StaticResolver {
+    hosts: HashMap<String, Vec<IpAddr>>,
+    fallback: Option<Rc<dyn Resolve>>,
+}
+
+impl StaticResolver {
+    pub fn new() -> StaticResolver {
+        StaticResolver::default()
+    }
+
+    pub fn insert(&mut self, host: &str, ip: IpAddr) -> &mut Self {
+        self.hosts
+            .entry(host.to_lowercase())
+            .or_insert_with(Vec::new)
+            .push(ip);
+        self
+    }
+
+    pub fn fallback<R: Resolve + 'static>(&mut self, resolver: R) -> &mut Self {
+        self.fallback = Some(Rc::new(resolver));
+        self
+    }
+}
+
+impl Resolve for StaticResolver {
+    fn resolve(
+        &self,
+        host: &str,
+        port: u16,
+    ) -> LocalBoxFuture<'static, io::Result<Vec<SocketAddr>>> {
+        let res = match self.hosts.get(&host.to_lowercase()) {
+            Some(ips) => Ok(ips.iter().map(|&ip| SocketAddr::new(ip, port)).collect()),
+            None => match &self.fallback {
+                Some(fallback) => return fallback.resolve(host, port),
+                None => Err(not_found(host)),
+            },
+        };
+        futures::future::ready(res).boxed_local()
+    }
+}
@@ -0,0 +1,63 @@
+// listening sockets passed by systemd socket activation (sd_listen_fds(3)). the `.socket`
+// unit keeps listening while the service restarts, so connections arriving meanwhile wait in
+// the backlog for the new process instead of being refused:
+//
+//     let listener = systemd::listeners()?.pop().expect("no socket passed");
+//     HttpServer::from_listener(listener, app).run()
+use super::{Listener, TcpListener, UnixListener};
+use std::{
+    env, io,
+    os::unix::io::{FromRawFd, IntoRawFd, RawFd},
+    process,
+};
+
+// the first passed descriptor; the others follow it
+const LISTEN_FDS_START: RawFd = 3;
+
+// the sockets passed to this process, in the order of the unit's Listen*= lines; none
+// without socket activation, or when they were meant for another process. the variables
+// saying so are removed, so child processes don't take the sockets for theirs too.
+pub fn listeners() -> io::Result<Vec<Listener>> {
+    let pid = env::var("LISTEN_PID").ok();
+    let fds = env::var("LISTEN_FDS").ok();
+    for name in &["LISTEN_PID", "LISTEN_FDS", "LISTEN_FDNAMES"] {
+        env::remove_var(name);
+    }
+    let (pid, fds) = match (pid, fds) {
+        (Some(pid), Some(fds)) => (pid, fds),
+        _ => return Ok(Vec::new()),
+    };
+    if pid.parse::<u32>().ok() != Some(process::id()) {
+        return Ok(Vec::new());
+    }
+    let fds: RawFd = fds
+        .parse()
+        .map_err(|_| io::Error::new(io::ErrorKind::InvalidData, "bad LISTEN_FDS"))?;
+    (LISTEN_FDS_START..LISTEN_FDS_START + fds)
+        .map(|fd| unsafe { inherit(fd) })
+        .collect()
+}
+
+// getsockname tells the kinds apart: only Unix sockets have a Unix address
+unsafe fn inherit(fd: RawFd) -> io::Result<Listener> {
+    set_cloexec(fd)?;
+    let unix = std::os::unix::net::UnixListener::from_raw_fd(fd);
+    if unix.local_addr().is_ok() {
+        return UnixListener::from_std(unix).map(Listener::Unix);
+    }
+    let tcp = std::net::TcpListener::from_raw_fd(unix.into_raw_fd());
+    TcpListener::from_std(tcp).map(Listener::Tcp)
+}
+
+// passed descriptors are inherited across exec; ours aren't, as sd_listen_fds makes them
+fn set_cloexec(fd: RawFd) -> io::Result<()> {
+    const F_SETFD: i32 = 2;
+    const FD_CLOEXEC: i32 = 1;
+    extern "C" {
+        fn fcntl(fd: i32, cmd: i32, ...) -> i32;
+    }
+    if unsafe { fcntl(fd, F_SETFD, FD_CLOEXEC) } < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
@@ -0,0 +1,81 @@
+// fast, non-cryptographic random numbers for jitter, IDs and multipart boundaries. every
+// thread has its own generator (wyrand), seeded once from the OS-seeded keys of std's
+// `RandomState`. not for secrets: keys and nonces come from the OS.
+use std::cell::Cell;
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hash, Hasher};
+use std::time::{Duration, SystemTime};
+
+thread_local! {
+    static STATE: Cell<u64> = Cell::new(seed());
+}
+
+fn seed() -> u64 {
+    let mut hasher = RandomState::new().build_hasher();
+    SystemTime::now().hash(&mut hasher);
+    std::thread::current().id().hash(&mut hasher);
+    hasher.finish()
+}
+
+pub fn u64() -> u64 {
+    STATE.with(|state| {
+        let s = state.get().wrapping_add(0xa076_1d64_78bd_642f);
+        state.set(s);
+        let t = u128::from(s) * u128::from(s ^ 0xe703_7ed1_a0b4_28db);
+        (t as u64) ^ (t >> 64) as u64
+    })
+}
+
+pub fn u32() -> u32 {
+    (u64() >> 32) as u32
+}
+
+// uniform in 0..n; n must not be 0
+pub fn below(n: u64) -> u64 {
+    assert!(n > 0, "random::below(0)");
+    // multiply-shift, rejecting the few low products that would bias the result
+    let threshold = n.wrapping_neg() % n;
+    loop {
+        let m = u128::from(u64()) * u128::from(n);
+        if (m as u64) >= threshold {
+            return (m >> 64) as u64;
+        }
+    }
+}
+
+// uniform in [0, 1)
+pub fn f64() -> f64 {
+    (u64() >> 11) as f64 / (1u64 << 53) as f64
+}
+
+pub fn fill(buf: &mut [u8]) {
+    for chunk in buf.chunks_mut(8) {
+        let bytes = u64().to_le_bytes();
+        chunk.copy_from_slice(&bytes[..chunk.len()]);
+    }
+}
+
+// `d` scaled by a random factor in [1 - fraction, 1 + fraction], e.g. to spread retries
+pub fn jitter(d: Duration, fraction: f64) -> Duration {
+    let fraction = fraction.max(0.0).min(1.0);
+    let factor = 1.0 - fraction + 2.0 * fraction * f64();
+    Duration::from_nanos((d.as_nanos() as f64 * factor) as u64)
+}
+
+// `len` characters of [0-9a-zA-Z]
+pub fn alphanumeric(len: usize) -> String {
+    const CHARS: &[u8] = b"0123456789abcdefghijklmnopqrstuvwxyzABCDEFGHIJKLMNOPQRSTUVWXYZ";
+    (0..len)
+        .map(|_| CHARS[below(CHARS.len() as u64) as usize] as char)
+        .collect()
+}
+
+// 128 random bits in 32 hex digits, e.g. for request IDs
+pub fn id() -> String {
+    format!("{:016x}{:016x}", u64(), u64())
+}
+
+// a multipart boundary, unlikely to turn up in any body
+pub fn boundary() -> String {
+    alphanumeric(32)
+}
@@ -1,114 +1,534 @@
+use crate::time;
+use backend::Backend;
 use log::*;
-use mio::*;
 use slab::Slab;
 use std::cell::RefCell;
+use std::collections::BTreeMap;
+use std::fmt;
 use std::io;
+use std::ops::{BitAnd, BitOr, BitOrAssign};
+use std::sync::{Arc, Mutex, PoisonError};
 use std::task::Waker;
+use std::time::{Duration, Instant};
+
+pub mod backend;
 
 thread_local! {
     static REACTOR: RefCell<Reactor> = RefCell::new(Reactor::new().unwrap());
+    // nodes of handles dropped while the reactor was borrowed, freed on the next turn
+    static DROPPED: RefCell<Vec<usize>> = RefCell::new(Vec::new());
 }
 
 struct Reactor {
-    poll: Poll,
-    events: Events,
+    backend: Box<dyn Backend>,
+    // reused from turn to turn
+    events: Vec<(usize, Ready)>,
     nodes: Slab<Node>,
+    timers: BTreeMap<TimerKey, Waker>,
+    next_timer_seq: u64,
+    turns: u64,
+    events_seen: u64,
+    // events the last turn dispatched
+    last_events: usize,
+    created: Instant,
+    // time blocked in `poll`, and time between polls: waking tasks, and running them until
+    // the next turn
+    poll_time: Duration,
+    dispatch_time: Duration,
+    // when the last poll returned
+    polled_at: Option<Instant>,
+    next_serial: u64,
+    // set up with the first `ReactorWaker`
+    remote: Option<Remote>,
 }
 
+// what a handle is ready for, or what it is registered for
+#[derive(Clone, Copy, Default, PartialEq, Eq)]
+pub struct Ready(u8);
+
+const READABLE: u8 = 1;
+const WRITABLE: u8 = 2;
+
+impl Ready {
+    pub fn empty() -> Ready {
+        Ready(0)
+    }
+
+    pub fn readable() -> Ready {
+        Ready(READABLE)
+    }
+
+    pub fn writable() -> Ready {
+        Ready(WRITABLE)
+    }
+
+    pub fn is_empty(self) -> bool {
+        self.0 == 0
+    }
+
+    pub fn is_readable(self) -> bool {
+        self.0 & READABLE != 0
+    }
+
+    pub fn is_writable(self) -> bool {
+        self.0 & WRITABLE != 0
+    }
+
+    pub fn contains(self, other: Ready) -> bool {
+        self.0 & other.0 == other.0
+    }
+
+    pub fn insert(&mut self, other: Ready) {
+        self.0 |= other.0;
+    }
+
+    pub fn remove(&mut self, other: Ready) {
+        self.0 &= !other.0;
+    }
+}
+
+impl BitOr for Ready {
+    type Output = Ready;
+
+    fn bitor(self, other: Ready) -> Ready {
+        Ready(self.0 | other.0)
+    }
+}
+
+impl BitOrAssign for Ready {
+    fn bitor_assign(&mut self, other: Ready) {
+        self.0 |= other.0;
+    }
+}
+
+impl BitAnd for Ready {
+    type Output = Ready;
+
+    fn bitand(self, other: Ready) -> Ready {
+        Ready(self.0 & other.0)
+    }
+}
+
+impl fmt::Debug for Ready {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match (self.is_readable(), self.is_writable()) {
+            (true, true) => f.write_str("Ready(readable | writable)"),
+            (true, false) => f.write_str("Ready(readable)"),
+            (false, true) => f.write_str("Ready(writable)"),
+            (false, false) => f.write_str("Ready(empty)"),
+        }
+    }
+}
+
+// what is registered with the reactor: a descriptor on Unix, which every backend can wait
+// on, and elsewhere a mio source for the mio backend, the only one there. handles of every
+// platform are passed as `&mut`, which both take.
+#[cfg(unix)]
+pub type Source<'a> = &'a dyn std::os::unix::io::AsRawFd;
+#[cfg(not(unix))]
+pub type Source<'a> = &'a mut dyn mio::event::Source;
+
+// timers are ordered by deadline, the sequence number keeps equal deadlines apart
+pub type TimerKey = (Instant, u64);
+
 struct Node {
+    // tells the handle apart from those its slot held before; see `ReactorWaker`
+    serial: u64,
     readiness: Ready,
-    read_waker: Waker,
-    write_waker: Waker,
+    // when the node last turned readable; None while it isn't
+    readable_since: Option<Instant>,
+    // the tasks waiting for the node to turn readable or writable, each woken once: a task
+    // that still has to wait adds its waker again. several tasks may wait on one socket,
+    // e.g. the halves of a split stream, or two futures in a select.
+    read_wakers: Vec<Waker>,
+    write_wakers: Vec<Waker>,
+}
+
+impl Node {
+    fn turn_ready(&mut self, ready: Ready, now: Instant) {
+        if ready.is_readable() && !self.readiness.is_readable() {
+            self.readable_since = Some(now);
+        }
+        self.readiness |= ready;
+        if ready.is_readable() {
+            self.read_wakers.drain(..).for_each(Waker::wake);
+        }
+        if ready.is_writable() {
+            self.write_wakers.drain(..).for_each(Waker::wake);
+        }
+    }
 }
 
 impl Reactor {
     fn new() -> io::Result<Reactor> {
+        #[cfg(all(unix, feature = "poll-backend"))]
+        let backend = Box::new(backend::PollBackend::new());
+        #[cfg(not(all(unix, feature = "poll-backend")))]
+        let backend = Box::new(backend::MioBackend::new()?);
         Ok(Reactor {
-            poll: mio::Poll::new()?,
-            events: mio::Events::with_capacity(1024),
+            backend,
+            events: Vec::new(),
             nodes: Slab::new(),
+            timers: BTreeMap::new(),
+            next_timer_seq: 0,
+            turns: 0,
+            events_seen: 0,
+            last_events: 0,
+            created: Instant::now(),
+            poll_time: Duration::from_secs(0),
+            dispatch_time: Duration::from_secs(0),
+            polled_at: None,
+            next_serial: 0,
+            remote: None,
         })
     }
 
-    fn register<E: ?Sized + Evented>(
-        &mut self,
-        handle: &E,
-        read_waker: Waker,
-        write_waker: Waker,
-        interest: Ready,
-    ) -> io::Result<ReactorHandle> {
-        let key = self.nodes.insert(Node {
+    fn insert_node(&mut self) -> usize {
+        self.next_serial += 1;
+        self.nodes.insert(Node {
+            serial: self.next_serial,
             readiness: Ready::empty(),
-            read_waker,
-            write_waker,
-        });
-        self.poll
-            .register(handle, Token(key), interest, PollOpt::edge())?;
+            readable_since: None,
+            read_wakers: Vec::new(),
+            write_wakers: Vec::new(),
+        })
+    }
+
+    fn register(&mut self, source: Source, interest: Ready) -> io::Result<ReactorHandle> {
+        let key = self.insert_node();
+        if let Err(e) = self.backend.register(source, key, interest) {
+            self.nodes.remove(key);
+            return Err(e);
+        }
         Ok(ReactorHandle::new(key))
     }
 
-    fn deregister<E: Evented>(&mut self, key: usize, handle: &E) -> io::Result<()> {
-        self.poll.deregister(handle)?;
-        self.nodes.remove(key);
-        Ok(())
+    // the node stays until the handle is dropped
+    fn reregister(&mut self, key: usize, source: Source, interest: Ready) -> io::Result<()> {
+        self.backend.reregister(source, key, interest)
     }
 
-    fn turn(&mut self, timeout: Option<std::time::Duration>) -> io::Result<usize> {
+    fn deregister(&mut self, source: Source) -> io::Result<()> {
+        self.backend.deregister(source)
+    }
+
+    fn remove_node(&mut self, key: usize) -> Option<Node> {
+        if self.nodes.contains(key) {
+            Some(self.nodes.remove(key))
+        } else {
+            None
+        }
+    }
+
+    fn turn(&mut self, timeout: Option<Duration>) -> io::Result<usize> {
         trace!("begin turn");
-        let n = self.poll.poll(&mut self.events, timeout)?;
-        for event in &self.events {
-            trace!("evented {:?}", &event);
-            if let Some(node) = self.nodes.get_mut(event.token().0) {
-                node.readiness |= event.readiness();
-                if event.readiness().is_readable() {
-                    node.read_waker.wake_by_ref();
-                }
-                if event.readiness().is_writable() {
-                    node.write_waker.wake_by_ref();
-                }
+        for key in DROPPED.with(|dropped| dropped.replace(Vec::new())) {
+            self.remove_node(key);
+        }
+        #[cfg(feature = "tracing")]
+        let span = tracing::trace_span!("reactor_turn", turn = self.turns + 1);
+        #[cfg(feature = "tracing")]
+        let _enter = span.enter();
+        let timeout = match self.timers.keys().next() {
+            Some(&(deadline, _)) => {
+                let now = time::now();
+                let until = if deadline > now {
+                    deadline - now
+                } else {
+                    Duration::from_secs(0)
+                };
+                Some(timeout.map_or(until, |t| t.min(until)))
+            }
+            None => timeout,
+        };
+        let start = Instant::now();
+        if let Some(polled_at) = self.polled_at {
+            self.dispatch_time += start - polled_at;
+        }
+        let mut events = std::mem::replace(&mut self.events, Vec::new());
+        events.clear();
+        let polled = self.backend.poll(&mut events, timeout);
+        let now = Instant::now();
+        self.poll_time += now - start;
+        self.polled_at = Some(now);
+        if let Err(e) = polled {
+            self.events = events;
+            return Err(e);
+        }
+        let n = events.len();
+        self.turns += 1;
+        self.events_seen += n as u64;
+        self.last_events = n;
+        #[cfg(feature = "tracing")]
+        tracing::trace!(events = n, "polled");
+        let remote_key = self.remote.as_ref().map(|remote| remote.key);
+        let mut remote_woke = false;
+        for &(key, ready) in &events {
+            if Some(key) == remote_key {
+                remote_woke = true;
+            } else if let Some(node) = self.nodes.get_mut(key) {
+                node.turn_ready(ready, now);
             }
         }
+        self.events = events;
+        if remote_woke {
+            self.wake_remote(now);
+        }
+        self.fire_timers();
         Ok(n)
     }
 
+    // set up with the first `ReactorWaker`
+    fn remote(&mut self) -> io::Result<&Remote> {
+        if self.remote.is_none() {
+            let key = self.insert_node();
+            match Remote::new(&mut *self.backend, key) {
+                Ok(remote) => self.remote = Some(remote),
+                Err(e) => {
+                    self.nodes.remove(key);
+                    return Err(e);
+                }
+            }
+        }
+        Ok(self.remote.as_ref().unwrap())
+    }
+
+    fn wake_remote(&mut self, now: Instant) {
+        let woken = match &self.remote {
+            Some(remote) => {
+                let woken = remote.take();
+                self.backend.rearm(remote.key, Ready::readable());
+                woken
+            }
+            None => return,
+        };
+        for (key, serial) in woken {
+            match self.nodes.get_mut(key) {
+                Some(node) if node.serial == serial => node.turn_ready(Ready::readable(), now),
+                // the handle is gone
+                _ => {}
+            }
+        }
+    }
+
+    fn fire_timers(&mut self) {
+        let now = time::now();
+        while let Some(&key) = self.timers.keys().next() {
+            if key.0 > now {
+                break;
+            }
+            if let Some(waker) = self.timers.remove(&key) {
+                waker.wake();
+            }
+        }
+    }
+
+    fn add_timer(&mut self, deadline: Instant, waker: Waker) -> TimerKey {
+        let key = (deadline, self.next_timer_seq);
+        self.next_timer_seq += 1;
+        self.timers.insert(key, waker);
+        key
+    }
+
     fn readiness(&self, key: usize) -> Option<Ready> {
         self.nodes.get(key).map(|node| node.readiness)
     }
 
-    fn remove_readiness<R: Into<Ready>>(&mut self, key: usize, ready: R) {
+    fn remove_readiness(&mut self, key: usize, ready: Ready) {
+        self.backend.rearm(key, ready);
         if let Some(node) = self.nodes.get_mut(key) {
             node.readiness.remove(ready);
+            if !node.readiness.is_readable() {
+                node.readable_since = None;
+            }
         }
     }
 
-    fn set_read_waker(&mut self, key: usize, waker: Waker) {
+    fn add_read_waker(&mut self, key: usize, waker: Waker) {
         if let Some(node) = self.nodes.get_mut(key) {
-            node.read_waker = waker;
+            add_waker(&mut node.read_wakers, waker);
         }
     }
 
-    fn set_write_waker(&mut self, key: usize, waker: Waker) {
+    fn add_write_waker(&mut self, key: usize, waker: Waker) {
         if let Some(node) = self.nodes.get_mut(key) {
-            node.write_waker = waker;
+            add_waker(&mut node.write_wakers, waker);
         }
     }
 }
 
-pub fn register<E: ?Sized + Evented>(handle: &E, interest: Ready) -> io::Result<ReactorHandle> {
+// a task polled again before it was woken is in `wakers` already
+fn add_waker(wakers: &mut Vec<Waker>, waker: Waker) {
+    if !wakers.iter().any(|w| w.will_wake(&waker)) {
+        wakers.push(waker);
+    }
+}
+
+pub fn register(source: Source, interest: Ready) -> io::Result<ReactorHandle> {
+    REACTOR.with(|reactor| reactor.borrow_mut().register(source, interest))
+}
+
+// replaces the backend of this thread's reactor; only while nothing is registered with it
+pub fn set_backend(backend: Box<dyn Backend>) -> io::Result<()> {
+    REACTOR.with(|reactor| {
+        let mut reactor = reactor.borrow_mut();
+        if !reactor.nodes.is_empty() {
+            return Err(io::Error::new(
+                io::ErrorKind::Other,
+                "the reactor has handles registered",
+            ));
+        }
+        reactor.backend = backend;
+        Ok(())
+    })
+}
+
+// a handle with nothing registered, turned readable by the `ReactorWaker` that comes with
+// it, e.g. once a thread has finished work the handle's task waits for
+pub fn register_waker() -> io::Result<(ReactorHandle, ReactorWaker)> {
+    REACTOR.with(|reactor| {
+        let mut reactor = reactor.borrow_mut();
+        let shared = Arc::clone(&reactor.remote()?.shared);
+        let key = reactor.insert_node();
+        let waker = ReactorWaker {
+            key,
+            serial: reactor.nodes[key].serial,
+            shared,
+        };
+        Ok((ReactorHandle::new(key), waker))
+    })
+}
+
+// wakes a `turn` of this thread's reactor from any thread, turning no handle readable; e.g.
+// for a runner whose tasks are woken from other threads
+pub fn thread_waker() -> io::Result<ReactorWaker> {
     REACTOR.with(|reactor| {
-        reactor.borrow_mut().register(
-            handle,
-            futures::task::noop_waker(),
-            futures::task::noop_waker(),
-            interest,
-        )
+        let mut reactor = reactor.borrow_mut();
+        let remote = reactor.remote()?;
+        // no node has serial 0, the wake is ignored once the turn returns
+        Ok(ReactorWaker {
+            key: remote.key,
+            serial: 0,
+            shared: Arc::clone(&remote.shared),
+        })
+    })
+}
+
+// the deadline of the timer due first
+#[cfg(feature = "test-util")]
+pub(crate) fn next_timer() -> Option<Instant> {
+    REACTOR.with(|reactor| {
+        reactor
+            .borrow()
+            .timers
+            .keys()
+            .next()
+            .map(|&(deadline, _)| deadline)
     })
 }
 
-pub fn turn(timeout: Option<std::time::Duration>) -> io::Result<usize> {
+pub fn turn(timeout: Option<Duration>) -> io::Result<usize> {
     REACTOR.with(|reactor| reactor.borrow_mut().turn(timeout))
 }
 
+// how many times this thread's reactor has polled for events
+pub fn turns() -> u64 {
+    REACTOR.with(|reactor| reactor.borrow().turns)
+}
+
+// handles registered with this thread's reactor and not dropped yet
+pub fn registered() -> usize {
+    REACTOR.with(|reactor| {
+        let reactor = reactor.borrow();
+        reactor.nodes.len() - reactor.remote.iter().count()
+    })
+}
+
+// how many I/O events this thread's reactor has dispatched
+pub fn events() -> u64 {
+    REACTOR.with(|reactor| reactor.borrow().events_seen)
+}
+
+// a snapshot of this thread's reactor, for telling whether the event loop keeps up: a loop
+// that spends most of its time dispatching rather than waiting in `poll` is saturated
+#[derive(Clone, Debug)]
+pub struct ReactorStats {
+    // handles not dropped yet, as `registered`
+    pub registered: usize,
+    // pending timers
+    pub timers: usize,
+    pub turns: u64,
+    pub events: u64,
+    pub last_turn_events: usize,
+    // since the reactor was set up, on the thread's first use of it
+    pub uptime: Duration,
+    // time blocked waiting for events or timers
+    pub poll_time: Duration,
+    // time from each poll returning to the next one starting: waking tasks and running them
+    pub dispatch_time: Duration,
+}
+
+impl ReactorStats {
+    pub fn events_per_turn(&self) -> f64 {
+        if self.turns == 0 {
+            0.0
+        } else {
+            self.events as f64 / self.turns as f64
+        }
+    }
+
+    // over the reactor's lifetime; take the difference of two snapshots for a recent rate
+    pub fn turns_per_second(&self) -> f64 {
+        let secs = self.uptime.as_secs() as f64 + f64::from(self.uptime.subsec_nanos()) / 1e9;
+        if secs > 0.0 {
+            self.turns as f64 / secs
+        } else {
+            0.0
+        }
+    }
+}
+
+pub fn stats() -> ReactorStats {
+    REACTOR.with(|reactor| {
+        let reactor = reactor.borrow();
+        ReactorStats {
+            registered: reactor.nodes.len() - reactor.remote.iter().count(),
+            timers: reactor.timers.len(),
+            turns: reactor.turns,
+            events: reactor.events_seen,
+            last_turn_events: reactor.last_events,
+            uptime: reactor.created.elapsed(),
+            poll_time: reactor.poll_time,
+            dispatch_time: reactor.dispatch_time,
+        }
+    })
+}
+
+// wakes `waker` once `deadline` has passed; the key is gone from the reactor after it fired
+pub fn add_timer(deadline: Instant, waker: Waker) -> TimerKey {
+    REACTOR.with(|reactor| reactor.borrow_mut().add_timer(deadline, waker))
+}
+
+// replaces the waker of a pending timer; false if it already fired or was removed
+pub fn set_timer_waker(key: TimerKey, waker: Waker) -> bool {
+    REACTOR.with(|reactor| match reactor.borrow_mut().timers.get_mut(&key) {
+        Some(w) => {
+            *w = waker;
+            true
+        }
+        None => false,
+    })
+}
+
+pub fn remove_timer(key: TimerKey) {
+    REACTOR.with(|reactor| {
+        reactor.borrow_mut().timers.remove(&key);
+    })
+}
+
+// a registration with the reactor, its slot freed when the handle is dropped. the handle
+// doesn't own what was registered: the owner deregisters it, or closes it, which
+// deregisters a socket too.
 #[derive(Debug)]
 pub struct ReactorHandle {
     key: usize,
@@ -123,41 +543,177 @@ impl ReactorHandle {
         REACTOR.with(|reactor| reactor.borrow().readiness(self.key).unwrap())
     }
 
-    pub fn remove_readiness<R: Into<Ready>>(&self, ready: R) {
-        REACTOR.with(|reactor| reactor.borrow_mut().remove_readiness(self.key, ready))
-    }
-
-    pub fn set_read_waker(&self, waker: Waker) {
-        REACTOR.with(|reactor| reactor.borrow_mut().set_read_waker(self.key, waker))
+    // when the handle last turned readable, if it still is
+    pub fn readable_since(&self) -> Option<Instant> {
+        REACTOR.with(|reactor| {
+            reactor
+                .borrow()
+                .nodes
+                .get(self.key)
+                .and_then(|node| node.readable_since)
+        })
     }
 
-    pub fn reset_read_waker(&self) {
+    pub fn remove_readiness<R: Into<Ready>>(&self, ready: R) {
         REACTOR.with(|reactor| {
             reactor
                 .borrow_mut()
-                .set_read_waker(self.key, futures::task::noop_waker())
+                .remove_readiness(self.key, ready.into())
         })
     }
 
-    pub fn set_write_waker(&self, waker: Waker) {
-        REACTOR.with(|reactor| reactor.borrow_mut().set_write_waker(self.key, waker))
+    // wakes the task of `waker` once the handle turns readable
+    pub fn add_read_waker(&self, waker: Waker) {
+        REACTOR.with(|reactor| reactor.borrow_mut().add_read_waker(self.key, waker))
     }
 
-    pub fn reset_write_waker(&self) {
-        REACTOR.with(|reactor| {
-            reactor
-                .borrow_mut()
-                .set_write_waker(self.key, futures::task::noop_waker())
-        })
+    // wakes the task of `waker` once the handle turns writable
+    pub fn add_write_waker(&self, waker: Waker) {
+        REACTOR.with(|reactor| reactor.borrow_mut().add_write_waker(self.key, waker))
     }
 
-    pub fn deregister<E: Evented>(&self, handle: &E) -> io::Result<()> {
-        REACTOR.with(|reactor| reactor.borrow_mut().deregister(self.key, handle))
+    // watches what was registered for `interest` instead; readiness already seen stays
+    pub fn reregister(&self, source: Source, interest: Ready) -> io::Result<()> {
+        REACTOR.with(|reactor| reactor.borrow_mut().reregister(self.key, source, interest))
+    }
+
+    pub fn deregister(&self, source: Source) -> io::Result<()> {
+        REACTOR.with(|reactor| reactor.borrow_mut().deregister(source))
     }
 }
 
 impl Drop for ReactorHandle {
     fn drop(&mut self) {
-        // deregister
+        // nothing to free once the thread's reactor is gone
+        let _ = REACTOR.try_with(|reactor| {
+            // the node's wakers are dropped after the borrow ends, as dropping one may run
+            // arbitrary code
+            let node = match reactor.try_borrow_mut() {
+                Ok(mut reactor) => reactor.remove_node(self.key),
+                Err(_) => {
+                    DROPPED.with(|dropped| dropped.borrow_mut().push(self.key));
+                    None
+                }
+            };
+            drop(node);
+        });
+    }
+}
+
+// wakes a thread's reactor from any thread: a `turn` waiting for events returns, and the
+// handle the waker was made for turns readable (see `register_waker`). wakes before the
+// reactor's next turn are handled in one go, and a wake after the handle is dropped is
+// ignored.
+#[derive(Clone)]
+pub struct ReactorWaker {
+    key: usize,
+    serial: u64,
+    shared: Arc<Shared>,
+}
+
+impl ReactorWaker {
+    pub fn wake(&self) {
+        let mut woken = self
+            .shared
+            .woken
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner);
+        // the reactor is signalled once until it takes what was woken
+        let signal = woken.is_empty();
+        woken.push((self.key, self.serial));
+        drop(woken);
+        if signal {
+            self.shared.signal();
+        }
+    }
+}
+
+impl std::fmt::Debug for ReactorWaker {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "ReactorWaker({})", self.key)
+    }
+}
+
+// the reactor's end of the `ReactorWaker`s: a socket pair on Unix, a mio waker elsewhere
+struct Remote {
+    key: usize,
+    shared: Arc<Shared>,
+    #[cfg(unix)]
+    rx: std::os::unix::net::UnixStream,
+}
+
+struct Shared {
+    // the nodes woken since the reactor last looked, and their serials
+    woken: Mutex<Vec<(usize, u64)>>,
+    #[cfg(unix)]
+    tx: std::os::unix::net::UnixStream,
+    #[cfg(not(unix))]
+    waker: mio::Waker,
+}
+
+impl Remote {
+    #[cfg(unix)]
+    fn new(backend: &mut dyn Backend, key: usize) -> io::Result<Remote> {
+        let (tx, rx) = std::os::unix::net::UnixStream::pair()?;
+        tx.set_nonblocking(true)?;
+        rx.set_nonblocking(true)?;
+        backend.register(&rx, key, Ready::readable())?;
+        let shared = Shared {
+            woken: Mutex::new(Vec::new()),
+            tx,
+        };
+        Ok(Remote {
+            key,
+            shared: Arc::new(shared),
+            rx,
+        })
+    }
+
+    #[cfg(not(unix))]
+    fn new(backend: &mut dyn Backend, key: usize) -> io::Result<Remote> {
+        let shared = Shared {
+            woken: Mutex::new(Vec::new()),
+            waker: backend.waker(key)?,
+        };
+        Ok(Remote {
+            key,
+            shared: Arc::new(shared),
+        })
+    }
+
+    // the signal is consumed before the list is taken, so that a wake in between signals
+    // again rather than being missed. a mio waker has nothing to consume.
+    fn take(&self) -> Vec<(usize, u64)> {
+        #[cfg(unix)]
+        {
+            use std::io::Read;
+            let mut buf = [0; 64];
+            while let Ok(n) = (&self.rx).read(&mut buf) {
+                if n == 0 {
+                    break;
+                }
+            }
+        }
+        let mut woken = self
+            .shared
+            .woken
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner);
+        std::mem::replace(&mut *woken, Vec::new())
+    }
+}
+
+impl Shared {
+    fn signal(&self) {
+        #[cfg(unix)]
+        {
+            use std::io::Write;
+            // a full buffer has a signal in it already
+            let _ = (&self.tx).write(&[1]);
+        }
+        #[cfg(not(unix))]
+        {
+            let _ = self.waker.wake();
+        }
     }
 }
@@ -0,0 +1,314 @@
+// what the reactor waits on for events. mio's is the default; `PollBackend` runs on plain
+// poll(2), e.g. where epoll is unavailable or to rule out the event source when debugging.
+// the `poll-backend` feature makes it the default, `reactor::set_backend` picks one for a
+// thread at run time.
+use super::{Ready, Source};
+use log::*;
+use std::{io, time::Duration};
+
+// readiness is edge-triggered as the reactor sees it: once reported, a handle is taken to
+// stay ready until an operation on it would block, after which the reactor `rearm`s it
+pub trait Backend {
+    // events for `source` come with `token`
+    fn register(&mut self, source: Source, token: usize, interest: Ready) -> io::Result<()>;
+
+    // changes what `source`, registered before, is watched for
+    fn reregister(&mut self, source: Source, token: usize, interest: Ready) -> io::Result<()>;
+
+    fn deregister(&mut self, source: Source) -> io::Result<()>;
+
+    // waits up to `timeout`, forever without one, for events, appending them to `events`.
+    // returning none is fine, e.g. when interrupted by a signal.
+    fn poll(
+        &mut self,
+        events: &mut Vec<(usize, Ready)>,
+        timeout: Option<Duration>,
+    ) -> io::Result<()>;
+
+    // `ready` was used up: an operation on the handle of `token` would block. backends that
+    // are edge-triggered themselves have nothing to do.
+    fn rearm(&mut self, _token: usize, _ready: Ready) {}
+
+    // wakes a poll from any thread, its events coming with `token`. on Unix the reactor uses
+    // a socket pair instead, which works with any backend.
+    #[cfg(not(unix))]
+    fn waker(&mut self, token: usize) -> io::Result<mio::Waker>;
+}
+
+pub struct MioBackend {
+    poll: mio::Poll,
+    events: mio::Events,
+}
+
+impl MioBackend {
+    pub fn new() -> io::Result<MioBackend> {
+        Ok(MioBackend {
+            poll: mio::Poll::new()?,
+            events: mio::Events::with_capacity(1024),
+        })
+    }
+}
+
+impl Backend for MioBackend {
+    fn register(&mut self, source: Source, token: usize, interest: Ready) -> io::Result<()> {
+        #[cfg(unix)]
+        let source = &mut mio::unix::SourceFd(&source.as_raw_fd());
+        self.poll
+            .registry()
+            .register(source, mio::Token(token), interest_of(interest)?)
+    }
+
+    fn reregister(&mut self, source: Source, token: usize, interest: Ready) -> io::Result<()> {
+        #[cfg(unix)]
+        let source = &mut mio::unix::SourceFd(&source.as_raw_fd());
+        self.poll
+            .registry()
+            .reregister(source, mio::Token(token), interest_of(interest)?)
+    }
+
+    fn deregister(&mut self, source: Source) -> io::Result<()> {
+        #[cfg(unix)]
+        let source = &mut mio::unix::SourceFd(&source.as_raw_fd());
+        self.poll.registry().deregister(source)
+    }
+
+    fn poll(
+        &mut self,
+        events: &mut Vec<(usize, Ready)>,
+        timeout: Option<Duration>,
+    ) -> io::Result<()> {
+        match self.poll.poll(&mut self.events, timeout) {
+            Err(ref e) if e.kind() == io::ErrorKind::Interrupted => return Ok(()),
+            res => res?,
+        }
+        for event in &self.events {
+            trace!("evented {:?}", &event);
+            events.push((event.token().0, ready_of(event)));
+        }
+        Ok(())
+    }
+
+    #[cfg(not(unix))]
+    fn waker(&mut self, token: usize) -> io::Result<mio::Waker> {
+        mio::Waker::new(self.poll.registry(), mio::Token(token))
+    }
+}
+
+// mio has no empty interest: something registered is watched for something
+fn interest_of(ready: Ready) -> io::Result<mio::Interest> {
+    match (ready.is_readable(), ready.is_writable()) {
+        (true, true) => Ok(mio::Interest::READABLE | mio::Interest::WRITABLE),
+        (true, false) => Ok(mio::Interest::READABLE),
+        (false, true) => Ok(mio::Interest::WRITABLE),
+        (false, false) => Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "registering for no readiness",
+        )),
+    }
+}
+
+// a closed or failed handle is ready for both, so that the operation waiting on it runs and
+// reports what happened
+fn ready_of(event: &mio::event::Event) -> Ready {
+    let mut ready = Ready::empty();
+    if event.is_readable() || event.is_read_closed() || event.is_error() {
+        ready |= Ready::readable();
+    }
+    if event.is_writable() || event.is_write_closed() || event.is_error() {
+        ready |= Ready::writable();
+    }
+    ready
+}
+
+#[cfg(unix)]
+pub use self::poll::PollBackend;
+
+#[cfg(unix)]
+mod poll {
+    use super::{Backend, Ready, Source};
+    use std::{
+        collections::{BTreeMap, HashMap},
+        io,
+        os::unix::io::RawFd,
+        time::Duration,
+    };
+
+    #[repr(C)]
+    struct PollFd {
+        fd: i32,
+        events: i16,
+        revents: i16,
+    }
+
+    const POLLIN: i16 = 0x1;
+    const POLLOUT: i16 = 0x4;
+    const POLLERR: i16 = 0x8;
+    const POLLHUP: i16 = 0x10;
+    const POLLNVAL: i16 = 0x20;
+
+    #[cfg(any(target_os = "linux", target_os = "android"))]
+    type Nfds = std::os::raw::c_ulong;
+    #[cfg(not(any(target_os = "linux", target_os = "android")))]
+    type Nfds = std::os::raw::c_uint;
+
+    extern "C" {
+        fn poll(fds: *mut PollFd, nfds: Nfds, timeout: i32) -> i32;
+    }
+
+    // poll(2) is level-triggered: a handle is left out of the next polls for what was
+    // reported until it is rearmed, or it would be reported on every turn. each poll passes
+    // every armed descriptor, so this is for modest numbers of them.
+    #[derive(Default)]
+    pub struct PollBackend {
+        watches: BTreeMap<usize, Watch>,
+        tokens: HashMap<RawFd, usize>,
+        // reused from poll to poll, with the token of each entry
+        fds: Vec<PollFd>,
+        polled: Vec<usize>,
+    }
+
+    struct Watch {
+        fd: RawFd,
+        interest: Ready,
+        // what is still to be reported
+        armed: Ready,
+    }
+
+    impl PollBackend {
+        pub fn new() -> PollBackend {
+            PollBackend::default()
+        }
+    }
+
+    impl Backend for PollBackend {
+        fn register(&mut self, source: Source, token: usize, interest: Ready) -> io::Result<()> {
+            let fd = source.as_raw_fd();
+            if self.tokens.contains_key(&fd) {
+                return Err(io::Error::new(
+                    io::ErrorKind::AlreadyExists,
+                    "descriptor already registered",
+                ));
+            }
+            self.tokens.insert(fd, token);
+            let watch = Watch {
+                fd,
+                interest,
+                armed: interest,
+            };
+            self.watches.insert(token, watch);
+            Ok(())
+        }
+
+        fn reregister(&mut self, source: Source, token: usize, interest: Ready) -> io::Result<()> {
+            let fd = source.as_raw_fd();
+            if self.tokens.get(&fd) != Some(&token) {
+                self.deregister(source)?;
+                return self.register(source, token, interest);
+            }
+            if let Some(watch) = self.watches.get_mut(&token) {
+                watch.interest = interest;
+                watch.armed = interest;
+            }
+            Ok(())
+        }
+
+        fn deregister(&mut self, source: Source) -> io::Result<()> {
+            match self.tokens.remove(&source.as_raw_fd()) {
+                Some(token) => {
+                    self.watches.remove(&token);
+                    Ok(())
+                }
+                None => Err(io::Error::new(
+                    io::ErrorKind::NotFound,
+                    "descriptor not registered",
+                )),
+            }
+        }
+
+        fn poll(
+            &mut self,
+            events: &mut Vec<(usize, Ready)>,
+            timeout: Option<Duration>,
+        ) -> io::Result<()> {
+            self.fds.clear();
+            self.polled.clear();
+            for (&token, watch) in &self.watches {
+                let mut interest = 0;
+                if watch.armed.is_readable() {
+                    interest |= POLLIN;
+                }
+                if watch.armed.is_writable() {
+                    interest |= POLLOUT;
+                }
+                // errors and hangups are reported even when nothing is asked for
+                if interest != 0 {
+                    self.fds.push(PollFd {
+                        fd: watch.fd,
+                        events: interest,
+                        revents: 0,
+                    });
+                    self.polled.push(token);
+                }
+            }
+            let n = unsafe {
+                poll(
+                    self.fds.as_mut_ptr(),
+                    self.fds.len() as Nfds,
+                    millis(timeout),
+                )
+            };
+            if n < 0 {
+                let e = io::Error::last_os_error();
+                return match e.kind() {
+                    io::ErrorKind::Interrupted => Ok(()),
+                    _ => Err(e),
+                };
+            }
+            for (fd, &token) in self.fds.iter().zip(&self.polled) {
+                if fd.revents == 0 {
+                    continue;
+                }
+                // closed without being deregistered
+                if fd.revents & POLLNVAL != 0 {
+                    self.watches.remove(&token);
+                    self.tokens.remove(&fd.fd);
+                    continue;
+                }
+                let watch = match self.watches.get_mut(&token) {
+                    Some(watch) => watch,
+                    None => continue,
+                };
+                let mut ready = Ready::empty();
+                if fd.revents & (POLLIN | POLLERR | POLLHUP) != 0 && watch.armed.is_readable() {
+                    ready |= Ready::readable();
+                }
+                if fd.revents & (POLLOUT | POLLERR | POLLHUP) != 0 && watch.armed.is_writable() {
+                    ready |= Ready::writable();
+                }
+                if !ready.is_empty() {
+                    watch.armed.remove(ready);
+                    events.push((token, ready));
+                }
+            }
+            Ok(())
+        }
+
+        fn rearm(&mut self, token: usize, ready: Ready) {
+            if let Some(watch) = self.watches.get_mut(&token) {
+                watch.armed |= ready & watch.interest;
+            }
+        }
+    }
+
+    // rounded up, so that a poll for a timer doesn't return just before it is due
+    fn millis(timeout: Option<Duration>) -> i32 {
+        match timeout {
+            Some(timeout) => {
+                let ms = timeout.as_secs() * 1000
+                    + u64::from((timeout.subsec_nanos() + 999_999) / 1_000_000);
+                ms.min(i32::max_value() as u64) as i32
+            }
+            None => -1,
+        }
+    }
+}
@@ -0,0 +1,287 @@
+use crate::http::middleware::BoxedApp;
+use crate::http::*;
+use futures::future::{self, FutureExt, LocalBoxFuture};
+use std::collections::HashMap;
+
+pub mod openapi;
+mod vhost;
+
+pub use vhost::VirtualHosts;
+
+// dispatches on method and path. patterns are `/`-separated: `:name` captures one segment,
+// a trailing `*name` captures the rest; captures are available through `Request::param`.
+// when several routes match, literal segments win over captures. a path routed only for
+// other methods gets 405 Method Not Allowed, and OPTIONS is answered, both with `Allow`.
+#[derive(Default)]
+pub struct Router {
+    routes: Vec<Route>,
+    fallback: Option<BoxedApp>,
+}
+
+pub struct Route {
+    method: String,
+    pattern: String,
+    segments: Vec<Segment>,
+    app: BoxedApp,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+enum Segment {
+    Literal(String),
+    Param(String),
+    Rest(String),
+}
+
+impl Segment {
+    // lower is more specific
+    fn rank(&self) -> u8 {
+        match self {
+            Segment::Literal(_) => 0,
+            Segment::Param(_) => 1,
+            Segment::Rest(_) => 2,
+        }
+    }
+
+    // same requests matched, whatever the capture names
+    fn same_shape(&self, other: &Segment) -> bool {
+        match (self, other) {
+            (Segment::Literal(a), Segment::Literal(b)) => a == b,
+            _ => self.rank() == other.rank(),
+        }
+    }
+}
+
+impl Route {
+    pub fn method(&self) -> &str {
+        &self.method
+    }
+
+    pub fn pattern(&self) -> &str {
+        &self.pattern
+    }
+
+    // names of the captures in the pattern, in order
+    pub fn params(&self) -> impl Iterator<Item = &str> {
+        self.segments.iter().filter_map(|segment| match segment {
+            Segment::Param(name) | Segment::Rest(name) => Some(&**name),
+            Segment::Literal(_) => None,
+        })
+    }
+
+    // the pattern in OpenAPI/URI template form: `/users/:id` -> `/users/{id}`
+    pub fn template(&self) -> String {
+        let mut out = String::new();
+        for segment in &self.segments {
+            out.push('/');
+            match segment {
+                Segment::Literal(lit) => out.push_str(lit),
+                Segment::Param(name) | Segment::Rest(name) => {
+                    out.push('{');
+                    out.push_str(name);
+                    out.push('}');
+                }
+            }
+        }
+        if out.is_empty() {
+            out.push('/');
+        }
+        out
+    }
+
+    fn matches(&self, path: &[&str]) -> Option<HashMap<String, String>> {
+        let mut params = HashMap::new();
+        for (i, segment) in self.segments.iter().enumerate() {
+            match segment {
+                Segment::Rest(name) => {
+                    params.insert(name.clone(), path.get(i..).unwrap_or(&[]).join("/"));
+                    return Some(params);
+                }
+                Segment::Literal(lit) if path.get(i) != Some(&&**lit) => return None,
+                Segment::Literal(_) => {}
+                Segment::Param(name) => {
+                    params.insert(name.clone(), (*path.get(i)?).to_owned());
+                }
+            }
+        }
+        if path.len() == self.segments.len() {
+            Some(params)
+        } else {
+            None
+        }
+    }
+}
+
+impl Router {
+    pub fn new() -> Router {
+        Router::default()
+    }
+
+    pub fn route<T>(&mut self, method: &str, pattern: &str, app: T) -> &mut Self
+    where
+        T: HttpApp + 'static,
+        T::Output: 'static,
+    {
+        self.routes.push(Route {
+            method: method.to_uppercase(),
+            pattern: pattern.to_owned(),
+            segments: parse_pattern(pattern),
+            app: BoxedApp::new(app),
+        });
+        self
+    }
+
+    pub fn get<T>(&mut self, pattern: &str, app: T) -> &mut Self
+    where
+        T: HttpApp + 'static,
+        T::Output: 'static,
+    {
+        self.route("GET", pattern, app)
+    }
+
+    pub fn post<T>(&mut self, pattern: &str, app: T) -> &mut Self
+    where
+        T: HttpApp + 'static,
+        T::Output: 'static,
+    {
+        self.route("POST", pattern, app)
+    }
+
+    pub fn put<T>(&mut self, pattern: &str, app: T) -> &mut Self
+    where
+        T: HttpApp + 'static,
+        T::Output: 'static,
+    {
+        self.route("PUT", pattern, app)
+    }
+
+    pub fn delete<T>(&mut self, pattern: &str, app: T) -> &mut Self
+    where
+        T: HttpApp + 'static,
+        T::Output: 'static,
+    {
+        self.route("DELETE", pattern, app)
+    }
+
+    // handles requests no route matches, instead of 404
+    pub fn fallback<T>(&mut self, app: T) -> &mut Self
+    where
+        T: HttpApp + 'static,
+        T::Output: 'static,
+    {
+        self.fallback = Some(BoxedApp::new(app));
+        self
+    }
+
+    // in registration order
+    pub fn routes(&self) -> impl Iterator<Item = &Route> {
+        self.routes.iter()
+    }
+
+    // an OpenAPI 3 document (JSON) listing every route; see `openapi::skeleton`
+    pub fn openapi(&self, title: &str, version: &str) -> String {
+        openapi::skeleton(self, title, version)
+    }
+
+    fn find(&self, method: &str, path: &str) -> Option<(&Route, HashMap<String, String>)> {
+        let path = split_path(path);
+        self.routes
+            .iter()
+            .filter(|route| route.method == method)
+            .filter_map(|route| route.matches(&path).map(|params| (route, params)))
+            .min_by_key(|(route, _)| route.segments.iter().map(Segment::rank).collect::<Vec<_>>())
+    }
+
+    // the methods some route takes `path` with, sorted; OPTIONS is always answered
+    fn allowed(&self, path: &str) -> Vec<&str> {
+        let path = split_path(path);
+        let mut methods: Vec<&str> = self
+            .routes
+            .iter()
+            .filter(|route| route.matches(&path).is_some())
+            .map(|route| &*route.method)
+            .collect();
+        if !methods.is_empty() {
+            methods.push("OPTIONS");
+        }
+        methods.sort();
+        methods.dedup();
+        methods
+    }
+}
+
+impl HttpApp for Router {
+    type Output = LocalBoxFuture<'static, Response>;
+
+    fn app(&self, mut req: Request) -> Self::Output {
+        let path = req.uri().split('?').next().unwrap_or("").to_owned();
+        if let Some((route, params)) = self.find(req.method(), &path) {
+            req.set_params(params);
+            return route.app.app(req);
+        }
+        // the path is known, the method isn't
+        let allowed = self.allowed(&path);
+        if !allowed.is_empty() {
+            let mut res = if req.method() == "OPTIONS" {
+                Response::ok()
+            } else {
+                Response::with_status_code(StatusCode::MethodNotAllowed)
+            };
+            res.set_header("Allow", allowed.join(", "));
+            return future::ready(res).boxed_local();
+        }
+        match &self.fallback {
+            Some(fallback) => fallback.app(req),
+            None => future::ready(Response::with_status_code(StatusCode::NotFound)).boxed_local(),
+        }
+    }
+
+    fn check(&self, problems: &mut Vec<String>) {
+        for (i, a) in self.routes.iter().enumerate() {
+            for b in &self.routes[i + 1..] {
+                if a.method == b.method
+                    && a.segments.len() == b.segments.len()
+                    && a.segments
+                        .iter()
+                        .zip(&b.segments)
+                        .all(|(x, y)| x.same_shape(y))
+                {
+                    problems.push(format!(
+                        "route {} {} conflicts with {} {}",
+                        b.method, b.pattern, a.method, a.pattern
+                    ));
+                }
+            }
+            if let Some(pos) = a.segments.iter().position(|s| s.rank() == 2) {
+                if pos + 1 != a.segments.len() {
+                    problems.push(format!(
+                        "route {} {}: `*` must come last",
+                        a.method, a.pattern
+                    ));
+                }
+            }
+            a.app.check(problems);
+        }
+        if let Some(fallback) = &self.fallback {
+            fallback.check(problems);
+        }
+    }
+}
+
+fn split_path(path: &str) -> Vec<&str> {
+    path.split('/').filter(|s| !s.is_empty()).collect()
+}
+
+fn parse_pattern(pattern: &str) -> Vec<Segment> {
+    split_path(pattern)
+        .into_iter()
+        .map(|s| {
+            if s.starts_with(':') {
+                Segment::Param(s[1..].to_owned())
+            } else if s.starts_with('*') {
+                Segment::Rest(s[1..].to_owned())
+            } else {
+                Segment::Literal(s.to_owned())
+            }
+        })
+        .collect()
+}
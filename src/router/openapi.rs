@@ -0,0 +1,37 @@
+use super::Router;
+use crate::json::string;
+use std::collections::BTreeMap;
+
+// paths, methods and path parameters of `router` as an OpenAPI 3 document. request and
+// response schemas aren't known to the router, so every operation gets a generic response.
+pub fn skeleton(router: &Router, title: &str, version: &str) -> String {
+    let mut paths: BTreeMap<String, Vec<String>> = BTreeMap::new();
+    for route in router.routes() {
+        let params = route
+            .params()
+            .map(|name| {
+                format!(
+                    r#"{{"name":{},"in":"path","required":true,"schema":{{"type":"string"}}}}"#,
+                    string(name)
+                )
+            })
+            .collect::<Vec<_>>();
+        let operation = format!(
+            r#"{}:{{"operationId":{},"parameters":[{}],"responses":{{"default":{{"description":"response"}}}}}}"#,
+            string(&route.method().to_lowercase()),
+            string(&format!("{} {}", route.method(), route.pattern())),
+            params.join(","),
+        );
+        paths.entry(route.template()).or_default().push(operation);
+    }
+    let paths = paths
+        .iter()
+        .map(|(path, operations)| format!("{}:{{{}}}", string(path), operations.join(",")))
+        .collect::<Vec<_>>();
+    format!(
+        r#"{{"openapi":"3.0.3","info":{{"title":{},"version":{}}},"paths":{{{}}}}}"#,
+        string(title),
+        string(version),
+        paths.join(",")
+    )
+}
@@ -0,0 +1,77 @@
+use crate::http::host::{self, HostPattern};
+use crate::http::middleware::BoxedApp;
+use crate::http::*;
+use futures::future::{self, FutureExt, LocalBoxFuture};
+use log::*;
+
+// dispatches on the host a request is addressed to, so one listener can serve several sites.
+// an exact name beats a wildcard and a longer wildcard beats a shorter one; requests no
+// pattern matches go to the default host's app, or get 421 Misdirected Request without one.
+#[derive(Default)]
+pub struct VirtualHosts {
+    hosts: Vec<(HostPattern, BoxedApp)>,
+    default: Option<BoxedApp>,
+}
+
+impl VirtualHosts {
+    pub fn new() -> VirtualHosts {
+        VirtualHosts::default()
+    }
+
+    // `pattern` is an exact name, `*.example.com` or `*`; see `HostPattern`
+    pub fn host<P, T>(&mut self, pattern: P, app: T) -> &mut Self
+    where
+        P: Into<HostPattern>,
+        T: HttpApp + 'static,
+        T::Output: 'static,
+    {
+        self.hosts.push((pattern.into(), BoxedApp::new(app)));
+        self
+    }
+
+    // handles requests for hosts no pattern matches, and requests without a host
+    pub fn default_host<T>(&mut self, app: T) -> &mut Self
+    where
+        T: HttpApp + 'static,
+        T::Output: 'static,
+    {
+        self.default = Some(BoxedApp::new(app));
+        self
+    }
+
+    fn find(&self, req: &Request) -> Option<&BoxedApp> {
+        let host = host::request_host(req)?;
+        self.hosts
+            .iter()
+            .filter(|(pattern, _)| pattern.matches(&host))
+            .max_by_key(|(pattern, _)| pattern.rank())
+            .map(|(_, app)| app)
+    }
+}
+
+impl HttpApp for VirtualHosts {
+    type Output = LocalBoxFuture<'static, Response>;
+
+    fn app(&self, req: Request) -> Self::Output {
+        match self.find(&req).or_else(|| self.default.as_ref()) {
+            Some(app) => app.app(req),
+            None => {
+                debug!("no virtual host for {:?}", host::request_host(&req));
+                future::ready(Response::with_status_code(StatusCode::MisdirectedRequest))
+                    .boxed_local()
+            }
+        }
+    }
+
+    fn check(&self, problems: &mut Vec<String>) {
+        for (i, (pattern, app)) in self.hosts.iter().enumerate() {
+            if self.hosts[..i].iter().any(|(p, _)| p == pattern) {
+                problems.push(format!("virtual host {:?} registered twice", pattern));
+            }
+            app.check(problems);
+        }
+        if let Some(default) = &self.default {
+            default.check(problems);
+        }
+    }
+}
@@ -1,19 +1,257 @@
-use futures::future::LocalBoxFuture;
+use crate::reactor::{self, ReactorWaker};
+use crate::runtime::Runtime;
+use futures::channel::oneshot;
+use futures::future::{self, AbortHandle, FutureExt, LocalBoxFuture};
+use futures::task::ArcWake;
 use log::*;
 use std::future::Future;
+use std::pin::Pin;
 use std::task::*;
 use std::{
-    cell::RefCell,
-    collections::{HashMap, HashSet},
+    cell::{Cell, RefCell},
+    collections::{BTreeMap, HashMap, HashSet, VecDeque},
+    error, fmt,
     rc::Rc,
+    sync::{Arc, Mutex, MutexGuard, PoisonError},
+    thread::{self, ThreadId},
+    time::{Duration, Instant},
 };
 
+pub mod blocking;
+pub mod cancel;
+pub mod scope;
+
+thread_local! {
+    static LOCAL_SPAWNED: RefCell<Vec<NewTask<'static>>> = RefCell::new(Vec::new());
+    // over all runners on the thread: the tasks that haven't finished, by their keys, which
+    // are unique on the thread
+    static TASKS: RefCell<BTreeMap<usize, TaskInfo>> = RefCell::new(BTreeMap::new());
+    static NEXT_KEY: Cell<usize> = Cell::new(1);
+    // the task being polled
+    static CURRENT: Cell<Option<usize>> = Cell::new(None);
+    static QUEUED: Cell<usize> = Cell::new(0);
+    static TOTALS: RefCell<Totals> = RefCell::new(Totals {
+        since: Instant::now(),
+        polls: 0,
+        busy: Duration::from_secs(0),
+        longest_poll: Duration::from_secs(0),
+    });
+}
+
+// a task on its way to a runner
+struct NewTask<'a> {
+    fut: LocalBoxFuture<'a, ()>,
+    // the type of the spawned future, or of the closure for `spawn_blocking`
+    future: &'static str,
+    name: Option<String>,
+}
+
+struct TaskInfo {
+    future: &'static str,
+    name: Option<String>,
+    spawned: Instant,
+    polls: u64,
+    polled_at: Option<Instant>,
+    busy: Duration,
+    running: bool,
+    // the queue of its runner, telling whether it is woken
+    woke: Arc<ReadyQueue>,
+}
+
+struct Totals {
+    since: Instant,
+    polls: u64,
+    busy: Duration,
+    longest_poll: Duration,
+}
+
+// tasks spawned on this thread's runners that haven't finished
+pub fn tasks() -> usize {
+    TASKS.with(|tasks| tasks.borrow().len())
+}
+
+// tasks woken and waiting for their next poll, as of the end of the last run of a runner
+pub fn queued() -> usize {
+    QUEUED.with(Cell::get)
+}
+
+// a snapshot of this thread's runners, as `reactor::stats` is of its reactor. every task
+// waits while one is polled, so a long poll holds up the others, connections included.
+#[derive(Clone, Debug)]
+pub struct RunnerStats {
+    pub tasks: usize,
+    pub queued: usize,
+    pub polls: u64,
+    // since the thread's first run of a runner
+    pub uptime: Duration,
+    // time spent polling tasks
+    pub busy: Duration,
+    pub longest_poll: Duration,
+}
+
+impl RunnerStats {
+    // over the uptime; take the difference of two snapshots for a recent rate
+    pub fn polls_per_second(&self) -> f64 {
+        let secs = self.uptime.as_secs() as f64 + f64::from(self.uptime.subsec_nanos()) / 1e9;
+        if secs > 0.0 {
+            self.polls as f64 / secs
+        } else {
+            0.0
+        }
+    }
+}
+
+pub fn stats() -> RunnerStats {
+    TOTALS.with(|totals| {
+        let totals = totals.borrow();
+        RunnerStats {
+            tasks: tasks(),
+            queued: queued(),
+            polls: totals.polls,
+            uptime: totals.since.elapsed(),
+            busy: totals.busy,
+            longest_poll: totals.longest_poll,
+        }
+    })
+}
+
+// a task of this thread's runners as `dump` found it
+#[derive(Clone, Debug)]
+pub struct TaskDump {
+    // the key the runner polls it by, as in trace logs
+    pub key: usize,
+    // the type of the spawned future
+    pub future: &'static str,
+    // as given to `spawn_named`
+    pub name: Option<String>,
+    pub state: TaskState,
+    pub age: Duration,
+    pub polls: u64,
+    // None until its first poll
+    pub since_poll: Option<Duration>,
+    // time spent polling it
+    pub busy: Duration,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TaskState {
+    // in a poll: the task asking for the dump, or one running the runner it is on
+    Running,
+    // woken, and waiting for its poll
+    Queued,
+    // waiting to be woken
+    Idle,
+}
+
+// the unfinished tasks of this thread's runners, oldest first, for finding stuck ones: an
+// idle task not polled for long waits for something that may never come
+pub fn dump() -> Vec<TaskDump> {
+    let now = Instant::now();
+    TASKS.with(|tasks| {
+        tasks
+            .borrow()
+            .iter()
+            .map(|(&key, info)| TaskDump {
+                key,
+                future: info.future,
+                name: info.name.clone(),
+                state: if info.running {
+                    TaskState::Running
+                } else if info.woke.contains(key) {
+                    TaskState::Queued
+                } else {
+                    TaskState::Idle
+                },
+                age: now - info.spawned,
+                polls: info.polls,
+                since_poll: info.polled_at.map(|at| now - at),
+                busy: info.busy,
+            })
+            .collect()
+    })
+}
+
+// the name of the task being polled on this thread, for log lines; None outside of a task,
+// or in one spawned without a name
+pub fn task_name() -> Option<String> {
+    name_of(CURRENT.with(Cell::get)?)
+}
+
+fn name_of(key: usize) -> Option<String> {
+    TASKS.with(|tasks| tasks.borrow().get(&key)?.name.clone())
+}
+
+// "task 3", or "task 3 (connection 127.0.0.1:54321)" for a named one
+fn describe(key: usize) -> String {
+    match name_of(key) {
+        Some(name) => format!("task {} ({})", key, name),
+        None => format!("task {}", key),
+    }
+}
+
+// runs `fut` to completion on a runtime of its own, and with it whatever it spawns with
+// `spawn_local`; see `Runtime::block_on`. for tools and tests:
+//
+//     let body = runner::block_on(async { fetch(&addr).await })?;
+pub fn block_on<F: Future>(fut: F) -> F::Output {
+    Runtime::new().block_on(fut)
+}
+
+// spawns onto whichever runner is running on this thread, e.g. from inside a request handler
+pub fn spawn_local<F>(fut: F) -> JoinHandle<F::Output>
+where
+    F: Future + 'static,
+{
+    let (task, handle) = joinable(fut, None);
+    LOCAL_SPAWNED.with(|tasks| tasks.borrow_mut().push(task));
+    handle
+}
+
+// as `spawn_local`, naming the task; see `Spawner::spawn_named`
+pub fn spawn_local_named<N, F>(name: N, fut: F) -> JoinHandle<F::Output>
+where
+    N: Into<String>,
+    F: Future + 'static,
+{
+    let (task, handle) = joinable(fut, Some(name.into()));
+    LOCAL_SPAWNED.with(|tasks| tasks.borrow_mut().push(task));
+    handle
+}
+
+// runs `f` on a thread of the `blocking` pool, for code that would stall the reactor. the
+// handle resolves on this thread's runner, as one from `spawn_local` does, once `f` has
+// returned; `Err(Aborted)` if `f` panicked. aborting keeps `f` from starting, but one already
+// running is left to finish.
+//
+//     let hash = runner::spawn_blocking(move || bcrypt(&password)).await?;
+pub fn spawn_blocking<F, T>(f: F) -> JoinHandle<T>
+where
+    F: FnOnce() -> T + Send + 'static,
+    T: Send + 'static,
+{
+    let (tx, rx) = oneshot::channel();
+    blocking::spawn(move || {
+        // aborted while queued
+        if !tx.is_canceled() {
+            let _ = tx.send(f());
+        }
+    });
+    let (rx, abort) = future::abortable(rx);
+    // the sender is dropped without sending if `f` panics
+    let rx = rx.map(|output| match output {
+        Ok(Ok(output)) => Ok(output),
+        _ => Err(Aborted),
+    });
+    let (task, handle) = join_task(rx, abort, std::any::type_name::<F>(), None);
+    LOCAL_SPAWNED.with(|tasks| tasks.borrow_mut().push(task));
+    handle
+}
+
 #[derive(Default)]
 pub struct Runner<'a> {
     tasks: HashMap<usize, (LocalBoxFuture<'a, ()>, Option<Waker>)>,
-    spawned_tasks: Rc<RefCell<Vec<LocalBoxFuture<'a, ()>>>>,
-    woke: Rc<RefCell<HashSet<usize>>>,
-    next_key: usize,
+    spawned_tasks: Rc<RefCell<Vec<NewTask<'a>>>>,
+    woke: Arc<ReadyQueue>,
 }
 
 impl<'a> Runner<'a> {
@@ -27,95 +265,411 @@ impl<'a> Runner<'a> {
         }
     }
 
-    fn move_tasks(&mut self) {
-        for task in self.spawned_tasks.borrow_mut().drain(..) {
-            let key = self.next_key;
-            self.next_key += 1;
-            self.tasks
-                .insert(key, (task, None));
-            self.woke.borrow_mut().insert(key);
+    // false if nothing was spawned since the last call
+    fn move_tasks(&mut self) -> bool {
+        let spawned: Vec<_> = self.spawned_tasks.borrow_mut().drain(..).collect();
+        let local =
+            LOCAL_SPAWNED.with(|tasks| std::mem::replace(&mut *tasks.borrow_mut(), Vec::new()));
+        let moved = !spawned.is_empty() || !local.is_empty();
+        let now = Instant::now();
+        for task in spawned.into_iter().chain(local) {
+            let key = NEXT_KEY.with(|next| next.replace(next.get() + 1));
+            let info = TaskInfo {
+                future: task.future,
+                name: task.name,
+                spawned: now,
+                polls: 0,
+                polled_at: None,
+                busy: Duration::from_secs(0),
+                running: false,
+                woke: Arc::clone(&self.woke),
+            };
+            TASKS.with(|tasks| tasks.borrow_mut().insert(key, info));
+            trace!("spawn {}", describe(key));
+            self.tasks.insert(key, (task.fut, None));
+            self.woke.push(key);
         }
+        moved
     }
 
+    // polls the tasks woken since they were last polled, and those spawned since the last
+    // run; the rest wait for their wakers. a task woken while this runs, by itself or
+    // another, is polled in the next run.
     pub fn run(&mut self) {
+        self.woke.unpark_this_thread();
         self.move_tasks();
-        let mut new_woke = HashSet::new();
-        for key in self.woke.borrow_mut().drain() {
-            if let Some((fut, waker)) = self.tasks.get_mut(&key) {
-                if waker.is_none() {
-                    *waker = Some(WakerImpl::waker(key, Rc::clone(&self.woke)));
-                }
-                let mut cx = Context::from_waker(waker.as_ref().unwrap());
-                if fut.as_mut().poll(&mut cx).is_ready() {
-                    self.tasks.remove(&key);
-                } else {
-                    new_woke.insert(key);
-                }
+        loop {
+            // taken first: polling may wake other tasks, which locks `woke`
+            for key in self.woke.take() {
+                self.poll_task(key);
+            }
+            // tasks spawned while polling get their first poll in the same run
+            if !self.move_tasks() {
+                break;
+            }
+        }
+        QUEUED.with(|queued| queued.set(self.woke.len()));
+    }
+
+    // polls the task woken first, for `test_util::Driver`, which steps through the tasks a
+    // poll at a time: its key, its name, and whether it finished
+    #[cfg(feature = "test-util")]
+    pub(crate) fn poll_one(&mut self) -> Option<(usize, Option<String>, bool)> {
+        self.woke.unpark_this_thread();
+        self.move_tasks();
+        let key = self.woke.pop()?;
+        let name = name_of(key);
+        let ready = self.poll_task(key);
+        QUEUED.with(|queued| queued.set(self.woke.len()));
+        Some((key, name, ready))
+    }
+
+    // true if the task finished; a woken task may have been dropped since, with its runner
+    fn poll_task(&mut self, key: usize) -> bool {
+        let (fut, waker) = match self.tasks.get_mut(&key) {
+            Some(task) => task,
+            None => return false,
+        };
+        if waker.is_none() {
+            *waker = Some(task_waker(key, &self.woke));
+        }
+        let mut cx = Context::from_waker(waker.as_ref().unwrap());
+        #[cfg(feature = "tracing")]
+        let span =
+            tracing::trace_span!("poll", task = key, name = name_of(key).unwrap_or_default());
+        #[cfg(feature = "tracing")]
+        let _enter = span.enter();
+        let polling = Polling::start(key);
+        let start = Instant::now();
+        let ready = fut.as_mut().poll(&mut cx).is_ready();
+        drop(polling);
+        polled(key, start, ready);
+        if ready {
+            self.tasks.remove(&key);
+        }
+        ready
+    }
+
+    // a task was woken since it was last polled, and is due a poll without waiting for the
+    // reactor
+    pub(crate) fn is_woken(&self) -> bool {
+        !self.woke.is_empty()
+    }
+
+    // no task is left; after a run, nothing spawned before it is still waiting to be moved
+    pub(crate) fn is_empty(&self) -> bool {
+        self.tasks.is_empty()
+    }
+}
+
+impl<'a> Drop for Runner<'a> {
+    fn drop(&mut self) {
+        let _ = TASKS.try_with(|tasks| {
+            let mut tasks = tasks.borrow_mut();
+            for key in self.tasks.keys() {
+                tasks.remove(key);
+            }
+        });
+    }
+}
+
+// marks task `key` running for the length of its poll, and names it if the poll panics. the
+// borrows end before the poll, which may ask for a `dump`.
+struct Polling {
+    key: usize,
+    // of the runner this one runs in, if any
+    outer: Option<usize>,
+}
+
+impl Polling {
+    fn start(key: usize) -> Polling {
+        TASKS.with(|tasks| {
+            if let Some(info) = tasks.borrow_mut().get_mut(&key) {
+                info.running = true;
             }
+        });
+        Polling {
+            key,
+            outer: CURRENT.with(|current| current.replace(Some(key))),
         }
-        *self.woke.borrow_mut() = new_woke;
     }
 }
 
+impl Drop for Polling {
+    fn drop(&mut self) {
+        if thread::panicking() {
+            error!("{} panicked", describe(self.key));
+        }
+        CURRENT.with(|current| current.set(self.outer));
+    }
+}
+
+fn polled(key: usize, start: Instant, ready: bool) {
+    let took = start.elapsed();
+    TOTALS.with(|totals| {
+        let mut totals = totals.borrow_mut();
+        totals.polls += 1;
+        totals.busy += took;
+        totals.longest_poll = totals.longest_poll.max(took);
+    });
+    TASKS.with(|tasks| {
+        let mut tasks = tasks.borrow_mut();
+        if ready {
+            tasks.remove(&key);
+        } else if let Some(info) = tasks.get_mut(&key) {
+            info.polls += 1;
+            info.polled_at = Some(start);
+            info.busy += took;
+            info.running = false;
+        }
+    });
+}
+
 pub struct Spawner<'a> {
-    tasks: Rc<RefCell<Vec<LocalBoxFuture<'a, ()>>>>,
+    tasks: Rc<RefCell<Vec<NewTask<'a>>>>,
 }
 
 impl<'a> Spawner<'a> {
-    pub fn spawn<F: Future<Output = ()> + 'a>(&self, fut: F) {
-        self.tasks.borrow_mut().push(Box::pin(fut));
+    pub fn spawn<F>(&self, fut: F) -> JoinHandle<F::Output>
+    where
+        F: Future + 'a,
+    {
+        let (task, handle) = joinable(fut, None);
+        self.tasks.borrow_mut().push(task);
+        handle
+    }
+
+    // as `spawn`, naming the task for log lines, panic messages, the task `dump` and the
+    // `poll` spans of the `tracing` feature:
+    //
+    //     spawner.spawn_named(format!("connection {}", peer), serve(sock));
+    pub fn spawn_named<N, F>(&self, name: N, fut: F) -> JoinHandle<F::Output>
+    where
+        N: Into<String>,
+        F: Future + 'a,
+    {
+        let (task, handle) = joinable(fut, Some(name.into()));
+        self.tasks.borrow_mut().push(task);
+        handle
     }
 }
 
-#[derive(Clone)]
-struct WakerImpl {
-    key: usize,
-    woke: Rc<RefCell<HashSet<usize>>>,
+// the task the runner polls for `fut`, handing the output to the handle
+fn joinable<'a, F>(fut: F, name: Option<String>) -> (NewTask<'a>, JoinHandle<F::Output>)
+where
+    F: Future + 'a,
+{
+    let (fut, abort) = future::abortable(fut);
+    let fut = fut.map(|output| output.map_err(|_| Aborted));
+    join_task(fut, abort, std::any::type_name::<F>(), name)
+}
+
+// as `joinable`, for a future that is abortable by `abort` already
+fn join_task<'a, T, F>(
+    fut: F,
+    abort: AbortHandle,
+    future: &'static str,
+    name: Option<String>,
+) -> (NewTask<'a>, JoinHandle<T>)
+where
+    F: Future<Output = Result<T, Aborted>> + 'a,
+    T: 'a,
+{
+    let state = Rc::new(JoinState {
+        output: RefCell::new(None),
+        finished: Cell::new(false),
+        waker: RefCell::new(None),
+    });
+    let handle = JoinHandle {
+        state: Rc::clone(&state),
+        abort,
+    };
+    let task = fut.map(move |output| {
+        *state.output.borrow_mut() = Some(output);
+        state.finished.set(true);
+        if let Some(waker) = state.waker.borrow_mut().take() {
+            waker.wake();
+        }
+    });
+    let task = NewTask {
+        fut: task.boxed_local(),
+        future,
+        name,
+    };
+    (task, handle)
+}
+
+// resolves to the output of a spawned task. dropping the handle leaves the task running;
+// `abort` stops it.
+pub struct JoinHandle<T> {
+    state: Rc<JoinState<T>>,
+    abort: AbortHandle,
+}
+
+struct JoinState<T> {
+    output: RefCell<Option<Result<T, Aborted>>>,
+    // aborted ones too; the output may have been taken since
+    finished: Cell<bool>,
+    // the task waiting for the output
+    waker: RefCell<Option<Waker>>,
+}
+
+// the task was aborted before it finished
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Aborted;
+
+impl fmt::Display for Aborted {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("task aborted")
+    }
+}
+
+impl error::Error for Aborted {}
+
+impl<T> JoinHandle<T> {
+    // the task is dropped at its next poll, which the abort wakes it for, rather than polled
+    // again; the handle then resolves to `Err(Aborted)`. a finished task keeps its output.
+    pub fn abort(&self) {
+        self.abort.abort();
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.state.finished.get()
+    }
+
+    pub(crate) fn abort_handle(&self) -> AbortHandle {
+        self.abort.clone()
+    }
+}
+
+impl<T> Future for JoinHandle<T> {
+    type Output = Result<T, Aborted>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Self::Output> {
+        match self.state.output.borrow_mut().take() {
+            Some(output) => Poll::Ready(output),
+            None => {
+                *self.state.waker.borrow_mut() = Some(cx.waker().clone());
+                Poll::Pending
+            }
+        }
+    }
 }
 
-impl WakerImpl {
-    fn waker(key: usize, woke: Rc<RefCell<HashSet<usize>>>) -> Waker {
-        unsafe {
-            let boxed = Box::into_raw(Box::new(Self::new(key, woke))) as *const ();
-            trace!("create waker {:?}", boxed);
-            Waker::from_raw(RawWaker::new(boxed, &VTABLE))
+impl<T> fmt::Debug for JoinHandle<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("JoinHandle")
+            .field("finished", &self.is_finished())
+            .finish()
+    }
+}
+
+// keys of the tasks woken since their last poll, in the order they were first woken; waking
+// a task again before its poll doesn't queue it twice. wakers may be woken on other threads,
+// e.g. by a thread pool finishing work for the task, so the queue is shared between them, and
+// such a wake also wakes the reactor the runner's thread may be waiting in.
+#[derive(Default)]
+pub(crate) struct ReadyQueue {
+    keys: Mutex<Keys>,
+    // the thread the tasks are polled on, and a waker of its reactor
+    unpark: Mutex<Option<(ThreadId, ReactorWaker)>>,
+}
+
+#[derive(Default)]
+struct Keys {
+    order: VecDeque<usize>,
+    queued: HashSet<usize>,
+}
+
+fn lock<T>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+    mutex.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+impl ReadyQueue {
+    fn push(&self, key: usize) {
+        let mut keys = lock(&self.keys);
+        if !keys.queued.insert(key) {
+            return;
+        }
+        let first = keys.order.is_empty();
+        keys.order.push_back(key);
+        drop(keys);
+        // the thread is awake already, or waiting since before the first wake
+        if first {
+            if let Some((id, unpark)) = &*lock(&self.unpark) {
+                if *id != thread::current().id() {
+                    unpark.wake();
+                }
+            }
         }
     }
 
-    fn new(key: usize, woke: Rc<RefCell<HashSet<usize>>>) -> WakerImpl {
-        WakerImpl { key, woke }
+    fn take(&self) -> VecDeque<usize> {
+        let mut keys = lock(&self.keys);
+        keys.queued.clear();
+        std::mem::replace(&mut keys.order, VecDeque::new())
     }
 
-    unsafe fn clone(this: *const ()) -> RawWaker {
-        let this = this as *mut Self;
-        let boxed = Box::from_raw(this);
-        let cloned = Box::into_raw(Box::clone(&boxed)) as *const ();
-        trace!("clone {:?} -> {:?}", this, cloned);
-        std::mem::forget(boxed);
-        RawWaker::new(cloned, &VTABLE)
+    #[cfg(feature = "test-util")]
+    fn pop(&self) -> Option<usize> {
+        let mut keys = lock(&self.keys);
+        let key = keys.order.pop_front()?;
+        keys.queued.remove(&key);
+        Some(key)
     }
 
-    unsafe fn wake(this: *const ()) {
-        trace!("wake {:?}", this);
-        Self::wake_by_ref(this);
-        Self::drop(this);
+    fn len(&self) -> usize {
+        lock(&self.keys).order.len()
     }
 
-    unsafe fn wake_by_ref(this: *const ()) {
-        trace!("wake_by_ref {:?}", this);
-        let this = this as *const Self;
-        (*this).woke.borrow_mut().insert((*this).key);
+    fn contains(&self, key: usize) -> bool {
+        lock(&self.keys).queued.contains(&key)
     }
 
-    pub unsafe fn drop(this: *const ()) {
-        trace!("drop {:?}", this);
-        Box::from_raw(this as *mut Self);
+    pub(crate) fn is_empty(&self) -> bool {
+        lock(&self.keys).order.is_empty()
+    }
+
+    pub(crate) fn clear(&self) {
+        self.take();
+    }
+
+    // on the thread the tasks are polled on, before it first waits for them
+    fn unpark_this_thread(&self) {
+        let mut unpark = lock(&self.unpark);
+        if unpark.is_none() {
+            match reactor::thread_waker() {
+                Ok(waker) => *unpark = Some((thread::current().id(), waker)),
+                Err(e) => debug!("tasks woken on other threads won't wake this one: {}", e),
+            }
+        }
     }
 }
 
-static VTABLE: RawWakerVTable = RawWakerVTable::new(
-    WakerImpl::clone,
-    WakerImpl::wake,
-    WakerImpl::wake_by_ref,
-    WakerImpl::drop,
-);
+// wakes task `key` into `woke`
+struct TaskWaker {
+    key: usize,
+    woke: Arc<ReadyQueue>,
+}
+
+impl ArcWake for TaskWaker {
+    fn wake_by_ref(arc_self: &Arc<Self>) {
+        trace!("wake {}", arc_self.key);
+        arc_self.woke.push(arc_self.key);
+    }
+}
+
+fn task_waker(key: usize, woke: &Arc<ReadyQueue>) -> Waker {
+    futures::task::waker(Arc::new(TaskWaker {
+        key,
+        woke: Arc::clone(woke),
+    }))
+}
+
+// for a future polled on this thread outside of any runner; the queue gets key 0 once it is
+// woken
+pub(crate) fn detached_waker() -> (Waker, Arc<ReadyQueue>) {
+    let woke = Arc::new(ReadyQueue::default());
+    woke.unpark_this_thread();
+    (task_waker(0, &woke), woke)
+}
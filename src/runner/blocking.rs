@@ -0,0 +1,125 @@
+// the threads `runner::spawn_blocking` runs closures on, for code that would stall the reactor:
+// blocking libraries, password hashing, heavy computation. threads are started as closures
+// queue up, up to a limit, and exit after sitting idle for a while.
+use lazy_static::*;
+use log::*;
+use std::{
+    collections::VecDeque,
+    panic,
+    sync::{Condvar, Mutex, MutexGuard, PoisonError},
+    thread,
+    time::Duration,
+};
+
+type Job = Box<dyn FnOnce() + Send>;
+
+lazy_static! {
+    static ref POOL: Pool = Pool {
+        state: Mutex::new(State {
+            jobs: VecDeque::new(),
+            threads: 0,
+            idle: 0,
+            max_threads: 64,
+            keep_alive: Duration::from_secs(10),
+            name: "blocking".to_owned(),
+        }),
+        job_ready: Condvar::new(),
+    };
+}
+
+struct Pool {
+    state: Mutex<State>,
+    job_ready: Condvar,
+}
+
+struct State {
+    jobs: VecDeque<Job>,
+    threads: usize,
+    // threads waiting for a job
+    idle: usize,
+    max_threads: usize,
+    keep_alive: Duration,
+    name: String,
+}
+
+fn lock() -> MutexGuard<'static, State> {
+    // jobs run outside the lock, their panics can't poison it
+    POOL.state.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+// at most this many threads run closures at a time, 64 by default; more wait in a queue.
+// threads above a lowered limit exit once idle.
+pub fn set_max_threads(n: usize) {
+    lock().max_threads = n.max(1);
+}
+
+// how long a thread waits for another closure before it exits, 10 seconds by default
+pub fn set_keep_alive(keep_alive: Duration) {
+    lock().keep_alive = keep_alive;
+    POOL.job_ready.notify_all();
+}
+
+// names threads started from now on
+pub fn set_thread_name<S: Into<String>>(name: S) {
+    lock().name = name.into();
+}
+
+// threads running, busy or idle
+pub fn threads() -> usize {
+    lock().threads
+}
+
+// closures waiting for a thread
+pub fn queued() -> usize {
+    lock().jobs.len()
+}
+
+// runs `job` on a thread of the pool, a new one if every one is busy and the limit allows
+pub(crate) fn spawn<F: FnOnce() + Send + 'static>(job: F) {
+    let mut state = lock();
+    state.jobs.push_back(Box::new(job));
+    if state.jobs.len() <= state.idle {
+        POOL.job_ready.notify_one();
+        return;
+    }
+    if state.threads >= state.max_threads {
+        return;
+    }
+    let spawned = thread::Builder::new().name(state.name.clone()).spawn(work);
+    match spawned {
+        Ok(_) => state.threads += 1,
+        // left for the threads there are; with none, it waits for the next spawn
+        Err(e) => error!("spawning a blocking thread: {}", e),
+    }
+}
+
+fn work() {
+    let mut state = lock();
+    loop {
+        if state.threads > state.max_threads {
+            break;
+        }
+        if let Some(job) = state.jobs.pop_front() {
+            drop(state);
+            // the panic fails the closure's handle only; the thread stays up
+            if panic::catch_unwind(panic::AssertUnwindSafe(job)).is_err() {
+                error!("blocking closure panicked");
+            }
+            state = lock();
+            continue;
+        }
+        state.idle += 1;
+        let keep_alive = state.keep_alive;
+        let (next, wait) = POOL
+            .job_ready
+            .wait_timeout(state, keep_alive)
+            .unwrap_or_else(PoisonError::into_inner);
+        state = next;
+        state.idle -= 1;
+        if wait.timed_out() && state.jobs.is_empty() {
+            break;
+        }
+    }
+    state.threads -= 1;
+    trace!("blocking thread exiting, {} left", state.threads);
+}
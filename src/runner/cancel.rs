@@ -0,0 +1,118 @@
+// cancelling work across tasks, cooperatively: a task races its work against `cancelled`, or
+// checks `is_cancelled` between steps, and cleans up as it likes. unlike `JoinHandle::abort`,
+// the task sees the cancellation coming.
+//
+//     let token = CancellationToken::new();
+//     runner::spawn_local({
+//         let token = token.child();
+//         async move { token.run_until_cancelled(poll_upstream()).await; }
+//     });
+//     ...
+//     token.cancel();
+use futures::future::{self, Either};
+use std::{
+    cell::{Cell, RefCell},
+    fmt,
+    future::Future,
+    pin::Pin,
+    rc::{Rc, Weak},
+    task::{Context, Poll, Waker},
+};
+
+// clones share the cancellation; see `child` for tokens cancelled along with another
+#[derive(Clone, Default)]
+pub struct CancellationToken(Rc<Node>);
+
+#[derive(Default)]
+struct Node {
+    cancelled: Cell<bool>,
+    wakers: RefCell<Vec<Waker>>,
+    children: RefCell<Vec<Weak<Node>>>,
+}
+
+impl Node {
+    fn cancel(&self) {
+        if self.cancelled.replace(true) {
+            return;
+        }
+        for waker in self.wakers.borrow_mut().drain(..) {
+            waker.wake();
+        }
+        let children = std::mem::replace(&mut *self.children.borrow_mut(), Vec::new());
+        for child in children.iter().filter_map(Weak::upgrade) {
+            child.cancel();
+        }
+    }
+}
+
+impl CancellationToken {
+    pub fn new() -> CancellationToken {
+        CancellationToken::default()
+    }
+
+    // wakes every task waiting in `cancelled`, and cancels the children. later calls have no
+    // effect.
+    pub fn cancel(&self) {
+        self.0.cancel();
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.0.cancelled.get()
+    }
+
+    // a token cancelled when this one is, or on its own without affecting this one; one
+    // made after the cancellation starts out cancelled
+    pub fn child(&self) -> CancellationToken {
+        let child = CancellationToken::new();
+        if self.is_cancelled() {
+            child.0.cancelled.set(true);
+        } else {
+            let mut children = self.0.children.borrow_mut();
+            children.retain(|child| child.upgrade().is_some());
+            children.push(Rc::downgrade(&child.0));
+        }
+        child
+    }
+
+    // resolves once the token is cancelled
+    pub fn cancelled(&self) -> Cancelled {
+        Cancelled(self.clone())
+    }
+
+    // `fut`'s output, or None if the token is cancelled first, in which case `fut` is dropped
+    pub async fn run_until_cancelled<F: Future>(&self, fut: F) -> Option<F::Output> {
+        futures::pin_mut!(fut);
+        match future::select(fut, self.cancelled()).await {
+            Either::Left((output, _)) => Some(output),
+            Either::Right(_) => None,
+        }
+    }
+}
+
+impl fmt::Debug for CancellationToken {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("CancellationToken")
+            .field("cancelled", &self.is_cancelled())
+            .finish()
+    }
+}
+
+// see `CancellationToken::cancelled`
+#[derive(Debug)]
+pub struct Cancelled(CancellationToken);
+
+impl Future for Cancelled {
+    type Output = ();
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context) -> Poll<()> {
+        let node = &(self.0).0;
+        if node.cancelled.get() {
+            return Poll::Ready(());
+        }
+        let mut wakers = node.wakers.borrow_mut();
+        if !wakers.iter().any(|w| w.will_wake(cx.waker())) {
+            wakers.push(cx.waker().clone());
+        }
+        Poll::Pending
+    }
+}
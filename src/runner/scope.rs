@@ -0,0 +1,145 @@
+// tasks tied to the one that spawned them: a connection's body streaming and its timeout
+// watchdog, say, which mustn't outlive the connection. `join` waits for the children; those
+// still running when the scope is dropped are aborted, so none is left behind when the
+// parent returns early, fails, or is aborted itself.
+//
+//     let scope = TaskScope::new();
+//     scope.spawn(stream_body(body, sock));
+//     scope.spawn(watchdog(deadline));
+//     scope.join().await;
+use crate::runner::{self, JoinHandle};
+use futures::future::{self, AbortHandle};
+use std::{
+    cell::{Cell, RefCell},
+    collections::HashMap,
+    fmt,
+    future::Future,
+    rc::Rc,
+    task::{Poll, Waker},
+};
+
+#[derive(Default)]
+pub struct TaskScope(Rc<State>);
+
+#[derive(Default)]
+struct State {
+    // the children not finished yet, by an id of the scope's
+    running: RefCell<HashMap<usize, AbortHandle>>,
+    next_id: Cell<usize>,
+    // the task in `join`
+    waker: RefCell<Option<Waker>>,
+}
+
+// held by a child's future, leaving the scope when it finishes or is dropped on an abort
+struct Child {
+    id: usize,
+    state: Rc<State>,
+}
+
+impl Drop for Child {
+    fn drop(&mut self) {
+        let mut running = self.state.running.borrow_mut();
+        running.remove(&self.id);
+        if running.is_empty() {
+            if let Some(waker) = self.state.waker.borrow_mut().take() {
+                waker.wake();
+            }
+        }
+    }
+}
+
+impl TaskScope {
+    pub fn new() -> TaskScope {
+        TaskScope::default()
+    }
+
+    // spawns `fut` with `runner::spawn_local`, as a child of the scope; the handle works as
+    // one from `spawn_local` does
+    pub fn spawn<F>(&self, fut: F) -> JoinHandle<F::Output>
+    where
+        F: Future + 'static,
+    {
+        let child = self.child();
+        let id = child.id;
+        let handle = runner::spawn_local(async move {
+            let _child = child;
+            fut.await
+        });
+        self.0
+            .running
+            .borrow_mut()
+            .insert(id, handle.abort_handle());
+        handle
+    }
+
+    // as `spawn`, naming the task; see `Spawner::spawn_named`
+    pub fn spawn_named<N, F>(&self, name: N, fut: F) -> JoinHandle<F::Output>
+    where
+        N: Into<String>,
+        F: Future + 'static,
+    {
+        let child = self.child();
+        let id = child.id;
+        let handle = runner::spawn_local_named(name, async move {
+            let _child = child;
+            fut.await
+        });
+        self.0
+            .running
+            .borrow_mut()
+            .insert(id, handle.abort_handle());
+        handle
+    }
+
+    fn child(&self) -> Child {
+        let id = self.0.next_id.replace(self.0.next_id.get() + 1);
+        Child {
+            id,
+            state: Rc::clone(&self.0),
+        }
+    }
+
+    // children not finished yet
+    pub fn len(&self) -> usize {
+        self.0.running.borrow().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    // aborts the children; see `JoinHandle::abort`. the scope stays usable for new ones.
+    pub fn abort(&self) {
+        for abort in self.0.running.borrow().values() {
+            abort.abort();
+        }
+    }
+
+    // resolves once every child has finished or been aborted, those spawned while waiting
+    // too
+    pub async fn join(&self) {
+        future::poll_fn(|cx| {
+            if self.is_empty() {
+                return Poll::Ready(());
+            }
+            *self.0.waker.borrow_mut() = Some(cx.waker().clone());
+            Poll::Pending
+        })
+        .await
+    }
+}
+
+impl Drop for TaskScope {
+    // the children are dropped at their next poll, which the abort wakes them for
+    fn drop(&mut self) {
+        self.abort();
+    }
+}
+
+impl fmt::Debug for TaskScope {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("TaskScope")
+            .field("running", &self.len())
+            .finish()
+    }
+}
@@ -0,0 +1,100 @@
+// the event loop: a runner and the reactor of the thread it runs on, turned together. tasks
+// are polled once woken, and in between the thread waits in the reactor for as long as
+// nothing is woken: until an I/O event, the next timer, or a `ReactorWaker` from another
+// thread.
+//
+//     let mut runtime = Runtime::new();
+//     runtime.spawn(serve(listener));
+//     runtime.run()?;
+use crate::reactor::{self, backend::Backend};
+use crate::runner::{self, JoinHandle, Runner, Spawner};
+use std::{future::Future, io, task::Context, task::Poll, time::Duration};
+
+pub mod threaded;
+
+#[derive(Default)]
+pub struct Runtime<'a> {
+    runner: Runner<'a>,
+}
+
+impl<'a> Runtime<'a> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // runs on `backend` rather than the default one; see `reactor::set_backend`
+    pub fn with_backend(backend: Box<dyn Backend>) -> io::Result<Self> {
+        reactor::set_backend(backend)?;
+        Ok(Self::new())
+    }
+
+    pub fn spawner(&self) -> Spawner<'a> {
+        self.runner.spawner()
+    }
+
+    // first polled on the next turn
+    pub fn spawn<F>(&self, fut: F) -> JoinHandle<F::Output>
+    where
+        F: Future + 'a,
+    {
+        self.runner.spawner().spawn(fut)
+    }
+
+    // see `Spawner::spawn_named`
+    pub fn spawn_named<N, F>(&self, name: N, fut: F) -> JoinHandle<F::Output>
+    where
+        N: Into<String>,
+        F: Future + 'a,
+    {
+        self.runner.spawner().spawn_named(name, fut)
+    }
+
+    // runs the tasks woken since the last turn, then waits on the reactor until one is
+    pub fn turn(&mut self) -> io::Result<()> {
+        self.runner.run();
+        self.park(false)
+    }
+
+    // turns until every task has finished, those spawned along the way too
+    pub fn run(&mut self) -> io::Result<()> {
+        loop {
+            self.runner.run();
+            if self.runner.is_empty() {
+                return Ok(());
+            }
+            self.park(false)?;
+        }
+    }
+
+    // polls `fut` and runs the tasks by turns until `fut` is done. tasks still pending are
+    // left on the runtime. panics if the reactor fails.
+    pub fn block_on<F: Future>(&mut self, fut: F) -> F::Output {
+        futures::pin_mut!(fut);
+        // the future is polled here rather than spawned, so it needn't live as long as the
+        // runtime's tasks; it is woken into a queue of its own
+        let (waker, woke) = runner::detached_waker();
+        let mut cx = Context::from_waker(&waker);
+        loop {
+            woke.clear();
+            if let Poll::Ready(output) = fut.as_mut().poll(&mut cx) {
+                return output;
+            }
+            self.runner.run();
+            let woken = !woke.is_empty();
+            if let Err(e) = self.park(woken) {
+                panic!("reactor failed: {}", e);
+            }
+        }
+    }
+
+    // a turn of the reactor, not waiting if a task is due a poll already. the reactor
+    // shortens the wait to the next timer itself.
+    fn park(&mut self, woken: bool) -> io::Result<()> {
+        let timeout = if woken || self.runner.is_woken() {
+            Some(Duration::from_secs(0))
+        } else {
+            None
+        };
+        reactor::turn(timeout).map(|_| ())
+    }
+}
@@ -0,0 +1,427 @@
+// a pool of worker threads running `Send` tasks, for apps whose state can be shared between
+// threads; `Runtime` stays for those whose can't. each worker turns the reactor of its own
+// thread, and a runner for what its tasks `spawn_local`.
+//
+// a task runs on the worker that first polls it: the one it was spawned onto, or an idle one
+// that stole it from that worker's queue. it stays there, as the sockets and timers it sets up
+// are registered with that worker's reactor, so only tasks that haven't started move.
+//
+//     let pool = ThreadedRuntime::new(4)?;
+//     let handle = pool.handle();
+//     pool.block_on(async move {
+//         for conn in conns {
+//             handle.spawn(serve(conn));
+//         }
+//     });
+use crate::reactor::{self, ReactorWaker};
+use crate::runner::{Aborted, Runner};
+use futures::channel::oneshot;
+use futures::future::{self, AbortHandle, BoxFuture, FutureExt};
+use futures::task::{waker_ref, ArcWake};
+use log::*;
+use std::{
+    cell::Cell,
+    collections::{HashMap, VecDeque},
+    fmt,
+    future::Future,
+    io, panic,
+    pin::Pin,
+    sync::{
+        atomic::{AtomicBool, AtomicUsize, Ordering},
+        Arc, Mutex, MutexGuard, PoisonError,
+    },
+    task::{Context, Poll},
+    thread,
+    time::Duration,
+};
+
+thread_local! {
+    // the pool the thread is a worker of, by address, and the worker's index in it
+    static WORKER: Cell<Option<(usize, usize)>> = Cell::new(None);
+}
+
+// the home of a task not polled yet
+const UNPLACED: usize = !0;
+
+pub struct ThreadedRuntime {
+    shared: Arc<Shared>,
+    threads: Vec<thread::JoinHandle<()>>,
+}
+
+struct Shared {
+    workers: Vec<Worker>,
+    // spawns from outside the workers go round-robin
+    next_worker: AtomicUsize,
+    next_id: AtomicUsize,
+    shutdown: AtomicBool,
+}
+
+// a worker as other threads see it
+struct Worker {
+    queue: Mutex<VecDeque<Arc<Task>>>,
+    // set while the worker waits in its reactor with nothing to run
+    parked: AtomicBool,
+    // wakes the worker's reactor; set once the worker has started
+    unpark: Mutex<Option<ReactorWaker>>,
+}
+
+struct Task {
+    id: usize,
+    fut: Mutex<Option<BoxFuture<'static, ()>>>,
+    // the worker the task runs on from its first poll
+    home: AtomicUsize,
+    // in a queue, or about to be
+    queued: AtomicBool,
+    shared: Arc<Shared>,
+}
+
+fn lock<T>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+    // a panicking task is caught outside the locks, it can't poison them
+    mutex.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+impl ThreadedRuntime {
+    // starts `workers` threads, at least one
+    pub fn new(workers: usize) -> io::Result<ThreadedRuntime> {
+        let workers = workers.max(1);
+        let shared = Arc::new(Shared {
+            workers: (0..workers).map(|_| Worker::new()).collect(),
+            next_worker: AtomicUsize::new(0),
+            next_id: AtomicUsize::new(0),
+            shutdown: AtomicBool::new(false),
+        });
+        // dropped on a failed spawn, stopping the workers started so far
+        let mut runtime = ThreadedRuntime {
+            shared,
+            threads: Vec::with_capacity(workers),
+        };
+        for index in 0..workers {
+            let shared = Arc::clone(&runtime.shared);
+            let thread = thread::Builder::new()
+                .name(format!("worker-{}", index))
+                .spawn(move || work(&shared, index))?;
+            runtime.threads.push(thread);
+        }
+        Ok(runtime)
+    }
+
+    pub fn workers(&self) -> usize {
+        self.shared.workers.len()
+    }
+
+    // for spawning from tasks, or from other threads
+    pub fn handle(&self) -> Handle {
+        Handle {
+            shared: Arc::clone(&self.shared),
+        }
+    }
+
+    pub fn spawn<F>(&self, fut: F) -> JoinHandle<F::Output>
+    where
+        F: Future + Send + 'static,
+        F::Output: Send + 'static,
+    {
+        self.handle().spawn(fut)
+    }
+
+    // spawns `fut` and blocks the calling thread until it is done; not to be called from a
+    // worker. panics if `fut` does.
+    pub fn block_on<F>(&self, fut: F) -> F::Output
+    where
+        F: Future + Send + 'static,
+        F::Output: Send + 'static,
+    {
+        match futures::executor::block_on(self.spawn(fut)) {
+            Ok(output) => output,
+            Err(Aborted) => panic!("the task passed to block_on panicked"),
+        }
+    }
+}
+
+impl Drop for ThreadedRuntime {
+    // stops the workers after the polls they are in; tasks not done are dropped
+    fn drop(&mut self) {
+        self.shared.shutdown.store(true, Ordering::SeqCst);
+        for worker in &self.shared.workers {
+            if let Some(unpark) = lock(&worker.unpark).as_ref() {
+                unpark.wake();
+            }
+        }
+        for thread in self.threads.drain(..) {
+            let _ = thread.join();
+        }
+        // queued tasks refer back to the queues
+        for worker in &self.shared.workers {
+            lock(&worker.queue).clear();
+        }
+    }
+}
+
+impl fmt::Debug for ThreadedRuntime {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ThreadedRuntime")
+            .field("workers", &self.workers())
+            .finish()
+    }
+}
+
+// spawns onto a pool; it can be sent to other threads, and is usable as long as the pool is
+#[derive(Clone)]
+pub struct Handle {
+    shared: Arc<Shared>,
+}
+
+impl Handle {
+    // onto the calling worker when called from a task of the pool, where an idle worker
+    // may steal it, and onto the workers in turn from elsewhere
+    pub fn spawn<F>(&self, fut: F) -> JoinHandle<F::Output>
+    where
+        F: Future + Send + 'static,
+        F::Output: Send + 'static,
+    {
+        let (fut, abort) = future::abortable(fut);
+        let (tx, rx) = oneshot::channel();
+        let fut = fut.map(move |output| {
+            if let Ok(output) = output {
+                let _ = tx.send(output);
+            }
+        });
+        let task = Arc::new(Task {
+            id: self.shared.next_id.fetch_add(1, Ordering::Relaxed),
+            fut: Mutex::new(Some(fut.boxed())),
+            home: AtomicUsize::new(UNPLACED),
+            queued: AtomicBool::new(true),
+            shared: Arc::clone(&self.shared),
+        });
+        let pool = &*self.shared as *const Shared as usize;
+        let index = match WORKER.with(Cell::get) {
+            Some((of, index)) if of == pool => index,
+            _ => {
+                self.shared.next_worker.fetch_add(1, Ordering::Relaxed) % self.shared.workers.len()
+            }
+        };
+        let busy = !self.shared.workers[index].parked.load(Ordering::SeqCst);
+        self.shared.push(index, task);
+        if busy {
+            self.shared.wake_idle(index);
+        }
+        JoinHandle { rx, abort }
+    }
+}
+
+impl fmt::Debug for Handle {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Handle")
+            .field("workers", &self.shared.workers.len())
+            .finish()
+    }
+}
+
+// resolves to the output of a task spawned onto a pool. dropping the handle leaves the task
+// running; `abort` stops it. a task that panics, or is dropped with the pool, resolves to
+// `Err(Aborted)` as well.
+pub struct JoinHandle<T> {
+    rx: oneshot::Receiver<T>,
+    abort: AbortHandle,
+}
+
+impl<T> JoinHandle<T> {
+    // the task is dropped at its next poll, on its worker
+    pub fn abort(&self) {
+        self.abort.abort();
+    }
+}
+
+impl<T> Future for JoinHandle<T> {
+    type Output = Result<T, Aborted>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Self::Output> {
+        Pin::new(&mut self.get_mut().rx)
+            .poll(cx)
+            .map(|output| output.map_err(|_| Aborted))
+    }
+}
+
+impl<T> fmt::Debug for JoinHandle<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("JoinHandle")
+    }
+}
+
+impl Worker {
+    fn new() -> Worker {
+        Worker {
+            queue: Mutex::new(VecDeque::new()),
+            parked: AtomicBool::new(false),
+            unpark: Mutex::new(None),
+        }
+    }
+
+    fn unpark(&self) {
+        if self.parked.load(Ordering::SeqCst) {
+            if let Some(unpark) = lock(&self.unpark).as_ref() {
+                unpark.wake();
+            }
+        }
+    }
+}
+
+impl Shared {
+    fn push(&self, index: usize, task: Arc<Task>) {
+        let worker = &self.workers[index];
+        lock(&worker.queue).push_back(task);
+        worker.unpark();
+    }
+
+    // a parked worker other than `except`, to steal what was just queued
+    fn wake_idle(&self, except: usize) {
+        let idle = self
+            .workers
+            .iter()
+            .enumerate()
+            .find(|&(index, worker)| index != except && worker.parked.load(Ordering::SeqCst));
+        if let Some((_, worker)) = idle {
+            worker.unpark();
+        }
+    }
+
+    // half of the unstarted tasks of the first worker that has any, from the back of its
+    // queue, as it runs the front first
+    fn steal(&self, thief: usize) -> Vec<Arc<Task>> {
+        let n = self.workers.len();
+        for victim in (1..n).map(|i| &self.workers[(thief + i) % n]) {
+            let mut queue = lock(&victim.queue);
+            let unplaced = queue.iter().filter(|task| task.is_unplaced()).count();
+            if unplaced == 0 {
+                continue;
+            }
+            let mut wanted = (unplaced + 1) / 2;
+            let mut stolen = Vec::with_capacity(wanted);
+            let mut kept = VecDeque::with_capacity(queue.len() - wanted);
+            while let Some(task) = queue.pop_back() {
+                if wanted > 0 && task.is_unplaced() {
+                    stolen.push(task);
+                    wanted -= 1;
+                } else {
+                    kept.push_front(task);
+                }
+            }
+            *queue = kept;
+            return stolen;
+        }
+        Vec::new()
+    }
+
+    fn has_unplaced(&self, thief: usize) -> bool {
+        self.workers.iter().enumerate().any(|(index, worker)| {
+            index != thief && lock(&worker.queue).iter().any(|task| task.is_unplaced())
+        })
+    }
+}
+
+impl Task {
+    fn is_unplaced(&self) -> bool {
+        self.home.load(Ordering::SeqCst) == UNPLACED
+    }
+
+    // polls the task on worker `index`, which keeps the tasks it is home to in `owned`
+    fn run(self: &Arc<Self>, index: usize, owned: &mut HashMap<usize, Arc<Task>>) {
+        if self.is_unplaced() {
+            self.home.store(index, Ordering::SeqCst);
+            owned.insert(self.id, Arc::clone(self));
+        }
+        // woken while it is polled, it is queued again
+        self.queued.store(false, Ordering::SeqCst);
+        let waker = waker_ref(self);
+        let mut cx = Context::from_waker(&waker);
+        let mut fut = lock(&self.fut);
+        let done = match fut.as_mut() {
+            Some(f) => {
+                match panic::catch_unwind(panic::AssertUnwindSafe(|| f.as_mut().poll(&mut cx))) {
+                    Ok(poll) => poll.is_ready(),
+                    Err(_) => {
+                        error!("task panicked on worker {}", index);
+                        true
+                    }
+                }
+            }
+            None => false,
+        };
+        if done {
+            let fut = fut.take();
+            drop(fut);
+            owned.remove(&self.id);
+        }
+    }
+}
+
+impl ArcWake for Task {
+    fn wake_by_ref(arc_self: &Arc<Self>) {
+        if arc_self.queued.swap(true, Ordering::SeqCst) {
+            return;
+        }
+        // only polled tasks have wakers, and they have a home
+        let home = arc_self.home.load(Ordering::SeqCst);
+        arc_self.shared.push(home, Arc::clone(arc_self));
+    }
+}
+
+fn work(shared: &Shared, index: usize) {
+    WORKER.with(|worker| worker.set(Some((shared as *const Shared as usize, index))));
+    let me = &shared.workers[index];
+    // kept registered for the waker to work
+    let _unpark_handle = match reactor::register_waker() {
+        Ok((handle, waker)) => {
+            *lock(&me.unpark) = Some(waker);
+            handle
+        }
+        Err(e) => {
+            error!("worker {}: {}", index, e);
+            return;
+        }
+    };
+    let mut runner = Runner::new();
+    let mut owned = HashMap::new();
+    while !shared.shutdown.load(Ordering::SeqCst) {
+        // taken one at a time, leaving the rest to steal. those queued while this turn runs
+        // wait for the next one, so that a task that keeps waking itself can't hold it up.
+        let queued = lock(&me.queue).len();
+        for _ in 0..queued {
+            let task = match lock(&me.queue).pop_front() {
+                Some(task) => task,
+                None => break,
+            };
+            task.run(index, &mut owned);
+        }
+        runner.run();
+        if lock(&me.queue).is_empty() {
+            let stolen = shared.steal(index);
+            if !stolen.is_empty() {
+                trace!("worker {} stole {} tasks", index, stolen.len());
+                lock(&me.queue).extend(stolen);
+            }
+        }
+        // announced before looking again, so that a task queued from here on unparks it
+        me.parked.store(true, Ordering::SeqCst);
+        let idle = lock(&me.queue).is_empty()
+            && !runner.is_woken()
+            && !shared.has_unplaced(index)
+            && !shared.shutdown.load(Ordering::SeqCst);
+        let timeout = if idle {
+            None
+        } else {
+            Some(Duration::from_secs(0))
+        };
+        let turned = reactor::turn(timeout);
+        me.parked.store(false, Ordering::SeqCst);
+        if let Err(e) = turned {
+            error!("worker {}: reactor failed: {}", index, e);
+            break;
+        }
+    }
+    // dropped here, where what they registered is
+    for task in owned.values() {
+        let fut = lock(&task.fut).take();
+        drop(fut);
+    }
+}
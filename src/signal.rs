@@ -0,0 +1,241 @@
+// Unix signals as streams, e.g. for a graceful shutdown on Ctrl-C and reloading configuration
+// on SIGHUP (`HttpServer::shutdown_on` and `HttpServer::on_signal` do both for a server):
+//
+//     let mut signals = Signals::new(&[Signal::Hangup])?;
+//     while let Some(_) = signals.recv().await {
+//         reload();
+//     }
+//
+// this is the self-pipe trick: the handler only writes the signal's number to a socket of
+// every live `Signals`, whose other end the reactor then sees readable. a signal that is
+// asked for loses its default action, e.g. ending the process, until no `Signals` asks for it.
+use crate::reactor::{self, Ready};
+use futures::prelude::*;
+use lazy_static::*;
+use log::*;
+use std::{
+    collections::HashMap,
+    io::{self, Read},
+    os::unix::{io::AsRawFd, net::UnixStream},
+    pin::Pin,
+    sync::{
+        atomic::{AtomicI32, Ordering},
+        Mutex,
+    },
+    task::{Context, Poll},
+};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Signal {
+    // SIGHUP; daemons take it to mean reload configuration or reopen log files
+    Hangup,
+    // SIGINT, sent by Ctrl-C
+    Interrupt,
+    // SIGQUIT, sent by Ctrl-\
+    Quit,
+    // SIGTERM, what `kill` and service managers stop processes with
+    Terminate,
+    // SIGUSR1
+    User1,
+    // SIGUSR2
+    User2,
+}
+
+const ALL: [Signal; 6] = [
+    Signal::Hangup,
+    Signal::Interrupt,
+    Signal::Quit,
+    Signal::Terminate,
+    Signal::User1,
+    Signal::User2,
+];
+
+impl Signal {
+    pub fn number(self) -> i32 {
+        match self {
+            Signal::Hangup => 1,
+            Signal::Interrupt => 2,
+            Signal::Quit => 3,
+            Signal::Terminate => 15,
+            Signal::User1 if cfg!(any(target_os = "linux", target_os = "android")) => 10,
+            Signal::User2 if cfg!(any(target_os = "linux", target_os = "android")) => 12,
+            Signal::User1 => 30,
+            Signal::User2 => 31,
+        }
+    }
+
+    fn from_number(number: i32) -> Option<Signal> {
+        ALL.iter().cloned().find(|signal| signal.number() == number)
+    }
+}
+
+// write ends of the sockets of live `Signals`, -1 in free slots. the handler may interrupt any
+// thread at any point, holding any lock, so it only loads these and writes: no locks, no
+// allocation.
+static SLOTS: [AtomicI32; 8] = [
+    AtomicI32::new(-1),
+    AtomicI32::new(-1),
+    AtomicI32::new(-1),
+    AtomicI32::new(-1),
+    AtomicI32::new(-1),
+    AtomicI32::new(-1),
+    AtomicI32::new(-1),
+    AtomicI32::new(-1),
+];
+
+lazy_static! {
+    // how many `Signals` ask for each signal; the handler is installed while any do
+    static ref INSTALLED: Mutex<HashMap<Signal, usize>> = Mutex::new(HashMap::new());
+}
+
+const SIG_DFL: usize = 0;
+const SIG_ERR: usize = !0;
+
+extern "C" {
+    #[link_name = "signal"]
+    fn set_handler(signum: i32, handler: usize) -> usize;
+    fn write(fd: i32, buf: *const u8, count: usize) -> isize;
+    #[cfg_attr(
+        any(target_os = "linux", target_os = "android"),
+        link_name = "__errno_location"
+    )]
+    #[cfg_attr(
+        any(target_os = "macos", target_os = "ios", target_os = "freebsd"),
+        link_name = "__error"
+    )]
+    #[cfg_attr(
+        any(target_os = "netbsd", target_os = "openbsd"),
+        link_name = "__errno"
+    )]
+    fn errno_location() -> *mut i32;
+}
+
+extern "C" fn handler(signum: i32) {
+    // the interrupted code may be about to read errno, which `write` can change
+    let errno = unsafe { *errno_location() };
+    let byte = signum as u8;
+    for slot in &SLOTS {
+        let fd = slot.load(Ordering::SeqCst);
+        if fd >= 0 {
+            // a full socket already has a wakeup in it
+            unsafe { write(fd, &byte, 1) };
+        }
+    }
+    unsafe { *errno_location() = errno };
+}
+
+fn install(signal: Signal) -> io::Result<()> {
+    let mut installed = INSTALLED.lock().unwrap_or_else(|e| e.into_inner());
+    let count = installed.entry(signal).or_insert(0);
+    if *count == 0
+        && unsafe { set_handler(signal.number(), handler as extern "C" fn(i32) as usize) }
+            == SIG_ERR
+    {
+        return Err(io::Error::last_os_error());
+    }
+    *count += 1;
+    Ok(())
+}
+
+fn uninstall(signal: Signal) {
+    let mut installed = INSTALLED.lock().unwrap_or_else(|e| e.into_inner());
+    if let Some(count) = installed.get_mut(&signal) {
+        *count -= 1;
+        if *count == 0 {
+            installed.remove(&signal);
+            unsafe { set_handler(signal.number(), SIG_DFL) };
+        }
+    }
+}
+
+// the signals asked for, as they arrive on the thread it was made on. ones arriving together
+// may be merged into one, as the kernel does with pending signals.
+#[derive(Debug)]
+pub struct Signals {
+    read: UnixStream,
+    // the handler's end, in `SLOTS[slot]`; only kept open
+    _write: UnixStream,
+    slot: usize,
+    // those installed so far
+    wanted: Vec<Signal>,
+    reactor: reactor::ReactorHandle,
+}
+
+impl Signals {
+    // at most 8 may be live at a time
+    pub fn new(wanted: &[Signal]) -> io::Result<Signals> {
+        let (read, write) = UnixStream::pair()?;
+        read.set_nonblocking(true)?;
+        write.set_nonblocking(true)?;
+        let reactor = reactor::register(&read, Ready::readable())?;
+        let slot = SLOTS
+            .iter()
+            .position(|slot| {
+                slot.compare_exchange(-1, write.as_raw_fd(), Ordering::SeqCst, Ordering::SeqCst)
+                    .is_ok()
+            })
+            .ok_or_else(|| io::Error::new(io::ErrorKind::Other, "too many Signals"))?;
+        let mut signals = Signals {
+            read,
+            _write: write,
+            slot,
+            wanted: Vec::new(),
+            reactor,
+        };
+        for &signal in wanted {
+            if !signals.wanted.contains(&signal) {
+                install(signal)?;
+                signals.wanted.push(signal);
+            }
+        }
+        Ok(signals)
+    }
+
+    // the next signal; `None` only if the socket failed
+    pub async fn recv(&mut self) -> Option<Signal> {
+        self.next().await
+    }
+}
+
+impl Stream for Signals {
+    type Item = Signal;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Option<Signal>> {
+        let this = self.get_mut();
+        loop {
+            if !this.reactor.readiness().is_readable() {
+                this.reactor.add_read_waker(cx.waker().clone());
+                return Poll::Pending;
+            }
+            // other `Signals` may have asked for other signals
+            let mut byte = [0];
+            match (&this.read).read(&mut byte) {
+                Ok(1) => match Signal::from_number(i32::from(byte[0])) {
+                    Some(signal) if this.wanted.contains(&signal) => {
+                        return Poll::Ready(Some(signal));
+                    }
+                    _ => {}
+                },
+                Ok(_) => return Poll::Ready(None),
+                Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => {
+                    this.reactor.remove_readiness(Ready::readable());
+                }
+                Err(ref e) if e.kind() == io::ErrorKind::Interrupted => {}
+                Err(e) => {
+                    error!("reading signals: {}", e);
+                    return Poll::Ready(None);
+                }
+            }
+        }
+    }
+}
+
+impl Drop for Signals {
+    fn drop(&mut self) {
+        SLOTS[self.slot].store(-1, Ordering::SeqCst);
+        for &signal in &self.wanted {
+            uninstall(signal);
+        }
+        let _ = self.reactor.deregister(&self.read);
+    }
+}
@@ -1,24 +1,134 @@
 use crate::fs;
+use crate::http::conditional::{self, Precondition};
 use crate::http::*;
+use crate::http::{date, range};
+use crate::json;
+use futures::future::{FutureExt, LocalBoxFuture};
 use futures::io::*;
-use std::path::Path;
+use std::path::{Component, Path, PathBuf};
+use std::rc::Rc;
+use std::time::UNIX_EPOCH;
+
+mod assets;
+mod cache;
+
+pub use assets::Assets;
+pub use cache::HotCache;
+
+// the static router with options; `static_router` serves with the defaults
+#[derive(Clone, Debug, Default)]
+pub struct StaticFiles {
+    json_listings: bool,
+    hot_cache: Option<Rc<HotCache>>,
+}
+
+impl StaticFiles {
+    pub fn new() -> StaticFiles {
+        StaticFiles::default()
+    }
+
+    // directory listings as a JSON array of {name, size, mtime, type} for clients whose
+    // Accept asks for application/json, HTML for everyone else
+    pub fn json_listings(&mut self, json_listings: bool) -> &mut Self {
+        self.json_listings = json_listings;
+        self
+    }
+
+    // whole files are served from `cache`, compressed ahead of time; see `HotCache`. the
+    // cache may be shared with other `StaticFiles`.
+    pub fn hot_cache(&mut self, cache: Rc<HotCache>) -> &mut Self {
+        self.hot_cache = Some(cache);
+        self
+    }
+}
+
+impl HttpApp for StaticFiles {
+    type Output = LocalBoxFuture<'static, Response>;
+
+    fn app(&self, req: Request) -> Self::Output {
+        let options = self.clone();
+        async move { serve(req, &options).await }.boxed_local()
+    }
+}
 
 pub async fn static_router(req: Request) -> Response {
-    let path = req.uri();
-    if let Ok(meta) = std::fs::metadata(path) {
+    serve(req, &StaticFiles::default()).await
+}
+
+async fn serve(req: Request, options: &StaticFiles) -> Response {
+    let path = match req.document_root() {
+        Some(root) => under_root(root, req.uri()),
+        None => PathBuf::from(req.uri()),
+    };
+    serve_path(&req, path, options).await
+}
+
+async fn serve_path(req: &Request, path: PathBuf, options: &StaticFiles) -> Response {
+    if let Ok(meta) = std::fs::metadata(&path) {
         if meta.is_dir() {
-            if let Ok(res) = dir_page(path) {
-                res
+            let wants_json = options.json_listings
+                && compress::negotiate(req.header("accept").unwrap_or(""), &["application/json"])
+                    .is_some();
+            let res = if wants_json {
+                dir_json(&path)
             } else {
-                Response::ok()
+                dir_page(&path, req.uri())
+            };
+            let mut res = res.unwrap_or_else(|_| Response::ok());
+            if options.json_listings {
+                compress::add_vary(&mut res, "Accept");
             }
+            res
         } else {
-            let mut res = Response::ok();
-            if let Ok(mut file) = fs::File::open(req.uri()).await {
-                let mut buf = vec![0; file.std().metadata().unwrap().len() as usize];
-                if file.read(&mut buf).await.is_ok() {
-                    res.extend(&buf);
+            let len = meta.len();
+            let etag = conditional::weak_etag(&meta);
+            let modified = meta.modified().ok();
+            let last_modified = modified.map(date::format);
+            match conditional::evaluate(req, Some(&etag), modified) {
+                Precondition::Proceed => {}
+                Precondition::NotModified => {
+                    let mut res = Response::with_status_code(StatusCode::NotModified);
+                    res.set_header("ETag", etag);
+                    return res;
                 }
+                Precondition::Failed => return conditional::precondition_failed(),
+            }
+            let mut res =
+                match range::requested(req, len, Some(&etag), last_modified.as_ref().map(|s| &**s))
+                {
+                    range::Requested::Whole => match &options.hot_cache {
+                        Some(cache) => {
+                            let accept = req.header("accept-encoding");
+                            match cache.get(&path, len, modified, accept) {
+                                Some(res) => res,
+                                None => match read_whole(&path, len).await {
+                                    Ok(body) => cache.insert(&path, len, modified, body, accept),
+                                    Err(_) => Response::ok(),
+                                },
+                            }
+                        }
+                        None => {
+                            let mut res = Response::ok();
+                            res.set_header("Accept-Ranges", "bytes".to_owned());
+                            if let Ok(body) = read_whole(&path, len).await {
+                                res.set_body(body);
+                            }
+                            res
+                        }
+                    },
+                    range::Requested::Unsatisfiable => range::unsatisfiable(len),
+                    range::Requested::Ranges(ranges) => match read_ranges(&path, &ranges).await {
+                        Ok(parts) => range::partial(&ranges, parts, len, None),
+                        Err(_) => Response::ok(),
+                    },
+                };
+            res.set_header("ETag", etag);
+            // a compressed variant from the hot cache
+            if res.header("content-encoding").is_some() {
+                compress::weaken_etag(&mut res);
+            }
+            if let Some(last_modified) = last_modified {
+                res.set_header("Last-Modified", last_modified);
             }
             res
         }
@@ -27,23 +137,63 @@ pub async fn static_router(req: Request) -> Response {
     }
 }
 
-fn dir_page<P: AsRef<Path>>(path: P) -> std::io::Result<Response> {
+async fn read_whole(path: &Path, len: u64) -> std::io::Result<Vec<u8>> {
+    let mut file = fs::File::open(path).await?;
+    let mut body = vec![0; len as usize];
+    file.read_exact(&mut body).await?;
+    Ok(body)
+}
+
+// only the requested parts of the file are read
+async fn read_ranges(path: &Path, ranges: &[range::ByteRange]) -> std::io::Result<Vec<Vec<u8>>> {
+    let mut file = fs::File::open(path).await?;
+    let mut parts = Vec::with_capacity(ranges.len());
+    for range in ranges {
+        file.seek(SeekFrom::Start(range.start))?;
+        let mut part = vec![0; range.len() as usize];
+        file.read_exact(&mut part).await?;
+        parts.push(part);
+    }
+    Ok(parts)
+}
+
+// `uri` with its query dropped, resolved below `root`; `..` can't climb out of it
+fn under_root(root: &Path, uri: &str) -> PathBuf {
+    let uri = uri.split(|c| c == '?' || c == '#').next().unwrap_or("");
+    let mut path = root.to_path_buf();
+    for c in Path::new(uri).components() {
+        match c {
+            Component::Normal(c) => path.push(c),
+            Component::ParentDir => {
+                if path != root {
+                    path.pop();
+                }
+            }
+            _ => {}
+        }
+    }
+    path
+}
+
+fn dir_page<P: AsRef<Path>>(path: P, uri: &str) -> std::io::Result<Response> {
     let mut res = Response::ok();
     let dir = std::fs::read_dir(&path)?;
     res.extend(
         format!(
             "<html><head><title>{0}</title></head><body><h1>{0}</h1><ul>",
-            path.as_ref().to_string_lossy()
+            uri
         )
         .bytes(),
     );
     for e in dir {
         let e = e?;
+        let name = e.file_name();
         res.extend(
             format!(
-                "<li><a href=\"{}\">{}</a>",
-                e.path().to_string_lossy(),
-                e.file_name().to_string_lossy(),
+                "<li><a href=\"{}/{}\">{}</a>",
+                uri.trim_end_matches('/'),
+                name.to_string_lossy(),
+                name.to_string_lossy(),
             )
             .bytes(),
         );
@@ -51,3 +201,37 @@ fn dir_page<P: AsRef<Path>>(path: P) -> std::io::Result<Response> {
     res.extend(b"</ol></body></html>");
     Ok(res)
 }
+
+fn dir_json<P: AsRef<Path>>(path: P) -> std::io::Result<Response> {
+    let mut entries = Vec::new();
+    for e in std::fs::read_dir(&path)? {
+        let e = e?;
+        let meta = e.metadata()?;
+        let kind = if meta.is_dir() {
+            "dir"
+        } else if meta.is_file() {
+            "file"
+        } else if meta.file_type().is_symlink() {
+            "symlink"
+        } else {
+            "other"
+        };
+        // seconds since the Unix epoch
+        let mtime = meta
+            .modified()
+            .ok()
+            .and_then(|t| t.duration_since(UNIX_EPOCH).ok())
+            .map_or("null".to_owned(), |d| d.as_secs().to_string());
+        entries.push(format!(
+            "{{\"name\":{},\"size\":{},\"mtime\":{},\"type\":\"{}\"}}",
+            json::string(&e.file_name().to_string_lossy()),
+            meta.len(),
+            mtime,
+            kind
+        ));
+    }
+    let mut res = Response::ok();
+    res.set_header("Content-Type", "application/json".to_owned());
+    res.extend(format!("[{}]", entries.join(",")).bytes());
+    Ok(res)
+}
@@ -0,0 +1,208 @@
+use super::{serve_path, under_root, StaticFiles};
+use crate::http::{HttpApp, Request, Response, StatusCode};
+use futures::future::{FutureExt, LocalBoxFuture};
+use log::*;
+use sha2::{Digest, Sha256};
+use std::{
+    cell::RefCell,
+    collections::HashMap,
+    fs, io,
+    path::{Path, PathBuf},
+    rc::Rc,
+};
+
+// hex digits of the content hash put into a file name
+const FINGERPRINT_LEN: usize = 6;
+
+const IMMUTABLE: &str = "public, max-age=31536000, immutable";
+
+// the files under `root`, served at URLs carrying a hash of their content: `app.css` as
+// `/app.3f9ab2.css`, which clients may cache for good since changed content gets a new URL.
+// a file's fingerprint is computed once, by `scan` at startup or by its first `asset_url`;
+// after deploying changed files, restart or `scan` again. clones share the fingerprints, so
+// one can be routed to while handlers keep another for `asset_url`.
+#[derive(Clone, Debug)]
+pub struct Assets {
+    root: PathBuf,
+    prefix: String,
+    files: StaticFiles,
+    // by path relative to `root`, `/`-separated
+    fingerprints: Rc<RefCell<HashMap<String, String>>>,
+}
+
+impl Assets {
+    pub fn new<P: Into<PathBuf>>(root: P) -> Assets {
+        Assets {
+            root: root.into(),
+            prefix: "/".to_owned(),
+            files: StaticFiles::new(),
+            fingerprints: Rc::new(RefCell::new(HashMap::new())),
+        }
+    }
+
+    // the URL path the assets are served under, e.g. "/static"; "/" by default
+    pub fn prefix(&mut self, prefix: &str) -> &mut Self {
+        self.prefix = format!("/{}", prefix.trim_matches('/'));
+        self
+    }
+
+    // how the files are served, e.g. with a hot cache
+    pub fn files(&mut self, files: StaticFiles) -> &mut Self {
+        self.files = files;
+        self
+    }
+
+    // fingerprints every file under the root now, dropping those computed before; returns
+    // how many files there are
+    pub fn scan(&self) -> io::Result<usize> {
+        let mut fingerprints = HashMap::new();
+        let mut dirs = vec![(self.root.clone(), String::new())];
+        while let Some((dir, rel_dir)) = dirs.pop() {
+            for entry in fs::read_dir(&dir)? {
+                let entry = entry?;
+                let rel = format!("{}{}", rel_dir, entry.file_name().to_string_lossy());
+                let file_type = entry.file_type()?;
+                if file_type.is_dir() {
+                    dirs.push((entry.path(), format!("{}/", rel)));
+                } else {
+                    fingerprints.insert(rel, hash_file(&entry.path())?);
+                }
+            }
+        }
+        debug!(
+            "fingerprinted {} assets under {}",
+            fingerprints.len(),
+            self.root.display()
+        );
+        let len = fingerprints.len();
+        *self.fingerprints.borrow_mut() = fingerprints;
+        Ok(len)
+    }
+
+    // the fingerprint of `path` (relative to the root), computing it if needed; None if the
+    // file can't be read
+    pub fn fingerprint(&self, path: &str) -> Option<String> {
+        let path = path.trim_start_matches('/');
+        if let Some(fingerprint) = self.fingerprints.borrow().get(path) {
+            return Some(fingerprint.clone());
+        }
+        let fingerprint = match hash_file(&under_root(&self.root, path)) {
+            Ok(fingerprint) => fingerprint,
+            Err(e) => {
+                debug!("fingerprinting {}: {}", path, e);
+                return None;
+            }
+        };
+        self.fingerprints
+            .borrow_mut()
+            .insert(path.to_owned(), fingerprint.clone());
+        Some(fingerprint)
+    }
+
+    // the URL to link `path` (relative to the root) with: "app.css" -> "/app.3f9ab2.css". a
+    // file that can't be read gets its plain URL.
+    pub fn asset_url(&self, path: &str) -> String {
+        let path = path.trim_start_matches('/');
+        let prefix = self.prefix.trim_end_matches('/');
+        match self.fingerprint(path) {
+            Some(fingerprint) => format!("{}/{}", prefix, fingerprinted(path, &fingerprint)),
+            None => {
+                warn!("no fingerprint for asset {}", path);
+                format!("{}/{}", prefix, path)
+            }
+        }
+    }
+
+    async fn serve(&self, req: Request) -> Response {
+        let uri = req
+            .uri()
+            .split(|c| c == '?' || c == '#')
+            .next()
+            .unwrap_or("");
+        let prefix = self.prefix.trim_end_matches('/');
+        if !uri.starts_with(prefix) || !uri[prefix.len()..].starts_with('/') {
+            return Response::with_status_code(StatusCode::NotFound);
+        }
+        let rel = &uri[prefix.len() + 1..];
+        let (path, cache_control) = match split_fingerprint(rel) {
+            Some((plain, requested)) => match self.fingerprint(&plain) {
+                Some(current) if current == requested => (plain, Some(IMMUTABLE)),
+                // a page from before the file changed; it gets the current content, which
+                // mustn't be cached under the old URL
+                Some(_) => (plain, Some("no-cache")),
+                None => (rel.to_owned(), None),
+            },
+            None => (rel.to_owned(), None),
+        };
+        let mut res = serve_path(&req, under_root(&self.root, &path), &self.files).await;
+        let cacheable = match res.status_code() {
+            StatusCode::Ok | StatusCode::PartialContent | StatusCode::NotModified => true,
+            _ => false,
+        };
+        if let (true, Some(cache_control)) = (cacheable, cache_control) {
+            res.set_header("Cache-Control", cache_control.to_owned());
+        }
+        res
+    }
+}
+
+impl HttpApp for Assets {
+    type Output = LocalBoxFuture<'static, Response>;
+
+    fn app(&self, req: Request) -> Self::Output {
+        let assets = self.clone();
+        async move { assets.serve(req).await }.boxed_local()
+    }
+
+    fn check(&self, problems: &mut Vec<String>) {
+        if !self.root.is_dir() {
+            problems.push(format!(
+                "asset root {} is not a directory",
+                self.root.display()
+            ));
+        }
+    }
+}
+
+fn hash_file(path: &Path) -> io::Result<String> {
+    let digest = Sha256::digest(&fs::read(path)?);
+    Ok(digest[..FINGERPRINT_LEN / 2]
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect())
+}
+
+// the fingerprint goes before the extension, or last in names without one
+fn fingerprinted(path: &str, fingerprint: &str) -> String {
+    let (dir, name) = match path.rfind('/') {
+        Some(i) => path.split_at(i + 1),
+        None => ("", path),
+    };
+    match name.rfind('.') {
+        Some(i) if i > 0 => format!("{}{}.{}{}", dir, &name[..i], fingerprint, &name[i..]),
+        _ => format!("{}{}.{}", dir, name, fingerprint),
+    }
+}
+
+// the inverse of `fingerprinted`: the plain path and the fingerprint
+fn split_fingerprint(path: &str) -> Option<(String, String)> {
+    let (dir, name) = match path.rfind('/') {
+        Some(i) => path.split_at(i + 1),
+        None => ("", path),
+    };
+    let parts = name.split('.').collect::<Vec<_>>();
+    let is_fingerprint =
+        |s: &str| s.len() == FINGERPRINT_LEN && s.bytes().all(|b| b.is_ascii_hexdigit());
+    let n = parts.len();
+    if n >= 3 && !parts[0].is_empty() && is_fingerprint(parts[n - 2]) {
+        let plain = format!("{}.{}", parts[..n - 2].join("."), parts[n - 1]);
+        Some((format!("{}{}", dir, plain), parts[n - 2].to_owned()))
+    } else if n >= 2 && is_fingerprint(parts[n - 1]) {
+        Some((
+            format!("{}{}", dir, parts[..n - 1].join(".")),
+            parts[n - 1].to_owned(),
+        ))
+    } else {
+        None
+    }
+}
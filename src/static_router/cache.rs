@@ -0,0 +1,178 @@
+use crate::http::compress::{self, Compression, Encoding};
+use crate::http::Response;
+use log::*;
+use std::{
+    cell::{Cell, RefCell},
+    collections::HashMap,
+    fmt,
+    path::{Path, PathBuf},
+    rc::Rc,
+    time::SystemTime,
+};
+
+// file bodies kept in memory together with their compressed variants, so a hot file is
+// read and compressed once per change instead of once per request; the variant sent is
+// picked by Accept-Encoding. an entry is dropped when the file's length or modification
+// time changes, and the least recently used go first once `max_bytes` is exceeded.
+pub struct HotCache {
+    max_bytes: usize,
+    compression: Compression,
+    entries: RefCell<HashMap<PathBuf, Rc<Entry>>>,
+    used: Cell<usize>,
+    // bumped on every lookup, for finding the least recently used entry
+    clock: Cell<u64>,
+}
+
+struct Entry {
+    len: u64,
+    modified: Option<SystemTime>,
+    body: Vec<u8>,
+    variants: Vec<(Encoding, Vec<u8>)>,
+    last_used: Cell<u64>,
+}
+
+impl Entry {
+    fn size(&self) -> usize {
+        self.body.len() + self.variants.iter().map(|(_, v)| v.len()).sum::<usize>()
+    }
+}
+
+impl HotCache {
+    pub fn new(max_bytes: usize) -> HotCache {
+        let mut compression = Compression::new();
+        // compressing once per change affords the slowest settings
+        compression.level(9).brotli_quality(11);
+        HotCache {
+            max_bytes,
+            compression,
+            entries: RefCell::new(HashMap::new()),
+            used: Cell::new(0),
+            clock: Cell::new(0),
+        }
+    }
+
+    // the encodings, levels and minimum size the variants are made with
+    pub fn compression(&mut self, compression: Compression) -> &mut Self {
+        self.compression = compression;
+        self
+    }
+
+    // bytes held, bodies and variants together
+    pub fn used(&self) -> usize {
+        self.used.get()
+    }
+
+    // a 200 for the cached body when it is still current
+    pub(crate) fn get(
+        &self,
+        path: &Path,
+        len: u64,
+        modified: Option<SystemTime>,
+        accept_encoding: Option<&str>,
+    ) -> Option<Response> {
+        let entry = Rc::clone(self.entries.borrow().get(path)?);
+        if entry.len != len || entry.modified != modified {
+            debug!("{} changed, dropped from the hot cache", path.display());
+            self.remove(path);
+            return None;
+        }
+        self.touch(&entry);
+        Some(respond(&entry, accept_encoding))
+    }
+
+    // caches `body` (compressing it) and returns a 200 for it. bodies too large to leave
+    // room for others are served without being cached.
+    pub(crate) fn insert(
+        &self,
+        path: &Path,
+        len: u64,
+        modified: Option<SystemTime>,
+        body: Vec<u8>,
+        accept_encoding: Option<&str>,
+    ) -> Response {
+        let cacheable = body.len() <= self.max_bytes / 4;
+        let variants = if cacheable {
+            self.compression.variants(&body)
+        } else {
+            Vec::new()
+        };
+        let entry = Rc::new(Entry {
+            len,
+            modified,
+            body,
+            variants,
+            last_used: Cell::new(0),
+        });
+        if cacheable && entry.size() <= self.max_bytes {
+            self.remove(path);
+            self.touch(&entry);
+            self.used.set(self.used.get() + entry.size());
+            self.entries
+                .borrow_mut()
+                .insert(path.to_path_buf(), Rc::clone(&entry));
+            self.evict();
+        }
+        respond(&entry, accept_encoding)
+    }
+
+    fn touch(&self, entry: &Entry) {
+        self.clock.set(self.clock.get() + 1);
+        entry.last_used.set(self.clock.get());
+    }
+
+    fn remove(&self, path: &Path) {
+        if let Some(entry) = self.entries.borrow_mut().remove(path) {
+            self.used.set(self.used.get() - entry.size());
+        }
+    }
+
+    fn evict(&self) {
+        while self.used.get() > self.max_bytes {
+            let oldest = self
+                .entries
+                .borrow()
+                .iter()
+                .min_by_key(|(_, entry)| entry.last_used.get())
+                .map(|(path, _)| path.clone());
+            match oldest {
+                Some(path) => self.remove(&path),
+                None => return,
+            }
+        }
+    }
+}
+
+impl fmt::Debug for HotCache {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("HotCache")
+            .field("max_bytes", &self.max_bytes)
+            .field("entries", &self.entries.borrow().len())
+            .field("used", &self.used.get())
+            .finish()
+    }
+}
+
+fn respond(entry: &Entry, accept_encoding: Option<&str>) -> Response {
+    let mut res = Response::ok();
+    res.set_header("Accept-Ranges", "bytes".to_owned());
+    if entry.variants.is_empty() {
+        res.set_body(entry.body.clone());
+        return res;
+    }
+    compress::add_vary(&mut res, "Accept-Encoding");
+    let names = entry
+        .variants
+        .iter()
+        .map(|(encoding, _)| encoding.name())
+        .collect::<Vec<_>>();
+    let chosen = compress::negotiate(accept_encoding.unwrap_or(""), &names)
+        .and_then(|name| entry.variants.iter().find(|(e, _)| e.name() == name));
+    match chosen {
+        Some((encoding, body)) => {
+            res.set_header("Content-Encoding", encoding.name().to_owned());
+            res.set_body(body.clone());
+        }
+        None => res.set_body(entry.body.clone()),
+    }
+    res
+}
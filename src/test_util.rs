@@ -0,0 +1,446 @@
+// helpers for tests that check exactly when futures are pending, ready and woken, behind the
+// `test-util` feature:
+//
+//     let mut read = TestTask::new(async move { sock.read(&mut buf).await });
+//     assert_pending!(read.poll());
+//     peer.write_all(b"x")?;
+//     reactor::turn(Some(Duration::from_millis(10)))?;
+//     assert!(read.is_woken());
+//     assert_eq!(assert_ready!(read.poll())?, 1);
+//
+// `TestTask` polls by hand, outside any runner; `spawn_and_poll_once` puts the future on a
+// `Runner` of its own, so it also sees what `runner::spawn_local` spawns. `poll_*` methods
+// are tested through `futures::future::poll_fn`. `Driver` steps through tasks a poll at a
+// time on a paused clock, for timeouts and cancellation. `MemoryStream` stands in for a
+// socket, for `HttpServer::serve_connection`.
+use crate::{
+    reactor,
+    runner::{JoinHandle, Runner, Spawner},
+    time,
+};
+use futures::io::{AsyncRead, AsyncWrite};
+use std::{
+    cell::RefCell,
+    future::Future,
+    io,
+    pin::Pin,
+    rc::Rc,
+    sync::{
+        atomic::{AtomicBool, AtomicUsize, Ordering},
+        Arc, Mutex,
+    },
+    task::{Context, Poll, RawWaker, RawWakerVTable, Waker},
+    time::{Duration, Instant},
+};
+
+// panics unless `$e` is `Poll::Pending`
+#[macro_export]
+macro_rules! assert_pending {
+    ($e:expr) => {
+        $crate::assert_pending!($e, "expected Pending, got Ready")
+    };
+    ($e:expr, $($arg:tt)+) => {
+        match $e {
+            ::std::task::Poll::Pending => {}
+            ::std::task::Poll::Ready(_) => panic!($($arg)+),
+        }
+    };
+}
+
+// panics unless `$e` is `Poll::Ready`, and evaluates to what is in it
+#[macro_export]
+macro_rules! assert_ready {
+    ($e:expr) => {
+        $crate::assert_ready!($e, "expected Ready, got Pending")
+    };
+    ($e:expr, $($arg:tt)+) => {
+        match $e {
+            ::std::task::Poll::Ready(v) => v,
+            ::std::task::Poll::Pending => panic!($($arg)+),
+        }
+    };
+}
+
+// how often a waker, and its clones, woke its task. wakes are passed on to `next`, the
+// waker of whoever polls the tracked future, if there is one.
+#[derive(Default)]
+struct Wakes {
+    count: AtomicUsize,
+    // since the last poll
+    woken: AtomicBool,
+    next: Mutex<Option<Waker>>,
+}
+
+impl Wakes {
+    fn waker(this: &Arc<Wakes>) -> Waker {
+        let raw = RawWaker::new(Arc::into_raw(Arc::clone(this)) as *const (), &VTABLE);
+        unsafe { Waker::from_raw(raw) }
+    }
+
+    fn poll<F: Future + ?Sized>(
+        this: &Arc<Wakes>,
+        fut: Pin<&mut F>,
+        next: Option<&Waker>,
+    ) -> Poll<F::Output> {
+        *this.next.lock().unwrap() = next.cloned();
+        this.woken.store(false, Ordering::SeqCst);
+        let waker = Wakes::waker(this);
+        fut.poll(&mut Context::from_waker(&waker))
+    }
+
+    unsafe fn clone(this: *const ()) -> RawWaker {
+        let this = Arc::from_raw(this as *const Wakes);
+        let cloned = Arc::clone(&this);
+        std::mem::forget(this);
+        RawWaker::new(Arc::into_raw(cloned) as *const (), &VTABLE)
+    }
+
+    unsafe fn wake(this: *const ()) {
+        Self::wake_by_ref(this);
+        Self::drop(this);
+    }
+
+    unsafe fn wake_by_ref(this: *const ()) {
+        let this = &*(this as *const Wakes);
+        this.count.fetch_add(1, Ordering::SeqCst);
+        this.woken.store(true, Ordering::SeqCst);
+        if let Some(next) = &*this.next.lock().unwrap() {
+            next.wake_by_ref();
+        }
+    }
+
+    unsafe fn drop(this: *const ()) {
+        drop(Arc::from_raw(this as *const Wakes));
+    }
+}
+
+static VTABLE: RawWakerVTable =
+    RawWakerVTable::new(Wakes::clone, Wakes::wake, Wakes::wake_by_ref, Wakes::drop);
+
+// a future polled by hand with a waker that counts its wakes
+pub struct TestTask<F: Future> {
+    fut: Pin<Box<F>>,
+    wakes: Arc<Wakes>,
+}
+
+impl<F: Future> TestTask<F> {
+    pub fn new(fut: F) -> TestTask<F> {
+        TestTask {
+            fut: Box::pin(fut),
+            wakes: Arc::default(),
+        }
+    }
+
+    // polls whether woken or not, which a runner wouldn't
+    pub fn poll(&mut self) -> Poll<F::Output> {
+        Wakes::poll(&self.wakes, self.fut.as_mut(), None)
+    }
+
+    // woken since the last poll
+    pub fn is_woken(&self) -> bool {
+        self.wakes.woken.load(Ordering::SeqCst)
+    }
+
+    // over the task's life
+    pub fn wake_count(&self) -> usize {
+        self.wakes.count.load(Ordering::SeqCst)
+    }
+}
+
+// a future on a runner of its own; see `spawn_and_poll_once`
+pub struct Spawned<T> {
+    runner: Runner<'static>,
+    output: Rc<RefCell<Option<T>>>,
+    wakes: Arc<Wakes>,
+    done: bool,
+}
+
+// spawns `fut` on a new runner and runs that once, so `fut` and whatever it spawns get their
+// first poll
+pub fn spawn_and_poll_once<F>(fut: F) -> (Spawned<F::Output>, Poll<F::Output>)
+where
+    F: Future + 'static,
+{
+    let runner = Runner::new();
+    let output = Rc::new(RefCell::new(None));
+    let wakes = Arc::new(Wakes::default());
+    let tracked = Tracked {
+        fut: Box::pin(fut),
+        wakes: Arc::clone(&wakes),
+    };
+    let out = Rc::clone(&output);
+    runner.spawner().spawn(async move {
+        let value = tracked.await;
+        *out.borrow_mut() = Some(value);
+    });
+    let mut spawned = Spawned {
+        runner,
+        output,
+        wakes,
+        done: false,
+    };
+    let first = spawned.run();
+    (spawned, first)
+}
+
+impl<T> Spawned<T> {
+    // runs the runner once, polling the future and its companions as the server's loop would
+    pub fn run(&mut self) -> Poll<T> {
+        assert!(!self.done, "Spawned::run after the future completed");
+        self.runner.run();
+        match self.output.borrow_mut().take() {
+            Some(value) => {
+                self.done = true;
+                Poll::Ready(value)
+            }
+            None => Poll::Pending,
+        }
+    }
+
+    // waits up to `timeout` for I/O or a timer on the reactor, then runs the runner once
+    pub fn turn(&mut self, timeout: Duration) -> io::Result<Poll<T>> {
+        reactor::turn(Some(timeout))?;
+        Ok(self.run())
+    }
+
+    // woken since its last poll
+    pub fn is_woken(&self) -> bool {
+        self.wakes.woken.load(Ordering::SeqCst)
+    }
+
+    pub fn wake_count(&self) -> usize {
+        self.wakes.count.load(Ordering::SeqCst)
+    }
+
+    // for companion tasks, e.g. the peer of a socket under test, on the same runner
+    pub fn spawner(&self) -> Spawner<'static> {
+        self.runner.spawner()
+    }
+}
+
+// counts the wakes of `fut` on its way to the runner's waker
+struct Tracked<F: Future> {
+    fut: Pin<Box<F>>,
+    wakes: Arc<Wakes>,
+}
+
+impl<F: Future> Future for Tracked<F> {
+    type Output = F::Output;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<F::Output> {
+        let this = &mut *self;
+        Wakes::poll(&this.wakes, this.fut.as_mut(), Some(cx.waker()))
+    }
+}
+
+// runs tasks a poll at a time, in the order they are woken, on a clock that only moves when
+// told to, so a test of a timeout or a keep-alive comes out the same every run and takes no
+// time. `time::now` and the thread's timers go by the driver's clock while it lives.
+//
+//     let mut driver = Driver::new();
+//     let conn = driver.spawn_named("conn", time::timeout(Duration::from_secs(5), read));
+//     driver.run_until_stalled();
+//     driver.advance(Duration::from_secs(5));
+//     driver.run_until_stalled();
+//     assert!(conn.is_finished());
+//
+// there is one clock per thread, so one driver at a time.
+pub struct Driver {
+    runner: Runner<'static>,
+    start: Instant,
+    schedule: Vec<Step>,
+}
+
+// a poll of a task, as `Driver::schedule` recorded it
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Step {
+    // the key the runner polls it by, as in `runner::dump`
+    pub task: usize,
+    pub name: Option<String>,
+    // on the driver's clock, since it started
+    pub at: Duration,
+    // the poll returned Ready
+    pub finished: bool,
+}
+
+impl Driver {
+    // pauses the thread's clock where it is
+    pub fn new() -> Driver {
+        time::pause();
+        Driver {
+            runner: Runner::new(),
+            start: time::now(),
+            schedule: Vec::new(),
+        }
+    }
+
+    pub fn spawner(&self) -> Spawner<'static> {
+        self.runner.spawner()
+    }
+
+    pub fn spawn<F: Future + 'static>(&self, fut: F) -> JoinHandle<F::Output> {
+        self.runner.spawner().spawn(fut)
+    }
+
+    pub fn spawn_named<N, F>(&self, name: N, fut: F) -> JoinHandle<F::Output>
+    where
+        N: Into<String>,
+        F: Future + 'static,
+    {
+        self.runner.spawner().spawn_named(name, fut)
+    }
+
+    // turns the reactor without waiting, firing the timers due, then polls the task woken
+    // first; None if none was
+    pub fn step(&mut self) -> Option<&Step> {
+        if let Err(e) = reactor::turn(Some(Duration::from_secs(0))) {
+            panic!("reactor failed: {}", e);
+        }
+        let (task, name, finished) = self.runner.poll_one()?;
+        self.schedule.push(Step {
+            task,
+            name,
+            at: self.elapsed(),
+            finished,
+        });
+        self.schedule.last()
+    }
+
+    // steps until no task is woken; the number of polls
+    pub fn run_until_stalled(&mut self) -> usize {
+        let mut polls = 0;
+        while self.step().is_some() {
+            polls += 1;
+        }
+        polls
+    }
+
+    // runs until every task has finished, moving the clock on to the next timer whenever
+    // they stall. panics if they stall with no timer left, waiting on something the driver
+    // can't move on, like a socket.
+    pub fn run(&mut self) {
+        loop {
+            self.run_until_stalled();
+            if self.runner.is_empty() {
+                return;
+            }
+            if !self.advance_to_next_timer() {
+                panic!("tasks stalled with no timer to wait for");
+            }
+        }
+    }
+
+    // moves the clock on; the timers due by then fire on the next step
+    pub fn advance(&mut self, by: Duration) {
+        time::advance_to(time::now() + by);
+    }
+
+    // moves the clock on to the timer due first; false if there is none
+    pub fn advance_to_next_timer(&mut self) -> bool {
+        match reactor::next_timer() {
+            Some(deadline) => {
+                time::advance_to(deadline);
+                true
+            }
+            None => false,
+        }
+    }
+
+    pub fn now(&self) -> Instant {
+        time::now()
+    }
+
+    // on the driver's clock
+    pub fn elapsed(&self) -> Duration {
+        time::now() - self.start
+    }
+
+    // every poll so far, in order
+    pub fn schedule(&self) -> &[Step] {
+        &self.schedule
+    }
+}
+
+impl Default for Driver {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Drop for Driver {
+    // back to the real clock; timers set on the paused one keep their deadlines
+    fn drop(&mut self) {
+        time::resume();
+    }
+}
+
+// a connection whose peer sent `input` and then closed its side; what is written to it is
+// kept for `written`
+pub struct MemoryStream {
+    input: Vec<u8>,
+    pos: usize,
+    // the most a read hands out, to split the input where a network might
+    read_size: usize,
+    output: Vec<u8>,
+}
+
+impl MemoryStream {
+    pub fn new(input: &[u8]) -> MemoryStream {
+        MemoryStream {
+            input: input.to_vec(),
+            pos: 0,
+            read_size: usize::max_value(),
+            output: Vec::new(),
+        }
+    }
+
+    pub fn read_size(&mut self, size: usize) -> &mut Self {
+        self.read_size = size.max(1);
+        self
+    }
+
+    // input not read yet
+    pub fn unread(&self) -> &[u8] {
+        &self.input[self.pos..]
+    }
+
+    pub fn written(&self) -> &[u8] {
+        &self.output
+    }
+}
+
+impl AsyncRead for MemoryStream {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        _cx: &mut Context,
+        buf: &mut [u8],
+    ) -> Poll<io::Result<usize>> {
+        let this = &mut *self;
+        let len = buf
+            .len()
+            .min(this.read_size)
+            .min(this.input.len() - this.pos);
+        buf[..len].copy_from_slice(&this.input[this.pos..this.pos + len]);
+        this.pos += len;
+        Poll::Ready(Ok(len))
+    }
+}
+
+impl AsyncWrite for MemoryStream {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        _cx: &mut Context,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        self.output.extend_from_slice(buf);
+        Poll::Ready(Ok(buf.len()))
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, _cx: &mut Context) -> Poll<io::Result<()>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn poll_close(self: Pin<&mut Self>, _cx: &mut Context) -> Poll<io::Result<()>> {
+        Poll::Ready(Ok(()))
+    }
+}
@@ -0,0 +1,228 @@
+// timers for handlers and other tasks. they are driven by the reactor of the thread they are
+// polled on, the one `HttpServer::run` turns, so they work wherever this crate's futures do;
+// timers from other runtimes (tokio, async-std, futures-timer) need that runtime's own
+// driver and never fire, or fire on a thread of their own. polled outside a reactor's
+// thread, a timer here never fires either.
+use crate::reactor::{self, TimerKey};
+use futures::stream::Stream;
+use std::fmt;
+use std::future::Future;
+use std::io;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use std::time::{Duration, Instant};
+
+#[cfg(feature = "test-util")]
+thread_local! {
+    // set while a test moves the thread's time on by hand
+    static PAUSED: std::cell::Cell<Option<Instant>> = std::cell::Cell::new(None);
+}
+
+// the time the thread's timers go by: the clock's, unless a `test_util::Driver` has paused
+// it. deadlines and rates reckoned by it move on with the timers in such tests, where ones
+// reckoned by `Instant::now` don't.
+pub fn now() -> Instant {
+    #[cfg(feature = "test-util")]
+    {
+        if let Some(now) = PAUSED.with(|paused| paused.get()) {
+            return now;
+        }
+    }
+    Instant::now()
+}
+
+// stops `now` where it is, until `resume`
+#[cfg(feature = "test-util")]
+pub(crate) fn pause() {
+    PAUSED.with(|paused| paused.set(Some(paused.get().unwrap_or_else(Instant::now))));
+}
+
+#[cfg(feature = "test-util")]
+pub(crate) fn resume() {
+    PAUSED.with(|paused| paused.set(None));
+}
+
+// moves a paused `now` on; timers due by then fire on the next turn of the reactor
+#[cfg(feature = "test-util")]
+pub(crate) fn advance_to(to: Instant) {
+    PAUSED.with(|paused| {
+        if let Some(now) = paused.get() {
+            paused.set(Some(now.max(to)));
+        }
+    });
+}
+
+// completes once `duration` has passed, driven by the thread's reactor
+pub fn sleep(duration: Duration) -> Sleep {
+    sleep_until(now() + duration)
+}
+
+pub fn sleep_until(deadline: Instant) -> Sleep {
+    Sleep {
+        deadline,
+        timer: None,
+    }
+}
+
+#[derive(Debug)]
+pub struct Sleep {
+    deadline: Instant,
+    timer: Option<TimerKey>,
+}
+
+impl Sleep {
+    pub fn deadline(&self) -> Instant {
+        self.deadline
+    }
+
+    pub fn reset(&mut self, deadline: Instant) {
+        self.cancel();
+        self.deadline = deadline;
+    }
+
+    fn cancel(&mut self) {
+        if let Some(key) = self.timer.take() {
+            reactor::remove_timer(key);
+        }
+    }
+}
+
+impl Future for Sleep {
+    type Output = ();
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<()> {
+        if now() >= self.deadline {
+            self.cancel();
+            return Poll::Ready(());
+        }
+        let registered = match self.timer {
+            Some(key) => reactor::set_timer_waker(key, cx.waker().clone()),
+            None => false,
+        };
+        if !registered {
+            self.timer = Some(reactor::add_timer(self.deadline, cx.waker().clone()));
+        }
+        Poll::Pending
+    }
+}
+
+impl Drop for Sleep {
+    fn drop(&mut self) {
+        self.cancel();
+    }
+}
+
+// `fut`, unless `duration` passes first; then `fut` is dropped and the result is `Elapsed`
+pub fn timeout<F: Future>(duration: Duration, fut: F) -> Timeout<F> {
+    timeout_at(now() + duration, fut)
+}
+
+pub fn timeout_at<F: Future>(deadline: Instant, fut: F) -> Timeout<F> {
+    Timeout {
+        fut,
+        sleep: sleep_until(deadline),
+    }
+}
+
+#[derive(Debug)]
+pub struct Timeout<F> {
+    fut: F,
+    sleep: Sleep,
+}
+
+impl<F> Timeout<F> {
+    pub fn get_ref(&self) -> &F {
+        &self.fut
+    }
+
+    pub fn into_inner(self) -> F {
+        self.fut
+    }
+}
+
+impl<F: Future> Future for Timeout<F> {
+    type Output = Result<F::Output, Elapsed>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        // `fut` is structurally pinned: it is never moved out of `self`
+        let this = unsafe { self.get_unchecked_mut() };
+        if let Poll::Ready(out) = unsafe { Pin::new_unchecked(&mut this.fut) }.poll(cx) {
+            return Poll::Ready(Ok(out));
+        }
+        Pin::new(&mut this.sleep).poll(cx).map(|()| Err(Elapsed))
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Elapsed;
+
+impl fmt::Display for Elapsed {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("deadline has elapsed")
+    }
+}
+
+impl std::error::Error for Elapsed {}
+
+impl From<Elapsed> for io::Error {
+    fn from(e: Elapsed) -> io::Error {
+        io::Error::new(io::ErrorKind::TimedOut, e)
+    }
+}
+
+// ticks every `period`, the first time at once
+pub fn interval(period: Duration) -> Interval {
+    interval_at(now(), period)
+}
+
+// ticks at `start`, then every `period`. ticks missed while the task was busy are skipped,
+// not fired in a burst; later ticks stay on the same schedule.
+pub fn interval_at(start: Instant, period: Duration) -> Interval {
+    assert!(
+        period > Duration::from_secs(0),
+        "interval period must be non-zero"
+    );
+    Interval {
+        sleep: sleep_until(start),
+        period,
+    }
+}
+
+#[derive(Debug)]
+pub struct Interval {
+    sleep: Sleep,
+    period: Duration,
+}
+
+impl Interval {
+    // completes at the next tick with its scheduled time
+    pub async fn tick(&mut self) -> Instant {
+        futures::future::poll_fn(|cx| self.poll_tick(cx)).await
+    }
+
+    pub fn poll_tick(&mut self, cx: &mut Context<'_>) -> Poll<Instant> {
+        if Pin::new(&mut self.sleep).poll(cx).is_pending() {
+            return Poll::Pending;
+        }
+        let tick = self.sleep.deadline();
+        let now = now();
+        let mut next = tick + self.period;
+        while next <= now {
+            next += self.period;
+        }
+        self.sleep.reset(next);
+        Poll::Ready(tick)
+    }
+
+    pub fn period(&self) -> Duration {
+        self.period
+    }
+}
+
+impl Stream for Interval {
+    type Item = Instant;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Instant>> {
+        self.get_mut().poll_tick(cx).map(Some)
+    }
+}
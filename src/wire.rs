@@ -0,0 +1,156 @@
+use futures::io::{AsyncRead, AsyncWrite};
+use log::*;
+use std::fmt::Write;
+use std::io;
+use std::pin::Pin;
+use std::task;
+
+// logs every chunk read from / written to `inner` as a hex dump, tagged with the connection id.
+// `limit` caps how many bytes of each chunk are dumped; `None` turns the tracing off.
+pub struct WireTrace<S> {
+    inner: S,
+    conn: usize,
+    limit: Option<usize>,
+}
+
+impl<S> WireTrace<S> {
+    pub fn new(inner: S, conn: usize, limit: Option<usize>) -> WireTrace<S> {
+        WireTrace { inner, conn, limit }
+    }
+
+    pub fn conn(&self) -> usize {
+        self.conn
+    }
+
+    pub fn get_ref(&self) -> &S {
+        &self.inner
+    }
+
+    pub fn get_mut(&mut self) -> &mut S {
+        &mut self.inner
+    }
+
+    pub fn into_inner(self) -> S {
+        self.inner
+    }
+
+    fn dump(&self, dir: &str, buf: &[u8]) {
+        if let Some(limit) = self.limit {
+            if !log_enabled!(Level::Debug) {
+                return;
+            }
+            let shown = &buf[..buf.len().min(limit)];
+            let mut msg = format!(
+                "#{} {} {} bytes\n{}",
+                self.conn,
+                dir,
+                buf.len(),
+                hexdump(shown)
+            );
+            if shown.len() < buf.len() {
+                let _ = writeln!(msg, "... {} bytes omitted", buf.len() - shown.len());
+            }
+            debug!("{}", msg);
+        }
+    }
+}
+
+impl<S: AsyncRead + Unpin> AsyncRead for WireTrace<S> {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut task::Context,
+        buf: &mut [u8],
+    ) -> task::Poll<io::Result<usize>> {
+        let poll = Pin::new(&mut self.inner).poll_read(cx, buf);
+        if let task::Poll::Ready(Ok(len)) = poll {
+            self.dump("recv", &buf[..len]);
+        }
+        poll
+    }
+
+    fn poll_read_vectored(
+        mut self: Pin<&mut Self>,
+        cx: &mut task::Context,
+        bufs: &mut [io::IoSliceMut],
+    ) -> task::Poll<io::Result<usize>> {
+        let poll = Pin::new(&mut self.inner).poll_read_vectored(cx, bufs);
+        // gathered only when dumped
+        if let (task::Poll::Ready(Ok(len)), Some(_)) = (&poll, self.limit) {
+            self.dump("recv", &gather(bufs.iter().map(|buf| &**buf), *len));
+        }
+        poll
+    }
+}
+
+impl<S: AsyncWrite + Unpin> AsyncWrite for WireTrace<S> {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut task::Context,
+        buf: &[u8],
+    ) -> task::Poll<io::Result<usize>> {
+        let poll = Pin::new(&mut self.inner).poll_write(cx, buf);
+        if let task::Poll::Ready(Ok(len)) = poll {
+            self.dump("sent", &buf[..len]);
+        }
+        poll
+    }
+
+    fn poll_write_vectored(
+        mut self: Pin<&mut Self>,
+        cx: &mut task::Context,
+        bufs: &[io::IoSlice],
+    ) -> task::Poll<io::Result<usize>> {
+        let poll = Pin::new(&mut self.inner).poll_write_vectored(cx, bufs);
+        if let (task::Poll::Ready(Ok(len)), Some(_)) = (&poll, self.limit) {
+            self.dump("sent", &gather(bufs.iter().map(|buf| &**buf), *len));
+        }
+        poll
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut task::Context) -> task::Poll<io::Result<()>> {
+        Pin::new(&mut self.inner).poll_flush(cx)
+    }
+
+    fn poll_close(mut self: Pin<&mut Self>, cx: &mut task::Context) -> task::Poll<io::Result<()>> {
+        Pin::new(&mut self.inner).poll_close(cx)
+    }
+}
+
+// the first `len` bytes of `bufs`, dumped as one chunk
+fn gather<'a>(bufs: impl Iterator<Item = &'a [u8]>, len: usize) -> Vec<u8> {
+    let mut out = Vec::with_capacity(len);
+    for buf in bufs {
+        let rest = len - out.len();
+        out.extend_from_slice(&buf[..buf.len().min(rest)]);
+    }
+    out
+}
+
+// 16 bytes per line: offset, hex and printable ascii
+pub fn hexdump(buf: &[u8]) -> String {
+    let mut out = String::new();
+    for (i, line) in buf.chunks(16).enumerate() {
+        let _ = write!(out, "{:08x} ", i * 16);
+        for j in 0..16 {
+            if j == 8 {
+                out.push(' ');
+            }
+            match line.get(j) {
+                Some(b) => {
+                    let _ = write!(out, " {:02x}", b);
+                }
+                None => out.push_str("   "),
+            }
+        }
+        out.push_str("  |");
+        out.extend(line.iter().map(|&b| {
+            if b.is_ascii_graphic() || b == b' ' {
+                b as char
+            } else {
+                '.'
+            }
+        }));
+        out.push_str("|\n");
+    }
+    out
+}
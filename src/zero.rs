@@ -0,0 +1,74 @@
+// overwriting buffers that held secrets before their memory is freed, for the
+// `secure-buffers` feature: request heads and bodies (Authorization headers, cookies, login
+// forms), session data and keys. only the final allocation is wiped; copies a `Vec` left
+// behind when it grew are not, so buffers holding secrets should be sized up front.
+use std::{
+    collections::HashMap,
+    ops::{Deref, DerefMut},
+    ptr,
+    sync::atomic::{self, Ordering},
+};
+
+pub(crate) trait Zero {
+    fn zero(&mut self);
+}
+
+impl Zero for Vec<u8> {
+    fn zero(&mut self) {
+        let ptr = self.as_mut_ptr();
+        // the spare capacity too: bytes drained from the front were moved, not erased
+        for i in 0..self.capacity() {
+            // volatile, so the writes to memory about to be freed aren't optimized away
+            unsafe { ptr::write_volatile(ptr.add(i), 0) };
+        }
+        atomic::compiler_fence(Ordering::SeqCst);
+        self.clear();
+    }
+}
+
+impl Zero for String {
+    fn zero(&mut self) {
+        // emptied right after, so it never holds the zeros as (valid) UTF-8 anyway
+        unsafe { self.as_mut_vec() }.zero();
+    }
+}
+
+impl<T: Zero> Zero for Option<T> {
+    fn zero(&mut self) {
+        if let Some(inner) = self {
+            inner.zero();
+        }
+    }
+}
+
+impl Zero for HashMap<String, String> {
+    fn zero(&mut self) {
+        for (mut key, mut value) in self.drain() {
+            key.zero();
+            value.zero();
+        }
+    }
+}
+
+// zeroes `T` when dropped, otherwise used like it
+pub(crate) struct Zeroing<T: Zero>(pub(crate) T);
+
+impl<T: Zero> Deref for Zeroing<T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        &self.0
+    }
+}
+
+impl<T: Zero> DerefMut for Zeroing<T> {
+    fn deref_mut(&mut self) -> &mut T {
+        &mut self.0
+    }
+}
+
+impl<T: Zero> Drop for Zeroing<T> {
+    fn drop(&mut self) {
+        self.0.zero();
+    }
+}
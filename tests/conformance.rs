@@ -0,0 +1,282 @@
+// runs the HTTP/1.1 conformance corpus in tests/conformance/ against the server's connection
+// handling, with `test_util::MemoryStream` standing in for the socket:
+//
+//     cargo test --features test-util --test conformance
+//
+// a protocol change that alters how any case is answered fails here; if the new behavior is
+// intended, the case is updated with it.
+#![feature(async_await)]
+
+use futures::future;
+use net_test3::http::{HttpServer, Request, Response};
+use net_test3::test_util::{spawn_and_poll_once, MemoryStream};
+use std::{fmt::Write, task::Poll, time::Duration};
+
+const CORPUS: &str = include_str!("conformance/http1.txt");
+
+// how long a case may take before it is taken to hang
+const TURNS: usize = 500;
+const TURN: Duration = Duration::from_millis(10);
+
+#[derive(Debug, Default)]
+struct Case {
+    name: String,
+    line: usize,
+    config: Vec<String>,
+    input: Vec<u8>,
+    // status and body substring of each response expected
+    expect: Vec<(u16, Option<Vec<u8>>)>,
+}
+
+#[derive(Debug)]
+struct Answer {
+    status: u16,
+    body: Vec<u8>,
+}
+
+fn unescape(s: &str, line: usize) -> Vec<u8> {
+    let mut out = Vec::new();
+    let mut bytes = s.bytes();
+    while let Some(b) = bytes.next() {
+        if b != b'\\' {
+            out.push(b);
+            continue;
+        }
+        match bytes.next() {
+            Some(b'r') => out.push(b'\r'),
+            Some(b'n') => out.push(b'\n'),
+            Some(b't') => out.push(b'\t'),
+            Some(b'0') => out.push(0),
+            Some(b'\\') => out.push(b'\\'),
+            Some(b'"') => out.push(b'"'),
+            Some(b'x') => {
+                let hex: Vec<u8> = bytes.by_ref().take(2).collect();
+                let hex = std::str::from_utf8(&hex).unwrap();
+                out.push(u8::from_str_radix(hex, 16).expect(&format!("line {}: bad \\x", line)));
+            }
+            other => panic!("line {}: bad escape {:?}", line, other.map(char::from)),
+        }
+    }
+    out
+}
+
+fn parse_corpus(corpus: &str) -> Vec<Case> {
+    let mut cases: Vec<Case> = Vec::new();
+    for (i, line) in corpus.lines().enumerate() {
+        let line_no = i + 1;
+        if line.trim().is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let colon = line
+            .find(": ")
+            .unwrap_or_else(|| panic!("line {}: expected `key: value`", line_no));
+        let (key, value) = (&line[..colon], &line[colon + 2..]);
+        if key == "case" {
+            cases.push(Case {
+                name: value.to_owned(),
+                line: line_no,
+                ..Case::default()
+            });
+            continue;
+        }
+        let case = cases
+            .last_mut()
+            .unwrap_or_else(|| panic!("line {}: {} before the first case", line_no, key));
+        match key {
+            "config" => case.config.push(value.to_owned()),
+            "send" => case.input.extend(unescape(value, line_no)),
+            _ if key.starts_with("send*") => {
+                let times: usize = key[5..].parse().expect("send*<n>");
+                let bytes = unescape(value, line_no);
+                for _ in 0..times {
+                    case.input.extend_from_slice(&bytes);
+                }
+            }
+            "expect" if value == "none" => {}
+            "expect" => {
+                let (status, body) = match value.find(' ') {
+                    Some(space) => (&value[..space], Some(&value[space + 1..])),
+                    None => (value, None),
+                };
+                let body = body.map(|body| {
+                    let quoted = body.trim();
+                    assert!(
+                        quoted.len() >= 2 && quoted.starts_with('"') && quoted.ends_with('"'),
+                        "line {}: body substrings are quoted",
+                        line_no
+                    );
+                    unescape(&quoted[1..quoted.len() - 1], line_no)
+                });
+                case.expect.push((status.parse().expect("status"), body));
+            }
+            _ => panic!("line {}: unknown key {:?}", line_no, key),
+        }
+    }
+    cases
+}
+
+fn echo(req: Request) -> future::Ready<Response> {
+    let mut body = format!("{} {}\n", req.method(), req.uri()).into_bytes();
+    if let Some(value) = req.header("x-echo") {
+        body.extend_from_slice(format!("x-echo={}\n", value).as_bytes());
+    }
+    body.extend_from_slice(req.body());
+    let mut res = Response::ok();
+    res.set_body(body);
+    future::ready(res)
+}
+
+// what the server wrote for `input`, fed to it `read_size` bytes at a time
+fn serve(case: &Case, read_size: usize) -> Vec<u8> {
+    let addr = "127.0.0.1:0".parse().unwrap();
+    let mut server = HttpServer::bind(&addr, echo).unwrap();
+    for config in &case.config {
+        let mut words = config.split_whitespace();
+        match (words.next(), words.next()) {
+            (Some("lenient"), None) => server.strict_framing(false),
+            (Some("strict-utf8"), None) => server.strict_utf8(true),
+            (Some("max-body"), Some(len)) => server.max_body_len(len.parse().unwrap()),
+            _ => panic!("{}: unknown config {:?}", case.name, config),
+        };
+    }
+    let input = case.input.clone();
+    let (mut task, mut poll) = spawn_and_poll_once(async move {
+        let mut sock = MemoryStream::new(&input);
+        sock.read_size(read_size);
+        let peer = "127.0.0.1:50000".parse().unwrap();
+        // I/O errors, e.g. the input ending mid-request, only close the connection
+        let _ = server.serve_connection(peer, &mut sock).await;
+        sock
+    });
+    for _ in 0..TURNS {
+        if let Poll::Ready(sock) = poll {
+            return sock.written().to_vec();
+        }
+        poll = task.turn(TURN).unwrap();
+    }
+    panic!(
+        "{}: still serving after {:?}",
+        case.name,
+        TURN * TURNS as u32
+    );
+}
+
+fn find(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    haystack.windows(needle.len()).position(|w| w == needle)
+}
+
+// the responses in `out`, as framed by their heads
+fn parse_responses(mut out: &[u8]) -> Result<Vec<Answer>, String> {
+    let mut answers = Vec::new();
+    while !out.is_empty() {
+        let head_end = find(out, b"\r\n\r\n").ok_or("head without end")? + 4;
+        let head = String::from_utf8_lossy(&out[..head_end]).into_owned();
+        out = &out[head_end..];
+        let mut lines = head.split("\r\n");
+        let status_line = lines.next().unwrap_or("");
+        let status: u16 = status_line
+            .split(' ')
+            .nth(1)
+            .and_then(|s| s.parse().ok())
+            .ok_or_else(|| format!("bad status line {:?}", status_line))?;
+        let header = |name: &str| {
+            head.split("\r\n").skip(1).find_map(|line| {
+                let colon = line.find(':')?;
+                if line[..colon].eq_ignore_ascii_case(name) {
+                    Some(line[colon + 1..].trim().to_owned())
+                } else {
+                    None
+                }
+            })
+        };
+        let mut body = Vec::new();
+        if status < 200 || status == 204 || status == 304 {
+        } else if header("transfer-encoding").map_or(false, |te| te.ends_with("chunked")) {
+            loop {
+                let line_end = find(out, b"\r\n").ok_or("chunk size without end")?;
+                let line = std::str::from_utf8(&out[..line_end]).map_err(|e| e.to_string())?;
+                let size = usize::from_str_radix(line.split(';').next().unwrap().trim(), 16)
+                    .map_err(|e| format!("chunk size {:?}: {}", line, e))?;
+                out = &out[line_end + 2..];
+                if size == 0 {
+                    let end = find(out, b"\r\n").ok_or("trailers without end")?;
+                    out = &out[end + 2..];
+                    break;
+                }
+                if out.len() < size + 2 {
+                    return Err("chunk cut off".to_owned());
+                }
+                body.extend_from_slice(&out[..size]);
+                out = &out[size + 2..];
+            }
+        } else if let Some(len) = header("content-length") {
+            let len: usize = len.parse().map_err(|_| "bad Content-Length")?;
+            if out.len() < len {
+                return Err("body cut off".to_owned());
+            }
+            body.extend_from_slice(&out[..len]);
+            out = &out[len..];
+        } else {
+            body.extend_from_slice(out);
+            out = &[];
+        }
+        answers.push(Answer { status, body });
+    }
+    Ok(answers)
+}
+
+fn check(case: &Case, out: &[u8]) -> Result<(), String> {
+    let answers = parse_responses(out)?;
+    if answers.len() != case.expect.len() {
+        return Err(format!(
+            "{} responses, expected {}: {:?}",
+            answers.len(),
+            case.expect.len(),
+            answers.iter().map(|a| a.status).collect::<Vec<_>>()
+        ));
+    }
+    for (answer, (status, body)) in answers.iter().zip(&case.expect) {
+        if answer.status != *status {
+            return Err(format!("status {}, expected {}", answer.status, status));
+        }
+        if let Some(body) = body {
+            if find(&answer.body, body).is_none() {
+                return Err(format!(
+                    "body {:?} without {:?}",
+                    String::from_utf8_lossy(&answer.body),
+                    String::from_utf8_lossy(body)
+                ));
+            }
+        }
+    }
+    Ok(())
+}
+
+#[test]
+fn http1_corpus() {
+    let cases = parse_corpus(CORPUS);
+    assert!(!cases.is_empty());
+    let mut failures = String::new();
+    for case in &cases {
+        for &read_size in &[usize::max_value(), 1] {
+            let out = serve(case, read_size);
+            if let Err(e) = check(case, &out) {
+                let split = if read_size == 1 {
+                    ", a byte per read"
+                } else {
+                    ""
+                };
+                let _ = writeln!(
+                    failures,
+                    "line {}: {}{}: {}\n  wrote {:?}",
+                    case.line,
+                    case.name,
+                    split,
+                    e,
+                    String::from_utf8_lossy(&out)
+                );
+            }
+        }
+    }
+    assert!(failures.is_empty(), "conformance failures:\n{}", failures);
+}
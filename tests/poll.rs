@@ -0,0 +1,465 @@
+// when the crate's futures are pending, ready and woken:
+//
+//     cargo test --features test-util --test poll
+#![feature(async_await)]
+
+use futures::prelude::*;
+use net_test3::http::client::Client;
+use net_test3::http::sse::{Event, SseBody};
+use net_test3::test_util::{self, spawn_and_poll_once, TestTask};
+use net_test3::{assert_pending, assert_ready, fs, net, reactor, runner, runtime, time};
+use std::{io::Write, time::Duration};
+
+const TURN: Duration = Duration::from_millis(50);
+
+#[test]
+fn tcp_read_waits_for_the_peer() {
+    let std_listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = std_listener.local_addr().unwrap();
+    let mut connect = TestTask::new(net::TcpStream::connect(&addr));
+    let mut sock = loop {
+        if let std::task::Poll::Ready(sock) = connect.poll() {
+            break sock.unwrap();
+        }
+        reactor::turn(Some(TURN)).unwrap();
+    };
+    let (mut peer, _) = std_listener.accept().unwrap();
+
+    let mut buf = [0u8; 16];
+    let mut read = TestTask::new(sock.read(&mut buf));
+    assert_pending!(read.poll());
+    assert!(!read.is_woken());
+
+    peer.write_all(b"ping").unwrap();
+    reactor::turn(Some(TURN)).unwrap();
+    assert!(read.is_woken());
+    assert_eq!(assert_ready!(read.poll()).unwrap(), 4);
+    drop(read);
+    assert_eq!(&buf[..4], b"ping");
+}
+
+#[test]
+fn sleep_is_woken_once_at_its_deadline() {
+    let mut sleep = TestTask::new(time::sleep(Duration::from_millis(20)));
+    assert_pending!(sleep.poll());
+    while !sleep.is_woken() {
+        reactor::turn(Some(TURN)).unwrap();
+    }
+    assert_ready!(sleep.poll());
+    assert_eq!(sleep.wake_count(), 1);
+}
+
+#[test]
+fn timeout_gives_up_on_a_pending_future() {
+    let (mut task, first) = spawn_and_poll_once(time::timeout(
+        Duration::from_millis(20),
+        future::pending::<()>(),
+    ));
+    assert_pending!(first);
+    let result = loop {
+        if let std::task::Poll::Ready(result) = task.turn(TURN).unwrap() {
+            break result;
+        }
+    };
+    assert!(result.is_err());
+}
+
+#[test]
+fn file_reads_complete_on_the_runner() {
+    let (mut task, first) = spawn_and_poll_once(async {
+        let mut file = fs::File::open("Cargo.toml").await?;
+        let mut contents = String::new();
+        file.read_to_string(&mut contents).await?;
+        Ok::<_, std::io::Error>(contents)
+    });
+    // the pool hasn't opened the file yet
+    assert_pending!(first, "opened without the worker pool");
+    let contents = loop {
+        if let std::task::Poll::Ready(contents) = task.turn(TURN).unwrap() {
+            break contents.unwrap();
+        }
+    };
+    assert!(contents.contains("[package]"));
+}
+
+#[test]
+fn block_on_waits_for_io_and_runs_what_it_spawns() {
+    let std_listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = std_listener.local_addr().unwrap();
+    let peer = std::thread::spawn(move || {
+        let (mut peer, _) = std_listener.accept().unwrap();
+        std::thread::sleep(TURN);
+        peer.write_all(b"pong").unwrap();
+    });
+    let (tx, rx) = futures::channel::oneshot::channel();
+    let got = runner::block_on(async move {
+        runner::spawn_local(async move {
+            let _ = tx.send(7);
+        });
+        let mut sock = net::TcpStream::connect(&addr).await.unwrap();
+        let mut buf = Vec::new();
+        sock.read_to_end(&mut buf).await.unwrap();
+        (buf, rx.await.unwrap())
+    });
+    assert_eq!(got, (b"pong".to_vec(), 7));
+    peer.join().unwrap();
+}
+
+#[test]
+fn aborted_tasks_are_dropped_at_their_next_poll() {
+    let dropped = std::rc::Rc::new(std::cell::Cell::new(false));
+    let on_drop = scopeguard(std::rc::Rc::clone(&dropped));
+    let result = runner::block_on(async move {
+        let task = runner::spawn_local(async move {
+            let _on_drop = on_drop;
+            future::pending::<()>().await
+        });
+        time::sleep(Duration::from_millis(10)).await;
+        assert!(!task.is_finished());
+        task.abort();
+        task.await
+    });
+    assert_eq!(result, Err(runner::Aborted));
+    assert!(dropped.get());
+}
+
+#[test]
+fn cancelling_a_token_cancels_its_children() {
+    let token = runner::cancel::CancellationToken::new();
+    let child = token.child();
+    let output = runner::block_on(async {
+        let work = runner::spawn_local({
+            let child = child.clone();
+            async move { child.run_until_cancelled(future::pending::<()>()).await }
+        });
+        time::sleep(Duration::from_millis(10)).await;
+        token.cancel();
+        work.await
+    });
+    assert_eq!(output, Ok(None));
+    assert!(child.is_cancelled());
+    assert!(token.child().is_cancelled());
+}
+
+#[test]
+fn runner_polls_pending_tasks_only_when_woken() {
+    let polls = std::rc::Rc::new(std::cell::Cell::new(0));
+    let mut runner = runner::Runner::new();
+    let counted = std::rc::Rc::clone(&polls);
+    let _task = runner.spawner().spawn(future::poll_fn(move |_| {
+        counted.set(counted.get() + 1);
+        std::task::Poll::<()>::Pending
+    }));
+    for _ in 0..3 {
+        runner.run();
+    }
+    assert_eq!(polls.get(), 1);
+    assert_eq!(runner::queued(), 0);
+}
+
+#[test]
+fn runtime_runs_until_every_task_has_finished() {
+    let done = std::rc::Rc::new(std::cell::Cell::new(0));
+    let mut runtime = runtime::Runtime::new();
+    for ms in &[30, 10] {
+        let done = std::rc::Rc::clone(&done);
+        runtime.spawn(async move {
+            time::sleep(Duration::from_millis(*ms)).await;
+            // spawned along the way, and waited for too
+            runner::spawn_local(async move {
+                time::sleep(Duration::from_millis(*ms)).await;
+                done.set(done.get() + 1);
+            });
+        });
+    }
+    runtime.run().unwrap();
+    assert_eq!(done.get(), 2);
+    assert_eq!(runner::tasks(), 0);
+}
+
+#[test]
+fn threaded_runtime_runs_tasks_on_its_workers() {
+    let pool = runtime::threaded::ThreadedRuntime::new(3).unwrap();
+    let handle = pool.handle();
+    let (names, panicked) = pool.block_on(async move {
+        // each sleeps on the reactor of the worker it runs on
+        let tasks: Vec<_> = (0..12u64)
+            .map(|i| {
+                handle.spawn(async move {
+                    time::sleep(Duration::from_millis(20 + i)).await;
+                    // left to the worker's runner
+                    runner::spawn_local(async {});
+                    std::thread::current().name().unwrap().to_owned()
+                })
+            })
+            .collect();
+        let mut names = Vec::new();
+        for task in tasks {
+            names.push(task.await.unwrap());
+        }
+        let panicked = handle.spawn(async { panic!("in a task") }).await;
+        (names, panicked)
+    });
+    assert_eq!(names.len(), 12);
+    assert!(names.iter().all(|name| name.starts_with("worker-")));
+    assert_eq!(panicked, Err(runner::Aborted));
+    // the worker survived the panic
+    assert_eq!(pool.block_on(async { 1 + 1 }), 2);
+}
+
+#[test]
+fn tasks_woken_on_other_threads_wake_the_runner() {
+    let (tx, rx) = futures::channel::oneshot::channel();
+    let sender = std::thread::spawn(move || {
+        std::thread::sleep(Duration::from_millis(20));
+        tx.send(7).unwrap();
+    });
+    // the runner waits in the reactor, with no timer or I/O to end the wait
+    let value = runner::block_on(async { runner::spawn_local(rx).await.unwrap().unwrap() });
+    assert_eq!(value, 7);
+    sender.join().unwrap();
+}
+
+#[test]
+fn client_pipelines_and_reuses_one_connection() {
+    let std_listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+    let url = format!("http://{}/", std_listener.local_addr().unwrap());
+    let peer = std::thread::spawn(move || {
+        let (mut peer, _) = std_listener.accept().unwrap();
+        let (mut received, mut answered) = (Vec::new(), 0);
+        let mut buf = [0u8; 1024];
+        while answered < 3 {
+            let len = std::io::Read::read(&mut peer, &mut buf).unwrap();
+            received.extend_from_slice(&buf[..len]);
+            let heads = received.windows(4).filter(|w| w == b"\r\n\r\n").count();
+            while answered < heads {
+                answered += 1;
+                write!(
+                    peer,
+                    "HTTP/1.1 200 OK\r\nContent-Length: 1\r\n\r\n{}",
+                    answered
+                )
+                .unwrap();
+            }
+        }
+        // no other connection was opened
+        std_listener.set_nonblocking(true).unwrap();
+        std_listener.accept().is_err()
+    });
+    let bodies = runner::block_on(async move {
+        let mut client = Client::new();
+        client.timeout(Duration::from_secs(5));
+        let mut bodies = Vec::new();
+        let batch = vec![client.get(&url), client.get(&url)];
+        for res in client.pipeline(batch).await.unwrap() {
+            bodies.push(res.text().await.unwrap());
+        }
+        let res = client.get(&url).send().await.unwrap();
+        bodies.push(res.text().await.unwrap());
+        bodies
+    });
+    assert_eq!(bodies, ["1", "2", "3"]);
+    assert!(peer.join().unwrap());
+}
+
+#[test]
+fn spawn_blocking_runs_off_the_runner_thread() {
+    let runner_thread = std::thread::current().id();
+    let (thread, ticks) = runner::block_on(async {
+        let blocking = runner::spawn_blocking(|| {
+            std::thread::sleep(Duration::from_millis(60));
+            std::thread::current().id()
+        });
+        // the runner keeps turning meanwhile
+        let mut ticks = 0;
+        while !blocking.is_finished() {
+            time::sleep(Duration::from_millis(5)).await;
+            ticks += 1;
+        }
+        (blocking.await.unwrap(), ticks)
+    });
+    assert_ne!(thread, runner_thread);
+    assert!(ticks > 1);
+    let panicked = runner::block_on(runner::spawn_blocking(|| panic!("in a closure")));
+    assert_eq!(panicked, Err::<(), _>(runner::Aborted));
+}
+
+#[test]
+fn dump_lists_unfinished_tasks_by_state() {
+    let mut runner = runner::Runner::new();
+    let _idle = runner.spawner().spawn(future::pending::<()>());
+    let dumped = std::rc::Rc::new(std::cell::RefCell::new(Vec::new()));
+    let into = std::rc::Rc::clone(&dumped);
+    let _dumping = runner.spawner().spawn(async move {
+        *into.borrow_mut() = runner::dump();
+    });
+    runner.run();
+    let states: Vec<_> = dumped.borrow().iter().map(|task| task.state).collect();
+    assert_eq!(
+        states,
+        [runner::TaskState::Idle, runner::TaskState::Running]
+    );
+    let dump = runner::dump();
+    assert_eq!(dump.len(), 1);
+    assert_eq!(dump[0].polls, 1);
+    assert!(dump[0].future.contains("Pending"));
+    assert_eq!(runner::stats().polls, 2);
+}
+
+#[test]
+fn named_tasks_carry_their_names() {
+    let mut runner = runner::Runner::new();
+    let _idle = runner
+        .spawner()
+        .spawn_named("connection 127.0.0.1:54321", future::pending::<()>());
+    let seen = std::rc::Rc::new(std::cell::RefCell::new(None));
+    let into = std::rc::Rc::clone(&seen);
+    let _naming = runner.spawner().spawn_named("naming", async move {
+        *into.borrow_mut() = runner::task_name();
+    });
+    runner.run();
+    assert_eq!(seen.borrow().as_ref().map(|name| &name[..]), Some("naming"));
+    assert_eq!(runner::task_name(), None);
+    let dump = runner::dump();
+    assert_eq!(dump.len(), 1);
+    assert_eq!(
+        dump[0].name.as_ref().map(|name| &name[..]),
+        Some("connection 127.0.0.1:54321")
+    );
+}
+
+#[test]
+fn dropping_a_scope_aborts_its_children() {
+    let dropped = std::rc::Rc::new(std::cell::Cell::new(false));
+    let on_drop = scopeguard(std::rc::Rc::clone(&dropped));
+    let (quick, stuck) = runner::block_on(async move {
+        let scope = runner::scope::TaskScope::new();
+        let quick = scope.spawn(time::sleep(Duration::from_millis(5)));
+        let stuck = scope.spawn(async move {
+            let _on_drop = on_drop;
+            future::pending::<()>().await
+        });
+        let quick = quick.await;
+        assert_eq!(scope.len(), 1);
+        drop(scope);
+        (quick, stuck.await)
+    });
+    assert_eq!(quick, Ok(()));
+    assert_eq!(stuck, Err(runner::Aborted));
+    assert!(dropped.get());
+}
+
+#[test]
+fn joining_a_scope_waits_for_its_children() {
+    let done = runner::block_on(async {
+        let scope = runner::scope::TaskScope::new();
+        let done = std::rc::Rc::new(std::cell::Cell::new(0));
+        for ms in 1..4 {
+            let done = std::rc::Rc::clone(&done);
+            scope.spawn(async move {
+                time::sleep(Duration::from_millis(ms)).await;
+                done.set(done.get() + 1);
+            });
+        }
+        scope.join().await;
+        assert!(scope.is_empty());
+        done.get()
+    });
+    assert_eq!(done, 3);
+}
+
+#[test]
+fn driver_moves_timers_on_by_its_clock() {
+    let started = std::time::Instant::now();
+    let mut driver = test_util::Driver::new();
+    let timeout = driver.spawn_named(
+        "timeout",
+        time::timeout(Duration::from_secs(5), future::pending::<()>()),
+    );
+    let ticks = driver.spawn(async {
+        let mut interval = time::interval(Duration::from_secs(2));
+        for _ in 0..3 {
+            interval.tick().await;
+        }
+    });
+    assert_eq!(driver.run_until_stalled(), 2);
+    driver.advance(Duration::from_secs(4));
+    driver.run_until_stalled();
+    assert!(!timeout.is_finished());
+    driver.run();
+    assert!(timeout.is_finished() && ticks.is_finished());
+    assert_eq!(driver.elapsed(), Duration::from_secs(6));
+    let schedule: Vec<_> = driver
+        .schedule()
+        .iter()
+        .map(|step| {
+            (
+                step.name.as_ref().map(|name| &name[..]),
+                step.at.as_secs(),
+                step.finished,
+            )
+        })
+        .collect();
+    assert_eq!(
+        schedule,
+        [
+            (Some("timeout"), 0, false),
+            (None, 0, false),
+            // the tick due at 4 is skipped, as it is due already when the one at 2 fires
+            (None, 4, false),
+            (Some("timeout"), 5, true),
+            (None, 6, true),
+        ]
+    );
+    assert!(started.elapsed() < Duration::from_secs(1));
+}
+
+#[test]
+fn sse_data_gets_a_field_per_line_whatever_breaks_it() {
+    let (tx, rx) = futures::channel::mpsc::unbounded();
+    let mut body = SseBody::new(rx, None);
+    tx.unbounded_send(Event::data("a\rb\r\nc\nd")).unwrap();
+    assert_eq!(
+        assert_ready!(TestTask::new(body.next()).poll()).unwrap(),
+        b"data: a\ndata: b\ndata: c\ndata: d\n\n"
+    );
+    assert_pending!(TestTask::new(body.next()).poll());
+}
+
+#[test]
+fn sse_keep_alive_follows_the_driver_clock() {
+    let started = std::time::Instant::now();
+    let mut driver = test_util::Driver::new();
+    let (_tx, rx) = futures::channel::mpsc::unbounded();
+    let comments = std::rc::Rc::new(std::cell::Cell::new(0));
+    let counted = comments.clone();
+    driver.spawn(async move {
+        let mut body = SseBody::new(rx, Some(Duration::from_secs(15)));
+        while let Some(bytes) = body.next().await {
+            assert_eq!(bytes, b":\n\n");
+            counted.set(counted.get() + 1);
+        }
+    });
+    driver.run_until_stalled();
+    driver.advance(Duration::from_secs(15));
+    driver.run_until_stalled();
+    assert_eq!(comments.get(), 1);
+    driver.advance(Duration::from_secs(14));
+    driver.run_until_stalled();
+    assert_eq!(comments.get(), 1);
+    driver.advance(Duration::from_secs(1));
+    driver.run_until_stalled();
+    assert_eq!(comments.get(), 2);
+    assert!(started.elapsed() < Duration::from_secs(1));
+}
+
+// sets the flag when dropped
+fn scopeguard(flag: std::rc::Rc<std::cell::Cell<bool>>) -> impl Drop {
+    struct Guard(std::rc::Rc<std::cell::Cell<bool>>);
+    impl Drop for Guard {
+        fn drop(&mut self) {
+            self.0.set(true);
+        }
+    }
+    Guard(flag)
+}